@@ -1,5 +1,5 @@
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
-use pariter::IteratorExt;
+use pariter::{IteratorExt, Workload};
 
 #[inline]
 fn fibonacci(n: u64) -> u64 {
@@ -63,5 +63,78 @@ pub fn map_fibonacci(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, map_fibonacci);
+/// Closures too cheap for parallelism to pay off, without `Workload::TinyItems` or batching
+pub fn map_tiny(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tiny");
+
+    for num_elements in [1_000, 10_000, 100_000] {
+        let sample = sample_vec(num_elements);
+
+        group.throughput(criterion::Throughput::Elements(num_elements));
+
+        group.bench_with_input(
+            BenchmarkId::new("map", num_elements),
+            &num_elements,
+            |b, _i| {
+                b.iter_batched(
+                    || sample.clone(),
+                    |v| {
+                        v.into_iter()
+                            .map(|i| black_box(i * 3 + 1))
+                            .collect::<Vec<_>>()
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+
+        for (name, workload) in [
+            ("parallel_map", Workload::Balanced),
+            ("parallel_map-tiny", Workload::TinyItems),
+        ] {
+            group.bench_with_input(
+                BenchmarkId::new(name, num_elements),
+                &num_elements,
+                |b, _i| {
+                    b.iter_batched(
+                        || sample.clone(),
+                        |v| {
+                            v.into_iter()
+                                .parallel_map_custom(
+                                    |o| o.threads(4).optimize_for(workload),
+                                    |i| black_box(i * 3 + 1),
+                                )
+                                .collect::<Vec<_>>()
+                        },
+                        BatchSize::SmallInput,
+                    )
+                },
+            );
+        }
+
+        group.bench_with_input(
+            BenchmarkId::new("parallel_batch_map", num_elements),
+            &num_elements,
+            |b, _i| {
+                b.iter_batched(
+                    || sample.clone(),
+                    |v| {
+                        v.into_iter()
+                            .parallel_batch_map_custom(
+                                |o| o.threads(4),
+                                1024,
+                                |batch: Vec<u64>| {
+                                    batch.into_iter().map(|i| black_box(i * 3 + 1)).collect()
+                                },
+                            )
+                            .collect::<Vec<_>>()
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+}
+
+criterion_group!(benches, map_fibonacci, map_tiny);
 criterion_main!(benches);
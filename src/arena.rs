@@ -0,0 +1,115 @@
+use std::{fmt, ops, sync::Arc};
+
+/// An allocator a worker of [`crate::ParallelMapBuilder::with_arena`] allocates outputs from
+///
+/// E.g. a bump allocator, or just a big buffer outputs are carved from.
+pub trait Arena {
+    /// Free everything allocated so far, to be reused
+    ///
+    /// Only called once all the [`ArenaItem`]s allocated since the previous
+    /// reset were dropped.
+    fn reset(&mut self);
+}
+
+/// An output allocated from an [`Arena`]
+///
+/// The arena it was allocated from will not be reset while it's alive,
+/// so drop it as soon as possible to let the worker reuse the arena.
+pub struct ArenaItem<O> {
+    item: O,
+    // keeps the epoch of the arena alive
+    _epoch: Arc<()>,
+}
+
+impl<O> ops::Deref for ArenaItem<O> {
+    type Target = O;
+
+    fn deref(&self) -> &O {
+        &self.item
+    }
+}
+
+impl<O> ops::DerefMut for ArenaItem<O> {
+    fn deref_mut(&mut self) -> &mut O {
+        &mut self.item
+    }
+}
+
+impl<O> fmt::Debug for ArenaItem<O>
+where
+    O: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ArenaItem").field(&self.item).finish()
+    }
+}
+
+/// The arenas of a single worker thread
+///
+/// Outputs are allocated from the current arena for `epoch_items` items, then the
+/// worker moves to an arena whose outputs were all dropped (resetting it), or
+/// creates a new one, if there are none.
+pub(crate) struct WorkerArenas<A, M> {
+    worker_i: usize,
+    // creates new arenas
+    make: M,
+    // number of items allocated from an arena before moving to the next one
+    epoch_items: usize,
+    // the arena used now, and number of items allocated from it so far
+    current: (A, Arc<()>),
+    current_items: usize,
+    // arenas whose outputs might still be alive
+    retired: Vec<(A, Arc<()>)>,
+}
+
+impl<A, M> WorkerArenas<A, M>
+where
+    A: Arena,
+    M: FnMut(usize) -> A,
+{
+    pub(crate) fn new(worker_i: usize, mut make: M, epoch_items: usize) -> Self {
+        debug_assert!(epoch_items > 0);
+        let arena = make(worker_i);
+        Self {
+            worker_i,
+            make,
+            epoch_items,
+            current: (arena, Arc::new(())),
+            current_items: 0,
+            retired: Vec::new(),
+        }
+    }
+
+    /// Call `f` with the current arena, and tag its output with the epoch
+    pub(crate) fn alloc<O>(&mut self, f: impl FnOnce(&mut A) -> O) -> ArenaItem<O> {
+        if self.current_items == self.epoch_items {
+            self.next_epoch();
+        }
+        self.current_items += 1;
+
+        ArenaItem {
+            item: f(&mut self.current.0),
+            _epoch: self.current.1.clone(),
+        }
+    }
+
+    fn next_epoch(&mut self) {
+        // an epoch is only referenced by us when all its outputs were dropped
+        let next = match self
+            .retired
+            .iter()
+            .position(|(_, epoch)| Arc::strong_count(epoch) == 1)
+        {
+            Some(pos) => {
+                let (mut arena, epoch) = self.retired.swap_remove(pos);
+                arena.reset();
+                (arena, epoch)
+            }
+            None => ((self.make)(self.worker_i), Arc::new(())),
+        };
+
+        self.retired
+            .push(std::mem::replace(&mut self.current, next));
+        self.current_items = 0;
+    }
+}
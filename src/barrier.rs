@@ -0,0 +1,60 @@
+use std::{collections::VecDeque, fmt};
+
+/// Waits for the inner iterator to be exhausted before returning any items
+///
+/// See [`crate::IteratorExt::barrier`].
+pub struct Barrier<I>
+where
+    I: Iterator,
+{
+    // the iterator we wrapped, until exhausted
+    iter: Option<I>,
+    // all the items of `iter`
+    buffered: VecDeque<I::Item>,
+}
+
+impl<I> Barrier<I>
+where
+    I: Iterator,
+{
+    pub(crate) fn new(iter: I) -> Self {
+        Self {
+            iter: Some(iter),
+            buffered: VecDeque::new(),
+        }
+    }
+}
+
+impl<I> fmt::Debug for Barrier<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Barrier")
+            .field("passed", &self.iter.is_none())
+            .field("buffered", &self.buffered.len())
+            .finish()
+    }
+}
+
+impl<I> Iterator for Barrier<I>
+where
+    I: Iterator,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(iter) = self.iter.take() {
+            self.buffered.extend(iter);
+        }
+
+        self.buffered.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.iter.as_ref() {
+            Some(iter) => iter.size_hint(),
+            None => (self.buffered.len(), Some(self.buffered.len())),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! Checkpointing of long-running pipelines
+//!
+//! Tag the items with their input positions using [`crate::IteratorExt::resume_from`],
+//! which on a restart skips the items completed before, and record the completed
+//! items with [`crate::IteratorExt::checkpoint`]:
+//!
+//! ```
+//! use pariter::{checkpoint::Checkpoint, IteratorExt as _};
+//!
+//! // loaded from wherever it was persisted, 0 on the first run
+//! let resume_from = 0;
+//!
+//! let checkpoint = Checkpoint::new(resume_from)
+//!     .persist_every(10, |completed| println!("resume from {} on restart", completed));
+//!
+//! (0..100)
+//!     .resume_from(resume_from)
+//!     .parallel_map(|(i, x)| (i, x * 2))
+//!     .checkpoint(checkpoint.clone())
+//!     .for_each(|(_i, x)| {
+//!         // write `x` somewhere
+//!     });
+//!
+//! assert_eq!(checkpoint.completed(), 100);
+//! ```
+//!
+//! For closures with side effects, that should happen at least once, use
+//! [`crate::IteratorExt::acknowledged`] instead, so items are completed only when
+//! acknowledged explicitly, and redelivered after a restart otherwise.
+use std::{
+    collections::BTreeSet,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+/// Callback persisting the number of completed items
+type Persist = Box<dyn FnMut(usize) + Send>;
+
+/// Tracks the completed items, by their input positions
+///
+/// Items can be completed in any order, the checkpoint is the number of items
+/// from the beginning of the input that are all completed, which is
+/// where processing should be resumed from after a restart.
+///
+/// Cloning returns a handle to the same checkpoint.
+#[derive(Clone)]
+pub struct Checkpoint {
+    state: Arc<Mutex<CheckpointState>>,
+}
+
+struct CheckpointState {
+    // all the items before this one are completed
+    completed: usize,
+    // items completed out of order, after `completed`
+    ahead: BTreeSet<usize>,
+    // persist every that many items, with the callback
+    persist: Option<(usize, Persist)>,
+    // `completed` when last persisted
+    persisted: usize,
+}
+
+impl CheckpointState {
+    fn persist(&mut self, force: bool) {
+        let completed = self.completed;
+        if let Some((every, callback)) = self.persist.as_mut() {
+            if (force && self.persisted < completed) || self.persisted + *every <= completed {
+                (callback)(completed);
+                self.persisted = completed;
+            }
+        }
+    }
+}
+
+impl Checkpoint {
+    /// Start tracking, with the first `completed` items done already
+    pub fn new(completed: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CheckpointState {
+                completed,
+                ahead: BTreeSet::new(),
+                persist: None,
+                persisted: completed,
+            })),
+        }
+    }
+
+    /// Call `callback` with the checkpoint whenever it advances by at least `every` items
+    ///
+    /// Also called when the input is exhausted, and on [`Checkpoint::persist`].
+    pub fn persist_every<F>(self, every: usize, callback: F) -> Self
+    where
+        F: FnMut(usize) + Send + 'static,
+    {
+        self.lock().persist = Some((every, Box::new(callback)));
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CheckpointState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Mark the item at input position `index` as completed
+    pub fn complete(&self, index: usize) {
+        let mut guard = self.lock();
+        let state = &mut *guard;
+
+        if index < state.completed {
+            return;
+        }
+        state.ahead.insert(index);
+        while state.ahead.remove(&state.completed) {
+            state.completed += 1;
+        }
+
+        state.persist(false);
+    }
+
+    /// Number of items from the beginning of the input that are all completed
+    pub fn completed(&self) -> usize {
+        self.lock().completed
+    }
+
+    /// Call the persist callback, if the checkpoint advanced since the last call
+    pub fn persist(&self) {
+        self.lock().persist(true);
+    }
+}
+
+impl fmt::Debug for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("Checkpoint")
+            .field("completed", &state.completed)
+            .field("ahead", &state.ahead.len())
+            .field("persisted", &state.persisted)
+            .finish()
+    }
+}
+
+/// Items tagged with their input positions, skipping the ones completed before
+///
+/// See [`crate::IteratorExt::resume_from`].
+#[derive(Debug)]
+pub struct Resume<I> {
+    // the iterator we wrapped
+    iter: I,
+    // position of the next item
+    next_i: usize,
+    // number of items to skip yet
+    skip: usize,
+}
+
+impl<I> Resume<I> {
+    pub(crate) fn new(iter: I, completed: usize) -> Self {
+        Self {
+            iter,
+            next_i: 0,
+            skip: completed,
+        }
+    }
+}
+
+impl<I> Iterator for Resume<I>
+where
+    I: Iterator,
+{
+    type Item = (usize, I::Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = if 0 < self.skip {
+            let skip = std::mem::take(&mut self.skip);
+            self.next_i += skip;
+            self.iter.nth(skip)?
+        } else {
+            self.iter.next()?
+        };
+
+        let i = self.next_i;
+        self.next_i += 1;
+        Some((i, item))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        (
+            lower.saturating_sub(self.skip),
+            upper.map(|upper| upper.saturating_sub(self.skip)),
+        )
+    }
+}
+
+/// Marks items as completed once the consumer is done with them
+///
+/// See [`crate::IteratorExt::checkpoint`].
+#[derive(Debug)]
+pub struct Checkpointed<I> {
+    // the iterator we wrapped
+    iter: I,
+    checkpoint: Checkpoint,
+    // position of the item last returned
+    pending: Option<usize>,
+}
+
+impl<I> Checkpointed<I> {
+    pub(crate) fn new(iter: I, checkpoint: Checkpoint) -> Self {
+        Self {
+            iter,
+            checkpoint,
+            pending: None,
+        }
+    }
+}
+
+impl<I, T> Iterator for Checkpointed<I>
+where
+    I: Iterator<Item = (usize, T)>,
+{
+    type Item = (usize, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // asking for the next item means the previous one is done
+        if let Some(index) = self.pending.take() {
+            self.checkpoint.complete(index);
+        }
+
+        match self.iter.next() {
+            Some((index, item)) => {
+                self.pending = Some(index);
+                Some((index, item))
+            }
+            None => {
+                self.checkpoint.persist();
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// Items completed only when the consumer acknowledges them
+///
+/// See [`crate::IteratorExt::acknowledged`].
+#[derive(Debug)]
+pub struct Acknowledged<I> {
+    // the iterator we wrapped
+    iter: I,
+    checkpoint: Checkpoint,
+}
+
+impl<I> Acknowledged<I> {
+    pub(crate) fn new(iter: I, checkpoint: Checkpoint) -> Self {
+        Self { iter, checkpoint }
+    }
+}
+
+impl<I, T> Acknowledged<I>
+where
+    I: Iterator<Item = (usize, T)>,
+{
+    /// The next item, completed only once [`Ack::ack`] is called
+    pub fn next_ack(&mut self) -> Option<Ack<T>> {
+        match self.iter.next() {
+            Some((index, item)) => Some(Ack {
+                index,
+                item,
+                checkpoint: self.checkpoint.clone(),
+            }),
+            None => {
+                self.checkpoint.persist();
+                None
+            }
+        }
+    }
+}
+
+impl<I, T> Iterator for Acknowledged<I>
+where
+    I: Iterator<Item = (usize, T)>,
+{
+    type Item = Ack<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_ack()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// An item to acknowledge once it's done with
+///
+/// Dropping it without calling [`Ack::ack`] leaves the item not completed,
+/// so the checkpoint doesn't advance past it, and it's delivered again after a restart.
+#[derive(Debug)]
+pub struct Ack<T> {
+    index: usize,
+    item: T,
+    checkpoint: Checkpoint,
+}
+
+impl<T> Ack<T> {
+    /// Input position of the item
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Mark the item as completed, returning it
+    pub fn ack(self) -> T {
+        self.checkpoint.complete(self.index);
+        self.item
+    }
+}
+
+impl<T> Deref for Ack<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.item
+    }
+}
+
+impl<T> DerefMut for Ack<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.item
+    }
+}
@@ -0,0 +1,151 @@
+//! Checksumming items on worker threads
+//!
+//! [`crate::IteratorExt::parallel_checksum`] computes the CRC-32 of every item
+//! on worker threads, and attaches it to the item, so it can be verified later,
+//! e.g. after a copy. The digest of the whole stream is computed from the checksums,
+//! in order, as the items are returned.
+//!
+//! ```
+//! use pariter::IteratorExt as _;
+//!
+//! let mut items = vec!["a".to_owned(), "b".to_owned()].into_iter().parallel_checksum();
+//! let first = items.next().unwrap();
+//! assert!(first.verify());
+//! assert_eq!(first.checksum(), pariter::checksum::crc32(b"a"));
+//! items.by_ref().for_each(drop);
+//! println!("digest: {:08x}", items.digest());
+//! ```
+use crate::{Describe, ParallelMap, PipelineNode};
+
+use std::ops::Deref;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |crc, &b| {
+        CRC32_TABLE[usize::from(crc as u8 ^ b)] ^ (crc >> 8)
+    })
+}
+
+/// CRC-32 (IEEE) of `bytes`
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_update(0, bytes)
+}
+
+/// An item with its checksum attached
+///
+/// See [`crate::IteratorExt::parallel_checksum`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksummed<T> {
+    item: T,
+    checksum: u32,
+}
+
+impl<T> Checksummed<T>
+where
+    T: AsRef<[u8]>,
+{
+    /// Attach the checksum of `item`
+    pub fn new(item: T) -> Self {
+        let checksum = crc32(item.as_ref());
+        Self { item, checksum }
+    }
+
+    /// Does the checksum still match the item
+    pub fn verify(&self) -> bool {
+        crc32(self.item.as_ref()) == self.checksum
+    }
+}
+
+impl<T> Checksummed<T> {
+    /// The checksum computed when the item was checksummed
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub fn into_inner(self) -> T {
+        self.item
+    }
+}
+
+impl<T> Deref for Checksummed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.item
+    }
+}
+
+/// An iterator checksumming items on worker threads
+///
+/// See [`crate::IteratorExt::parallel_checksum`].
+pub struct ParallelChecksum<I>
+where
+    I: Iterator,
+{
+    inner: ParallelMap<I, Checksummed<I::Item>>,
+    digest: u32,
+}
+
+impl<I> ParallelChecksum<I>
+where
+    I: Iterator,
+{
+    pub(crate) fn new(inner: ParallelMap<I, Checksummed<I::Item>>) -> Self {
+        Self { inner, digest: 0 }
+    }
+
+    /// Digest of all the items returned so far, in order
+    ///
+    /// The CRC-32 of the checksums of all the items, so it depends on their order.
+    pub fn digest(&self) -> u32 {
+        self.digest
+    }
+}
+
+impl<I> Iterator for ParallelChecksum<I>
+where
+    I: Iterator,
+    I::Item: Send,
+{
+    type Item = Checksummed<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+        self.digest = crc32_update(self.digest, &item.checksum.to_le_bytes());
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<I> Describe for ParallelChecksum<I>
+where
+    I: Iterator + Describe,
+{
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        self.inner.describe_into(nodes);
+    }
+}
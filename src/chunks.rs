@@ -0,0 +1,41 @@
+/// Iterator over `Vec`s of up to `size` consecutive items
+///
+/// Only the last chunk can be shorter than `size`.
+pub(crate) struct Chunks<I> {
+    // the iterator we wrapped
+    iter: I,
+    // number of items in each chunk
+    size: usize,
+}
+
+impl<I> Chunks<I> {
+    pub(crate) fn new(iter: I, size: usize) -> Self {
+        assert!(0 < size, "chunk size must be positive");
+        Self { iter, size }
+    }
+}
+
+impl<I> Iterator for Chunks<I>
+where
+    I: Iterator,
+{
+    type Item = Vec<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk: Vec<_> = self.iter.by_ref().take(self.size).collect();
+
+        if chunk.is_empty() {
+            None
+        } else {
+            Some(chunk)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        (
+            (lower / self.size) + usize::from(lower % self.size != 0),
+            upper.map(|upper| (upper / self.size) + usize::from(upper % self.size != 0)),
+        )
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    thread, time,
+};
+
+/// Stops calling a failing function for a while
+///
+/// After `threshold` consecutive failures, the breaker opens and no calls are made
+/// for `cooldown`. Then a single probe call is made: if it succeeds, the breaker closes,
+/// otherwise it stays open for another `cooldown`.
+///
+/// Cloning returns a handle to the same breaker, so it can be inspected
+/// while in use.
+///
+/// See [`crate::IteratorExt::parallel_map_breaker`].
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    threshold: usize,
+    cooldown: time::Duration,
+    // wait for the breaker to close instead of failing items
+    wait: bool,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: usize,
+    // when the breaker can be probed again, if open
+    open_until: Option<time::Instant>,
+    // is a probe call in progress
+    probing: bool,
+}
+
+/// Permission to make a call
+enum Permit {
+    Allowed,
+    Probe,
+    // denied until at least the given time
+    Denied(time::Instant),
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: usize, cooldown: time::Duration) -> Self {
+        assert!(0 < threshold, "threshold must be positive");
+        Self {
+            threshold,
+            cooldown,
+            wait: false,
+            state: Arc::new(Mutex::new(BreakerState::default())),
+        }
+    }
+
+    /// Wait for the breaker to close, instead of failing items with [`BreakerError::Open`]
+    ///
+    /// Stops processing until the cooldown is over, effectively buffering
+    /// the items in flight.
+    pub fn wait_when_open(self) -> Self {
+        Self { wait: true, ..self }
+    }
+
+    /// Is the breaker currently open
+    pub fn is_open(&self) -> bool {
+        self.lock().open_until.is_some()
+    }
+
+    /// Number of consecutive failures so far
+    pub fn consecutive_failures(&self) -> usize {
+        self.lock().consecutive_failures
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn acquire(&self) -> Permit {
+        let mut state = self.lock();
+        let now = time::Instant::now();
+
+        match state.open_until {
+            None => Permit::Allowed,
+            Some(until) if now < until => Permit::Denied(until),
+            // only one probe at a time, the others try again in a while
+            Some(_) if state.probing => Permit::Denied(now + self.cooldown / 10),
+            Some(_) => {
+                state.probing = true;
+                Permit::Probe
+            }
+        }
+    }
+
+    fn record(&self, success: bool, probe: bool) {
+        let mut state = self.lock();
+
+        if success {
+            *state = BreakerState::default();
+        } else {
+            state.consecutive_failures += 1;
+            if probe || self.threshold <= state.consecutive_failures {
+                state.open_until = Some(time::Instant::now() + self.cooldown);
+            }
+        }
+        if probe {
+            state.probing = false;
+        }
+    }
+
+    /// Call `f` on `item`, unless the breaker is open
+    pub(crate) fn call<T, O, E, F>(&self, item: T, f: F) -> Result<O, BreakerError<T, E>>
+    where
+        F: FnOnce(T) -> Result<O, E>,
+    {
+        let probe = loop {
+            match self.acquire() {
+                Permit::Allowed => break false,
+                Permit::Probe => break true,
+                Permit::Denied(until) if self.wait => {
+                    thread::sleep(until.saturating_duration_since(time::Instant::now()));
+                }
+                Permit::Denied(_) => return Err(BreakerError::Open(item)),
+            }
+        };
+
+        let res = (f)(item);
+        self.record(res.is_ok(), probe);
+        res.map_err(BreakerError::Failed)
+    }
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("CircuitBreaker")
+            .field("threshold", &self.threshold)
+            .field("cooldown", &self.cooldown)
+            .field("wait", &self.wait)
+            .field("consecutive_failures", &state.consecutive_failures)
+            .field("open", &state.open_until.is_some())
+            .finish()
+    }
+}
+
+/// Error returned by [`crate::IteratorExt::parallel_map_breaker`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakerError<T, E> {
+    /// The breaker was open, the item was not processed
+    Open(T),
+    /// Processing the item failed
+    Failed(E),
+}
+
+impl<T, E> fmt::Display for BreakerError<T, E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerError::Open(_) => write!(f, "circuit breaker open"),
+            BreakerError::Failed(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<T, E> std::error::Error for BreakerError<T, E>
+where
+    T: fmt::Debug,
+    E: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BreakerError::Open(_) => None,
+            BreakerError::Failed(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Compressing and decompressing streams on worker threads
+//!
+//! Like `pigz`: the input is split into chunks, (de)compressed in parallel,
+//! and reassembled in order. The codec is up to the caller, e.g. `flate2` or `zstd`.
+//!
+//! The compressed stream is a sequence of frames, each holding a single compressed
+//! chunk, prefixed with its length as a little-endian `u64`, so they can be
+//! decompressed independently.
+//!
+//! ```
+//! use pariter::compress::{read_compressed_chunks, write_compressed_ordered};
+//!
+//! // a trivial "codec", just for the example
+//! let reverse = |chunk: &[u8]| Ok(chunk.iter().rev().copied().collect::<Vec<_>>());
+//!
+//! let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+//! let mut compressed = vec![];
+//! write_compressed_ordered(&data[..], &mut compressed, 4096, reverse).unwrap();
+//!
+//! let mut decompressed = vec![];
+//! for chunk in read_compressed_chunks(&compressed[..], move |chunk: Vec<u8>| reverse(&chunk)) {
+//!     decompressed.extend(chunk.unwrap());
+//! }
+//! assert_eq!(data, decompressed);
+//! ```
+use crate::{IteratorExt as _, ParallelMap};
+
+use std::io::{self, Read, Write};
+
+/// Compress everything read from `reader`, in chunks of `chunk_size` bytes, and
+/// write the frames to `writer` in order
+///
+/// Returns the number of bytes read.
+pub fn write_compressed_ordered<R, W, C>(
+    reader: R,
+    mut writer: W,
+    chunk_size: usize,
+    compress: C,
+) -> io::Result<u64>
+where
+    R: Read,
+    W: Write,
+    C: FnMut(&[u8]) -> io::Result<Vec<u8>>,
+    C: 'static + Send + Clone,
+{
+    assert!(0 < chunk_size, "chunk size must be positive");
+    let mut total = 0;
+    let mut compress = compress;
+    let chunks = Chunks {
+        reader,
+        chunk_size: Some(chunk_size),
+    };
+
+    for frame in chunks.parallel_map(move |chunk: io::Result<Vec<u8>>| {
+        let chunk = chunk?;
+        let len = chunk.len() as u64;
+        io::Result::Ok((len, (compress)(&chunk)?))
+    }) {
+        let (len, frame) = frame?;
+        writer.write_all(&(frame.len() as u64).to_le_bytes())?;
+        writer.write_all(&frame)?;
+        total += len;
+    }
+    writer.flush()?;
+
+    Ok(total)
+}
+
+/// Decompress the frames read from `reader`, returning the chunks in order
+///
+/// Stops after the first error.
+pub fn read_compressed_chunks<R, D>(
+    reader: R,
+    mut decompress: D,
+) -> ParallelMap<Frames<R>, io::Result<Vec<u8>>>
+where
+    R: Read,
+    D: FnMut(Vec<u8>) -> io::Result<Vec<u8>>,
+    D: 'static + Send + Clone,
+{
+    Frames {
+        reader: Some(reader),
+    }
+    .parallel_map(move |frame: io::Result<Vec<u8>>| (decompress)(frame?))
+}
+
+/// Splits a reader into chunks
+struct Chunks<R> {
+    reader: R,
+    // `None` after the end or an error
+    chunk_size: Option<usize>,
+}
+
+impl<R> Iterator for Chunks<R>
+where
+    R: Read,
+{
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk_size = self.chunk_size?;
+        let mut chunk = Vec::with_capacity(chunk_size);
+        match (&mut self.reader)
+            .take(chunk_size as u64)
+            .read_to_end(&mut chunk)
+        {
+            Ok(0) => {
+                self.chunk_size = None;
+                None
+            }
+            Ok(_) => Some(Ok(chunk)),
+            Err(e) => {
+                self.chunk_size = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Reads the compressed frames
+///
+/// See [`read_compressed_chunks`].
+pub struct Frames<R> {
+    // `None` after the end or an error
+    reader: Option<R>,
+}
+
+impl<R> Iterator for Frames<R>
+where
+    R: Read,
+{
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = self.reader.as_mut()?;
+
+        let mut len = [0; 8];
+        let res = match reader.read(&mut len[..1]) {
+            Ok(0) => None,
+            Ok(_) => Some(reader.read_exact(&mut len[1..]).and_then(|()| {
+                let mut frame = vec![0; u64::from_le_bytes(len) as usize];
+                reader.read_exact(&mut frame)?;
+                Ok(frame)
+            })),
+            Err(e) => Some(Err(e)),
+        };
+
+        if !matches!(res, Some(Ok(_))) {
+            self.reader = None;
+        }
+        res
+    }
+}
@@ -0,0 +1,159 @@
+//! Describing the topology of pipelines
+//!
+//! Useful to log the effective configuration of a pipeline at startup:
+//!
+//! ```
+//! use pariter::{Describe, IteratorExt};
+//!
+//! let pipeline = (0..10)
+//!     .parallel_map_custom(|o| o.threads(2).name("double"), |x| x * 2)
+//!     .parallel_filter(|x| x % 3 == 0);
+//!
+//! println!("{}", pipeline.describe());
+//! ```
+use std::fmt;
+
+/// A stage of a pipeline, see [`Describe`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PipelineNode {
+    /// Name of the stage, if it was given one
+    pub name: Option<String>,
+    /// Kind of the stage, e.g. `parallel_map`
+    pub kind: &'static str,
+    /// Number of threads used by the stage, if any
+    pub threads: Option<usize>,
+    /// Max number of items buffered by the stage, if any
+    pub buffer: Option<usize>,
+}
+
+impl PipelineNode {
+    pub(crate) fn new(kind: &'static str) -> Self {
+        Self {
+            name: None,
+            kind,
+            threads: None,
+            buffer: None,
+        }
+    }
+}
+
+impl fmt::Display for PipelineNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.kind)?;
+        if let Some(name) = self.name.as_ref() {
+            write!(f, " `{}`", name)?;
+        }
+        match (self.threads, self.buffer) {
+            (Some(threads), Some(buffer)) => {
+                write!(f, " (threads: {}, buffer: {})", threads, buffer)
+            }
+            (Some(threads), None) => write!(f, " (threads: {})", threads),
+            (None, Some(buffer)) => write!(f, " (buffer: {})", buffer),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// The stages of a pipeline, from the source to the last one
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PipelineDescription {
+    nodes: Vec<PipelineNode>,
+}
+
+impl PipelineDescription {
+    pub fn nodes(&self) -> &[PipelineNode] {
+        &self.nodes
+    }
+}
+
+impl fmt::Display for PipelineDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, node) in self.nodes.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" -> ")?;
+            }
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator that can describe the pipeline it's the last stage of
+///
+/// Implemented by the adapters of this crate, and some common sources of items from the
+/// standard library. Adapters of the standard library don't give access to the iterators
+/// they wrap, so use [`crate::IteratorExt::describe_as`] on them (or any other source of items)
+/// to describe them as a source.
+///
+/// Note: stages running their inner iterator on another thread (like
+/// [`crate::Readahead`]) can't walk it anymore, so their upstream is not described.
+pub trait Describe {
+    /// Append the nodes of the pipeline to `nodes`, starting from the source, ending with `self`
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>);
+
+    /// Describe the pipeline
+    fn describe(&self) -> PipelineDescription {
+        let mut nodes = vec![];
+        self.describe_into(&mut nodes);
+        PipelineDescription { nodes }
+    }
+}
+
+/// An iterator described as a named source of items
+///
+/// See [`crate::IteratorExt::describe_as`].
+#[derive(Debug)]
+pub struct Described<I> {
+    iter: I,
+    name: String,
+}
+
+impl<I> Described<I> {
+    pub(crate) fn new(iter: I, name: String) -> Self {
+        Self { iter, name }
+    }
+}
+
+impl<I> Iterator for Described<I>
+where
+    I: Iterator,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<I> Describe for Described<I> {
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        nodes.push(PipelineNode {
+            name: Some(self.name.clone()),
+            ..PipelineNode::new("source")
+        });
+    }
+}
+
+impl<T> Describe for std::ops::Range<T> {
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        nodes.push(PipelineNode::new("range"));
+    }
+}
+
+impl<T> Describe for std::vec::IntoIter<T> {
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        nodes.push(PipelineNode::new("vec"));
+    }
+}
+
+impl<'a, T> Describe for std::slice::Iter<'a, T> {
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        nodes.push(PipelineNode::new("slice"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+};
+
+pub(crate) type DropStatsCallback = Arc<dyn Fn(&DropStats) + Send + Sync>;
+
+/// What to do with the work in flight when a stage is dropped
+///
+/// See [`crate::ParallelMapBuilder::drop_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    /// Skip the items not started yet, and discard the results
+    #[default]
+    Abort,
+    /// Process all the items already pulled, and pass the results
+    /// to [`crate::ParallelMap::on_orphaned`], if set
+    Finish,
+}
+
+/// What happened to the work of a stage, reported once it's done
+///
+/// See [`crate::ParallelMapBuilder::report_drop_stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DropStats {
+    /// Items processed by the workers, but never returned, because the stage was dropped
+    pub discarded: usize,
+    /// Items pulled, but never processed, because the stage was dropped
+    ///
+    /// See [`DropPolicy::Abort`].
+    pub skipped: usize,
+}
+
+impl fmt::Display for DropStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} processed items discarded, {} skipped",
+            self.discarded, self.skipped
+        )
+    }
+}
+
+/// Counts the items completed by the workers, and returned by the stage
+///
+/// Shared by the stage and all its workers; reports the [`DropStats`] when
+/// the last of them is gone.
+pub(crate) struct DropTracker {
+    completed: AtomicUsize,
+    returned: AtomicUsize,
+    skipped: AtomicUsize,
+    callback: DropStatsCallback,
+}
+
+impl DropTracker {
+    pub(crate) fn new(callback: DropStatsCallback) -> Self {
+        Self {
+            completed: AtomicUsize::new(0),
+            returned: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            callback,
+        }
+    }
+
+    pub(crate) fn inc_completed(&self) {
+        self.completed.fetch_add(1, SeqCst);
+    }
+
+    pub(crate) fn inc_returned(&self) {
+        self.returned.fetch_add(1, SeqCst);
+    }
+
+    pub(crate) fn inc_skipped(&self) {
+        self.skipped.fetch_add(1, SeqCst);
+    }
+}
+
+impl Drop for DropTracker {
+    fn drop(&mut self) {
+        (self.callback)(&DropStats {
+            discarded: self
+                .completed
+                .load(SeqCst)
+                .saturating_sub(self.returned.load(SeqCst)),
+            skipped: self.skipped.load(SeqCst),
+        });
+    }
+}
@@ -0,0 +1,160 @@
+use std::{error, fmt};
+
+/// Invalid configuration of a builder
+///
+/// See [`crate::ParallelMapBuilder::try_with`] and [`crate::ReadaheadBuilder::try_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// `buffer_size(0)` was set
+    ZeroBufferSize,
+    /// The buffer is smaller than the number of threads, so some would always be idle
+    BufferSmallerThanThreads { buffer_size: usize, threads: usize },
+    /// The `stall_warning` period is zero
+    ZeroStallPeriod,
+    /// `memory_cap` was set, but there's no `size_of` to measure the items with
+    MemoryCapWithoutSizeOf,
+    /// The `cpu_quota` is not a positive number of cores
+    NonPositiveCpuQuota,
+    /// The `panic_poll_interval` is zero
+    ZeroPanicPollInterval,
+    /// The `sample_occupancy` interval is zero
+    ZeroSamplingInterval,
+    /// The `shed_load` probability is not between 0 and 1
+    InvalidShedProbability,
+    /// The epoch of `with_arena` contains no items
+    ZeroEpoch,
+    /// `buffer_bytes(0, ..)` was set
+    ZeroBufferBytes,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::ZeroBufferSize => write!(f, "buffer size must be positive"),
+            BuildError::BufferSmallerThanThreads {
+                buffer_size,
+                threads,
+            } => write!(
+                f,
+                "buffer size {} is smaller than the number of threads {}",
+                buffer_size, threads
+            ),
+            BuildError::ZeroStallPeriod => write!(f, "stall warning period must be positive"),
+            BuildError::MemoryCapWithoutSizeOf => {
+                write!(f, "memory cap requires a `size_of` function")
+            }
+            BuildError::NonPositiveCpuQuota => write!(f, "cpu quota must be positive"),
+            BuildError::ZeroPanicPollInterval => {
+                write!(f, "panic poll interval must be positive")
+            }
+            BuildError::ZeroSamplingInterval => write!(f, "sampling interval must be positive"),
+            BuildError::InvalidShedProbability => {
+                write!(f, "shedding probability must be between 0 and 1")
+            }
+            BuildError::ZeroEpoch => write!(f, "epoch must contain at least one item"),
+            BuildError::ZeroBufferBytes => write!(f, "buffer byte limit must be positive"),
+        }
+    }
+}
+
+impl error::Error for BuildError {}
+
+/// Failure of a pipeline stage, like a panic of a worker thread
+///
+/// See [`crate::ParallelMap::results`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineError {
+    // name of the kind of stage, e.g. `parallel_map`
+    stage: &'static str,
+    // unique id of the stage, if known
+    stage_id: Option<u64>,
+    // how the failure was detected
+    reason: &'static str,
+    // details of the worker panic, if known
+    item_index: Option<usize>,
+    item: Option<String>,
+    panic_message: Option<String>,
+}
+
+impl PipelineError {
+    pub(crate) fn new(stage: &'static str, reason: &'static str) -> Self {
+        Self {
+            stage,
+            stage_id: None,
+            reason,
+            item_index: None,
+            item: None,
+            panic_message: None,
+        }
+    }
+
+    pub(crate) fn with_stage_id(self, stage_id: u64) -> Self {
+        Self {
+            stage_id: Some(stage_id),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_panic(
+        self,
+        item_index: usize,
+        item: Option<String>,
+        panic_message: Option<String>,
+    ) -> Self {
+        Self {
+            item_index: Some(item_index),
+            item,
+            panic_message,
+            ..self
+        }
+    }
+
+    /// Unique id of the stage, see [`crate::ParallelMap::id`]
+    pub fn stage_id(&self) -> Option<u64> {
+        self.stage_id
+    }
+
+    /// Id of the item being processed by the worker that panicked, if known
+    pub fn item_index(&self) -> Option<usize> {
+        self.item_index
+    }
+
+    /// The item being processed by the worker that panicked, if item rendering was enabled
+    pub fn item(&self) -> Option<&str> {
+        self.item.as_deref()
+    }
+
+    /// Message of the worker panic, if it was a string
+    pub fn panic_message(&self) -> Option<&str> {
+        self.panic_message.as_deref()
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.stage)?;
+        if let Some(stage_id) = self.stage_id {
+            write!(f, "#{}", stage_id)?;
+        }
+        let index = match self.item_index {
+            Some(index) => index,
+            None => return write!(f, " worker thread panicked: {}", self.reason),
+        };
+
+        write!(
+            f,
+            " worker thread panicked while processing item #{}",
+            index
+        )?;
+        if let Some(item) = self.item.as_ref() {
+            write!(f, " ({})", item)?;
+        }
+        if let Some(message) = self.panic_message.as_ref() {
+            write!(f, ": {}", message)?;
+        }
+        write!(f, " ({})", self.reason)
+    }
+}
+
+impl error::Error for PipelineError {}
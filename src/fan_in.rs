@@ -0,0 +1,146 @@
+use crate::{Readahead, ReadaheadBuilder, Scope};
+
+use std::{cmp::Reverse, collections::BinaryHeap, fmt};
+
+/// Merges multiple pipelines producing `(seq, item)` into one stream ordered by `seq`
+///
+/// See [`fan_in`].
+pub struct FanIn<P, T>
+where
+    P: Iterator,
+{
+    // every pipeline, pulled in its own thread
+    pipelines: Vec<Readahead<P>>,
+    // the next item of every pipeline, if started
+    heads: Option<BinaryHeap<Reverse<Head<T>>>>,
+}
+
+/// The next item of a pipeline
+struct Head<T> {
+    seq: usize,
+    // index of the pipeline
+    pipeline: usize,
+    item: T,
+}
+
+impl<T> PartialEq for Head<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.seq, self.pipeline) == (other.seq, other.pipeline)
+    }
+}
+
+impl<T> Eq for Head<T> {}
+
+impl<T> PartialOrd for Head<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Head<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.seq, self.pipeline).cmp(&(other.seq, other.pipeline))
+    }
+}
+
+/// Merge `pipelines` producing `(seq, item)` into one stream of items, ordered by `seq`
+///
+/// Every pipeline must produce items in increasing `seq` order, e.g. the shards
+/// of an input processed with separate [`crate::IteratorExt::parallel_map`]s, with
+/// the positions of the items in the input. Each pipeline is pulled in its own thread,
+/// like with [`crate::IteratorExt::readahead`], so they all keep working while the
+/// others are waited for.
+///
+/// Pass the single writer to [`Iterator::for_each`]:
+///
+/// ```
+/// use pariter::IteratorExt as _;
+///
+/// let mut output = vec![];
+/// pariter::fan_in(vec![
+///     vec![0, 2, 4].into_iter().parallel_map(|i| (i, i * 10)),
+///     vec![1, 3].into_iter().parallel_map(|i| (i, i * 10)),
+/// ])
+/// .for_each(|item| output.push(item));
+///
+/// assert_eq!(output, vec![0, 10, 20, 30, 40]);
+/// ```
+pub fn fan_in<I, P, T>(pipelines: I) -> FanIn<P, T>
+where
+    I: IntoIterator<Item = P>,
+    P: Iterator<Item = (usize, T)> + Send + 'static,
+    T: Send + 'static,
+{
+    FanIn {
+        pipelines: pipelines
+            .into_iter()
+            .map(|p| ReadaheadBuilder::new(p).with())
+            .collect(),
+        heads: None,
+    }
+}
+
+/// Scoped version of [`fan_in`]
+pub fn fan_in_scoped<'env, 'scope, I, P, T>(scope: &'scope Scope<'env>, pipelines: I) -> FanIn<P, T>
+where
+    I: IntoIterator<Item = P>,
+    P: Iterator<Item = (usize, T)> + Send + 'env,
+    T: Send + 'env,
+{
+    FanIn {
+        pipelines: pipelines
+            .into_iter()
+            .map(|p| ReadaheadBuilder::new(p).with_scoped(scope))
+            .collect(),
+        heads: None,
+    }
+}
+
+impl<P, T> fmt::Debug for FanIn<P, T>
+where
+    P: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FanIn")
+            .field("pipelines", &self.pipelines)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<P, T> Iterator for FanIn<P, T>
+where
+    P: Iterator<Item = (usize, T)> + Send,
+    T: Send,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pipelines = &mut self.pipelines;
+        let heads = self.heads.get_or_insert_with(|| {
+            pipelines
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(pipeline, p)| {
+                    let (seq, item) = p.next()?;
+                    Some(Reverse(Head {
+                        seq,
+                        pipeline,
+                        item,
+                    }))
+                })
+                .collect()
+        });
+
+        let Reverse(head) = heads.pop()?;
+        if let Some((seq, item)) = pipelines[head.pipeline].next() {
+            debug_assert!(head.seq <= seq, "pipeline items out of order");
+            heads.push(Reverse(Head {
+                seq,
+                pipeline: head.pipeline,
+                item,
+            }));
+        }
+
+        Some(head.item)
+    }
+}
@@ -0,0 +1,84 @@
+use std::{cmp, fmt};
+
+/// Iterator over groups of consecutive items with the same key
+///
+/// See [`crate::IteratorExt::parallel_group_by`].
+pub struct GroupConsecutive<I, KF, K>
+where
+    I: Iterator,
+{
+    // the iterator we wrapped
+    iter: I,
+    // computes the key of each item
+    key: KF,
+    // first item of the next group, already pulled from `iter`
+    next: Option<(K, I::Item)>,
+}
+
+impl<I, KF, K> GroupConsecutive<I, KF, K>
+where
+    I: Iterator,
+    KF: FnMut(&I::Item) -> K,
+    K: PartialEq,
+{
+    pub(crate) fn new(iter: I, key: KF) -> Self {
+        Self {
+            iter,
+            key,
+            next: None,
+        }
+    }
+
+    fn pull(&mut self) -> Option<(K, I::Item)> {
+        let item = self.iter.next()?;
+        Some(((self.key)(&item), item))
+    }
+}
+
+impl<I, KF, K> fmt::Debug for GroupConsecutive<I, KF, K>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupConsecutive")
+            .field("pending", &self.next.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, KF, K> Iterator for GroupConsecutive<I, KF, K>
+where
+    I: Iterator,
+    KF: FnMut(&I::Item) -> K,
+    K: PartialEq,
+{
+    type Item = (K, Vec<I::Item>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, first) = match self.next.take() {
+            Some(next) => next,
+            None => self.pull()?,
+        };
+
+        let mut group = vec![first];
+        while let Some((next_key, item)) = self.pull() {
+            if next_key != key {
+                self.next = Some((next_key, item));
+                break;
+            }
+            group.push(item);
+        }
+
+        Some((key, group))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let pending = usize::from(self.next.is_some());
+        let (lower, upper) = self.iter.size_hint();
+        // all the items could end up in a single group
+        (
+            cmp::min(lower.saturating_add(pending), 1),
+            upper.and_then(|upper| upper.checked_add(pending)),
+        )
+    }
+}
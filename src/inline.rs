@@ -0,0 +1,107 @@
+/// Maps items on the consuming thread, see [`crate::ParallelMapBuilder::with_enumerate_inline`]
+pub(crate) trait InlineMap<T, O> {
+    fn map(&mut self, i: usize, item: T) -> O;
+
+    /// The next item returned after all the mapped ones, see [`crate::ParallelMapBuilder::with_state`]
+    fn next_tail(&mut self) -> Option<O> {
+        None
+    }
+}
+
+impl<T, O, F> InlineMap<T, O> for F
+where
+    F: FnMut(usize, T) -> O,
+{
+    fn map(&mut self, i: usize, item: T) -> O {
+        (self)(i, item)
+    }
+}
+
+/// The single "worker" of [`crate::ParallelMapBuilder::with_state`], on the consuming thread
+pub(crate) struct InlineState<S, INIT, F, FIN, R>
+where
+    R: IntoIterator,
+{
+    init: Option<INIT>,
+    state: Option<S>,
+    f: F,
+    finish: Option<FIN>,
+    // items returned by `finish`
+    tail: Option<R::IntoIter>,
+}
+
+impl<S, INIT, F, FIN, R> InlineState<S, INIT, F, FIN, R>
+where
+    INIT: FnOnce(usize) -> S,
+    R: IntoIterator,
+{
+    pub(crate) fn new(init: INIT, f: F, finish: FIN) -> Self {
+        Self {
+            init: Some(init),
+            state: None,
+            f,
+            finish: Some(finish),
+            tail: None,
+        }
+    }
+
+    /// The state, created on first use, like the workers do when they start
+    fn take_state(&mut self) -> S {
+        match self.state.take() {
+            Some(state) => state,
+            None => (self.init.take().expect("state created once"))(0),
+        }
+    }
+}
+
+impl<T, O, S, INIT, F, FIN, R> InlineMap<T, O> for InlineState<S, INIT, F, FIN, R>
+where
+    INIT: FnOnce(usize) -> S,
+    F: FnMut(&mut S, T) -> O,
+    FIN: FnOnce(S) -> R,
+    R: IntoIterator<Item = O>,
+{
+    fn map(&mut self, _i: usize, item: T) -> O {
+        let state = self.take_state();
+        (self.f)(self.state.insert(state), item)
+    }
+
+    fn next_tail(&mut self) -> Option<O> {
+        if let Some(finish) = self.finish.take() {
+            let state = self.take_state();
+            self.tail = Some(finish(state).into_iter());
+        }
+        self.tail.as_mut()?.next()
+    }
+}
+
+/// Makes an [`InlineMap`] with state that's not `Send`, like that of the workers, `Send`
+///
+/// Only exists on targets without threads, where nothing is ever sent to another thread.
+pub(crate) struct NoThreads<M>(M);
+
+impl<M> NoThreads<M> {
+    pub(crate) fn new(map: M) -> Self {
+        assert!(
+            !crate::threads_supported(),
+            "only for targets without threads"
+        );
+        Self(map)
+    }
+}
+
+// SAFETY: only created on targets without threads, see `NoThreads::new`
+unsafe impl<M> Send for NoThreads<M> {}
+
+impl<T, O, M> InlineMap<T, O> for NoThreads<M>
+where
+    M: InlineMap<T, O>,
+{
+    fn map(&mut self, i: usize, item: T) -> O {
+        self.0.map(i, item)
+    }
+
+    fn next_tail(&mut self) -> Option<O> {
+        self.0.next_tail()
+    }
+}
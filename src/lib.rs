@@ -4,15 +4,151 @@ use std::sync::{
     Arc,
 };
 
+mod error;
+pub use self::error::{BuildError, PipelineError};
+
 mod parallel_map;
-pub use self::parallel_map::{ParallelMap, ParallelMapBuilder};
+pub use self::parallel_map::{
+    ParallelMap, ParallelMapBuilder, ParallelMapConfig, ParallelMapResults, WithInputIndices,
+};
+
+#[cfg(feature = "priority")]
+mod priority;
+#[cfg(feature = "priority")]
+pub use self::priority::ThreadPriority;
+
+#[cfg(feature = "rayon")]
+mod rayon_bridge;
+#[cfg(feature = "rayon")]
+pub use self::rayon_bridge::RayonOrdered;
 
 mod readahead;
-pub use self::readahead::{Readahead, ReadaheadBuilder};
+mod readahead_split;
+pub use self::readahead::{
+    from_fn_readahead, from_fn_readahead_scoped, Readahead, ReadaheadBuilder, ReadaheadConfig,
+    StopOnError,
+};
+pub use self::readahead_split::ReadaheadSplit;
+
+#[cfg(feature = "async")]
+mod parallel_map_async;
+#[cfg(feature = "async")]
+pub use self::parallel_map_async::{
+    ParallelMapAsync, ParallelMapAsyncBuilder, ParallelMapAsyncResults,
+};
+
+mod parallel_map_fused;
+pub use self::parallel_map_fused::ParallelMapFused;
+
+mod parallel_fold;
+
+mod load_shedding;
+
+mod parallel_batch_map;
+pub use self::parallel_batch_map::ParallelBatchMap;
 
 mod parallel_filter;
 pub use self::parallel_filter::{ParallelFilter, ParallelFilterBuilder};
 
+mod group_by;
+pub use self::group_by::GroupConsecutive;
+
+mod parallel_dedup;
+pub use self::parallel_dedup::ParallelDedupBy;
+
+mod parallel_unique;
+pub use self::parallel_unique::{ParallelUnique, ParallelUniqueBuilder};
+
+mod chunks;
+
+mod parallel_sort;
+pub use self::parallel_sort::{ParallelSort, ParallelSortBuilder, ParallelSortResults};
+
+mod barrier;
+pub use self::barrier::Barrier;
+
+mod circuit_breaker;
+pub use self::circuit_breaker::{BreakerError, CircuitBreaker};
+
+mod fan_in;
+pub use self::fan_in::{fan_in, fan_in_scoped, FanIn};
+
+mod merge_join;
+pub use self::merge_join::MergeJoin;
+
+mod parallel_scan_keyed;
+pub use self::parallel_scan_keyed::{
+    ParallelScanKeyed, ParallelScanKeyedBuilder, ParallelScanKeyedResults,
+};
+
+mod arena;
+pub use self::arena::{Arena, ArenaItem};
+
+mod straggler;
+pub use self::straggler::Straggler;
+
+mod inline;
+
+mod spawn;
+pub use self::spawn::Spawner;
+
+mod workload;
+pub use self::workload::Workload;
+
+mod drop_stats;
+pub use self::drop_stats::{DropPolicy, DropStats};
+
+mod pool;
+pub use self::pool::{Pool, PoolStats};
+
+mod reorder;
+
+mod shared;
+pub use self::shared::SharedIter;
+
+mod recycler;
+pub use self::recycler::Recycler;
+
+mod route;
+pub use self::route::Route;
+
+mod sample;
+
+mod throttle;
+pub use self::throttle::Throttle;
+
+mod traced;
+pub use self::traced::{Stamp, Traced, Tracing};
+
+mod top_k;
+use self::top_k::TopK;
+
+mod windows;
+pub use self::windows::Windows;
+
+mod write_at;
+
+mod write_rotating;
+pub use self::write_rotating::WriteRotatingBuilder;
+
+mod describe;
+pub use self::describe::{Describe, Described, PipelineDescription, PipelineNode};
+
+mod watchdog;
+pub use self::watchdog::StallReport;
+
+pub mod checkpoint;
+
+pub mod pipeline;
+
+pub mod tune;
+pub use self::tune::diagnose;
+
+pub mod checksum;
+
+#[cfg(feature = "compression")]
+pub mod compress;
+
 pub mod profile;
 pub use self::profile::{
     ProfileEgress, ProfileIngress, Profiler, TotalTimeProfiler, TotalTimeStats,
@@ -66,6 +202,10 @@ pub trait IteratorExt {
     /// borrowed values.
     ///
     /// See [`IteratorExt::parallel_map`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
     fn parallel_map_scoped<'env, 'scope, F, O>(
         self,
         scope: &'scope Scope<'env>,
@@ -83,6 +223,10 @@ pub trait IteratorExt {
     }
 
     /// See [`IteratorExt::parallel_map_scoped`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
     fn parallel_map_scoped_custom<'env, 'scope, F, O, OF>(
         self,
         scope: &'scope Scope<'env>,
@@ -101,193 +245,3034 @@ pub trait IteratorExt {
         of(ParallelMapBuilder::new(self)).with_scoped(scope, f)
     }
 
-    /// Run `filter` function in parallel on multiple threads
+    /// Like [`IteratorExt::parallel_map`], with the options of `config`
     ///
-    /// A wrapper around [`IteratorExt::parallel_map`] really, so it has similiar properties.
-    fn parallel_filter<F>(self, f: F) -> ParallelFilter<Self>
+    /// Useful to define a tuned configuration once, and use it for many inputs.
+    fn parallel_map_cfg<F, O>(self, config: &ParallelMapConfig, f: F) -> ParallelMap<Self, O>
     where
         Self: Sized,
         Self: Iterator,
         F: 'static + Send + Clone,
+        F: FnMut(Self::Item) -> O,
         Self::Item: Send + 'static,
-        F: FnMut(&Self::Item) -> bool,
+        O: Send + 'static,
     {
-        ParallelFilterBuilder::new(self).with(f)
+        ParallelMapBuilder::new(self).config(config).with(f)
     }
 
-    /// See [`IteratorExt::parallel_filter`]
-    fn parallel_filter_custom<F, OF>(self, of: OF, f: F) -> ParallelFilter<Self>
+    /// Like [`IteratorExt::parallel_map_scoped`], with the options of `config`
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_scoped_cfg<'env, 'scope, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        config: &ParallelMapConfig,
+        f: F,
+    ) -> ParallelMap<Self, O>
     where
         Self: Sized,
         Self: Iterator,
-        F: 'static + Send + Clone,
-        Self::Item: Send + 'static,
-        F: FnMut(&Self::Item) -> bool,
-        OF: FnOnce(ParallelFilterBuilder<Self>) -> ParallelFilterBuilder<Self>,
+        F: 'env + Send + Clone,
+        Self::Item: Send + 'env,
+        F: FnMut(Self::Item) -> O,
+        O: Send + 'env,
     {
-        of(ParallelFilterBuilder::new(self)).with(f)
+        ParallelMapBuilder::new(self)
+            .config(config)
+            .with_scoped(scope, f)
     }
 
-    /// See [`IteratorExt::parallel_filter`]
-    fn parallel_filter_scoped<'env, 'scope, F>(
+    /// Like [`IteratorExt::parallel_map_scoped`] for iterators over references,
+    /// cloning each item on the worker thread before passing it to `f`
+    ///
+    /// Saves writing `|x| f(x.clone())`.
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_scoped_cloned<'env, 'scope, 'a, T, F, O>(
         self,
         scope: &'scope Scope<'env>,
         f: F,
-    ) -> ParallelFilter<Self>
+    ) -> ParallelMap<Self, O>
     where
         Self: Sized,
-        Self: Iterator,
+        Self: Iterator<Item = &'a T>,
+        T: 'a + Clone + Sync,
+        'a: 'env,
         F: 'env + Send + Clone,
-        Self::Item: Send + 'env,
-        F: FnMut(&Self::Item) -> bool,
+        F: FnMut(T) -> O,
+        O: Send + 'env,
     {
-        ParallelFilterBuilder::new(self).with_scoped(scope, f)
+        self.parallel_map_scoped_cloned_custom(scope, |o| o, f)
     }
 
-    /// See [`IteratorExt::parallel_filter`]
-    fn parallel_filter_scoped_custom<'env, 'scope, F, OF>(
+    /// See [`IteratorExt::parallel_map_scoped_cloned`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_scoped_cloned_custom<'env, 'scope, 'a, T, F, O, OF>(
         self,
         scope: &'scope Scope<'env>,
         of: OF,
+        mut f: F,
+    ) -> ParallelMap<Self, O>
+    where
+        Self: Sized,
+        Self: Iterator<Item = &'a T>,
+        T: 'a + Clone + Sync,
+        'a: 'env,
+        F: 'env + Send + Clone,
+        F: FnMut(T) -> O,
+        O: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with_scoped(scope, move |item: &T| f(item.clone()))
+    }
+
+    /// Like [`IteratorExt::parallel_map_scoped_cloned`], but copying the items
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_scoped_copied<'env, 'scope, 'a, T, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
         f: F,
-    ) -> ParallelFilter<Self>
+    ) -> ParallelMap<Self, O>
     where
         Self: Sized,
-        Self: Iterator,
+        Self: Iterator<Item = &'a T>,
+        T: 'a + Copy + Sync,
+        'a: 'env,
         F: 'env + Send + Clone,
-        Self::Item: Send + 'env,
-        F: FnMut(&Self::Item) -> bool,
-        OF: FnOnce(ParallelFilterBuilder<Self>) -> ParallelFilterBuilder<Self>,
+        F: FnMut(T) -> O,
+        O: Send + 'env,
     {
-        of(ParallelFilterBuilder::new(self)).with_scoped(scope, f)
+        self.parallel_map_scoped_copied_custom(scope, |o| o, f)
     }
-    /// Run the current iterator in another thread and return elements
-    /// through a buffered channel.
+
+    /// See [`IteratorExt::parallel_map_scoped_copied`]
     ///
-    /// `buffer_size` defines the size of the output channel connecting
-    /// current and the inner thread.
-    //
-    /// It's a common mistake to use large channel sizes needlessly
-    /// in hopes of achieving higher performance. The only benefit
-    /// large buffer size value provides is smooting out the variance
-    /// of the inner iterator returning items. The cost - wasting memory.
-    /// In normal circumstances `0` is recommended (the default).
-    fn readahead(self) -> Readahead<Self>
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_scoped_copied_custom<'env, 'scope, 'a, T, F, O, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        mut f: F,
+    ) -> ParallelMap<Self, O>
+    where
+        Self: Sized,
+        Self: Iterator<Item = &'a T>,
+        T: 'a + Copy + Sync,
+        'a: 'env,
+        F: 'env + Send + Clone,
+        F: FnMut(T) -> O,
+        O: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with_scoped(scope, move |item: &T| f(*item))
+    }
+
+    /// Like [`IteratorExt::parallel_map`], but `f` gets the index of each item too
+    ///
+    /// The index is the position of the item in this iterator, like
+    /// with [`Iterator::enumerate`], and the same one `parallel_map` uses
+    /// internally to keep the results in order.
+    fn parallel_map_enumerate<F, O>(self, f: F) -> ParallelMap<Self, O>
     where
-        Self: Iterator + Send + 'static,
         Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
         Self::Item: Send + 'static,
+        F: FnMut(usize, Self::Item) -> O,
+        O: Send + 'static,
     {
-        ReadaheadBuilder::new(self).with()
+        ParallelMapBuilder::new(self).with_enumerate(f)
     }
 
-    fn readahead_custom<OF>(self, of: OF) -> Readahead<Self>
+    /// See [`IteratorExt::parallel_map_enumerate`]
+    fn parallel_map_enumerate_custom<F, O, OF>(self, of: OF, f: F) -> ParallelMap<Self, O>
     where
+        Self: Sized,
         Self: Iterator,
-        Self: Sized + Send + 'static,
+        F: 'static + Send + Clone,
+        F: FnMut(usize, Self::Item) -> O,
         Self::Item: Send + 'static,
-        OF: FnOnce(ReadaheadBuilder<Self>) -> ReadaheadBuilder<Self>,
+        O: Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
     {
-        of(ReadaheadBuilder::new(self)).with()
+        of(ParallelMapBuilder::new(self)).with_enumerate(f)
     }
 
-    /// Scoped version of [`IteratorExt::readahead`]
+    /// See [`IteratorExt::parallel_map_enumerate`]
     ///
-    /// Use when you want to process in parallel items that contain
-    /// borrowed references.
+    /// # Panics
     ///
-    /// See [`scope`].
-    fn readahead_scoped<'env, 'scope>(self, scope: &'scope Scope<'env>) -> Readahead<Self>
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_enumerate_scoped<'env, 'scope, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> ParallelMap<Self, O>
     where
-        Self: Sized + Send,
-        Self: Iterator + 'scope + 'env,
-        Self::Item: Send + 'env + 'scope + Send,
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        Self::Item: Send + 'env,
+        F: FnMut(usize, Self::Item) -> O,
+        O: Send + 'env,
     {
-        ReadaheadBuilder::new(self).with_scoped(scope)
+        ParallelMapBuilder::new(self).with_enumerate_scoped(scope, f)
     }
 
-    fn readahead_scoped_custom<'env, 'scope, OF>(
+    /// See [`IteratorExt::parallel_map_enumerate`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_enumerate_scoped_custom<'env, 'scope, F, O, OF>(
         self,
         scope: &'scope Scope<'env>,
         of: OF,
-    ) -> Readahead<Self>
+        f: F,
+    ) -> ParallelMap<Self, O>
     where
-        Self: Sized + Send,
-        Self: Iterator + 'scope + 'env,
-        Self::Item: Send + 'env + 'scope + Send,
-        OF: FnOnce(ReadaheadBuilder<Self>) -> ReadaheadBuilder<Self>,
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        Self::Item: Send + 'env,
+        F: FnMut(usize, Self::Item) -> O,
+        O: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
     {
-        of(ReadaheadBuilder::new(self)).with_scoped(scope)
+        of(ParallelMapBuilder::new(self)).with_enumerate_scoped(scope, f)
     }
 
-    /// Profile the time it takes downstream iterator step to consume the returned items.
+    /// Like [`IteratorExt::parallel_map`], but each worker thread has its own state
     ///
-    /// See [`ProfileEgress`] and [`profile::Profiler`].
-    fn profile_egress<P: profile::Profiler>(self, profiler: P) -> ProfileEgress<Self, P>
+    /// Each worker creates its state with `init`, passes it to `f` with every item,
+    /// and when done, passes it to `finish`. Items returned by `finish` are returned
+    /// after all the ordered items, in no particular order.
+    ///
+    /// See [`ParallelMapBuilder::with_state`].
+    fn parallel_map_with_state<S, INIT, F, FIN, R, O>(
+        self,
+        init: INIT,
+        f: F,
+        finish: FIN,
+    ) -> ParallelMap<Self, O>
     where
-        Self: Iterator,
         Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        INIT: 'static + Send + Clone,
+        INIT: FnOnce(usize) -> S,
+        F: 'static + Send + Clone,
+        F: FnMut(&mut S, Self::Item) -> O,
+        FIN: 'static + Send + Clone,
+        FIN: FnOnce(S) -> R,
+        S: 'static,
+        R: IntoIterator<Item = O> + 'static,
+        O: Send + 'static,
     {
-        ProfileEgress::new(self, profiler)
+        ParallelMapBuilder::new(self).with_state(init, f, finish)
     }
 
-    /// Profile the time it takes upstream iterator step to produce the returned items.
-    ///
-    /// See [`ProfileIngress`] and [`profile::Profiler`].
-    fn profile_ingress<P: profile::Profiler>(self, profiler: P) -> ProfileIngress<Self, P>
+    /// See [`IteratorExt::parallel_map_with_state`]
+    fn parallel_map_with_state_custom<S, INIT, F, FIN, R, O, OF>(
+        self,
+        of: OF,
+        init: INIT,
+        f: F,
+        finish: FIN,
+    ) -> ParallelMap<Self, O>
     where
-        Self: Iterator,
         Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        INIT: 'static + Send + Clone,
+        INIT: FnOnce(usize) -> S,
+        F: 'static + Send + Clone,
+        F: FnMut(&mut S, Self::Item) -> O,
+        FIN: 'static + Send + Clone,
+        FIN: FnOnce(S) -> R,
+        S: 'static,
+        R: IntoIterator<Item = O> + 'static,
+        O: Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
     {
-        ProfileIngress::new(self, profiler)
+        of(ParallelMapBuilder::new(self)).with_state(init, f, finish)
     }
 
-    /// Profiled version of [`IteratorExt::readahead`]
+    /// See [`IteratorExt::parallel_map_with_state`]
     ///
-    /// Literally `.profile_egress(tx_profiler).readahead(n).profile_ingress(rx_profiler)`
+    /// # Panics
     ///
-    /// See [`Profiler`] for more info.
-    fn readahead_profiled<TxP: profile::Profiler, RxP: profile::Profiler>(
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_with_state_scoped<'env, 'scope, S, INIT, F, FIN, R, O>(
         self,
-        tx_profiler: TxP,
-        rx_profiler: RxP,
-    ) -> ProfileIngress<Readahead<ProfileEgress<Self, TxP>>, RxP>
+        scope: &'scope Scope<'env>,
+        init: INIT,
+        f: F,
+        finish: FIN,
+    ) -> ParallelMap<Self, O>
     where
-        Self: Iterator,
         Self: Sized,
-        Self: Send + 'static,
-        Self::Item: Send + 'static,
-        TxP: Send + 'static,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        INIT: 'env + Send + Clone,
+        INIT: FnOnce(usize) -> S,
+        F: 'env + Send + Clone,
+        F: FnMut(&mut S, Self::Item) -> O,
+        FIN: 'env + Send + Clone,
+        FIN: FnOnce(S) -> R,
+        R: IntoIterator<Item = O>,
+        O: Send + 'env,
     {
-        self.profile_egress(tx_profiler)
-            .readahead()
-            .profile_ingress(rx_profiler)
+        ParallelMapBuilder::new(self).with_state_scoped(scope, init, f, finish)
     }
 
-    /// Profiled version of [`IteratorExt::readahead_scoped`]
+    /// See [`IteratorExt::parallel_map_with_state`]
     ///
-    /// Literally `.profile_egress(tx_profiler).readahead_scoped(scope, n).profile_ingress(rx_profiler)`
+    /// # Panics
     ///
-    /// See [`Profiler`] for more info.
-    fn readahead_scoped_profiled<'env, 'scope, TxP: profile::Profiler, RxP: profile::Profiler>(
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    #[allow(clippy::too_many_arguments)]
+    fn parallel_map_with_state_scoped_custom<'env, 'scope, S, INIT, F, FIN, R, O, OF>(
         self,
         scope: &'scope Scope<'env>,
-        tx_profiler: TxP,
-        rx_profiler: RxP,
-    ) -> ProfileIngress<Readahead<ProfileEgress<Self, TxP>>, RxP>
+        of: OF,
+        init: INIT,
+        f: F,
+        finish: FIN,
+    ) -> ParallelMap<Self, O>
     where
-        Self: Sized + Send,
-        Self: Iterator + 'scope + 'env,
-        Self::Item: Send + 'env + 'scope + Send,
-        TxP: Send + 'static,
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        INIT: 'env + Send + Clone,
+        INIT: FnOnce(usize) -> S,
+        F: 'env + Send + Clone,
+        F: FnMut(&mut S, Self::Item) -> O,
+        FIN: 'env + Send + Clone,
+        FIN: FnOnce(S) -> R,
+        R: IntoIterator<Item = O>,
+        O: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
     {
-        self.profile_egress(tx_profiler)
-            .readahead_scoped(scope)
-            .profile_ingress(rx_profiler)
+        of(ParallelMapBuilder::new(self)).with_state_scoped(scope, init, f, finish)
     }
-}
 
-impl<I> IteratorExt for I where I: Iterator {}
+    /// Group consecutive items with the same `key` and run `f` on each group,
+    /// in parallel on multiple threads
+    ///
+    /// Like `itertools`' `group_by`, followed by [`IteratorExt::parallel_map`]:
+    /// each group is a single unit of work. Results are returned in order,
+    /// together with the key of their group.
+    ///
+    /// `key` is called on the calling thread, while pulling items.
+    fn parallel_group_by<KF, K, F, R>(
+        self,
+        key: KF,
+        f: F,
+    ) -> ParallelMap<GroupConsecutive<Self, KF, K>, (K, R)>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        KF: FnMut(&Self::Item) -> K,
+        K: PartialEq + Send + 'static,
+        F: FnMut(&K, Vec<Self::Item>) -> R + 'static + Send + Clone,
+        R: Send + 'static,
+    {
+        self.parallel_group_by_custom(|o| o, key, f)
+    }
+
+    /// See [`IteratorExt::parallel_group_by`]
+    fn parallel_group_by_custom<KF, K, F, R, OF>(
+        self,
+        of: OF,
+        key: KF,
+        mut f: F,
+    ) -> ParallelMap<GroupConsecutive<Self, KF, K>, (K, R)>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        KF: FnMut(&Self::Item) -> K,
+        K: PartialEq + Send + 'static,
+        F: FnMut(&K, Vec<Self::Item>) -> R + 'static + Send + Clone,
+        R: Send + 'static,
+        OF: FnOnce(
+            ParallelMapBuilder<GroupConsecutive<Self, KF, K>>,
+        ) -> ParallelMapBuilder<GroupConsecutive<Self, KF, K>>,
+    {
+        of(ParallelMapBuilder::new(GroupConsecutive::new(self, key))).with(move |(key, group)| {
+            let res = (f)(&key, group);
+            (key, res)
+        })
+    }
+
+    /// See [`IteratorExt::parallel_group_by`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_group_by_scoped<'env, 'scope, KF, K, F, R>(
+        self,
+        scope: &'scope Scope<'env>,
+        key: KF,
+        f: F,
+    ) -> ParallelMap<GroupConsecutive<Self, KF, K>, (K, R)>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        KF: FnMut(&Self::Item) -> K,
+        K: PartialEq + Send + 'env,
+        F: FnMut(&K, Vec<Self::Item>) -> R + 'env + Send + Clone,
+        R: Send + 'env,
+    {
+        self.parallel_group_by_scoped_custom(scope, |o| o, key, f)
+    }
+
+    /// See [`IteratorExt::parallel_group_by`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_group_by_scoped_custom<'env, 'scope, KF, K, F, R, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        key: KF,
+        mut f: F,
+    ) -> ParallelMap<GroupConsecutive<Self, KF, K>, (K, R)>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        KF: FnMut(&Self::Item) -> K,
+        K: PartialEq + Send + 'env,
+        F: FnMut(&K, Vec<Self::Item>) -> R + 'env + Send + Clone,
+        R: Send + 'env,
+        OF: FnOnce(
+            ParallelMapBuilder<GroupConsecutive<Self, KF, K>>,
+        ) -> ParallelMapBuilder<GroupConsecutive<Self, KF, K>>,
+    {
+        of(ParallelMapBuilder::new(GroupConsecutive::new(self, key))).with_scoped(
+            scope,
+            move |(key, group)| {
+                let res = (f)(&key, group);
+                (key, res)
+            },
+        )
+    }
+
+    /// Remove consecutive items with the same key, computing the keys
+    /// in parallel on multiple threads
+    ///
+    /// Useful when computing the key is expensive, e.g. hashing the content.
+    /// The decision which items to drop is made in order, on the calling thread,
+    /// so the first item of every run of duplicates is kept, like in [`Vec::dedup_by_key`].
+    fn parallel_dedup_by<F, K>(self, key: F) -> ParallelDedupBy<Self, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item) -> K + 'static + Send + Clone,
+        K: PartialEq + Send + 'static,
+    {
+        self.parallel_dedup_by_custom(|o| o, key)
+    }
+
+    /// See [`IteratorExt::parallel_dedup_by`]
+    fn parallel_dedup_by_custom<F, K, OF>(self, of: OF, mut key: F) -> ParallelDedupBy<Self, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item) -> K + 'static + Send + Clone,
+        K: PartialEq + Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        ParallelDedupBy::new(
+            of(ParallelMapBuilder::new(self)).with(move |item| ((key)(&item), item)),
+        )
+    }
+
+    /// See [`IteratorExt::parallel_dedup_by`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_dedup_by_scoped<'env, 'scope, F, K>(
+        self,
+        scope: &'scope Scope<'env>,
+        key: F,
+    ) -> ParallelDedupBy<Self, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item) -> K + 'env + Send + Clone,
+        K: PartialEq + Send + 'env,
+    {
+        self.parallel_dedup_by_scoped_custom(scope, |o| o, key)
+    }
+
+    /// See [`IteratorExt::parallel_dedup_by`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_dedup_by_scoped_custom<'env, 'scope, F, K, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        mut key: F,
+    ) -> ParallelDedupBy<Self, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item) -> K + 'env + Send + Clone,
+        K: PartialEq + Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        ParallelDedupBy::new(
+            of(ParallelMapBuilder::new(self)).with_scoped(scope, move |item| ((key)(&item), item)),
+        )
+    }
+
+    /// Return only the first item with every key, computing the keys
+    /// in parallel on multiple threads
+    ///
+    /// Results will be returned in order. See [`ParallelUniqueBuilder::relaxed`]
+    /// for letting the workers decide which of the duplicates is kept.
+    fn parallel_unique<F, K>(self, key: F) -> ParallelUnique<Self, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item) -> K + 'static + Send + Clone,
+        K: std::hash::Hash + Eq + Send + 'static,
+    {
+        ParallelUniqueBuilder::new(self).with(key)
+    }
+
+    /// See [`IteratorExt::parallel_unique`]
+    fn parallel_unique_custom<F, K, OF>(self, of: OF, key: F) -> ParallelUnique<Self, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item) -> K + 'static + Send + Clone,
+        K: std::hash::Hash + Eq + Send + 'static,
+        OF: FnOnce(ParallelUniqueBuilder<Self>) -> ParallelUniqueBuilder<Self>,
+    {
+        of(ParallelUniqueBuilder::new(self)).with(key)
+    }
+
+    /// See [`IteratorExt::parallel_unique`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_unique_scoped<'env, 'scope, F, K>(
+        self,
+        scope: &'scope Scope<'env>,
+        key: F,
+    ) -> ParallelUnique<Self, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item) -> K + 'env + Send + Clone,
+        K: std::hash::Hash + Eq + Send + 'env,
+    {
+        ParallelUniqueBuilder::new(self).with_scoped(scope, key)
+    }
+
+    /// See [`IteratorExt::parallel_unique`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_unique_scoped_custom<'env, 'scope, F, K, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        key: F,
+    ) -> ParallelUnique<Self, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item) -> K + 'env + Send + Clone,
+        K: std::hash::Hash + Eq + Send + 'env,
+        OF: FnOnce(ParallelUniqueBuilder<Self>) -> ParallelUniqueBuilder<Self>,
+    {
+        of(ParallelUniqueBuilder::new(self)).with_scoped(scope, key)
+    }
+
+    /// Run `f` on items with per-key state, in parallel on multiple threads
+    ///
+    /// All the items with the same `key` are processed sequentially, in order,
+    /// by the same worker thread, which passes them to `f` along with the state of
+    /// their key, created with `init` when the key is first seen. Items with different
+    /// keys are processed in parallel.
+    ///
+    /// Results will be returned in order.
+    ///
+    /// See [`ParallelScanKeyedBuilder::with`].
+    fn parallel_scan_keyed<KF, K, INIT, S, F, O>(
+        self,
+        key: KF,
+        init: INIT,
+        f: F,
+    ) -> ParallelScanKeyed<Self, KF, K, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        KF: FnMut(&Self::Item) -> K + 'static,
+        K: std::hash::Hash + Eq + Send + 'static,
+        INIT: FnMut(&K) -> S + 'static + Send + Clone,
+        S: 'static,
+        F: FnMut(&mut S, Self::Item) -> O + 'static + Send + Clone,
+        O: Send + 'static,
+    {
+        ParallelScanKeyedBuilder::new(self).with(key, init, f)
+    }
+
+    /// See [`IteratorExt::parallel_scan_keyed`]
+    fn parallel_scan_keyed_custom<KF, K, INIT, S, F, O, OF>(
+        self,
+        of: OF,
+        key: KF,
+        init: INIT,
+        f: F,
+    ) -> ParallelScanKeyed<Self, KF, K, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        KF: FnMut(&Self::Item) -> K + 'static,
+        K: std::hash::Hash + Eq + Send + 'static,
+        INIT: FnMut(&K) -> S + 'static + Send + Clone,
+        S: 'static,
+        F: FnMut(&mut S, Self::Item) -> O + 'static + Send + Clone,
+        O: Send + 'static,
+        OF: FnOnce(ParallelScanKeyedBuilder<Self>) -> ParallelScanKeyedBuilder<Self>,
+    {
+        of(ParallelScanKeyedBuilder::new(self)).with(key, init, f)
+    }
+
+    /// See [`IteratorExt::parallel_scan_keyed`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelScanKeyedBuilder::with_scoped`].
+    fn parallel_scan_keyed_scoped<'env, 'scope, KF, K, INIT, S, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        key: KF,
+        init: INIT,
+        f: F,
+    ) -> ParallelScanKeyed<Self, KF, K, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        KF: FnMut(&Self::Item) -> K + 'env,
+        K: std::hash::Hash + Eq + Send + 'env,
+        INIT: FnMut(&K) -> S + 'env + Send + Clone,
+        F: FnMut(&mut S, Self::Item) -> O + 'env + Send + Clone,
+        O: Send + 'env,
+    {
+        ParallelScanKeyedBuilder::new(self).with_scoped(scope, key, init, f)
+    }
+
+    /// See [`IteratorExt::parallel_scan_keyed`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelScanKeyedBuilder::with_scoped`].
+    #[allow(clippy::too_many_arguments)]
+    fn parallel_scan_keyed_scoped_custom<'env, 'scope, KF, K, INIT, S, F, O, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        key: KF,
+        init: INIT,
+        f: F,
+    ) -> ParallelScanKeyed<Self, KF, K, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        KF: FnMut(&Self::Item) -> K + 'env,
+        K: std::hash::Hash + Eq + Send + 'env,
+        INIT: FnMut(&K) -> S + 'env + Send + Clone,
+        F: FnMut(&mut S, Self::Item) -> O + 'env + Send + Clone,
+        O: Send + 'env,
+        OF: FnOnce(ParallelScanKeyedBuilder<Self>) -> ParallelScanKeyedBuilder<Self>,
+    {
+        of(ParallelScanKeyedBuilder::new(self)).with_scoped(scope, key, init, f)
+    }
+
+    /// Return `k` items with the largest keys, computing the keys
+    /// in parallel on multiple threads
+    ///
+    /// Each worker thread keeps its own `k` best items, which are merged
+    /// at the end, so the whole stream is never collected. Items are returned
+    /// best first. Which of the items with equal keys are returned is unspecified.
+    ///
+    /// Use [`std::cmp::Reverse`] as the key to select the smallest keys instead.
+    fn parallel_top_k<F, K>(self, k: usize, key: F) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item) -> K + 'static + Send + Clone,
+        K: Ord + Send + 'static,
+    {
+        self.parallel_top_k_custom(|o| o, k, key)
+    }
+
+    /// See [`IteratorExt::parallel_top_k`]
+    fn parallel_top_k_custom<F, K, OF>(self, of: OF, k: usize, mut key: F) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item) -> K + 'static + Send + Clone,
+        K: Ord + Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        let mut top = TopK::new(k);
+        for (key, item) in of(ParallelMapBuilder::new(self))
+            .with_state(
+                move |_worker_i| TopK::new(k),
+                move |top, item| {
+                    top.push((key)(&item), item);
+                    None
+                },
+                |top| top.into_entries().map(Some),
+            )
+            .flatten()
+        {
+            top.push(key, item);
+        }
+        top.into_sorted_items()
+    }
+
+    /// See [`IteratorExt::parallel_top_k`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_top_k_scoped<'env, 'scope, F, K>(
+        self,
+        scope: &'scope Scope<'env>,
+        k: usize,
+        key: F,
+    ) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item) -> K + 'env + Send + Clone,
+        K: Ord + Send + 'env,
+    {
+        self.parallel_top_k_scoped_custom(scope, |o| o, k, key)
+    }
+
+    /// See [`IteratorExt::parallel_top_k`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_top_k_scoped_custom<'env, 'scope, F, K, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        k: usize,
+        mut key: F,
+    ) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item) -> K + 'env + Send + Clone,
+        K: Ord + Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        let mut top = TopK::new(k);
+        for (key, item) in of(ParallelMapBuilder::new(self))
+            .with_state_scoped(
+                scope,
+                move |_worker_i| TopK::new(k),
+                move |top, item| {
+                    top.push((key)(&item), item);
+                    None
+                },
+                |top| top.into_entries().map(Some),
+            )
+            .flatten()
+        {
+            top.push(key, item);
+        }
+        top.into_sorted_items()
+    }
+
+    /// Take a weighted random sample of `n` items in one pass, computing
+    /// the weights in parallel on multiple threads
+    ///
+    /// Uses weighted reservoir sampling (Efraimidis-Spirakis A-Res): the probability
+    /// of an item being selected is proportional to its `weight`. Items with weights
+    /// that are not positive are never selected. `rng` must return numbers uniformly
+    /// distributed in `[0, 1)`, and is called on the calling thread.
+    ///
+    /// Only the reservoir is kept in memory. The selected items are returned in order.
+    fn parallel_sample<W, R>(self, n: usize, weight: W, rng: R) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        W: FnMut(&Self::Item) -> f64 + 'static + Send + Clone,
+        R: FnMut() -> f64,
+    {
+        self.parallel_sample_custom(|o| o, n, weight, rng)
+    }
+
+    /// See [`IteratorExt::parallel_sample`]
+    fn parallel_sample_custom<W, R, OF>(
+        self,
+        of: OF,
+        n: usize,
+        mut weight: W,
+        rng: R,
+    ) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        W: FnMut(&Self::Item) -> f64 + 'static + Send + Clone,
+        R: FnMut() -> f64,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        sample::reservoir(
+            n,
+            of(ParallelMapBuilder::new(self)).with(move |item| ((weight)(&item), item)),
+            rng,
+        )
+    }
+
+    /// See [`IteratorExt::parallel_sample`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_sample_scoped<'env, 'scope, W, R>(
+        self,
+        scope: &'scope Scope<'env>,
+        n: usize,
+        weight: W,
+        rng: R,
+    ) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        W: FnMut(&Self::Item) -> f64 + 'env + Send + Clone,
+        R: FnMut() -> f64,
+    {
+        self.parallel_sample_scoped_custom(scope, |o| o, n, weight, rng)
+    }
+
+    /// See [`IteratorExt::parallel_sample`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_sample_scoped_custom<'env, 'scope, W, R, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        n: usize,
+        mut weight: W,
+        rng: R,
+    ) -> Vec<Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        W: FnMut(&Self::Item) -> f64 + 'env + Send + Clone,
+        R: FnMut() -> f64,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        sample::reservoir(
+            n,
+            of(ParallelMapBuilder::new(self))
+                .with_scoped(scope, move |item| ((weight)(&item), item)),
+            rng,
+        )
+    }
+
+    /// Sort items by `key`, sorting runs of items in parallel on multiple threads
+    ///
+    /// The items are split into runs (see [`ParallelSortBuilder::run_size`]), each sorted
+    /// by a worker thread, and merged when returned. The sort is stable.
+    /// To sort streams bigger than memory, see [`ParallelSortBuilder::spill`].
+    ///
+    /// All the items are pulled from the inner iterator before the first one is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if spilling runs to disk, or reading them back fails, see [`ParallelSort::results`].
+    fn parallel_sort_by_key<KF, K>(self, key: KF) -> ParallelSort<Self, KF, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        KF: FnMut(&Self::Item) -> K + 'static + Send + Clone,
+        K: Ord,
+    {
+        ParallelSortBuilder::new(self).with(key)
+    }
+
+    /// See [`IteratorExt::parallel_sort_by_key`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if spilling runs to disk, or reading them back fails, see [`ParallelSort::results`].
+    fn parallel_sort_by_key_custom<KF, K, OF>(self, of: OF, key: KF) -> ParallelSort<Self, KF, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        KF: FnMut(&Self::Item) -> K + 'static + Send + Clone,
+        K: Ord,
+        OF: FnOnce(ParallelSortBuilder<Self>) -> ParallelSortBuilder<Self>,
+    {
+        of(ParallelSortBuilder::new(self)).with(key)
+    }
+
+    /// See [`IteratorExt::parallel_sort_by_key`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if spilling runs to disk, or reading them back fails, see [`ParallelSort::results`].
+    /// Also on targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_sort_by_key_scoped<'env, 'scope, KF, K>(
+        self,
+        scope: &'scope Scope<'env>,
+        key: KF,
+    ) -> ParallelSort<Self, KF, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        KF: FnMut(&Self::Item) -> K + 'env + Send + Clone,
+        K: Ord,
+    {
+        ParallelSortBuilder::new(self).with_scoped(scope, key)
+    }
+
+    /// See [`IteratorExt::parallel_sort_by_key`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if spilling runs to disk, or reading them back fails, see [`ParallelSort::results`].
+    /// Also on targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_sort_by_key_scoped_custom<'env, 'scope, KF, K, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        key: KF,
+    ) -> ParallelSort<Self, KF, K>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        KF: FnMut(&Self::Item) -> K + 'env + Send + Clone,
+        K: Ord,
+        OF: FnOnce(ParallelSortBuilder<Self>) -> ParallelSortBuilder<Self>,
+    {
+        of(ParallelSortBuilder::new(self)).with_scoped(scope, key)
+    }
+
+    /// Run `f` on windows of `size` consecutive items, in parallel on multiple threads
+    ///
+    /// Windows start every `step` items, so they overlap if `step` is smaller than `size`,
+    /// like [`slice::windows`] for `step` equal to 1. Only full windows are processed.
+    /// Items are cloned into each window they belong to.
+    ///
+    /// Results will be returned in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` or `step` is zero.
+    fn parallel_window_map<F, O>(
+        self,
+        size: usize,
+        step: usize,
+        f: F,
+    ) -> ParallelMap<Windows<Self>, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Clone + Send + 'static,
+        F: FnMut(&[Self::Item]) -> O + 'static + Send + Clone,
+        O: Send + 'static,
+    {
+        self.parallel_window_map_custom(|o| o, size, step, f)
+    }
+
+    /// See [`IteratorExt::parallel_window_map`]
+    fn parallel_window_map_custom<F, O, OF>(
+        self,
+        of: OF,
+        size: usize,
+        step: usize,
+        mut f: F,
+    ) -> ParallelMap<Windows<Self>, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Clone + Send + 'static,
+        F: FnMut(&[Self::Item]) -> O + 'static + Send + Clone,
+        O: Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Windows<Self>>) -> ParallelMapBuilder<Windows<Self>>,
+    {
+        of(ParallelMapBuilder::new(Windows::new(self, size, step)))
+            .with(move |window: Vec<_>| (f)(&window))
+    }
+
+    /// See [`IteratorExt::parallel_window_map`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_window_map_scoped<'env, 'scope, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        size: usize,
+        step: usize,
+        f: F,
+    ) -> ParallelMap<Windows<Self>, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Clone + Send + 'env,
+        F: FnMut(&[Self::Item]) -> O + 'env + Send + Clone,
+        O: Send + 'env,
+    {
+        self.parallel_window_map_scoped_custom(scope, |o| o, size, step, f)
+    }
+
+    /// See [`IteratorExt::parallel_window_map`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_window_map_scoped_custom<'env, 'scope, F, O, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        size: usize,
+        step: usize,
+        mut f: F,
+    ) -> ParallelMap<Windows<Self>, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Clone + Send + 'env,
+        F: FnMut(&[Self::Item]) -> O + 'env + Send + Clone,
+        O: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Windows<Self>>) -> ParallelMapBuilder<Windows<Self>>,
+    {
+        of(ParallelMapBuilder::new(Windows::new(self, size, step)))
+            .with_scoped(scope, move |window: Vec<_>| (f)(&window))
+    }
+
+    /// Join two iterators sorted by key, running `join` in parallel on multiple threads
+    ///
+    /// For every key present in both `self` and `other`, `join` is called with
+    /// the key and all the items with that key from each side (an inner join).
+    /// Both inputs must be sorted by their keys, and are pulled in [`IteratorExt::readahead`]
+    /// threads. Keys are computed on the calling thread.
+    ///
+    /// Results will be returned in the order of the keys.
+    fn merge_join<J, KA, KB, K, F, O>(
+        self,
+        other: J,
+        left_key: KA,
+        right_key: KB,
+        join: F,
+    ) -> ParallelMap<MergeJoin<Self, J::IntoIter, KA, KB, K>, O>
+    where
+        Self: Sized,
+        Self: Iterator + Send + 'static,
+        Self::Item: Send + 'static,
+        J: IntoIterator,
+        J::IntoIter: Send + 'static,
+        J::Item: Send + 'static,
+        KA: FnMut(&Self::Item) -> K,
+        KB: FnMut(&J::Item) -> K,
+        K: Ord + Send + 'static,
+        F: FnMut(&K, Vec<Self::Item>, Vec<J::Item>) -> O + 'static + Send + Clone,
+        O: Send + 'static,
+    {
+        self.merge_join_custom(|o| o, other, left_key, right_key, join)
+    }
+
+    /// See [`IteratorExt::merge_join`]
+    fn merge_join_custom<J, KA, KB, K, F, O, OF>(
+        self,
+        of: OF,
+        other: J,
+        left_key: KA,
+        right_key: KB,
+        mut join: F,
+    ) -> ParallelMap<MergeJoin<Self, J::IntoIter, KA, KB, K>, O>
+    where
+        Self: Sized,
+        Self: Iterator + Send + 'static,
+        Self::Item: Send + 'static,
+        J: IntoIterator,
+        J::IntoIter: Send + 'static,
+        J::Item: Send + 'static,
+        KA: FnMut(&Self::Item) -> K,
+        KB: FnMut(&J::Item) -> K,
+        K: Ord + Send + 'static,
+        F: FnMut(&K, Vec<Self::Item>, Vec<J::Item>) -> O + 'static + Send + Clone,
+        O: Send + 'static,
+        OF: FnOnce(
+            ParallelMapBuilder<MergeJoin<Self, J::IntoIter, KA, KB, K>>,
+        ) -> ParallelMapBuilder<MergeJoin<Self, J::IntoIter, KA, KB, K>>,
+    {
+        let groups = MergeJoin::new(
+            self.readahead(),
+            other.into_iter().readahead(),
+            left_key,
+            right_key,
+        );
+        of(ParallelMapBuilder::new(groups))
+            .with(move |(key, left, right)| (join)(&key, left, right))
+    }
+
+    /// See [`IteratorExt::merge_join`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn merge_join_scoped<'env, 'scope, J, KA, KB, K, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        other: J,
+        left_key: KA,
+        right_key: KB,
+        join: F,
+    ) -> ParallelMap<MergeJoin<Self, J::IntoIter, KA, KB, K>, O>
+    where
+        Self: Sized,
+        Self: Iterator + Send + 'env,
+        Self::Item: Send + 'env,
+        J: IntoIterator,
+        J::IntoIter: Send + 'env,
+        J::Item: Send + 'env,
+        KA: FnMut(&Self::Item) -> K,
+        KB: FnMut(&J::Item) -> K,
+        K: Ord + Send + 'env,
+        F: FnMut(&K, Vec<Self::Item>, Vec<J::Item>) -> O + 'env + Send + Clone,
+        O: Send + 'env,
+    {
+        self.merge_join_scoped_custom(scope, |o| o, other, left_key, right_key, join)
+    }
+
+    /// See [`IteratorExt::merge_join`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    #[allow(clippy::too_many_arguments)]
+    fn merge_join_scoped_custom<'env, 'scope, J, KA, KB, K, F, O, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        other: J,
+        left_key: KA,
+        right_key: KB,
+        mut join: F,
+    ) -> ParallelMap<MergeJoin<Self, J::IntoIter, KA, KB, K>, O>
+    where
+        Self: Sized,
+        Self: Iterator + Send + 'env,
+        Self::Item: Send + 'env,
+        J: IntoIterator,
+        J::IntoIter: Send + 'env,
+        J::Item: Send + 'env,
+        KA: FnMut(&Self::Item) -> K,
+        KB: FnMut(&J::Item) -> K,
+        K: Ord + Send + 'env,
+        F: FnMut(&K, Vec<Self::Item>, Vec<J::Item>) -> O + 'env + Send + Clone,
+        O: Send + 'env,
+        OF: FnOnce(
+            ParallelMapBuilder<MergeJoin<Self, J::IntoIter, KA, KB, K>>,
+        ) -> ParallelMapBuilder<MergeJoin<Self, J::IntoIter, KA, KB, K>>,
+    {
+        let groups = MergeJoin::new(
+            ReadaheadBuilder::new(self).with_scoped(scope),
+            ReadaheadBuilder::new(other.into_iter()).with_scoped(scope),
+            left_key,
+            right_key,
+        );
+        of(ParallelMapBuilder::new(groups))
+            .with_scoped(scope, move |(key, left, right)| (join)(&key, left, right))
+    }
+
+    /// Run fallible `f` in parallel on multiple threads, guarded by a circuit `breaker`
+    ///
+    /// While the breaker is open, `f` is not called, and items are returned
+    /// as [`BreakerError::Open`], unless [`CircuitBreaker::wait_when_open`] was used.
+    /// Useful for calling unreliable services.
+    ///
+    /// Results will be returned in order.
+    fn parallel_map_breaker<F, O, E>(
+        self,
+        breaker: CircuitBreaker,
+        f: F,
+    ) -> ParallelMap<Self, Result<O, BreakerError<Self::Item, E>>>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        F: FnMut(Self::Item) -> Result<O, E> + 'static + Send + Clone,
+        O: Send + 'static,
+        E: Send + 'static,
+    {
+        self.parallel_map_breaker_custom(|o| o, breaker, f)
+    }
+
+    /// See [`IteratorExt::parallel_map_breaker`]
+    fn parallel_map_breaker_custom<F, O, E, OF>(
+        self,
+        of: OF,
+        breaker: CircuitBreaker,
+        mut f: F,
+    ) -> ParallelMap<Self, Result<O, BreakerError<Self::Item, E>>>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'static,
+        F: FnMut(Self::Item) -> Result<O, E> + 'static + Send + Clone,
+        O: Send + 'static,
+        E: Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with(move |item| breaker.call(item, &mut f))
+    }
+
+    /// See [`IteratorExt::parallel_map_breaker`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_breaker_scoped<'env, 'scope, F, O, E>(
+        self,
+        scope: &'scope Scope<'env>,
+        breaker: CircuitBreaker,
+        f: F,
+    ) -> ParallelMap<Self, Result<O, BreakerError<Self::Item, E>>>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        F: FnMut(Self::Item) -> Result<O, E> + 'env + Send + Clone,
+        O: Send + 'env,
+        E: Send + 'env,
+    {
+        self.parallel_map_breaker_scoped_custom(scope, |o| o, breaker, f)
+    }
+
+    /// See [`IteratorExt::parallel_map_breaker`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_breaker_scoped_custom<'env, 'scope, F, O, E, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        breaker: CircuitBreaker,
+        mut f: F,
+    ) -> ParallelMap<Self, Result<O, BreakerError<Self::Item, E>>>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send + 'env,
+        F: FnMut(Self::Item) -> Result<O, E> + 'env + Send + Clone,
+        O: Send + 'env,
+        E: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with_scoped(scope, move |item| breaker.call(item, &mut f))
+    }
+
+    /// Run `f` on the items on the rayon thread pool, returning the results in the original order
+    ///
+    /// Like [`IteratorExt::parallel_map`], but uses rayon's work-stealing thread pool
+    /// instead of dedicated worker threads. Useful to mix CPU-bound work shared with
+    /// the rest of a rayon-based application into a streaming pipeline.
+    ///
+    /// At most twice the number of rayon threads worth of items are in flight. Panics
+    /// of `f` are propagated to the consumer.
+    #[cfg(feature = "rayon")]
+    fn into_rayon_ordered<F, O>(self, f: F) -> RayonOrdered<Self, O>
+    where
+        Self: Iterator,
+        Self: Sized,
+        Self::Item: Send + 'static,
+        F: Fn(Self::Item) -> O + Send + Sync + 'static,
+        O: Send + 'static,
+    {
+        RayonOrdered::new(self, f)
+    }
+
+    /// Run the futures returned by `f` concurrently, returning their results in order
+    ///
+    /// Like [`IteratorExt::parallel_map`], but for I/O-bound work: the futures are polled
+    /// by a small dedicated executor, so many of them can be in flight without
+    /// a thread for each. `f` itself is called on the consuming thread.
+    ///
+    /// Up to 64 futures are in flight at a time, see [`ParallelMapAsyncBuilder::concurrency`].
+    /// Panics of the futures are propagated to the consumer.
+    #[cfg(feature = "async")]
+    fn parallel_map_async<F, Fut, O>(self, f: F) -> ParallelMapAsync<Self, F, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: FnMut(Self::Item) -> Fut,
+        Fut: std::future::Future<Output = O> + Send + 'static,
+        O: Send + 'static,
+    {
+        ParallelMapAsyncBuilder::new(self).with(f)
+    }
+
+    /// See [`IteratorExt::parallel_map_async`]
+    #[cfg(feature = "async")]
+    fn parallel_map_async_custom<F, Fut, O, OF>(self, of: OF, f: F) -> ParallelMapAsync<Self, F, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: FnMut(Self::Item) -> Fut,
+        Fut: std::future::Future<Output = O> + Send + 'static,
+        O: Send + 'static,
+        OF: FnOnce(ParallelMapAsyncBuilder<Self>) -> ParallelMapAsyncBuilder<Self>,
+    {
+        of(ParallelMapAsyncBuilder::new(self)).with(f)
+    }
+
+    /// Run `f` on whole batches of up to `batch_size` items, in parallel on a small pool of threads
+    ///
+    /// Meant for offloading work to a GPU, or vectorized FFI calls, that are only
+    /// worth it for many items at a time. `f` must return exactly one output for
+    /// each input of the batch, in the same order. The outputs are returned one by
+    /// one, in order, like with [`IteratorExt::parallel_map`].
+    ///
+    /// Uses 2 worker threads, unless configured otherwise with
+    /// [`IteratorExt::parallel_batch_map_custom`].
+    fn parallel_batch_map<F, O>(self, batch_size: usize, f: F) -> ParallelBatchMap<Self, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        F: FnMut(Vec<Self::Item>) -> Vec<O>,
+        Self::Item: Send + 'static,
+        O: Send + 'static,
+    {
+        self.parallel_batch_map_custom(|o| o, batch_size, f)
+    }
+
+    /// See [`IteratorExt::parallel_batch_map`]
+    fn parallel_batch_map_custom<F, O, OF>(
+        self,
+        of: OF,
+        batch_size: usize,
+        f: F,
+    ) -> ParallelBatchMap<Self, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        F: FnMut(Vec<Self::Item>) -> Vec<O>,
+        Self::Item: Send + 'static,
+        O: Send + 'static,
+        OF: FnOnce(ParallelMapConfig) -> ParallelMapConfig,
+    {
+        ParallelBatchMap::new(self, batch_size, of(ParallelMapConfig::new()), f)
+    }
+
+    /// See [`IteratorExt::parallel_batch_map`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_batch_map_scoped<'env, 'scope, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        batch_size: usize,
+        f: F,
+    ) -> ParallelBatchMap<Self, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        F: FnMut(Vec<Self::Item>) -> Vec<O>,
+        Self::Item: Send + 'env,
+        O: Send + 'env,
+    {
+        self.parallel_batch_map_scoped_custom(scope, |o| o, batch_size, f)
+    }
+
+    /// See [`IteratorExt::parallel_batch_map`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_batch_map_scoped_custom<'env, 'scope, F, O, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        batch_size: usize,
+        f: F,
+    ) -> ParallelBatchMap<Self, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        F: FnMut(Vec<Self::Item>) -> Vec<O>,
+        Self::Item: Send + 'env,
+        O: Send + 'env,
+        OF: FnOnce(ParallelMapConfig) -> ParallelMapConfig,
+    {
+        ParallelBatchMap::new_scoped(self, scope, batch_size, of(ParallelMapConfig::new()), f)
+    }
+
+    /// Like [`IteratorExt::parallel_map`], but further stages can be fused into it
+    ///
+    /// Chained [`IteratorExt::parallel_map`]s send each item through four channels and
+    /// two reorder buffers. Stages added with [`ParallelMapFused::then_parallel_map`]
+    /// run on the same worker instead, right after the previous ones, cutting
+    /// the latency and the wakeups of deep pipelines.
+    ///
+    /// The worker threads are started when the first item is pulled.
+    fn parallel_map_fused<F, O>(self, f: F) -> ParallelMapFused<Self, F, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        F: FnMut(Self::Item) -> O,
+        Self::Item: Send + 'static,
+        O: Send + 'static,
+    {
+        ParallelMapFused::new(ParallelMapBuilder::new(self), f)
+    }
+
+    /// See [`IteratorExt::parallel_map_fused`]
+    fn parallel_map_fused_custom<F, O, OF>(self, of: OF, f: F) -> ParallelMapFused<Self, F, O>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        F: FnMut(Self::Item) -> O,
+        Self::Item: Send + 'static,
+        O: Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        ParallelMapFused::new(of(ParallelMapBuilder::new(self)), f)
+    }
+
+    /// Run `f` on references to the items in parallel on multiple threads,
+    /// returning the items along with the outputs
+    ///
+    /// Like [`IteratorExt::parallel_map`], but the input travels through the worker
+    /// alongside the output, so `(item, output)` pairs are returned in order,
+    /// without the need to clone the items.
+    fn parallel_map_keep_input<F, O>(self, f: F) -> ParallelMap<Self, (Self::Item, O)>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        F: FnMut(&Self::Item) -> O,
+        Self::Item: Send + 'static,
+        O: Send + 'static,
+    {
+        self.parallel_map_keep_input_custom(|o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_map_keep_input`]
+    fn parallel_map_keep_input_custom<F, O, OF>(
+        self,
+        of: OF,
+        mut f: F,
+    ) -> ParallelMap<Self, (Self::Item, O)>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        F: FnMut(&Self::Item) -> O,
+        Self::Item: Send + 'static,
+        O: Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with(move |item| {
+            let output = (f)(&item);
+            (item, output)
+        })
+    }
+
+    /// See [`IteratorExt::parallel_map_keep_input`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_keep_input_scoped<'env, 'scope, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> ParallelMap<Self, (Self::Item, O)>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        F: FnMut(&Self::Item) -> O,
+        Self::Item: Send + 'env,
+        O: Send + 'env,
+    {
+        self.parallel_map_keep_input_scoped_custom(scope, |o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_map_keep_input`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_keep_input_scoped_custom<'env, 'scope, F, O, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        mut f: F,
+    ) -> ParallelMap<Self, (Self::Item, O)>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        F: FnMut(&Self::Item) -> O,
+        Self::Item: Send + 'env,
+        O: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with_scoped(scope, move |item| {
+            let output = (f)(&item);
+            (item, output)
+        })
+    }
+
+    /// Run `f` on the `Ok` values of the items in parallel on multiple threads,
+    /// passing the `Err`s through untouched
+    ///
+    /// Like [`IteratorExt::parallel_map`], but for streams of `Result`s, e.g. from
+    /// I/O sources. The items are returned in order, errors included.
+    fn parallel_map_ok<F, T, E, O>(self, f: F) -> ParallelMap<Self, Result<O, E>>
+    where
+        Self: Sized,
+        Self: Iterator<Item = Result<T, E>>,
+        F: 'static + Send + Clone,
+        F: FnMut(T) -> O,
+        T: Send + 'static,
+        E: Send + 'static,
+        O: Send + 'static,
+    {
+        self.parallel_map_ok_custom(|o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_map_ok`]
+    fn parallel_map_ok_custom<F, T, E, O, OF>(
+        self,
+        of: OF,
+        mut f: F,
+    ) -> ParallelMap<Self, Result<O, E>>
+    where
+        Self: Sized,
+        Self: Iterator<Item = Result<T, E>>,
+        F: 'static + Send + Clone,
+        F: FnMut(T) -> O,
+        T: Send + 'static,
+        E: Send + 'static,
+        O: Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with(move |item: Result<T, E>| item.map(&mut f))
+    }
+
+    /// See [`IteratorExt::parallel_map_ok`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_ok_scoped<'env, 'scope, F, T, E, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> ParallelMap<Self, Result<O, E>>
+    where
+        Self: Sized,
+        Self: Iterator<Item = Result<T, E>>,
+        F: 'env + Send + Clone,
+        F: FnMut(T) -> O,
+        T: Send + 'env,
+        E: Send + 'env,
+        O: Send + 'env,
+    {
+        self.parallel_map_ok_scoped_custom(scope, |o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_map_ok`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_ok_scoped_custom<'env, 'scope, F, T, E, O, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        mut f: F,
+    ) -> ParallelMap<Self, Result<O, E>>
+    where
+        Self: Sized,
+        Self: Iterator<Item = Result<T, E>>,
+        F: 'env + Send + Clone,
+        F: FnMut(T) -> O,
+        T: Send + 'env,
+        E: Send + 'env,
+        O: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self))
+            .with_scoped(scope, move |item: Result<T, E>| item.map(&mut f))
+    }
+
+    /// Run the fallible `f` in parallel on multiple threads, collecting the successes
+    /// and the failures separately
+    ///
+    /// Returns the outputs of the successes in order, and the errors of the failures
+    /// with the positions of their items in the iterator. Unlike collecting into a
+    /// `Result`, doesn't stop at the first failure, so all of them can be reported.
+    fn partition_result_parallel<F, O, E>(self, f: F) -> (Vec<O>, Vec<(usize, E)>)
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        F: FnMut(Self::Item) -> Result<O, E>,
+        Self::Item: Send + 'static,
+        O: Send + 'static,
+        E: Send + 'static,
+    {
+        self.partition_result_parallel_custom(|o| o, f)
+    }
+
+    /// See [`IteratorExt::partition_result_parallel`]
+    fn partition_result_parallel_custom<F, O, E, OF>(
+        self,
+        of: OF,
+        mut f: F,
+    ) -> (Vec<O>, Vec<(usize, E)>)
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        F: FnMut(Self::Item) -> Result<O, E>,
+        Self::Item: Send + 'static,
+        O: Send + 'static,
+        E: Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        partition_results(
+            of(ParallelMapBuilder::new(self))
+                .with_enumerate(move |i, item| (f)(item).map_err(|e| (i, e))),
+        )
+    }
+
+    /// See [`IteratorExt::partition_result_parallel`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn partition_result_parallel_scoped<'env, 'scope, F, O, E>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> (Vec<O>, Vec<(usize, E)>)
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        F: FnMut(Self::Item) -> Result<O, E>,
+        Self::Item: Send + 'env,
+        O: Send + 'env,
+        E: Send + 'env,
+    {
+        self.partition_result_parallel_scoped_custom(scope, |o| o, f)
+    }
+
+    /// See [`IteratorExt::partition_result_parallel`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn partition_result_parallel_scoped_custom<'env, 'scope, F, O, E, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        mut f: F,
+    ) -> (Vec<O>, Vec<(usize, E)>)
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        F: FnMut(Self::Item) -> Result<O, E>,
+        Self::Item: Send + 'env,
+        O: Send + 'env,
+        E: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        partition_results(
+            of(ParallelMapBuilder::new(self))
+                .with_enumerate_scoped(scope, move |i, item| (f)(item).map_err(|e| (i, e))),
+        )
+    }
+
+    /// Deliver each item to one of `num_routes` routes, chosen by `classifier`
+    /// in parallel on multiple threads
+    ///
+    /// The general form of splitting and partitioning a stream: `classifier` returns
+    /// the index of the route of the item, and is run on the worker threads, like with
+    /// [`IteratorExt::parallel_map`]. Each route gets its items in order, through
+    /// a bounded channel, from a router thread.
+    ///
+    /// All the routes need to be consumed concurrently (e.g. in threads of their own),
+    /// as a full route blocks the delivery to all the others. Dropped routes
+    /// are skipped.
+    ///
+    /// # Panics
+    ///
+    /// The workers panic if `classifier` returns an index out of range, and the routes
+    /// panic when the router thread does.
+    fn parallel_route<F>(self, num_routes: usize, classifier: F) -> Vec<Route<Self::Item>>
+    where
+        Self: Sized,
+        Self: Iterator + Send + 'static,
+        F: 'static + Send + Clone,
+        F: FnMut(&Self::Item) -> usize,
+        Self::Item: Send + 'static,
+    {
+        self.parallel_route_custom(|o| o, num_routes, classifier)
+    }
+
+    /// See [`IteratorExt::parallel_route`]
+    fn parallel_route_custom<F, OF>(
+        self,
+        of: OF,
+        num_routes: usize,
+        mut classifier: F,
+    ) -> Vec<Route<Self::Item>>
+    where
+        Self: Sized,
+        Self: Iterator + Send + 'static,
+        F: 'static + Send + Clone,
+        F: FnMut(&Self::Item) -> usize,
+        Self::Item: Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        let pipeline = of(ParallelMapBuilder::new(self))
+            .with(move |item| (classify(&mut classifier, &item, num_routes), item));
+        let (txs, routes, router_panicked) = route::channels(num_routes, pipeline.buffer_size());
+        route::spawn(pipeline, txs, router_panicked);
+        routes
+    }
+
+    /// See [`IteratorExt::parallel_route`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_route_scoped<'env, 'scope, F>(
+        self,
+        scope: &'scope Scope<'env>,
+        num_routes: usize,
+        classifier: F,
+    ) -> Vec<Route<Self::Item>>
+    where
+        Self: Sized,
+        Self: Iterator + Send + 'env,
+        F: 'env + Send + Clone,
+        F: FnMut(&Self::Item) -> usize,
+        Self::Item: Send + 'env,
+    {
+        self.parallel_route_scoped_custom(scope, |o| o, num_routes, classifier)
+    }
+
+    /// See [`IteratorExt::parallel_route`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_route_scoped_custom<'env, 'scope, F, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        num_routes: usize,
+        mut classifier: F,
+    ) -> Vec<Route<Self::Item>>
+    where
+        Self: Sized,
+        Self: Iterator + Send + 'env,
+        F: 'env + Send + Clone,
+        F: FnMut(&Self::Item) -> usize,
+        Self::Item: Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        let pipeline = of(ParallelMapBuilder::new(self)).with_scoped(scope, move |item| {
+            (classify(&mut classifier, &item, num_routes), item)
+        });
+        let (txs, routes, router_panicked) = route::channels(num_routes, pipeline.buffer_size());
+        route::spawn_scoped(scope, pipeline, txs, router_panicked);
+        routes
+    }
+
+    /// Compute the checksums of the items in parallel on multiple threads
+    ///
+    /// The items are returned in order, with their checksums attached, and
+    /// [`checksum::ParallelChecksum::digest`] tracks the digest of the whole stream.
+    /// See the [`checksum`] module.
+    fn parallel_checksum(self) -> checksum::ParallelChecksum<Self>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: AsRef<[u8]> + Send + 'static,
+    {
+        self.parallel_checksum_custom(|o| o)
+    }
+
+    /// See [`IteratorExt::parallel_checksum`]
+    fn parallel_checksum_custom<OF>(self, of: OF) -> checksum::ParallelChecksum<Self>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: AsRef<[u8]> + Send + 'static,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        checksum::ParallelChecksum::new(
+            of(ParallelMapBuilder::new(self)).with(checksum::Checksummed::new),
+        )
+    }
+
+    /// See [`IteratorExt::parallel_checksum`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_checksum_scoped<'env, 'scope>(
+        self,
+        scope: &'scope Scope<'env>,
+    ) -> checksum::ParallelChecksum<Self>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: AsRef<[u8]> + Send + 'env,
+    {
+        self.parallel_checksum_scoped_custom(scope, |o| o)
+    }
+
+    /// See [`IteratorExt::parallel_checksum`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_checksum_scoped_custom<'env, 'scope, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+    ) -> checksum::ParallelChecksum<Self>
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: AsRef<[u8]> + Send + 'env,
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        checksum::ParallelChecksum::new(
+            of(ParallelMapBuilder::new(self)).with_scoped(scope, checksum::Checksummed::new),
+        )
+    }
+
+    /// Fold the items in parallel, each worker thread folding its share into its own `identity()`
+    ///
+    /// The results of the workers are then combined with `combine`. The items are handed
+    /// to the workers in no particular order, without indices or reordering, so the
+    /// operations must be commutative and associative (e.g. adding up, or counting into maps),
+    /// and `identity()` must not change the result.
+    ///
+    /// ```
+    /// use pariter::IteratorExt as _;
+    ///
+    /// let longest = ["a", "abc", "ab"]
+    ///     .into_iter()
+    ///     .parallel_fold(|| 0, |max, s| max.max(s.len()), |a, b| a.max(b));
+    /// assert_eq!(longest, 3);
+    /// ```
+    fn parallel_fold<A, ID, F, C>(self, identity: ID, fold: F, combine: C) -> A
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send,
+        A: Send,
+        ID: Fn() -> A,
+        F: FnMut(A, Self::Item) -> A + Clone + Send,
+        C: FnMut(A, A) -> A,
+    {
+        parallel_fold::parallel_fold(self, identity, fold, combine)
+    }
+
+    /// Add up `f` of all the items, calling it in parallel, in no particular order
+    ///
+    /// See [`IteratorExt::parallel_fold`].
+    ///
+    /// ```
+    /// use pariter::IteratorExt as _;
+    ///
+    /// let sum: u64 = (0..100u64).parallel_sum_by(|x| x * x);
+    /// assert_eq!(sum, 328350);
+    /// ```
+    fn parallel_sum_by<S, F>(self, f: F) -> S
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send,
+        S: std::iter::Sum + Send,
+        F: FnMut(Self::Item) -> S + Clone + Send,
+    {
+        parallel_fold::parallel_sum_by(self, f)
+    }
+
+    /// Count the items for which `f` returns `true`, calling it in parallel, in no particular order
+    ///
+    /// See [`IteratorExt::parallel_fold`].
+    fn parallel_count<F>(self, mut f: F) -> usize
+    where
+        Self: Sized,
+        Self: Iterator,
+        Self::Item: Send,
+        F: FnMut(Self::Item) -> bool + Clone + Send,
+    {
+        self.parallel_sum_by(move |item| usize::from(f(item)))
+    }
+
+    /// Run `f` mutating items in place, in parallel on multiple threads
+    ///
+    /// Returns the mutated items, in order. Handy when the work is naturally
+    /// in-place, e.g. normalizing or filling in fields of larger structs.
+    fn parallel_update<F>(self, f: F) -> ParallelMap<Self, Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        Self::Item: Send + 'static,
+        F: FnMut(&mut Self::Item),
+    {
+        self.parallel_update_custom(|o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_update`]
+    fn parallel_update_custom<F, OF>(self, of: OF, mut f: F) -> ParallelMap<Self, Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        Self::Item: Send + 'static,
+        F: FnMut(&mut Self::Item),
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with(move |mut item| {
+            f(&mut item);
+            item
+        })
+    }
+
+    /// See [`IteratorExt::parallel_update`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_update_scoped<'env, 'scope, F>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> ParallelMap<Self, Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        Self::Item: Send + 'env,
+        F: FnMut(&mut Self::Item),
+    {
+        self.parallel_update_scoped_custom(scope, |o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_update`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_update_scoped_custom<'env, 'scope, F, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        mut f: F,
+    ) -> ParallelMap<Self, Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        Self::Item: Send + 'env,
+        F: FnMut(&mut Self::Item),
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with_scoped(scope, move |mut item| {
+            f(&mut item);
+            item
+        })
+    }
+
+    /// Call `f` on each item in parallel on multiple threads, passing the items through
+    ///
+    /// Like [`Iterator::inspect`], but `f` runs on the worker threads. Items
+    /// are returned unchanged and in order. Useful for side effects like warming
+    /// up caches or emitting per-item metrics.
+    fn parallel_inspect<F>(self, f: F) -> ParallelMap<Self, Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item),
+    {
+        self.parallel_inspect_custom(|o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_inspect`]
+    fn parallel_inspect_custom<F, OF>(self, of: OF, mut f: F) -> ParallelMap<Self, Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item),
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with(move |item| {
+            f(&item);
+            item
+        })
+    }
+
+    /// See [`IteratorExt::parallel_inspect`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_inspect_scoped<'env, 'scope, F>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> ParallelMap<Self, Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item),
+    {
+        self.parallel_inspect_scoped_custom(scope, |o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_inspect`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_inspect_scoped_custom<'env, 'scope, F, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        mut f: F,
+    ) -> ParallelMap<Self, Self::Item>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item),
+        OF: FnOnce(ParallelMapBuilder<Self>) -> ParallelMapBuilder<Self>,
+    {
+        of(ParallelMapBuilder::new(self)).with_scoped(scope, move |item| {
+            f(&item);
+            item
+        })
+    }
+
+    /// Run `filter` function in parallel on multiple threads
+    ///
+    /// A wrapper around [`IteratorExt::parallel_map`] really, so it has similiar properties.
+    /// In particular, the items kept are returned in their original order, unless
+    /// [`ParallelFilterBuilder::unordered`] is set.
+    fn parallel_filter<F>(self, f: F) -> ParallelFilter<Self>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item) -> bool,
+    {
+        ParallelFilterBuilder::new(self).with(f)
+    }
+
+    /// See [`IteratorExt::parallel_filter`]
+    fn parallel_filter_custom<F, OF>(self, of: OF, f: F) -> ParallelFilter<Self>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'static + Send + Clone,
+        Self::Item: Send + 'static,
+        F: FnMut(&Self::Item) -> bool,
+        OF: FnOnce(ParallelFilterBuilder<Self>) -> ParallelFilterBuilder<Self>,
+    {
+        of(ParallelFilterBuilder::new(self)).with(f)
+    }
+
+    /// See [`IteratorExt::parallel_filter`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_filter_scoped<'env, 'scope, F>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> ParallelFilter<Self>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item) -> bool,
+    {
+        ParallelFilterBuilder::new(self).with_scoped(scope, f)
+    }
+
+    /// See [`IteratorExt::parallel_filter`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_filter_scoped_custom<'env, 'scope, F, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        f: F,
+    ) -> ParallelFilter<Self>
+    where
+        Self: Sized,
+        Self: Iterator,
+        F: 'env + Send + Clone,
+        Self::Item: Send + 'env,
+        F: FnMut(&Self::Item) -> bool,
+        OF: FnOnce(ParallelFilterBuilder<Self>) -> ParallelFilterBuilder<Self>,
+    {
+        of(ParallelFilterBuilder::new(self)).with_scoped(scope, f)
+    }
+
+    /// Run `filter` function on the `Ok` values of the items in parallel on multiple threads,
+    /// keeping all the `Err`s
+    ///
+    /// Like [`IteratorExt::parallel_filter`], but for streams of `Result`s, so error
+    /// handling can be deferred to the end of the pipeline.
+    fn parallel_filter_ok<F, T, E>(self, f: F) -> ParallelFilter<Self>
+    where
+        Self: Sized,
+        Self: Iterator<Item = Result<T, E>>,
+        F: 'static + Send + Clone,
+        F: FnMut(&T) -> bool,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        self.parallel_filter_ok_custom(|o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_filter_ok`]
+    fn parallel_filter_ok_custom<F, T, E, OF>(self, of: OF, mut f: F) -> ParallelFilter<Self>
+    where
+        Self: Sized,
+        Self: Iterator<Item = Result<T, E>>,
+        F: 'static + Send + Clone,
+        F: FnMut(&T) -> bool,
+        T: Send + 'static,
+        E: Send + 'static,
+        OF: FnOnce(ParallelFilterBuilder<Self>) -> ParallelFilterBuilder<Self>,
+    {
+        of(ParallelFilterBuilder::new(self)).with(move |item: &Result<T, E>| match item {
+            Ok(value) => f(value),
+            Err(_) => true,
+        })
+    }
+
+    /// See [`IteratorExt::parallel_filter_ok`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_filter_ok_scoped<'env, 'scope, F, T, E>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> ParallelFilter<Self>
+    where
+        Self: Sized,
+        Self: Iterator<Item = Result<T, E>>,
+        F: 'env + Send + Clone,
+        F: FnMut(&T) -> bool,
+        T: Send + 'env,
+        E: Send + 'env,
+    {
+        self.parallel_filter_ok_scoped_custom(scope, |o| o, f)
+    }
+
+    /// See [`IteratorExt::parallel_filter_ok`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_filter_ok_scoped_custom<'env, 'scope, F, T, E, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+        mut f: F,
+    ) -> ParallelFilter<Self>
+    where
+        Self: Sized,
+        Self: Iterator<Item = Result<T, E>>,
+        F: 'env + Send + Clone,
+        F: FnMut(&T) -> bool,
+        T: Send + 'env,
+        E: Send + 'env,
+        OF: FnOnce(ParallelFilterBuilder<Self>) -> ParallelFilterBuilder<Self>,
+    {
+        of(ParallelFilterBuilder::new(self)).with_scoped(scope, move |item: &Result<T, E>| {
+            match item {
+                Ok(value) => f(value),
+                Err(_) => true,
+            }
+        })
+    }
+
+    /// Run the current iterator in another thread and return elements
+    /// through a buffered channel.
+    ///
+    /// `buffer_size` defines the size of the output channel connecting
+    /// current and the inner thread.
+    //
+    /// It's a common mistake to use large channel sizes needlessly
+    /// in hopes of achieving higher performance. The only benefit
+    /// large buffer size value provides is smooting out the variance
+    /// of the inner iterator returning items. The cost - wasting memory.
+    /// In normal circumstances `0` is recommended (the default).
+    fn readahead(self) -> Readahead<Self>
+    where
+        Self: Iterator + Send + 'static,
+        Self: Sized,
+        Self::Item: Send + 'static,
+    {
+        ReadaheadBuilder::new(self).with()
+    }
+
+    fn readahead_custom<OF>(self, of: OF) -> Readahead<Self>
+    where
+        Self: Iterator,
+        Self: Sized + Send + 'static,
+        Self::Item: Send + 'static,
+        OF: FnOnce(ReadaheadBuilder<Self>) -> ReadaheadBuilder<Self>,
+    {
+        of(ReadaheadBuilder::new(self)).with()
+    }
+
+    /// Scoped version of [`IteratorExt::readahead`]
+    ///
+    /// Use when you want to process in parallel items that contain
+    /// borrowed references.
+    ///
+    /// See [`scope`].
+    fn readahead_scoped<'env, 'scope>(self, scope: &'scope Scope<'env>) -> Readahead<Self>
+    where
+        Self: Sized + Send,
+        Self: Iterator + 'scope + 'env,
+        Self::Item: Send + 'env + 'scope + Send,
+    {
+        ReadaheadBuilder::new(self).with_scoped(scope)
+    }
+
+    fn readahead_scoped_custom<'env, 'scope, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+    ) -> Readahead<Self>
+    where
+        Self: Sized + Send,
+        Self: Iterator + 'scope + 'env,
+        Self::Item: Send + 'env + 'scope + Send,
+        OF: FnOnce(ReadaheadBuilder<Self>) -> ReadaheadBuilder<Self>,
+    {
+        of(ReadaheadBuilder::new(self)).with_scoped(scope)
+    }
+
+    /// Like [`IteratorExt::readahead`], with the options of `config`
+    fn readahead_cfg(self, config: &ReadaheadConfig) -> Readahead<Self>
+    where
+        Self: Iterator + Send + 'static,
+        Self: Sized,
+        Self::Item: Send + 'static,
+    {
+        ReadaheadBuilder::new(self).config(config).with()
+    }
+
+    /// Like [`IteratorExt::readahead_scoped`], with the options of `config`
+    fn readahead_scoped_cfg<'env, 'scope>(
+        self,
+        scope: &'scope Scope<'env>,
+        config: &ReadaheadConfig,
+    ) -> Readahead<Self>
+    where
+        Self: Sized + Send,
+        Self: Iterator + 'scope + 'env,
+        Self::Item: Send + 'env + 'scope + Send,
+    {
+        ReadaheadBuilder::new(self)
+            .config(config)
+            .with_scoped(scope)
+    }
+
+    /// Pull at most `items_per_sec` items per second from the inner iterator
+    ///
+    /// A token bucket holding at most a single token, so items are evenly spaced.
+    /// The calling thread sleeps when pulling items too fast. Use before a parallel
+    /// stage to limit the rate items are sent to it, e.g. when calling rate-limited APIs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `items_per_sec` is not positive and finite.
+    fn throttle(self, items_per_sec: f64) -> Throttle<Self, ()>
+    where
+        Self: Iterator,
+        Self: Sized,
+    {
+        Throttle::new(self, items_per_sec, ())
+    }
+
+    /// Like [`IteratorExt::throttle`], with `profiler` measuring the time
+    /// spent blocked on the rate limit
+    fn throttle_profiled<P: profile::Profiler>(
+        self,
+        items_per_sec: f64,
+        profiler: P,
+    ) -> Throttle<Self, P>
+    where
+        Self: Iterator,
+        Self: Sized,
+    {
+        Throttle::new(self, items_per_sec, profiler)
+    }
+
+    /// Process all the items of the inner iterator before returning the first one
+    ///
+    /// Marks a phase boundary in a pipeline: when placed after a parallel stage,
+    /// all of its items are done before any of them is passed downstream, e.g. to
+    /// finish all writes before starting verification. All the items are buffered
+    /// in memory.
+    fn barrier(self) -> Barrier<Self>
+    where
+        Self: Iterator,
+        Self: Sized,
+    {
+        Barrier::new(self)
+    }
+
+    /// Tag items with their positions, skipping the first `completed` ones
+    ///
+    /// Use with [`IteratorExt::checkpoint`] to resume interrupted processing.
+    /// See the [`checkpoint`] module.
+    fn resume_from(self, completed: usize) -> checkpoint::Resume<Self>
+    where
+        Self: Iterator,
+        Self: Sized,
+    {
+        checkpoint::Resume::new(self, completed)
+    }
+
+    /// Record the items tagged with their input positions as completed in `checkpoint`
+    ///
+    /// Each item is considered completed once the next one is pulled. Items can
+    /// be in any order. See the [`checkpoint`] module.
+    fn checkpoint<T>(self, checkpoint: checkpoint::Checkpoint) -> checkpoint::Checkpointed<Self>
+    where
+        Self: Iterator<Item = (usize, T)>,
+        Self: Sized,
+    {
+        checkpoint::Checkpointed::new(self, checkpoint)
+    }
+
+    /// Write the items, in order, to files rotated before exceeding `max_bytes`
+    ///
+    /// `{}` in `path_pattern` is replaced with the index of each file, starting at 0.
+    /// The writing and rotating happens on a dedicated thread. Returns the paths of all
+    /// the files written.
+    ///
+    /// ```no_run
+    /// use pariter::IteratorExt as _;
+    ///
+    /// let paths = (0..1000)
+    ///     .parallel_map(|i| format!("line {}\n", i))
+    ///     .write_rotating("out-{}.log", 1 << 20)
+    ///     .unwrap();
+    /// ```
+    fn write_rotating(
+        self,
+        path_pattern: impl Into<String>,
+        max_bytes: u64,
+    ) -> std::io::Result<Vec<std::path::PathBuf>>
+    where
+        Self: Iterator,
+        Self: Sized,
+        Self::Item: AsRef<[u8]> + Send + 'static,
+    {
+        WriteRotatingBuilder::new(self, path_pattern)
+            .max_bytes(max_bytes)
+            .with()
+    }
+
+    /// See [`IteratorExt::write_rotating`]
+    fn write_rotating_custom<OF>(
+        self,
+        path_pattern: impl Into<String>,
+        of: OF,
+    ) -> std::io::Result<Vec<std::path::PathBuf>>
+    where
+        Self: Iterator,
+        Self: Sized,
+        Self::Item: AsRef<[u8]> + Send + 'static,
+        OF: FnOnce(WriteRotatingBuilder<Self>) -> WriteRotatingBuilder<Self>,
+    {
+        of(WriteRotatingBuilder::new(self, path_pattern)).with()
+    }
+
+    /// Write every item at the offset `offset_fn` computes from its index, e.g. in a file
+    ///
+    /// Takes the items with the indices of their inputs, as returned by
+    /// [`ParallelMap::with_input_indices`], so with fixed-size records, results done out
+    /// of order (see [`ParallelMapConfig::unordered`]) still end up in the right places.
+    /// The writes happen on the consuming thread. Returns the number of bytes written.
+    ///
+    /// ```no_run
+    /// use pariter::IteratorExt as _;
+    ///
+    /// const RECORD: u64 = 8;
+    ///
+    /// let file = std::fs::File::create("squares.bin").unwrap();
+    /// (0..1000u64)
+    ///     .parallel_map_custom(|o| o.unordered(), |x| (x * x).to_le_bytes())
+    ///     .with_input_indices()
+    ///     .write_at_offsets(file, |i| i as u64 * RECORD)
+    ///     .unwrap();
+    /// ```
+    fn write_at_offsets<T, W, F>(self, writer: W, offset_fn: F) -> std::io::Result<u64>
+    where
+        Self: Iterator<Item = (usize, T)>,
+        Self: Sized,
+        T: AsRef<[u8]>,
+        W: std::io::Write + std::io::Seek,
+        F: FnMut(usize) -> u64,
+    {
+        write_at::write_at_offsets(self, writer, offset_fn)
+    }
+
+    /// Like [`IteratorExt::checkpoint`], but items are completed only when acknowledged
+    ///
+    /// Returns [`checkpoint::Ack`] guards, and items dropped without [`checkpoint::Ack::ack`]
+    /// hold the checkpoint back, so they are delivered again after a restart, giving
+    /// at-least-once processing. See the [`checkpoint`] module.
+    ///
+    /// ```
+    /// use pariter::{checkpoint::Checkpoint, IteratorExt as _};
+    ///
+    /// let checkpoint = Checkpoint::new(0);
+    /// let mut items = (0..10)
+    ///     .resume_from(0)
+    ///     .parallel_map(|(i, x)| (i, x * 2))
+    ///     .acknowledged(checkpoint.clone());
+    ///
+    /// while let Some(item) = items.next_ack() {
+    ///     if *item < 10 {
+    ///         // upload `*item` somewhere, and then
+    ///         item.ack();
+    ///     }
+    /// }
+    ///
+    /// // items from #5 on were not acknowledged, so will be redelivered
+    /// assert_eq!(checkpoint.completed(), 5);
+    /// ```
+    fn acknowledged<T>(self, checkpoint: checkpoint::Checkpoint) -> checkpoint::Acknowledged<Self>
+    where
+        Self: Iterator<Item = (usize, T)>,
+        Self: Sized,
+    {
+        checkpoint::Acknowledged::new(self, checkpoint)
+    }
+
+    /// Turn this iterator into a handle that can be cloned, and pulled from multiple threads
+    ///
+    /// Distributes the results of a pipeline between threads post-processing them,
+    /// when that doesn't fit another parallel stage. Each item is returned by only one
+    /// of the clones, so the order across them is lost.
+    ///
+    /// ```
+    /// use pariter::IteratorExt as _;
+    ///
+    /// let results = (0..100).parallel_map(|x| x * 2).into_shared();
+    /// let handles: Vec<_> = (0..4)
+    ///     .map(|_| {
+    ///         let results = results.clone();
+    ///         std::thread::spawn(move || results.sum::<i32>())
+    ///     })
+    ///     .collect();
+    /// drop(results);
+    ///
+    /// let sum: i32 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    /// assert_eq!(sum, 9900);
+    /// ```
+    fn into_shared(self) -> SharedIter<Self>
+    where
+        Self: Sized,
+    {
+        SharedIter::new(self)
+    }
+
+    /// Describe this iterator as a source of items named `name`
+    ///
+    /// See [`Describe`].
+    fn describe_as(self, name: impl Into<String>) -> Described<Self>
+    where
+        Self: Iterator,
+        Self: Sized,
+    {
+        Described::new(self, name.into())
+    }
+
+    /// Like [`IteratorExt::readahead`], but stops pulling the inner iterator after the first `Err`
+    ///
+    /// The items are still returned in order, up to and including the first `Err`,
+    /// but no work is wasted on items after it, that would be thrown away anyway.
+    fn readahead_results<T, E>(self) -> Readahead<StopOnError<Self>>
+    where
+        Self: Iterator<Item = Result<T, E>> + Send + 'static,
+        Self: Sized,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        ReadaheadBuilder::new(StopOnError::new(self)).with()
+    }
+
+    fn readahead_results_custom<T, E, OF>(self, of: OF) -> Readahead<StopOnError<Self>>
+    where
+        Self: Iterator<Item = Result<T, E>> + Send + 'static,
+        Self: Sized,
+        T: Send + 'static,
+        E: Send + 'static,
+        OF: FnOnce(ReadaheadBuilder<StopOnError<Self>>) -> ReadaheadBuilder<StopOnError<Self>>,
+    {
+        of(ReadaheadBuilder::new(StopOnError::new(self))).with()
+    }
+
+    /// Scoped version of [`IteratorExt::readahead_results`]
+    fn readahead_results_scoped<'env, 'scope, T, E>(
+        self,
+        scope: &'scope Scope<'env>,
+    ) -> Readahead<StopOnError<Self>>
+    where
+        Self: Iterator<Item = Result<T, E>> + Send + 'env,
+        Self: Sized,
+        T: Send + 'env,
+        E: Send + 'env,
+    {
+        ReadaheadBuilder::new(StopOnError::new(self)).with_scoped(scope)
+    }
+
+    fn readahead_results_scoped_custom<'env, 'scope, T, E, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        of: OF,
+    ) -> Readahead<StopOnError<Self>>
+    where
+        Self: Iterator<Item = Result<T, E>> + Send + 'env,
+        Self: Sized,
+        T: Send + 'env,
+        E: Send + 'env,
+        OF: FnOnce(ReadaheadBuilder<StopOnError<Self>>) -> ReadaheadBuilder<StopOnError<Self>>,
+    {
+        of(ReadaheadBuilder::new(StopOnError::new(self))).with_scoped(scope)
+    }
+
+    /// Like [`IteratorExt::readahead`], but runs `f` on the items in the readahead thread
+    ///
+    /// Cheap decoding or validation work can piggyback on the existing thread, without
+    /// a second channel hop or a whole [`IteratorExt::parallel_map`] stage.
+    ///
+    /// Literally `.map(f).readahead()`, so use `.map(f).readahead_custom(...)`
+    /// to customize it.
+    fn readahead_map<F, O>(self, f: F) -> Readahead<std::iter::Map<Self, F>>
+    where
+        Self: Iterator + Send + 'static,
+        Self: Sized,
+        F: FnMut(Self::Item) -> O + Send + 'static,
+        O: Send + 'static,
+    {
+        ReadaheadBuilder::new(self.map(f)).with()
+    }
+
+    /// Scoped version of [`IteratorExt::readahead_map`]
+    fn readahead_map_scoped<'env, 'scope, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> Readahead<std::iter::Map<Self, F>>
+    where
+        Self: Iterator + Send + 'env,
+        Self: Sized,
+        F: FnMut(Self::Item) -> O + Send + 'env,
+        O: Send + 'env,
+    {
+        ReadaheadBuilder::new(self.map(f)).with_scoped(scope)
+    }
+
+    /// Run `n` producer threads, each pulling every `n`-th item from its own clone of the iterator
+    ///
+    /// Like [`IteratorExt::readahead`], for when a single producer thread is
+    /// the bottleneck. Items are returned in the original order.
+    ///
+    /// Every producer thread skips the items of the other ones, so it only makes
+    /// sense for iterators that are cheap to clone and skip, like ranges or iterators over
+    /// (shared) lists of items, with the expensive part done in the iterator itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    fn readahead_split(self, n: usize) -> ReadaheadSplit<Self>
+    where
+        Self: Iterator + Clone + Send + 'static,
+        Self::Item: Send + 'static,
+        Self: Sized,
+    {
+        ReadaheadSplit::new(self, n, true)
+    }
+
+    /// Like [`IteratorExt::readahead_split`], but returns the items in any order
+    ///
+    /// No producer thread has to wait for the slower ones.
+    fn readahead_split_unordered(self, n: usize) -> ReadaheadSplit<Self>
+    where
+        Self: Iterator + Clone + Send + 'static,
+        Self::Item: Send + 'static,
+        Self: Sized,
+    {
+        ReadaheadSplit::new(self, n, false)
+    }
+
+    /// Scoped version of [`IteratorExt::readahead_split`]
+    fn readahead_split_scoped<'env, 'scope>(
+        self,
+        scope: &'scope Scope<'env>,
+        n: usize,
+    ) -> ReadaheadSplit<Self>
+    where
+        Self: Iterator + Clone + Send + 'env,
+        Self::Item: Send + 'env,
+        Self: Sized,
+    {
+        ReadaheadSplit::new_scoped(scope, self, n, true)
+    }
+
+    /// Scoped version of [`IteratorExt::readahead_split_unordered`]
+    fn readahead_split_unordered_scoped<'env, 'scope>(
+        self,
+        scope: &'scope Scope<'env>,
+        n: usize,
+    ) -> ReadaheadSplit<Self>
+    where
+        Self: Iterator + Clone + Send + 'env,
+        Self::Item: Send + 'env,
+        Self: Sized,
+    {
+        ReadaheadSplit::new_scoped(scope, self, n, false)
+    }
+
+    /// Wrap the items in [`Traced`], tracking their provenance through the pipeline
+    ///
+    /// Opt-in, as it costs a timestamp per item. Use [`Traced::map`] in the
+    /// following stages to keep the provenance, and [`IteratorExt::stamp`] to record
+    /// when the items pass given points, e.g. to observe per-item latency end-to-end.
+    fn traced(self) -> Tracing<Self>
+    where
+        Self: Iterator,
+        Self: Sized,
+    {
+        Tracing::new(self)
+    }
+
+    /// Record the time [`Traced`] items pass this point of the pipeline as `name`
+    ///
+    /// See [`Traced::stamps`].
+    fn stamp<T>(self, name: &'static str) -> Stamp<Self>
+    where
+        Self: Iterator<Item = Traced<T>>,
+        Self: Sized,
+    {
+        Stamp::new(self, name)
+    }
+
+    /// Record the end-to-end latency of [`Traced`] items in `recorder`
+    ///
+    /// Use at the end of the pipeline, to measure the time from the items entering
+    /// it (see [`IteratorExt::traced`]) to being returned. See [`profile::LatencyRecorder`].
+    fn record_latency<T>(self, recorder: profile::LatencyRecorder) -> profile::RecordLatency<Self>
+    where
+        Self: Iterator<Item = Traced<T>>,
+        Self: Sized,
+    {
+        profile::RecordLatency::new(self, recorder)
+    }
+
+    /// Profile the time it takes downstream iterator step to consume the returned items.
+    ///
+    /// See [`ProfileEgress`] and [`profile::Profiler`].
+    fn profile_egress<P: profile::Profiler>(self, profiler: P) -> ProfileEgress<Self, P>
+    where
+        Self: Iterator,
+        Self: Sized,
+    {
+        ProfileEgress::new(self, profiler)
+    }
+
+    /// Profile the time it takes upstream iterator step to produce the returned items.
+    ///
+    /// See [`ProfileIngress`] and [`profile::Profiler`].
+    fn profile_ingress<P: profile::Profiler>(self, profiler: P) -> ProfileIngress<Self, P>
+    where
+        Self: Iterator,
+        Self: Sized,
+    {
+        ProfileIngress::new(self, profiler)
+    }
+
+    /// Profiled version of [`IteratorExt::readahead`]
+    ///
+    /// Literally `.profile_egress(tx_profiler).readahead(n).profile_ingress(rx_profiler)`
+    ///
+    /// See [`Profiler`] for more info.
+    fn readahead_profiled<TxP, RxP>(
+        self,
+        tx_profiler: TxP,
+        rx_profiler: RxP,
+    ) -> ProfileIngress<Readahead<ProfileEgress<Self, TxP>>, RxP>
+    where
+        Self: Iterator,
+        Self: Sized,
+        Self: Send + 'static,
+        Self::Item: Send + 'static,
+        TxP: profile::Profiler + Send + 'static,
+        RxP: profile::Profiler,
+    {
+        self.profile_egress(tx_profiler)
+            .readahead()
+            .profile_ingress(rx_profiler)
+    }
+
+    /// Like [`IteratorExt::readahead_profiled`], with the options set by `of`
+    ///
+    /// ```
+    /// use pariter::{IteratorExt as _, TotalTimeProfiler};
+    ///
+    /// (0..10)
+    ///     .readahead_profiled_custom(
+    ///         TotalTimeProfiler::periodically_millis(10_000, || eprintln!("Blocked on sending")),
+    ///         TotalTimeProfiler::periodically_millis(10_000, || eprintln!("Blocked on receiving")),
+    ///         |o| o.buffer_size(4),
+    ///     )
+    ///     .for_each(drop);
+    /// ```
+    fn readahead_profiled_custom<TxP, RxP, OF>(
+        self,
+        tx_profiler: TxP,
+        rx_profiler: RxP,
+        of: OF,
+    ) -> ProfileIngress<Readahead<ProfileEgress<Self, TxP>>, RxP>
+    where
+        Self: Iterator,
+        Self: Sized,
+        Self: Send + 'static,
+        Self::Item: Send + 'static,
+        TxP: profile::Profiler + Send + 'static,
+        RxP: profile::Profiler,
+        OF: FnOnce(
+            ReadaheadBuilder<ProfileEgress<Self, TxP>>,
+        ) -> ReadaheadBuilder<ProfileEgress<Self, TxP>>,
+    {
+        self.profile_egress(tx_profiler)
+            .readahead_custom(of)
+            .profile_ingress(rx_profiler)
+    }
+
+    /// Profiled version of [`IteratorExt::parallel_map`]
+    ///
+    /// Literally `.profile_egress(tx_profiler).parallel_map(f).profile_ingress(rx_profiler)`.
+    /// To profile the worker threads too, use [`IteratorExt::parallel_map_profiled_custom`]
+    /// with [`ParallelMapBuilder::profile_worker_ingress`] and
+    /// [`ParallelMapBuilder::profile_worker_egress`].
+    ///
+    /// See [`Profiler`] for more info.
+    fn parallel_map_profiled<TxP, RxP, F, O>(
+        self,
+        tx_profiler: TxP,
+        rx_profiler: RxP,
+        f: F,
+    ) -> ProfileIngress<ParallelMap<ProfileEgress<Self, TxP>, O>, RxP>
+    where
+        Self: Iterator,
+        Self: Sized,
+        Self::Item: Send + 'static,
+        TxP: profile::Profiler,
+        RxP: profile::Profiler,
+        F: 'static + Send + Clone,
+        F: FnMut(Self::Item) -> O,
+        O: Send + 'static,
+    {
+        self.profile_egress(tx_profiler)
+            .parallel_map(f)
+            .profile_ingress(rx_profiler)
+    }
+
+    /// See [`IteratorExt::parallel_map_profiled`]
+    fn parallel_map_profiled_custom<TxP, RxP, F, O, OF>(
+        self,
+        tx_profiler: TxP,
+        rx_profiler: RxP,
+        of: OF,
+        f: F,
+    ) -> ProfileIngress<ParallelMap<ProfileEgress<Self, TxP>, O>, RxP>
+    where
+        Self: Iterator,
+        Self: Sized,
+        Self::Item: Send + 'static,
+        TxP: profile::Profiler,
+        RxP: profile::Profiler,
+        F: 'static + Send + Clone,
+        F: FnMut(Self::Item) -> O,
+        O: Send + 'static,
+        OF: FnOnce(
+            ParallelMapBuilder<ProfileEgress<Self, TxP>>,
+        ) -> ParallelMapBuilder<ProfileEgress<Self, TxP>>,
+    {
+        self.profile_egress(tx_profiler)
+            .parallel_map_custom(of, f)
+            .profile_ingress(rx_profiler)
+    }
+
+    /// See [`IteratorExt::parallel_map_profiled`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_scoped_profiled<'env, 'scope, TxP, RxP, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        tx_profiler: TxP,
+        rx_profiler: RxP,
+        f: F,
+    ) -> ProfileIngress<ParallelMap<ProfileEgress<Self, TxP>, O>, RxP>
+    where
+        Self: Iterator,
+        Self: Sized,
+        Self::Item: Send + 'env,
+        TxP: profile::Profiler,
+        RxP: profile::Profiler,
+        F: 'env + Send + Clone,
+        F: FnMut(Self::Item) -> O,
+        O: Send + 'env,
+    {
+        self.profile_egress(tx_profiler)
+            .parallel_map_scoped(scope, f)
+            .profile_ingress(rx_profiler)
+    }
+
+    /// See [`IteratorExt::parallel_map_profiled`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    fn parallel_map_scoped_profiled_custom<'env, 'scope, TxP, RxP, F, O, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        tx_profiler: TxP,
+        rx_profiler: RxP,
+        of: OF,
+        f: F,
+    ) -> ProfileIngress<ParallelMap<ProfileEgress<Self, TxP>, O>, RxP>
+    where
+        Self: Iterator,
+        Self: Sized,
+        Self::Item: Send + 'env,
+        TxP: profile::Profiler,
+        RxP: profile::Profiler,
+        F: 'env + Send + Clone,
+        F: FnMut(Self::Item) -> O,
+        O: Send + 'env,
+        OF: FnOnce(
+            ParallelMapBuilder<ProfileEgress<Self, TxP>>,
+        ) -> ParallelMapBuilder<ProfileEgress<Self, TxP>>,
+    {
+        self.profile_egress(tx_profiler)
+            .parallel_map_scoped_custom(scope, of, f)
+            .profile_ingress(rx_profiler)
+    }
+
+    /// Profiled version of [`IteratorExt::readahead_scoped`]
+    ///
+    /// Literally `.profile_egress(tx_profiler).readahead_scoped(scope, n).profile_ingress(rx_profiler)`
+    ///
+    /// See [`Profiler`] for more info.
+    fn readahead_scoped_profiled<'env, 'scope, TxP, RxP>(
+        self,
+        scope: &'scope Scope<'env>,
+        tx_profiler: TxP,
+        rx_profiler: RxP,
+    ) -> ProfileIngress<Readahead<ProfileEgress<Self, TxP>>, RxP>
+    where
+        Self: Sized + Send,
+        Self: Iterator + 'scope + 'env,
+        Self::Item: Send + 'env + 'scope + Send,
+        TxP: profile::Profiler + Send + 'static,
+        RxP: profile::Profiler,
+    {
+        self.profile_egress(tx_profiler)
+            .readahead_scoped(scope)
+            .profile_ingress(rx_profiler)
+    }
+
+    /// See [`IteratorExt::readahead_profiled_custom`]
+    fn readahead_scoped_profiled_custom<'env, 'scope, TxP, RxP, OF>(
+        self,
+        scope: &'scope Scope<'env>,
+        tx_profiler: TxP,
+        rx_profiler: RxP,
+        of: OF,
+    ) -> ProfileIngress<Readahead<ProfileEgress<Self, TxP>>, RxP>
+    where
+        Self: Sized + Send,
+        Self: Iterator + 'scope + 'env,
+        Self::Item: Send + 'env + 'scope + Send,
+        TxP: profile::Profiler + Send + 'static,
+        RxP: profile::Profiler,
+        OF: FnOnce(
+            ReadaheadBuilder<ProfileEgress<Self, TxP>>,
+        ) -> ReadaheadBuilder<ProfileEgress<Self, TxP>>,
+    {
+        self.profile_egress(tx_profiler)
+            .readahead_scoped_custom(scope, of)
+            .profile_ingress(rx_profiler)
+    }
+}
+
+impl<I> IteratorExt for I where I: Iterator {}
+
+/// A new unique id of a stage, telling apart concurrent pipelines in diagnostics
+fn next_stage_id() -> u64 {
+    static NEXT_STAGE_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+    NEXT_STAGE_ID.fetch_add(1, SeqCst)
+}
+
+/// The route of `item`, see [`IteratorExt::parallel_route`]
+fn classify<T>(classifier: &mut impl FnMut(&T) -> usize, item: &T, num_routes: usize) -> usize {
+    let route = classifier(item);
+    assert!(
+        route < num_routes,
+        "route {} out of range, there are {} routes",
+        route,
+        num_routes
+    );
+    route
+}
+
+/// Split the results into the successes and the failures, see [`IteratorExt::partition_result_parallel`]
+fn partition_results<O, E>(
+    results: impl Iterator<Item = Result<O, (usize, E)>>,
+) -> (Vec<O>, Vec<(usize, E)>) {
+    let mut oks = vec![];
+    let mut errs = vec![];
+    for result in results {
+        match result {
+            Ok(o) => oks.push(o),
+            Err(e) => errs.push(e),
+        }
+    }
+    (oks, errs)
+}
+
+/// Can we spawn threads on this target
+///
+/// On targets without threads (like `wasm32-unknown-unknown`), the stages fall back
+/// to running sequentially on the consuming thread, so libraries using them keep
+/// working there. Except the scoped versions of [`ParallelMap`] and
+/// [`ParallelScanKeyed`], see [`assert_threads_supported`].
+fn threads_supported() -> bool {
+    !cfg!(all(target_arch = "wasm32", not(target_feature = "atomics")))
+}
+
+/// Panic with a useful message, instead of failing to spawn, on targets without threads
+///
+/// Scoped stages borrow their functions, which their iterators can't keep to call
+/// on the consuming thread the way the other stages do.
+fn assert_threads_supported(stage: &str) {
+    assert!(
+        threads_supported(),
+        "{} needs threads, which this target doesn't support; use the non-scoped version",
+        stage
+    );
+}
 
 struct DropIndicator {
     canceled: bool,
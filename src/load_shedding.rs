@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+
+/// Drops items at random while the workers are behind, see
+/// [`crate::ParallelMapConfig::shed_load`]
+pub(crate) struct LoadShedding {
+    // how long the workers must be behind before shedding starts
+    threshold: Duration,
+    // chance of dropping each item while shedding
+    probability: f64,
+    // since when the workers are behind, if they are
+    congested_since: Option<Instant>,
+    // state of the xorshift generator deciding what to drop
+    rng: u64,
+    // number of items dropped so far
+    shed: usize,
+}
+
+impl LoadShedding {
+    pub(crate) fn new(threshold: Duration, probability: f64, seed: u64) -> Self {
+        Self {
+            threshold,
+            probability,
+            congested_since: None,
+            // xorshift gets stuck at 0
+            rng: seed | 1,
+            shed: 0,
+        }
+    }
+
+    /// Should the next item be dropped, given whether the workers are `congested`, i.e.
+    /// the queue of items waiting for them is full
+    pub(crate) fn should_shed(&mut self, congested: bool) -> bool {
+        if !congested {
+            self.congested_since = None;
+            return false;
+        }
+        let since = *self.congested_since.get_or_insert_with(Instant::now);
+        if since.elapsed() < self.threshold || self.probability <= self.next_f64() {
+            return false;
+        }
+        self.shed += 1;
+        true
+    }
+
+    /// Number of items dropped so far
+    pub(crate) fn shed(&self) -> usize {
+        self.shed
+    }
+
+    /// A pseudo-random number in `[0, 1)`
+    fn next_f64(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,133 @@
+use crate::Readahead;
+
+use std::{cmp, fmt};
+
+/// Iterator over groups of items with equal keys in two key-sorted iterators
+///
+/// See [`crate::IteratorExt::merge_join`].
+pub struct MergeJoin<A, B, KA, KB, K>
+where
+    A: Iterator,
+    B: Iterator,
+{
+    left: Readahead<A>,
+    right: Readahead<B>,
+    // compute the keys of the items
+    left_key: KA,
+    right_key: KB,
+    // next items, already pulled, with their keys
+    left_next: Option<(K, A::Item)>,
+    right_next: Option<(K, B::Item)>,
+}
+
+impl<A, B, KA, KB, K> MergeJoin<A, B, KA, KB, K>
+where
+    A: Iterator + Send,
+    B: Iterator + Send,
+    A::Item: Send,
+    B::Item: Send,
+    KA: FnMut(&A::Item) -> K,
+    KB: FnMut(&B::Item) -> K,
+    K: Ord,
+{
+    pub(crate) fn new(
+        left: Readahead<A>,
+        right: Readahead<B>,
+        left_key: KA,
+        right_key: KB,
+    ) -> Self {
+        Self {
+            left,
+            right,
+            left_key,
+            right_key,
+            left_next: None,
+            right_next: None,
+        }
+    }
+
+    fn pull_left(&mut self) -> Option<(K, A::Item)> {
+        let item = self.left.next()?;
+        Some(((self.left_key)(&item), item))
+    }
+
+    fn pull_right(&mut self) -> Option<(K, B::Item)> {
+        let item = self.right.next()?;
+        Some(((self.right_key)(&item), item))
+    }
+}
+
+impl<A, B, KA, KB, K> fmt::Debug for MergeJoin<A, B, KA, KB, K>
+where
+    A: Iterator,
+    B: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MergeJoin")
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<A, B, KA, KB, K> Iterator for MergeJoin<A, B, KA, KB, K>
+where
+    A: Iterator + Send,
+    B: Iterator + Send,
+    A::Item: Send,
+    B::Item: Send,
+    KA: FnMut(&A::Item) -> K,
+    KB: FnMut(&B::Item) -> K,
+    K: Ord,
+{
+    type Item = (K, Vec<A::Item>, Vec<B::Item>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (left_k, left_item) = match self.left_next.take() {
+                Some(next) => next,
+                None => self.pull_left()?,
+            };
+            let (right_k, right_item) = match self.right_next.take() {
+                Some(next) => next,
+                None => match self.pull_right() {
+                    Some(next) => next,
+                    None => {
+                        self.left_next = Some((left_k, left_item));
+                        return None;
+                    }
+                },
+            };
+
+            match left_k.cmp(&right_k) {
+                cmp::Ordering::Less => {
+                    self.right_next = Some((right_k, right_item));
+                }
+                cmp::Ordering::Greater => {
+                    self.left_next = Some((left_k, left_item));
+                }
+                cmp::Ordering::Equal => {
+                    let mut left_group = vec![left_item];
+                    while let Some((k, item)) = self.pull_left() {
+                        if k != left_k {
+                            self.left_next = Some((k, item));
+                            break;
+                        }
+                        left_group.push(item);
+                    }
+
+                    let mut right_group = vec![right_item];
+                    while let Some((k, item)) = self.pull_right() {
+                        if k != right_k {
+                            self.right_next = Some((k, item));
+                            break;
+                        }
+                        right_group.push(item);
+                    }
+
+                    return Some((left_k, left_group, right_group));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{chunks::Chunks, ParallelMap, ParallelMapBuilder, ParallelMapConfig, Scope};
+
+use std::{fmt, vec};
+
+/// Default number of worker threads of [`ParallelBatchMap`]
+///
+/// Batches are meant to be offloaded to a GPU or vectorized code,
+/// so there's little point in having many threads waiting for them.
+const DEFAULT_THREADS: usize = 2;
+
+/// Like [`ParallelMap`], but maps whole batches of items at a time
+///
+/// See [`crate::IteratorExt::parallel_batch_map`].
+pub struct ParallelBatchMap<I, O>
+where
+    I: Iterator,
+{
+    // batches being mapped
+    batches: ParallelMap<Chunks<I>, Vec<O>>,
+    // the remaining outputs of the current batch
+    current: vec::IntoIter<O>,
+    // max number of items in each batch
+    batch_size: usize,
+}
+
+impl<I, O> fmt::Debug for ParallelBatchMap<I, O>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelBatchMap")
+            .field("batches", &self.batches)
+            .field("current", &self.current.len())
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
+
+impl<I, O> ParallelBatchMap<I, O>
+where
+    I: Iterator,
+    I::Item: Send,
+    O: Send,
+{
+    /// The builder of the batches, with `config` and a small pool by default
+    fn builder(
+        iter: I,
+        batch_size: usize,
+        config: ParallelMapConfig,
+    ) -> ParallelMapBuilder<Chunks<I>> {
+        let config = if config.num_threads.is_none() {
+            config.threads(DEFAULT_THREADS)
+        } else {
+            config
+        };
+        ParallelMapBuilder::new(Chunks::new(iter, batch_size)).config(&config)
+    }
+
+    pub(crate) fn new<F>(iter: I, batch_size: usize, config: ParallelMapConfig, mut f: F) -> Self
+    where
+        F: FnMut(Vec<I::Item>) -> Vec<O>,
+        F: 'static + Send + Clone,
+        I::Item: 'static,
+        O: 'static,
+    {
+        Self {
+            batches: Self::builder(iter, batch_size, config)
+                .with(move |batch| map_batch(&mut f, batch)),
+            current: Vec::new().into_iter(),
+            batch_size,
+        }
+    }
+
+    pub(crate) fn new_scoped<'env, 'scope, F>(
+        iter: I,
+        scope: &'scope Scope<'env>,
+        batch_size: usize,
+        config: ParallelMapConfig,
+        mut f: F,
+    ) -> Self
+    where
+        F: FnMut(Vec<I::Item>) -> Vec<O>,
+        F: 'env + Send + Clone,
+        I::Item: 'env,
+        O: 'env,
+    {
+        Self {
+            batches: Self::builder(iter, batch_size, config)
+                .with_scoped(scope, move |batch| map_batch(&mut f, batch)),
+            current: Vec::new().into_iter(),
+            batch_size,
+        }
+    }
+}
+
+/// Map a batch with `f`, making sure the outputs can be matched with the inputs
+fn map_batch<T, O>(f: &mut impl FnMut(Vec<T>) -> Vec<O>, batch: Vec<T>) -> Vec<O> {
+    let len = batch.len();
+    let outputs = f(batch);
+    assert_eq!(
+        outputs.len(),
+        len,
+        "batch map returned a different number of outputs than inputs"
+    );
+    outputs
+}
+
+impl<I, O> Iterator for ParallelBatchMap<I, O>
+where
+    I: Iterator,
+    I::Item: Send,
+    O: Send,
+{
+    type Item = O;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.current.next() {
+                return Some(item);
+            }
+            self.current = self.batches.next()?.into_iter();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.batches.size_hint();
+        let current = self.current.len();
+        (
+            // every batch has at least one item
+            current.saturating_add(lower),
+            upper
+                .and_then(|upper| upper.checked_mul(self.batch_size))
+                .and_then(|upper| upper.checked_add(current)),
+        )
+    }
+}
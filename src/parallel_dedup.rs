@@ -0,0 +1,61 @@
+use crate::ParallelMap;
+
+use std::{cmp, fmt};
+
+/// Removes consecutive items with the same key, computed on worker threads
+///
+/// See [`crate::IteratorExt::parallel_dedup_by`].
+pub struct ParallelDedupBy<I, K>
+where
+    I: Iterator,
+{
+    // the iterator we wrapped, returning items with their keys
+    iter: ParallelMap<I, (K, I::Item)>,
+    // key of the last item returned
+    last: Option<K>,
+}
+
+impl<I, K> ParallelDedupBy<I, K>
+where
+    I: Iterator,
+{
+    pub(crate) fn new(iter: ParallelMap<I, (K, I::Item)>) -> Self {
+        Self { iter, last: None }
+    }
+}
+
+impl<I, K> fmt::Debug for ParallelDedupBy<I, K>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelDedupBy")
+            .field("iter", &self.iter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, K> Iterator for ParallelDedupBy<I, K>
+where
+    I: Iterator,
+    I::Item: Send,
+    K: PartialEq + Send,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, item) = self.iter.next()?;
+            if self.last.as_ref() != Some(&key) {
+                self.last = Some(key);
+                return Some(item);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // all the items could be duplicates of the first one
+        let (lower, upper) = self.iter.size_hint();
+        (cmp::min(lower, 1), upper)
+    }
+}
@@ -1,9 +1,24 @@
-use crate::{ParallelMap, ParallelMapBuilder, Scope};
+use crate::{
+    BuildError, Describe, ParallelMap, ParallelMapBuilder, PipelineNode, Scope, StallReport,
+};
+
+use std::{fmt, time};
 
 pub struct ParallelFilterBuilder<I>(ParallelMapBuilder<I>)
 where
     I: Iterator;
 
+impl<I> fmt::Debug for ParallelFilterBuilder<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ParallelFilterBuilder")
+            .field(&self.0)
+            .finish()
+    }
+}
+
 impl<I> ParallelFilterBuilder<I>
 where
     I: Iterator,
@@ -19,6 +34,240 @@ where
         Self(self.0.buffer_size(num))
     }
 
+    /// See [`ParallelMapBuilder::limit`]
+    pub fn limit(self, num: usize) -> Self {
+        Self(self.0.limit(num))
+    }
+
+    /// See [`ParallelMapBuilder::name`]
+    pub fn name(self, name: impl Into<String>) -> Self {
+        Self(self.0.name(name))
+    }
+
+    /// See [`ParallelMapBuilder::stall_warning`]
+    pub fn stall_warning<F>(self, period: time::Duration, callback: F) -> Self
+    where
+        F: Fn(&StallReport<'_>) + Send + Sync + 'static,
+    {
+        Self(self.0.stall_warning(period, callback))
+    }
+
+    /// See [`ParallelMapBuilder::debug_items`]
+    pub fn debug_items(self) -> Self
+    where
+        I::Item: fmt::Debug,
+    {
+        Self(self.0.debug_items())
+    }
+
+    /// See [`ParallelMapBuilder::format_items`]
+    pub fn format_items<D>(self, format: D) -> Self
+    where
+        D: Fn(&I::Item) -> String + Send + Sync + 'static,
+    {
+        Self(self.0.format_items(format))
+    }
+
+    /// See [`ParallelMapBuilder::on_worker_start`]
+    pub fn on_worker_start<H>(self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        Self(self.0.on_worker_start(hook))
+    }
+
+    /// See [`ParallelMapBuilder::on_worker_stop`]
+    pub fn on_worker_stop<H>(self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        Self(self.0.on_worker_stop(hook))
+    }
+
+    /// See [`ParallelMapBuilder::between_items`]
+    pub fn between_items<H>(self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        Self(self.0.between_items(hook))
+    }
+
+    /// See [`ParallelMapBuilder::cpu_quota`]
+    pub fn cpu_quota(self, cores: f64) -> Self {
+        Self(self.0.cpu_quota(cores))
+    }
+
+    /// See [`ParallelMapBuilder::profile_worker_ingress`]
+    pub fn profile_worker_ingress<M, P>(self, make: M) -> Self
+    where
+        M: Fn(usize) -> P + Send + Sync + 'static,
+        P: crate::Profiler + 'static,
+    {
+        Self(self.0.profile_worker_ingress(make))
+    }
+
+    /// See [`ParallelMapBuilder::profile_worker_egress`]
+    pub fn profile_worker_egress<M, P>(self, make: M) -> Self
+    where
+        M: Fn(usize) -> P + Send + Sync + 'static,
+        P: crate::Profiler + 'static,
+    {
+        Self(self.0.profile_worker_egress(make))
+    }
+
+    /// See [`ParallelMapBuilder::sample_occupancy`]
+    pub fn sample_occupancy(
+        self,
+        interval: time::Duration,
+        recorder: crate::profile::OccupancyRecorder,
+    ) -> Self {
+        Self(self.0.sample_occupancy(interval, recorder))
+    }
+
+    /// See [`ParallelMapBuilder::size_of`]
+    pub fn size_of<S>(self, size_of: S) -> Self
+    where
+        S: Fn(&I::Item) -> usize + Send + Sync + 'static,
+    {
+        Self(self.0.size_of(size_of))
+    }
+
+    /// See [`ParallelMapBuilder::memory_cap`]
+    pub fn memory_cap(self, bytes: usize) -> Self {
+        Self(self.0.memory_cap(bytes))
+    }
+
+    /// See [`ParallelMapBuilder::report_stragglers`]
+    pub fn report_stragglers<C>(self, n: usize, callback: C) -> Self
+    where
+        C: Fn(&[crate::Straggler]) + Send + Sync + 'static,
+    {
+        Self(self.0.report_stragglers(n, callback))
+    }
+
+    /// See [`ParallelMapBuilder::report_drop_stats`]
+    pub fn report_drop_stats<C>(self, callback: C) -> Self
+    where
+        C: Fn(&crate::DropStats) + Send + Sync + 'static,
+    {
+        Self(self.0.report_drop_stats(callback))
+    }
+
+    /// See [`ParallelMapBuilder::drop_policy`]
+    pub fn drop_policy(self, policy: crate::DropPolicy) -> Self {
+        Self(self.0.drop_policy(policy))
+    }
+
+    /// See [`ParallelMapBuilder::unordered`]
+    pub fn unordered(self) -> Self {
+        Self(self.0.unordered())
+    }
+
+    /// See [`ParallelMapBuilder::shed_load`]
+    pub fn shed_load(self, threshold: time::Duration, probability: f64) -> Self {
+        Self(self.0.shed_load(threshold, probability))
+    }
+
+    /// See [`ParallelMapBuilder::optimize_for`]
+    pub fn optimize_for(self, workload: crate::Workload) -> Self {
+        Self(self.0.optimize_for(workload))
+    }
+
+    /// See [`ParallelMapBuilder::spawner`]
+    pub fn spawner<S>(self, spawner: S) -> Self
+    where
+        S: crate::Spawner + 'static,
+    {
+        Self(self.0.spawner(spawner))
+    }
+
+    /// See [`ParallelMapBuilder::memory_meter`]
+    pub fn memory_meter(self, meter: crate::profile::MemoryMeter) -> Self {
+        Self(self.0.memory_meter(meter))
+    }
+
+    /// See [`ParallelMapBuilder::config`]
+    pub fn config(self, config: &crate::ParallelMapConfig) -> Self {
+        Self(self.0.config(config))
+    }
+
+    /// See [`ParallelMapBuilder::panic_poll_interval`]
+    pub fn panic_poll_interval(self, interval: time::Duration) -> Self {
+        Self(self.0.panic_poll_interval(interval))
+    }
+
+    /// See [`ParallelMapBuilder::priority`]
+    #[cfg(feature = "priority")]
+    pub fn priority(self, priority: crate::ThreadPriority) -> Self {
+        Self(self.0.priority(priority))
+    }
+
+    /// See [`ParallelMapBuilder::on_slow_item`]
+    pub fn on_slow_item<C>(self, threshold: time::Duration, callback: C) -> Self
+    where
+        I::Item: fmt::Debug,
+        C: Fn(time::Duration, &str) + Send + Sync + 'static,
+    {
+        Self(self.0.on_slow_item(threshold, callback))
+    }
+
+    /// See [`ParallelMapBuilder::max_item_age`]
+    pub fn max_item_age<T>(self, max_age: time::Duration, timestamp: T) -> Self
+    where
+        T: Fn(&I::Item) -> std::time::SystemTime + Send + Sync + 'static,
+    {
+        Self(self.0.max_item_age(max_age, timestamp))
+    }
+
+    /// See [`ParallelMapBuilder::on_expired`]
+    pub fn on_expired<C>(self, callback: C) -> Self
+    where
+        C: Fn(I::Item) + Send + Sync + 'static,
+    {
+        Self(self.0.on_expired(callback))
+    }
+
+    /// See [`ParallelMapBuilder::on_slow_item_with`]
+    pub fn on_slow_item_with<D, C>(self, threshold: time::Duration, format: D, callback: C) -> Self
+    where
+        D: Fn(&I::Item) -> String + Send + Sync + 'static,
+        C: Fn(time::Duration, &str) + Send + Sync + 'static,
+    {
+        Self(self.0.on_slow_item_with(threshold, format, callback))
+    }
+
+    /// See [`ParallelMapBuilder::try_with`]
+    pub fn try_with<F>(self, f: F) -> Result<ParallelFilter<I>, BuildError>
+    where
+        I: Iterator,
+        F: 'static + Send + Clone,
+        I::Item: Send + 'static,
+        F: FnMut(&I::Item) -> bool,
+    {
+        self.0.validate()?;
+        Ok(self.with(f))
+    }
+
+    /// See [`ParallelMapBuilder::try_with_scoped`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`crate::ParallelMapBuilder::with_scoped`].
+    pub fn try_with_scoped<'env, 'scope, F>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> Result<ParallelFilter<I>, BuildError>
+    where
+        I: Iterator,
+        F: 'env + Send + Clone,
+        I::Item: Send + 'env,
+        F: FnMut(&I::Item) -> bool + 'env + Send,
+    {
+        self.0.validate()?;
+        Ok(self.with_scoped(scope, f))
+    }
+
     pub fn with<F>(self, mut f: F) -> ParallelFilter<I>
     where
         I: Iterator,
@@ -31,6 +280,11 @@ where
         }
     }
 
+    /// See [`ParallelFilterBuilder::with`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`crate::ParallelMapBuilder::with_scoped`].
     pub fn with_scoped<'env, 'scope, F>(
         self,
         scope: &'scope Scope<'env>,
@@ -51,6 +305,9 @@ where
 }
 
 /// Like [`std::iter::Filter`] but multi-threaded
+///
+/// The items kept are returned in the order of the inner iterator, as with
+/// [`crate::ParallelMap`], unless [`ParallelFilterBuilder::unordered`] is set.
 pub struct ParallelFilter<I>
 where
     I: Iterator,
@@ -59,6 +316,46 @@ where
     iter: ParallelMap<I, Option<I::Item>>,
 }
 
+impl<I> fmt::Debug for ParallelFilter<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelFilter")
+            .field("iter", &self.iter)
+            .finish()
+    }
+}
+
+impl<I> ParallelFilter<I>
+where
+    I: Iterator,
+    I::Item: Send,
+{
+    /// Is [`Iterator::size_hint`] exact
+    ///
+    /// If so, exactly that many items will be returned (unless a worker panics).
+    /// Unlike [`crate::ParallelMap`], the number of items returned is not known
+    /// upfront, so this is the case only when the outcome of all the remaining items is
+    /// already known, e.g. when nothing is left.
+    pub fn is_size_hint_exact(&self) -> bool {
+        let (lower, upper) = self.size_hint();
+        upper == Some(lower)
+    }
+}
+
+impl<I> Describe for ParallelFilter<I>
+where
+    I: Iterator + Describe,
+{
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        self.iter.describe_into(nodes);
+        if let Some(node) = nodes.last_mut() {
+            node.kind = "parallel_filter";
+        }
+    }
+}
+
 impl<I> Iterator for ParallelFilter<I>
 where
     I: Iterator,
@@ -77,6 +374,17 @@ where
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.iter.size_hint()
+        // items already processed are known to be kept, or not
+        let (kept, dropped) =
+            self.iter
+                .buffered()
+                .fold((0, 0), |(kept, dropped), item| match item {
+                    Some(_) => (kept + 1, dropped),
+                    None => (kept, dropped + 1),
+                });
+        // counts the items in flight, which might all be kept
+        let upper = self.iter.size_hint().1;
+
+        (kept, upper.map(|upper| upper.saturating_sub(dropped)))
     }
 }
@@ -0,0 +1,99 @@
+use crate::ParallelMapBuilder;
+
+use std::{iter, panic};
+
+/// Process the items of `iter` with the `workers`, each on its own thread, and return their results
+///
+/// Every item goes to whichever worker is free first, without any index, so the
+/// results only make sense if the order of the items doesn't matter.
+fn partials<I, A, W>(mut iter: I, workers: Vec<W>) -> Vec<A>
+where
+    I: Iterator,
+    I::Item: Send,
+    A: Send,
+    W: FnOnce(&mut dyn Iterator<Item = I::Item>) -> A + Send,
+{
+    if !crate::threads_supported() {
+        let mut workers = workers.into_iter();
+        return workers
+            .next()
+            .map(|worker| worker(&mut iter))
+            .into_iter()
+            .chain(workers.map(|worker| worker(&mut iter::empty())))
+            .collect();
+    }
+
+    let (tx, rx) = crossbeam_channel::bounded(workers.len() * 2);
+    crate::scope(|scope| {
+        let handles: Vec<_> = workers
+            .into_iter()
+            .map(|worker| {
+                let rx = rx.clone();
+                scope.spawn(move |_| worker(&mut rx.iter()))
+            })
+            .collect();
+        drop(rx);
+
+        for item in iter {
+            if tx.send(item).is_err() {
+                // all the workers are gone
+                break;
+            }
+        }
+        drop(tx);
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload))
+            })
+            .collect()
+    })
+    .unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+/// Number of worker threads of the parallel terminals
+fn num_threads<I: Iterator>() -> usize {
+    ParallelMapBuilder::<I>::num_threads(None)
+}
+
+/// See [`crate::IteratorExt::parallel_fold`]
+pub(crate) fn parallel_fold<I, A, ID, F, C>(iter: I, identity: ID, fold: F, combine: C) -> A
+where
+    I: Iterator,
+    I::Item: Send,
+    A: Send,
+    ID: Fn() -> A,
+    F: FnMut(A, I::Item) -> A + Clone + Send,
+    C: FnMut(A, A) -> A,
+{
+    let workers = (0..num_threads::<I>())
+        .map(|_| {
+            let (init, fold) = (identity(), fold.clone());
+            move |items: &mut dyn Iterator<Item = I::Item>| items.fold(init, fold)
+        })
+        .collect();
+    partials(iter, workers)
+        .into_iter()
+        .reduce(combine)
+        .unwrap_or_else(identity)
+}
+
+/// See [`crate::IteratorExt::parallel_sum_by`]
+pub(crate) fn parallel_sum_by<I, S, F>(iter: I, f: F) -> S
+where
+    I: Iterator,
+    I::Item: Send,
+    S: iter::Sum + Send,
+    F: FnMut(I::Item) -> S + Clone + Send,
+{
+    let workers = (0..num_threads::<I>())
+        .map(|_| {
+            let f = f.clone();
+            move |items: &mut dyn Iterator<Item = I::Item>| items.map(f).sum::<S>()
+        })
+        .collect();
+    partials(iter, workers).into_iter().sum()
+}
@@ -1,18 +1,47 @@
 use crossbeam_channel::{Receiver, Sender};
 
-use super::{DropIndicator, Scope};
+#[cfg(feature = "priority")]
+use super::ThreadPriority;
+use super::{
+    arena::WorkerArenas,
+    drop_stats::{DropStatsCallback, DropTracker},
+    inline::{InlineMap, InlineState, NoThreads},
+    load_shedding::LoadShedding,
+    pipeline::StageContext,
+    profile::{MemoryMeter, OccupancyRecorder, Profiler, Sampler},
+    reorder::ReorderBuffer,
+    spawn::{self, Spawner},
+    straggler::{StragglerCallback, Stragglers},
+    watchdog::{Progress, StallCallback, StallReport, Watchdog},
+    workload, Arena, ArenaItem, BuildError, Describe, DropIndicator, DropPolicy, DropStats,
+    PipelineError, PipelineNode, Recycler, Scope, Straggler, Workload,
+};
 
 use std::{
+    any::Any,
     cmp,
+    collections::VecDeque,
+    fmt, mem,
+    panic::{self, AssertUnwindSafe},
     sync::{
         atomic::{AtomicBool, Ordering::SeqCst},
-        Arc,
+        Arc, Mutex,
     },
+    thread, time,
 };
 
+/// How often to check for worker panics while waiting for items, by default
+pub(crate) const DEFAULT_PANIC_POLL_INTERVAL: time::Duration = time::Duration::from_micros(100);
+
+/// Shortest period of the watchdog of `stall_warning`
+const MIN_STALL_PERIOD: time::Duration = time::Duration::from_millis(1);
+
 struct ParallelMapInner<I, O> {
     tx: Option<crossbeam_channel::Sender<(usize, I)>>,
-    rx: crossbeam_channel::Receiver<(usize, O)>,
+    // `None` for the items skipped by the workers, see `max_item_age`
+    rx: crossbeam_channel::Receiver<(usize, Option<O>)>,
+    // items sent by the workers when they are done, if enabled
+    tail_rx: Option<crossbeam_channel::Receiver<O>>,
 }
 
 pub struct ParallelMapBuilder<I>
@@ -21,10 +50,491 @@ where
 {
     // the iterator we wrapped
     iter: I,
+    // options not depending on the type of the items
+    config: ParallelMapConfig,
+    // rendering of the items for diagnostics
+    item_format: Option<ItemFormat<I::Item>>,
+    // slow item reporting
+    slow_item: Option<(time::Duration, SlowItemCallback)>,
+    // measuring the memory held by the items
+    size_of: Option<SizeOf<I::Item>>,
+    // max age of the items, and their timestamps
+    max_age: Option<(time::Duration, Timestamp<I::Item>)>,
+    // dead letters, see `on_expired`
+    on_expired: Option<ExpiredCallback<I::Item>>,
+}
+
+/// Options of [`ParallelMapBuilder`] not depending on the iterator
+///
+/// Can be defined once, and used for many pipelines, with
+/// [`crate::IteratorExt::parallel_map_cfg`] or [`ParallelMapBuilder::config`].
+#[derive(Clone, Default)]
+pub struct ParallelMapConfig {
     // number of worker threads to use
-    num_threads: Option<usize>,
+    pub(crate) num_threads: Option<usize>,
     // max number of items in flight
     buffer_size: Option<usize>,
+    // max number of items to pull from `iter`
+    limit: Option<usize>,
+    // name of the stage, for diagnostics
+    name: Option<String>,
+    // watchdog period and callback
+    stall_warning: Option<(time::Duration, StallCallback)>,
+    // hooks called in each worker thread
+    on_worker_start: Option<WorkerHook>,
+    on_worker_stop: Option<WorkerHook>,
+    between_items: Option<WorkerHook>,
+    // max CPU cores worth of time used by all the workers
+    cpu_quota: Option<f64>,
+    // scheduling priority of the worker threads
+    #[cfg(feature = "priority")]
+    priority: Option<ThreadPriority>,
+    // how often to check for worker panics while waiting for items
+    panic_poll_interval: Option<time::Duration>,
+    // profilers of the worker threads receiving and sending items
+    worker_ingress_profiler: Option<WorkerProfilerFactory>,
+    worker_egress_profiler: Option<WorkerProfilerFactory>,
+    // occupancy sampling interval and recorder
+    sample_occupancy: Option<(time::Duration, OccupancyRecorder)>,
+    // max bytes held by the stage, and the meter reporting them
+    memory_cap: Option<usize>,
+    memory_meter: Option<MemoryMeter>,
+    // starts the worker threads
+    spawner: Option<Arc<dyn Spawner>>,
+    // number of the slowest items to report at the end, and the callback
+    stragglers: Option<(usize, StragglerCallback)>,
+    // reporting of the discarded work when done
+    drop_stats: Option<DropStatsCallback>,
+    // what to do with the work in flight when dropped
+    drop_policy: DropPolicy,
+    // what the stage is tuned for
+    workload: Workload,
+    // return the results as they come
+    unordered: bool,
+    // how long the workers can be behind before shedding, and the chance of dropping each item
+    shed_load: Option<(time::Duration, f64)>,
+    // the first invalid value set, reported by `try_with`
+    invalid: Option<BuildError>,
+}
+
+impl fmt::Debug for ParallelMapConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("ParallelMapConfig");
+        d.field("num_threads", &self.num_threads)
+            .field("buffer_size", &self.buffer_size)
+            .field("limit", &self.limit)
+            .field("name", &self.name)
+            .field(
+                "stall_warning",
+                &self.stall_warning.as_ref().map(|(period, _)| period),
+            )
+            .field("on_worker_start", &self.on_worker_start.is_some())
+            .field("on_worker_stop", &self.on_worker_stop.is_some())
+            .field("between_items", &self.between_items.is_some())
+            .field("cpu_quota", &self.cpu_quota)
+            .field("panic_poll_interval", &self.panic_poll_interval)
+            .field(
+                "worker_ingress_profiler",
+                &self.worker_ingress_profiler.is_some(),
+            )
+            .field(
+                "worker_egress_profiler",
+                &self.worker_egress_profiler.is_some(),
+            )
+            .field(
+                "sample_occupancy",
+                &self.sample_occupancy.as_ref().map(|(interval, _)| interval),
+            )
+            .field("memory_cap", &self.memory_cap)
+            .field("memory_meter", &self.memory_meter)
+            .field("spawner", &self.spawner.is_some())
+            .field("stragglers", &self.stragglers.as_ref().map(|(n, _)| n))
+            .field("drop_stats", &self.drop_stats.is_some())
+            .field("drop_policy", &self.drop_policy)
+            .field("workload", &self.workload)
+            .field("unordered", &self.unordered)
+            .field("shed_load", &self.shed_load)
+            .field("invalid", &self.invalid);
+        #[cfg(feature = "priority")]
+        d.field("priority", &self.priority);
+        d.finish_non_exhaustive()
+    }
+}
+
+impl ParallelMapConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember `error`, unless an invalid value was set already
+    fn invalid(self, error: BuildError) -> Self {
+        Self {
+            invalid: self.invalid.or(Some(error)),
+            ..self
+        }
+    }
+
+    pub fn threads(self, num: usize) -> Self {
+        Self {
+            num_threads: Some(num),
+            ..self
+        }
+    }
+
+    pub fn buffer_size(self, num: usize) -> Self {
+        Self {
+            buffer_size: Some(num),
+            ..self
+        }
+    }
+
+    /// Never pull more than `num` items from the inner iterator
+    ///
+    /// Like calling `.take(num)` on the inner iterator. Useful when the consumer
+    /// is known to take only a prefix, as no work will be wasted on items past it.
+    pub fn limit(self, num: usize) -> Self {
+        Self {
+            limit: Some(num),
+            ..self
+        }
+    }
+
+    /// Name the stage
+    ///
+    /// Used in diagnostics, like [`StallReport`] and `Debug` output.
+    pub fn name(self, name: impl Into<String>) -> Self {
+        Self {
+            name: Some(name.into()),
+            ..self
+        }
+    }
+
+    /// Call `callback` if no item moved through the stage for `period`
+    ///
+    /// A watchdog thread will periodically check if any items were sent to,
+    /// completed by, or returned from the worker threads. If the stage is stalled,
+    /// `callback` will be called with a [`StallReport`] (and again after every
+    /// further `period` the stage remains stalled).
+    ///
+    /// Useful to detect otherwise silent, motionless pipelines, e.g. due to hung network calls.
+    /// Note that a stage not being pulled by the downstream consumer will be reported
+    /// too.
+    ///
+    /// Periods shorter than 1ms are raised to 1ms, as the watchdog wakes up a couple of
+    /// times per period. A zero `period` is reported by [`ParallelMapBuilder::try_with`].
+    pub fn stall_warning<F>(self, period: time::Duration, callback: F) -> Self
+    where
+        F: Fn(&StallReport<'_>) + Send + Sync + 'static,
+    {
+        let config = if period.is_zero() {
+            self.invalid(BuildError::ZeroStallPeriod)
+        } else {
+            self
+        };
+        Self {
+            stall_warning: Some((cmp::max(period, MIN_STALL_PERIOD), Arc::new(callback))),
+            ..config
+        }
+    }
+
+    /// Call `hook` in each worker thread, before it processes any items
+    ///
+    /// The `hook` gets the index of the worker. Useful for setting up thread-local
+    /// state (allocator arenas, RNG seeds, etc.).
+    pub fn on_worker_start<H>(self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        Self {
+            on_worker_start: Some(Arc::new(hook)),
+            ..self
+        }
+    }
+
+    /// Call `hook` in each worker thread, after it processed all the items
+    ///
+    /// The `hook` gets the index of the worker. Useful for tearing down or flushing
+    /// thread-local state. Not called if the worker thread panicked.
+    pub fn on_worker_stop<H>(self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        Self {
+            on_worker_stop: Some(Arc::new(hook)),
+            ..self
+        }
+    }
+
+    /// Call `hook` in each worker thread, after every item it processed
+    ///
+    /// The `hook` gets the index of the worker, and runs before the worker waits for
+    /// the next item. Useful for checking a pause flag, yielding to a cooperative
+    /// scheduler, or ticking a watchdog, without doing it in the map function.
+    pub fn between_items<H>(self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        Self {
+            between_items: Some(Arc::new(hook)),
+            ..self
+        }
+    }
+
+    /// Limit the processing time of all the workers to `cores` CPU cores worth of time
+    ///
+    /// Each worker sleeps after processing an item, for long enough to keep its
+    /// share of `cores` (e.g. `0.5` for half a core in total). Useful for background
+    /// batch processing coexisting with latency-sensitive services on the same host.
+    /// The time spent processing is measured as wall-clock time.
+    ///
+    /// A quota that is not positive is ignored, or reported by
+    /// [`ParallelMapBuilder::try_with`].
+    pub fn cpu_quota(self, cores: f64) -> Self {
+        if cores.is_nan() || cores <= 0.0 {
+            return self.invalid(BuildError::NonPositiveCpuQuota);
+        }
+        Self {
+            cpu_quota: Some(cores),
+            ..self
+        }
+    }
+
+    /// Run the worker threads with scheduling `priority`
+    ///
+    /// Useful to keep background processing from competing with
+    /// more important threads.
+    #[cfg(feature = "priority")]
+    pub fn priority(self, priority: ThreadPriority) -> Self {
+        Self {
+            priority: Some(priority),
+            ..self
+        }
+    }
+
+    /// Check for worker panics every `interval` while waiting for items
+    ///
+    /// Defaults to 100µs. Longer intervals mean fewer wakeups of the consumer thread
+    /// (noticeable in power profiles of low-power devices), shorter ones mean
+    /// faster detection of worker panics.
+    ///
+    /// A zero `interval` is ignored, or reported by [`ParallelMapBuilder::try_with`].
+    pub fn panic_poll_interval(self, interval: time::Duration) -> Self {
+        if interval.is_zero() {
+            return self.invalid(BuildError::ZeroPanicPollInterval);
+        }
+        Self {
+            panic_poll_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Profile the time each worker thread spends waiting for items to process
+    ///
+    /// `make` is called in each worker thread with the index of the worker, to create its
+    /// [`Profiler`]. A lot of time spent waiting means the workers are starved
+    /// by the upstream iterator.
+    ///
+    /// See [`crate::IteratorExt::profile_ingress`].
+    pub fn profile_worker_ingress<M, P>(self, make: M) -> Self
+    where
+        M: Fn(usize) -> P + Send + Sync + 'static,
+        P: Profiler + 'static,
+    {
+        Self {
+            worker_ingress_profiler: Some(Arc::new(move |worker_i| {
+                Box::new(make(worker_i)) as Box<dyn Profiler>
+            })),
+            ..self
+        }
+    }
+
+    /// Profile the time each worker thread spends blocked on sending processed items
+    ///
+    /// `make` is called in each worker thread with the index of the worker, to create its
+    /// [`Profiler`]. A lot of time spent blocked means the workers are saturated
+    /// and the downstream consumer can't keep up.
+    ///
+    /// See [`crate::IteratorExt::profile_egress`].
+    pub fn profile_worker_egress<M, P>(self, make: M) -> Self
+    where
+        M: Fn(usize) -> P + Send + Sync + 'static,
+        P: Profiler + 'static,
+    {
+        Self {
+            worker_egress_profiler: Some(Arc::new(move |worker_i| {
+                Box::new(make(worker_i)) as Box<dyn Profiler>
+            })),
+            ..self
+        }
+    }
+
+    /// Record the occupancy of the input and output channels every `interval` in `recorder`
+    ///
+    /// A background thread samples the number of items waiting for the workers and for
+    /// the consumer, while the stage is running. Useful for plotting utilization
+    /// and diagnosing bufferbloat.
+    ///
+    /// A zero `interval` disables the sampling, or is reported by
+    /// [`ParallelMapBuilder::try_with`].
+    pub fn sample_occupancy(self, interval: time::Duration, recorder: OccupancyRecorder) -> Self {
+        if interval.is_zero() {
+            return self.invalid(BuildError::ZeroSamplingInterval);
+        }
+        Self {
+            sample_occupancy: Some((interval, recorder)),
+            ..self
+        }
+    }
+
+    /// Stop pulling items from the inner iterator while the items in flight
+    /// take at least `bytes`
+    ///
+    /// Items are measured with [`ParallelMapBuilder::size_of`], from the moment they are
+    /// pulled from the inner iterator, until their results are returned in order,
+    /// so this bounds both the channels and the reorder buffer. At least one item
+    /// is always let in, so a single item bigger than `bytes` can't deadlock the stage.
+    ///
+    /// Ignored without `size_of`, see [`ParallelMapBuilder::try_with`].
+    pub fn memory_cap(self, bytes: usize) -> Self {
+        Self {
+            memory_cap: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Call `callback` with the `n` slowest items, slowest first, once all the items were returned
+    ///
+    /// The workers time every item anyway, and only take a lock for the items slower
+    /// than the `n` slowest seen so far, so the overhead is negligible. Items are
+    /// rendered if [`ParallelMapBuilder::format_items`] or
+    /// [`ParallelMapBuilder::debug_items`] was used.
+    ///
+    /// Useful to tell which items made a long job long.
+    pub fn report_stragglers<C>(self, n: usize, callback: C) -> Self
+    where
+        C: Fn(&[Straggler]) + Send + Sync + 'static,
+    {
+        Self {
+            stragglers: Some((n, Arc::new(callback))),
+            ..self
+        }
+    }
+
+    /// Call `callback` with the [`DropStats`] of the stage, once it's done
+    ///
+    /// The stats are final only after all the workers are done, so the callback is called
+    /// by whichever finishes last: the stage being dropped, or the last worker thread.
+    ///
+    /// Useful to detect pipelines abandoned mid-way, throwing away work done.
+    pub fn report_drop_stats<C>(self, callback: C) -> Self
+    where
+        C: Fn(&DropStats) + Send + Sync + 'static,
+    {
+        Self {
+            drop_stats: Some(Arc::new(callback)),
+            ..self
+        }
+    }
+
+    /// What to do with the items in flight, when the stage is dropped before it's done
+    ///
+    /// [`DropPolicy::Abort`] by default. Use [`DropPolicy::Finish`] if `f` has side
+    /// effects that must not be left half-done, and [`ParallelMap::on_orphaned`] to
+    /// handle the results nobody will pull.
+    pub fn drop_policy(self, policy: DropPolicy) -> Self {
+        Self {
+            drop_policy: policy,
+            ..self
+        }
+    }
+
+    /// Tune the stage for the `workload`
+    ///
+    /// See [`Workload::TinyItems`] for closures too cheap to be worth running in parallel
+    /// otherwise.
+    pub fn optimize_for(self, workload: Workload) -> Self {
+        Self { workload, ..self }
+    }
+
+    /// Return the results as soon as they are done, instead of in the order of the inputs
+    ///
+    /// A slow item doesn't hold up the ones after it. Use [`ParallelMap::with_input_indices`]
+    /// to tell which input each result is for. The memory accounted with
+    /// [`ParallelMapConfig::memory_cap`] is released in the order of the inputs, so it's
+    /// only approximate.
+    pub fn unordered(self) -> Self {
+        Self {
+            unordered: true,
+            ..self
+        }
+    }
+
+    /// Drop items at random, once the workers were behind for longer than `threshold`
+    ///
+    /// The workers are behind when they are all busy, and the rest of the buffer is
+    /// taken by items waiting for them. Every item pulled then is dropped, without
+    /// being processed, with `probability`, until they catch up.
+    /// Trades completeness for liveness, e.g. in telemetry pipelines fed from live
+    /// sources. Dropped items are counted by [`ParallelMap::shed_count`], and not
+    /// returned, so the lower bound of the size hint is 0.
+    ///
+    /// A `probability` that is not between 0 and 1 disables the shedding, or is reported
+    /// by [`ParallelMapBuilder::try_with`].
+    pub fn shed_load(self, threshold: time::Duration, probability: f64) -> Self {
+        if !(0.0..=1.0).contains(&probability) {
+            return self.invalid(BuildError::InvalidShedProbability);
+        }
+        Self {
+            shed_load: Some((threshold, probability)),
+            ..self
+        }
+    }
+
+    /// Start the worker threads with `spawner`, instead of [`std::thread::spawn`]
+    ///
+    /// Only used by the non-scoped stages, scoped ones always spawn their workers
+    /// in the [`Scope`].
+    pub fn spawner<S>(self, spawner: S) -> Self
+    where
+        S: Spawner + 'static,
+    {
+        Self {
+            spawner: Some(Arc::new(spawner)),
+            ..self
+        }
+    }
+
+    /// Report the bytes held by the stage in `meter`
+    ///
+    /// Requires [`ParallelMapBuilder::size_of`] to measure anything.
+    pub fn memory_meter(self, meter: MemoryMeter) -> Self {
+        Self {
+            memory_meter: Some(meter),
+            ..self
+        }
+    }
+}
+
+impl<I> fmt::Debug for ParallelMapBuilder<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelMapBuilder")
+            .field("config", &self.config)
+            .field("item_format", &self.item_format.is_some())
+            .field(
+                "slow_item",
+                &self.slow_item.as_ref().map(|(threshold, _)| threshold),
+            )
+            .field("size_of", &self.size_of.is_some())
+            .field(
+                "max_age",
+                &self.max_age.as_ref().map(|(max_age, _)| max_age),
+            )
+            .field("on_expired", &self.on_expired.is_some())
+            .finish_non_exhaustive()
+    }
 }
 
 impl<I> ParallelMapBuilder<I>
@@ -34,134 +544,1327 @@ where
     pub fn new(iter: I) -> Self {
         Self {
             iter,
-            num_threads: None,
-            buffer_size: None,
+            config: ParallelMapConfig::default(),
+            item_format: None,
+            slow_item: None,
+            size_of: None,
+            max_age: None,
+            on_expired: None,
         }
     }
 
+    /// See [`ParallelMapConfig::threads`]
     pub fn threads(self, num: usize) -> Self {
         Self {
-            num_threads: Some(num),
+            config: self.config.threads(num),
             ..self
         }
     }
+
+    /// See [`ParallelMapConfig::buffer_size`]
     pub fn buffer_size(self, num: usize) -> Self {
         Self {
-            buffer_size: Some(num),
+            config: self.config.buffer_size(num),
             ..self
         }
     }
 
-    fn num_threads<T: Into<Option<usize>>>(num_threads: T) -> usize {
-        let mut num = num_threads.into().unwrap_or(0);
-        if num == 0 {
-            num = num_cpus::get_physical();
+    /// See [`ParallelMapConfig::limit`]
+    pub fn limit(self, num: usize) -> Self {
+        Self {
+            config: self.config.limit(num),
+            ..self
         }
-        if num == 0 {
-            num = 1
+    }
+
+    /// See [`ParallelMapConfig::name`]
+    pub fn name(self, name: impl Into<String>) -> Self {
+        Self {
+            config: self.config.name(name),
+            ..self
         }
-        num
     }
 
-    fn with_common<O>(
-        self,
-    ) -> (
-        ParallelMap<I, O>,
-        Receiver<(usize, I::Item)>,
-        Sender<(usize, O)>,
-    )
+    /// See [`ParallelMapConfig::stall_warning`]
+    pub fn stall_warning<F>(self, period: time::Duration, callback: F) -> Self
     where
-        I: Iterator,
+        F: Fn(&StallReport<'_>) + Send + Sync + 'static,
     {
-        let num_threads = Self::num_threads(self.num_threads);
-        let buffer_size = cmp::max(1, self.buffer_size.unwrap_or(num_threads * 2));
+        Self {
+            config: self.config.stall_warning(period, callback),
+            ..self
+        }
+    }
 
-        // Note: we have enought capacity on both ends to hold all items
-        // in progress, though the actual amount of items in flight is controlled
-        // by `pump_tx`.
-        let (in_tx, in_rx) = crossbeam_channel::bounded(buffer_size);
-        let (out_tx, out_rx) = crossbeam_channel::bounded(buffer_size);
+    /// See [`ParallelMapConfig::on_worker_start`]
+    pub fn on_worker_start<H>(self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        Self {
+            config: self.config.on_worker_start(hook),
+            ..self
+        }
+    }
 
-        (
-            ParallelMap {
-                iter: self.iter,
-                iter_done: false,
-                worker_panicked: Arc::new(AtomicBool::new(false)),
-                num_threads,
-                buffer_size,
-                out_of_order: Vec::new(),
-                next_tx_i: 0,
-                next_rx_i: 0,
-                inner: Some(ParallelMapInner {
-                    tx: Some(in_tx),
-                    rx: out_rx,
-                }),
-            },
-            in_rx,
-            out_tx,
-        )
+    /// See [`ParallelMapConfig::on_worker_stop`]
+    pub fn on_worker_stop<H>(self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        Self {
+            config: self.config.on_worker_stop(hook),
+            ..self
+        }
     }
 
-    pub fn with<F, O>(self, f: F) -> ParallelMap<I, O>
+    /// See [`ParallelMapConfig::between_items`]
+    pub fn between_items<H>(self, hook: H) -> Self
     where
-        I: Iterator,
-        F: 'static + Send + Clone,
-        O: Send + 'static,
-        I::Item: Send + 'static,
-        F: FnMut(I::Item) -> O,
+        H: Fn(usize) + Send + Sync + 'static,
     {
-        let (ret, in_rx, out_tx) = self.with_common();
+        Self {
+            config: self.config.between_items(hook),
+            ..self
+        }
+    }
 
-        for _ in 0..ret.num_threads {
-            let in_rx = in_rx.clone();
-            let out_tx = out_tx.clone();
-            let mut f = f.clone();
-            let drop_indicator = DropIndicator::new(ret.worker_panicked.clone());
+    /// See [`ParallelMapConfig::cpu_quota`]
+    pub fn cpu_quota(self, cores: f64) -> Self {
+        Self {
+            config: self.config.cpu_quota(cores),
+            ..self
+        }
+    }
 
-            std::thread::spawn(move || {
-                for (i, item) in in_rx.into_iter() {
-                    // we ignore send failures, if the receiver is gone
-                    // we just throw the work away
-                    let _ = out_tx.send((i, (f)(item)));
-                }
-                drop_indicator.cancel();
-            });
+    /// See [`ParallelMapConfig::priority`]
+    #[cfg(feature = "priority")]
+    pub fn priority(self, priority: ThreadPriority) -> Self {
+        Self {
+            config: self.config.priority(priority),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::panic_poll_interval`]
+    pub fn panic_poll_interval(self, interval: time::Duration) -> Self {
+        Self {
+            config: self.config.panic_poll_interval(interval),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::profile_worker_ingress`]
+    pub fn profile_worker_ingress<M, P>(self, make: M) -> Self
+    where
+        M: Fn(usize) -> P + Send + Sync + 'static,
+        P: Profiler + 'static,
+    {
+        Self {
+            config: self.config.profile_worker_ingress(make),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::profile_worker_egress`]
+    pub fn profile_worker_egress<M, P>(self, make: M) -> Self
+    where
+        M: Fn(usize) -> P + Send + Sync + 'static,
+        P: Profiler + 'static,
+    {
+        Self {
+            config: self.config.profile_worker_egress(make),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::sample_occupancy`]
+    pub fn sample_occupancy(self, interval: time::Duration, recorder: OccupancyRecorder) -> Self {
+        Self {
+            config: self.config.sample_occupancy(interval, recorder),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::memory_cap`]
+    pub fn memory_cap(self, bytes: usize) -> Self {
+        Self {
+            config: self.config.memory_cap(bytes),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::report_stragglers`]
+    pub fn report_stragglers<C>(self, n: usize, callback: C) -> Self
+    where
+        C: Fn(&[Straggler]) + Send + Sync + 'static,
+    {
+        Self {
+            config: self.config.report_stragglers(n, callback),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::report_drop_stats`]
+    pub fn report_drop_stats<C>(self, callback: C) -> Self
+    where
+        C: Fn(&DropStats) + Send + Sync + 'static,
+    {
+        Self {
+            config: self.config.report_drop_stats(callback),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::drop_policy`]
+    pub fn drop_policy(self, policy: DropPolicy) -> Self {
+        Self {
+            config: self.config.drop_policy(policy),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::optimize_for`]
+    pub fn optimize_for(self, workload: Workload) -> Self {
+        Self {
+            config: self.config.optimize_for(workload),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::unordered`]
+    pub fn unordered(self) -> Self {
+        Self {
+            config: self.config.unordered(),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::shed_load`]
+    pub fn shed_load(self, threshold: time::Duration, probability: f64) -> Self {
+        Self {
+            config: self.config.shed_load(threshold, probability),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::spawner`]
+    pub fn spawner<S>(self, spawner: S) -> Self
+    where
+        S: Spawner + 'static,
+    {
+        Self {
+            config: self.config.spawner(spawner),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapConfig::memory_meter`]
+    pub fn memory_meter(self, meter: MemoryMeter) -> Self {
+        Self {
+            config: self.config.memory_meter(meter),
+            ..self
+        }
+    }
+
+    /// Measure the memory held by each item with `size_of`, in bytes
+    ///
+    /// Needed by [`ParallelMapBuilder::memory_cap`] and [`ParallelMapBuilder::memory_meter`].
+    /// The function is called once per item, on the thread pulling the items,
+    /// so it should be cheap, e.g. `|buf: &Vec<u8>| buf.len()`.
+    pub fn size_of<S>(self, size_of: S) -> Self
+    where
+        S: Fn(&I::Item) -> usize + Send + Sync + 'static,
+    {
+        Self {
+            size_of: Some(Arc::new(size_of)),
+            ..self
+        }
+    }
+
+    /// Use all the options of `config`, replacing the ones set so far
+    pub fn config(self, config: &ParallelMapConfig) -> Self {
+        Self {
+            config: config.clone(),
+            ..self
+        }
+    }
+
+    /// Render the input items with `Debug` for diagnostics
+    ///
+    /// With it, panic messages of the worker threads will include the item
+    /// that was being processed.
+    ///
+    /// Note: the item has to be rendered before being passed to the map function,
+    /// which is not free. See [`ParallelMapBuilder::format_items`] to control it.
+    pub fn debug_items(self) -> Self
+    where
+        I::Item: fmt::Debug,
+    {
+        self.format_items(|item| format!("{:?}", item))
+    }
+
+    /// Like [`ParallelMapBuilder::debug_items`], but with a custom `format` function
+    pub fn format_items<D>(self, format: D) -> Self
+    where
+        D: Fn(&I::Item) -> String + Send + Sync + 'static,
+    {
+        Self {
+            item_format: Some(Arc::new(format)),
+            ..self
+        }
+    }
+
+    /// Call `callback` for every item that took at least `threshold` to map
+    ///
+    /// The `callback` will be called on the worker thread, with the time it took
+    /// and the rendering of the input item, so it's possible to tell *which*
+    /// items are slow to process, and not just that the stage is slow.
+    ///
+    /// Items are rendered with `Debug`, unless [`ParallelMapBuilder::format_items`]
+    /// was used.
+    pub fn on_slow_item<C>(self, threshold: time::Duration, callback: C) -> Self
+    where
+        I::Item: fmt::Debug,
+        C: Fn(time::Duration, &str) + Send + Sync + 'static,
+    {
+        let builder = if self.item_format.is_none() {
+            self.debug_items()
+        } else {
+            self
+        };
+
+        Self {
+            slow_item: Some((threshold, Arc::new(callback))),
+            ..builder
+        }
+    }
+
+    /// Like [`ParallelMapBuilder::on_slow_item`], but with a custom `format` function
+    /// used to render the input items
+    ///
+    /// See [`ParallelMapBuilder::format_items`].
+    pub fn on_slow_item_with<D, C>(self, threshold: time::Duration, format: D, callback: C) -> Self
+    where
+        D: Fn(&I::Item) -> String + Send + Sync + 'static,
+        C: Fn(time::Duration, &str) + Send + Sync + 'static,
+    {
+        Self {
+            slow_item: Some((threshold, Arc::new(callback))),
+            ..self.format_items(format)
+        }
+    }
+
+    /// Skip the items older than `max_age` when a worker gets to them
+    ///
+    /// The age is measured from the `timestamp` of the item, e.g. when it was enqueued.
+    /// For real-time pipelines, fed from queues, that have to catch up when they fall behind.
+    /// Skipped items are not returned, so the lower bound of the size hint is 0. See
+    /// [`ParallelMapBuilder::on_expired`] to handle them.
+    pub fn max_item_age<T>(self, max_age: time::Duration, timestamp: T) -> Self
+    where
+        T: Fn(&I::Item) -> time::SystemTime + Send + Sync + 'static,
+    {
+        Self {
+            max_age: Some((max_age, Arc::new(timestamp))),
+            ..self
+        }
+    }
+
+    /// Pass the items skipped by [`ParallelMapBuilder::max_item_age`] to `callback`
+    ///
+    /// Called on the worker threads, e.g. to count the skipped items, or send them to a
+    /// dead-letter channel.
+    pub fn on_expired<C>(self, callback: C) -> Self
+    where
+        C: Fn(I::Item) + Send + Sync + 'static,
+    {
+        Self {
+            on_expired: Some(Arc::new(callback)),
+            ..self
+        }
+    }
+
+    /// Size hint of the stage, before it's started
+    pub(crate) fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+
+        match self.config.limit {
+            Some(left) => (
+                cmp::min(lower, left),
+                Some(upper.map_or(left, |upper| cmp::min(upper, left))),
+            ),
+            None => (lower, upper),
+        }
+    }
+
+    pub(crate) fn num_threads<T: Into<Option<usize>>>(num_threads: T) -> usize {
+        let mut num = num_threads.into().unwrap_or(0);
+        if num == 0 {
+            num = num_cpus::get_physical();
+        }
+        if num == 0 {
+            num = 1
+        }
+        num
+    }
+
+    /// Set up everything but the worker threads
+    ///
+    /// `tail` enables the channel for items sent by workers after all the ordered items.
+    fn with_common<O>(self, tail: bool) -> (ParallelMap<I, O>, Worker<I::Item, O>)
+    where
+        I: Iterator,
+    {
+        let num_threads = Self::num_threads(self.config.num_threads);
+        let workload = self.config.workload;
+        let buffer_size = cmp::max(
+            1,
+            self.config
+                .buffer_size
+                .unwrap_or(num_threads * workload.buffer_per_thread()),
+        );
+
+        // Note: we have enought capacity on both ends to hold all items
+        // in progress, though the actual amount of items in flight is controlled
+        // by `pump_tx`. Except when shedding load: then the input channel only fills
+        // up when all the workers are busy, and the items queued for them are
+        // the rest of the buffer.
+        let in_capacity = if self.config.shed_load.is_some() {
+            cmp::max(1, buffer_size.saturating_sub(num_threads))
+        } else {
+            buffer_size
+        };
+        let (in_tx, in_rx) = crossbeam_channel::bounded(in_capacity);
+        let (out_tx, out_rx) = crossbeam_channel::bounded(buffer_size);
+        // Note: unbounded, as nothing will be receiving tail items until
+        // all ordered items are done.
+        let (tail_tx, tail_rx) = if tail {
+            let (tx, rx) = crossbeam_channel::unbounded();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+
+        let worker_panicked = Arc::new(AtomicBool::new(false));
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let stage_context = StageContext::current();
+        let id = crate::next_stage_id();
+        let label = format!(
+            "{}#{}",
+            self.config.name.as_deref().unwrap_or("parallel_map"),
+            id
+        );
+        let worker_panic = PanicSlot::default();
+        let progress = (self.config.stall_warning.is_some()
+            || self.config.sample_occupancy.is_some())
+        .then(|| Arc::new(Progress::default()));
+        let name = self.config.name;
+        let watchdog = self.config.stall_warning.map(|(period, callback)| {
+            Watchdog::spawn(
+                name.clone(),
+                id,
+                progress.clone().expect("progress set"),
+                period,
+                callback,
+            )
+        });
+
+        let sampler = self.config.sample_occupancy.map(|(interval, recorder)| {
+            Sampler::spawn(progress.clone().expect("progress set"), interval, recorder)
+        });
+
+        let stragglers = self
+            .config
+            .stragglers
+            .map(|(n, callback)| Arc::new(Stragglers::new(n, callback)));
+
+        let drop_tracker = self
+            .config
+            .drop_stats
+            .map(|callback| Arc::new(DropTracker::new(callback)));
+
+        let (memory_cap, memory_meter) = (self.config.memory_cap, self.config.memory_meter);
+        let memory = self.size_of.map(|size_of| MemoryAccounting {
+            size_of,
+            cap: memory_cap,
+            meter: memory_meter.unwrap_or_default(),
+            sizes: VecDeque::new(),
+        });
+
+        (
+            ParallelMap {
+                iter: self.iter,
+                iter_done: false,
+                worker_panicked: worker_panicked.clone(),
+                worker_panic: worker_panic.clone(),
+                send_error: None,
+                panic_poll_interval: self
+                    .config
+                    .panic_poll_interval
+                    .unwrap_or(DEFAULT_PANIC_POLL_INTERVAL),
+                num_threads,
+                buffer_size,
+                limit_left: self.config.limit,
+                memory,
+                stragglers: stragglers.clone(),
+                drop_tracker: drop_tracker.clone(),
+                drop_policy: self.config.drop_policy,
+                spin: workload.spin(),
+                unordered: self.config.unordered,
+                may_skip: self.max_age.is_some() || self.config.shed_load.is_some(),
+                load_shedding: self
+                    .config
+                    .shed_load
+                    .map(|(threshold, probability)| LoadShedding::new(threshold, probability, id)),
+                tail_returned: 0,
+                abandoned: abandoned.clone(),
+                id,
+                on_orphaned: None,
+                name,
+                progress: progress.clone(),
+                watchdog,
+                sampler,
+                out_of_order: ReorderBuffer::with_capacity(buffer_size),
+                inline: None,
+                next_tx_i: 0,
+                next_rx_i: 0,
+                inner: Some(ParallelMapInner {
+                    tx: Some(in_tx),
+                    rx: out_rx,
+                    tail_rx,
+                }),
+            },
+            Worker {
+                in_rx,
+                out_tx,
+                tail_tx,
+                worker_panicked,
+                worker_panic,
+                progress,
+                item_format: self.item_format,
+                max_age: self.max_age,
+                on_expired: self.on_expired,
+                slow_item: self.slow_item,
+                stragglers,
+                drop_tracker,
+                abandoned,
+                spin: workload.spin(),
+                stage_context,
+                label,
+                on_worker_start: self.config.on_worker_start,
+                on_worker_stop: self.config.on_worker_stop,
+                between_items: self.config.between_items,
+                // no need to sleep if every worker can use a whole core
+                duty_cycle: self
+                    .config
+                    .cpu_quota
+                    .map(|cores| cores / num_threads as f64)
+                    .filter(|duty_cycle| *duty_cycle < 1.0),
+                #[cfg(feature = "priority")]
+                priority: self.config.priority,
+                ingress_profiler: self.config.worker_ingress_profiler,
+                egress_profiler: self.config.worker_egress_profiler,
+            },
+        )
+    }
+
+    /// Check the configuration for values that would be silently adjusted,
+    /// or combinations of options that make no sense
+    pub(crate) fn validate(&self) -> Result<(), BuildError> {
+        if let Some(error) = self.config.invalid.as_ref() {
+            return Err(error.clone());
+        }
+        if self.config.buffer_size == Some(0) {
+            return Err(BuildError::ZeroBufferSize);
+        }
+        if let Some(buffer_size) = self.config.buffer_size {
+            let threads = Self::num_threads(self.config.num_threads);
+            if buffer_size < threads {
+                return Err(BuildError::BufferSmallerThanThreads {
+                    buffer_size,
+                    threads,
+                });
+            }
+        }
+        if self.config.memory_cap.is_some() && self.size_of.is_none() {
+            return Err(BuildError::MemoryCapWithoutSizeOf);
+        }
+        Ok(())
+    }
+
+    /// Like [`ParallelMapBuilder::with`], but returns an error on invalid configuration,
+    /// instead of adjusting it
+    pub fn try_with<F, O>(self, f: F) -> Result<ParallelMap<I, O>, BuildError>
+    where
+        I: Iterator,
+        F: 'static + Send + Clone,
+        O: Send + 'static,
+        I::Item: Send + 'static,
+        F: FnMut(I::Item) -> O,
+    {
+        self.validate()?;
+        Ok(self.with(f))
+    }
+
+    /// Like [`ParallelMapBuilder::with_scoped`], but returns an error on invalid configuration,
+    /// instead of adjusting it
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    pub fn try_with_scoped<'env, 'scope, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> Result<ParallelMap<I, O>, BuildError>
+    where
+        I: Iterator,
+        F: 'env + Send + Clone,
+        O: Send + 'env,
+        I::Item: Send + 'env,
+        F: FnMut(I::Item) -> O,
+    {
+        self.validate()?;
+        Ok(self.with_scoped(scope, f))
+    }
+
+    pub fn with<F, O>(self, mut f: F) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        F: 'static + Send + Clone,
+        O: Send + 'static,
+        I::Item: Send + 'static,
+        F: FnMut(I::Item) -> O,
+    {
+        self.with_enumerate(move |_i, item| (f)(item))
+    }
+
+    /// Like [`ParallelMapBuilder::with`], but `f` can borrow from the environment of `scope`
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads (like `wasm32-unknown-unknown`). The non-scoped
+    /// versions call `f` on the consuming thread there instead, but the returned
+    /// iterator can't keep `f`, which borrows from `'env`, to do that.
+    pub fn with_scoped<'env, 'scope, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        mut f: F,
+    ) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        F: 'env + Send + Clone,
+        O: Send + 'env,
+        I::Item: Send + 'env,
+        F: FnMut(I::Item) -> O,
+    {
+        self.with_enumerate_scoped(scope, move |_i, item| (f)(item))
+    }
+
+    /// Like [`ParallelMapBuilder::with`], but `f` gets an object taken from `recycler` too
+    ///
+    /// Useful when the outputs are big reusable buffers: the consumer returns
+    /// them to the `recycler` once done with them, and `f` fills them again,
+    /// instead of allocating new ones.
+    pub fn with_recycler<F, O, T>(self, recycler: Recycler<T>, mut f: F) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        F: 'static + Send + Clone,
+        O: Send + 'static,
+        T: Send + 'static,
+        I::Item: Send + 'static,
+        F: FnMut(I::Item, T) -> O,
+    {
+        self.with(move |item| (f)(item, recycler.take()))
+    }
+
+    /// Like [`ParallelMapBuilder::with_scoped`], but `f` gets an object taken from `recycler` too
+    ///
+    /// See [`ParallelMapBuilder::with_recycler`].
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    pub fn with_recycler_scoped<'env, 'scope, F, O, T>(
+        self,
+        scope: &'scope Scope<'env>,
+        recycler: Recycler<T>,
+        mut f: F,
+    ) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        F: 'env + Send + Clone,
+        O: Send + 'env,
+        T: Send + 'env,
+        I::Item: Send + 'env,
+        F: FnMut(I::Item, T) -> O,
+    {
+        self.with_scoped(scope, move |item| (f)(item, recycler.take()))
+    }
+
+    /// Like [`ParallelMapBuilder::with`], but `f` allocates the outputs from a per-worker [`Arena`]
+    ///
+    /// Each worker creates its arenas with `make` (which gets the index of the worker).
+    /// After `epoch_items` items, the worker moves on to another arena, and the ones
+    /// all outputs of which ([`ArenaItem`]s) were dropped by the consumer are reset
+    /// and reused. New arenas are only made when the consumer holds on to the outputs
+    /// of all the existing ones.
+    ///
+    /// An `epoch_items` of 0 is raised to 1, see [`ParallelMapBuilder::try_with_arena`].
+    pub fn with_arena<A, M, F, O>(
+        self,
+        epoch_items: usize,
+        make: M,
+        mut f: F,
+    ) -> ParallelMap<I, ArenaItem<O>>
+    where
+        I: Iterator,
+        I::Item: Send + 'static,
+        A: Arena + 'static,
+        M: 'static + Send + Clone,
+        M: FnMut(usize) -> A,
+        F: 'static + Send + Clone,
+        F: FnMut(&mut A, I::Item) -> O,
+        O: Send + 'static,
+    {
+        let epoch_items = cmp::max(epoch_items, 1);
+        self.with_state(
+            move |worker_i| WorkerArenas::new(worker_i, make, epoch_items),
+            move |arenas, item| arenas.alloc(|arena| (f)(arena, item)),
+            |_arenas| None,
+        )
+    }
+
+    /// Like [`ParallelMapBuilder::with_scoped`], but `f` allocates the outputs from a per-worker [`Arena`]
+    ///
+    /// See [`ParallelMapBuilder::with_arena`].
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    pub fn with_arena_scoped<'env, 'scope, A, M, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        epoch_items: usize,
+        make: M,
+        mut f: F,
+    ) -> ParallelMap<I, ArenaItem<O>>
+    where
+        I: Iterator,
+        I::Item: Send + 'env,
+        A: Arena,
+        M: 'env + Send + Clone,
+        M: FnMut(usize) -> A,
+        F: 'env + Send + Clone,
+        F: FnMut(&mut A, I::Item) -> O,
+        O: Send + 'env,
+    {
+        let epoch_items = cmp::max(epoch_items, 1);
+        self.with_state_scoped(
+            scope,
+            move |worker_i| WorkerArenas::new(worker_i, make, epoch_items),
+            move |arenas, item| arenas.alloc(|arena| (f)(arena, item)),
+            |_arenas| None,
+        )
+    }
+
+    /// Like [`ParallelMapBuilder::with_arena`], but returns an error on invalid configuration,
+    /// instead of adjusting it
+    pub fn try_with_arena<A, M, F, O>(
+        self,
+        epoch_items: usize,
+        make: M,
+        f: F,
+    ) -> Result<ParallelMap<I, ArenaItem<O>>, BuildError>
+    where
+        I: Iterator,
+        I::Item: Send + 'static,
+        A: Arena + 'static,
+        M: 'static + Send + Clone,
+        M: FnMut(usize) -> A,
+        F: 'static + Send + Clone,
+        F: FnMut(&mut A, I::Item) -> O,
+        O: Send + 'static,
+    {
+        if epoch_items == 0 {
+            return Err(BuildError::ZeroEpoch);
+        }
+        self.validate()?;
+        Ok(self.with_arena(epoch_items, make, f))
+    }
+
+    /// Like [`ParallelMapBuilder::with_arena_scoped`], but returns an error on invalid
+    /// configuration, instead of adjusting it
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    pub fn try_with_arena_scoped<'env, 'scope, A, M, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        epoch_items: usize,
+        make: M,
+        f: F,
+    ) -> Result<ParallelMap<I, ArenaItem<O>>, BuildError>
+    where
+        I: Iterator,
+        I::Item: Send + 'env,
+        A: Arena,
+        M: 'env + Send + Clone,
+        M: FnMut(usize) -> A,
+        F: 'env + Send + Clone,
+        F: FnMut(&mut A, I::Item) -> O,
+        O: Send + 'env,
+    {
+        if epoch_items == 0 {
+            return Err(BuildError::ZeroEpoch);
+        }
+        self.validate()?;
+        Ok(self.with_arena_scoped(scope, epoch_items, make, f))
+    }
+
+    /// Like [`ParallelMapBuilder::with`], but `f` gets the index of each item too
+    ///
+    /// The index is the position of the item in the inner iterator.
+    pub fn with_enumerate<F, O>(self, f: F) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        F: 'static + Send + Clone,
+        O: Send + 'static,
+        I::Item: Send + 'static,
+        F: FnMut(usize, I::Item) -> O,
+    {
+        if !crate::threads_supported() {
+            return self.with_enumerate_inline(f);
+        }
+
+        let spawner = self.config.spawner.clone();
+        let (ret, worker) = self.with_common(false);
+
+        for worker_i in 0..ret.num_threads {
+            let worker = worker.clone();
+            let f = f.clone();
+
+            spawn::spawn(
+                spawner.as_deref(),
+                worker.thread_name(worker_i),
+                move || worker.run(worker_i, f),
+            );
+        }
+
+        ret
+    }
+
+    /// Don't spawn any workers, and call `f` on the consuming thread when items are needed
+    ///
+    /// The fallback for targets without threads. Worker hooks and profilers are
+    /// not called.
+    pub(crate) fn with_enumerate_inline<F, O>(self, f: F) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        F: 'static + Send,
+        O: Send + 'static,
+        I::Item: Send + 'static,
+        F: FnMut(usize, I::Item) -> O,
+    {
+        self.with_inline(f, false)
+    }
+
+    /// Like [`ParallelMapBuilder::with_enumerate_inline`], for any [`InlineMap`]
+    ///
+    /// `tail` tells if `map` returns items after the mapped ones. Neither the watchdog
+    /// of [`ParallelMapConfig::stall_warning`] nor the sampler of
+    /// [`ParallelMapConfig::sample_occupancy`] are started, as they need threads too.
+    pub(crate) fn with_inline<M, O>(mut self, map: M, tail: bool) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        M: InlineMap<I::Item, O> + Send + 'static,
+    {
+        self.config.stall_warning = None;
+        self.config.sample_occupancy = None;
+        let (mut ret, _worker) = self.with_common(tail);
+        ret.inline = Some(Box::new(map));
+        ret
+    }
+
+    /// Like [`ParallelMapBuilder::with_scoped`], but `f` gets the index of each item too
+    ///
+    /// See [`ParallelMapBuilder::with_enumerate`].
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    pub fn with_enumerate_scoped<'env, 'scope, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        f: F,
+    ) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        F: 'env + Send + Clone,
+        O: Send + 'env,
+        I::Item: Send + 'env,
+        F: FnMut(usize, I::Item) -> O,
+    {
+        crate::assert_threads_supported("scoped parallel_map");
+        let (ret, worker) = self.with_common(false);
+
+        for worker_i in 0..ret.num_threads {
+            let worker = worker.clone();
+            let f = f.clone();
+
+            scope
+                .builder()
+                .name(worker.thread_name(worker_i))
+                .spawn(move |_scope| worker.run(worker_i, f))
+                .expect("failed to spawn worker thread");
+        }
+
+        ret
+    }
+
+    /// Like [`ParallelMapBuilder::with`], but each worker thread has its own state
+    ///
+    /// Each worker thread creates its state with `init` (which gets the index of the
+    /// worker), passes it to `f` with every item it processes, and when there are no
+    /// more items, calls `finish` with it. The items returned by `finish` are returned
+    /// after all the ordered items, in no particular order.
+    ///
+    /// Useful for combiner-style processing, e.g. aggregating per-thread results.
+    ///
+    /// On targets without threads, there is a single "worker", on the consuming thread.
+    pub fn with_state<S, INIT, F, FIN, R, O>(
+        self,
+        init: INIT,
+        f: F,
+        finish: FIN,
+    ) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        I::Item: Send + 'static,
+        INIT: 'static + Send + Clone,
+        INIT: FnOnce(usize) -> S,
+        F: 'static + Send + Clone,
+        F: FnMut(&mut S, I::Item) -> O,
+        FIN: 'static + Send + Clone,
+        FIN: FnOnce(S) -> R,
+        S: 'static,
+        R: IntoIterator<Item = O> + 'static,
+        O: Send + 'static,
+    {
+        if !crate::threads_supported() {
+            return self.with_inline(NoThreads::new(InlineState::new(init, f, finish)), true);
+        }
+
+        let spawner = self.config.spawner.clone();
+        let (ret, worker) = self.with_common(true);
+
+        for worker_i in 0..ret.num_threads {
+            let worker = worker.clone();
+            let init = init.clone();
+            let mut f = f.clone();
+            let finish = finish.clone();
+
+            spawn::spawn(
+                spawner.as_deref(),
+                worker.thread_name(worker_i),
+                move || {
+                    worker.run_stateful(
+                        worker_i,
+                        init(worker_i),
+                        move |state, _i, item| (f)(state, item),
+                        finish,
+                    )
+                },
+            );
+        }
+
+        ret
+    }
+
+    /// Like [`ParallelMapBuilder::with_scoped`], but each worker thread has its own state
+    ///
+    /// See [`ParallelMapBuilder::with_state`].
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    pub fn with_state_scoped<'env, 'scope, S, INIT, F, FIN, R, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        init: INIT,
+        f: F,
+        finish: FIN,
+    ) -> ParallelMap<I, O>
+    where
+        I: Iterator,
+        I::Item: Send + 'env,
+        INIT: 'env + Send + Clone,
+        INIT: FnOnce(usize) -> S,
+        F: 'env + Send + Clone,
+        F: FnMut(&mut S, I::Item) -> O,
+        FIN: 'env + Send + Clone,
+        FIN: FnOnce(S) -> R,
+        R: IntoIterator<Item = O>,
+        O: Send + 'env,
+    {
+        crate::assert_threads_supported("scoped parallel_map");
+        let (ret, worker) = self.with_common(true);
+
+        for worker_i in 0..ret.num_threads {
+            let worker = worker.clone();
+            let init = init.clone();
+            let mut f = f.clone();
+            let finish = finish.clone();
+
+            scope
+                .builder()
+                .name(worker.thread_name(worker_i))
+                .spawn(move |_scope| {
+                    worker.run_stateful(
+                        worker_i,
+                        init(worker_i),
+                        move |state, _i, item| (f)(state, item),
+                        finish,
+                    )
+                })
+                .expect("failed to spawn worker thread");
+        }
+
+        ret
+    }
+}
+
+/// Function rendering items, see [`ParallelMapBuilder::format_items`]
+pub(crate) type ItemFormat<T> = Arc<dyn Fn(&T) -> String + Send + Sync>;
+
+/// See [`ParallelMapBuilder::on_worker_start`]
+type WorkerHook = Arc<dyn Fn(usize) + Send + Sync>;
+
+/// Callback of [`ParallelMapBuilder::on_slow_item`]
+type SlowItemCallback = Arc<dyn Fn(time::Duration, &str) + Send + Sync>;
+
+/// Handles the results not pulled, see [`ParallelMap::on_orphaned`]
+type OrphanCallback<O> = Box<dyn FnMut(O) + Send>;
+
+/// See [`ParallelMapBuilder::profile_worker_ingress`]
+type WorkerProfilerFactory = Arc<dyn Fn(usize) -> Box<dyn Profiler> + Send + Sync>;
+
+/// See [`ParallelMapBuilder::with_enumerate_inline`]
+type InlineBox<T, O> = Box<dyn InlineMap<T, O> + Send>;
+
+/// See [`ParallelMapBuilder::size_of`]
+type SizeOf<T> = Arc<dyn Fn(&T) -> usize + Send + Sync>;
+
+/// See [`ParallelMapBuilder::max_item_age`]
+type Timestamp<T> = Arc<dyn Fn(&T) -> time::SystemTime + Send + Sync>;
+
+/// See [`ParallelMapBuilder::on_expired`]
+type ExpiredCallback<T> = Arc<dyn Fn(T) + Send + Sync>;
+
+/// Bytes held by the items in flight, see [`ParallelMapBuilder::memory_cap`]
+struct MemoryAccounting<T> {
+    size_of: SizeOf<T>,
+    cap: Option<usize>,
+    meter: MemoryMeter,
+    // sizes of the items in flight, oldest first
+    sizes: VecDeque<usize>,
+}
+
+impl<T> MemoryAccounting<T> {
+    fn is_full(&self) -> bool {
+        self.cap.is_some_and(|cap| cap <= self.meter.current())
+    }
+
+    fn push(&mut self, item: &T) {
+        let size = (self.size_of)(item);
+        self.sizes.push_back(size);
+        self.meter.add(size);
+    }
+
+    /// Account for an empty slot, so the sizes are popped for the right items
+    fn push_skipped(&mut self) {
+        self.sizes.push_back(0);
+    }
+
+    fn pop(&mut self) {
+        if let Some(size) = self.sizes.pop_front() {
+            self.meter.sub(size);
+        }
+    }
+}
+
+/// Details of the first panic of a worker thread
+struct WorkerPanic {
+    // id of the item being processed
+    index: usize,
+    // the item, if rendering was enabled
+    item: Option<String>,
+    // panic message, if it was a string
+    message: Option<String>,
+}
+
+/// The first panic of the workers of a stage, shared by them and the stage
+#[derive(Clone, Default)]
+pub(crate) struct PanicSlot(Arc<Mutex<Option<WorkerPanic>>>);
+
+impl PanicSlot {
+    /// Save the details of the panic, unless some other worker panicked already
+    pub(crate) fn record(&self, index: usize, item: Option<String>, payload: &(dyn Any + Send)) {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned());
+
+        if let Ok(mut worker_panic) = self.0.lock() {
+            if worker_panic.is_none() {
+                *worker_panic = Some(WorkerPanic {
+                    index,
+                    item,
+                    message,
+                });
+            }
+        }
+    }
+
+    /// Add the details of the panic to `error`, if known
+    pub(crate) fn error(&self, error: PipelineError) -> PipelineError {
+        match self.0.lock().ok().as_ref().and_then(|p| p.as_ref()) {
+            Some(worker_panic) => error.with_panic(
+                worker_panic.index,
+                worker_panic.item.clone(),
+                worker_panic.message.clone(),
+            ),
+            None => error,
+        }
+    }
+}
+
+/// Everything a worker thread needs, besides the map function
+struct Worker<T, O> {
+    in_rx: Receiver<(usize, T)>,
+    out_tx: Sender<(usize, Option<O>)>,
+    tail_tx: Option<Sender<O>>,
+    worker_panicked: Arc<AtomicBool>,
+    worker_panic: PanicSlot,
+    progress: Option<Arc<Progress>>,
+    item_format: Option<ItemFormat<T>>,
+    max_age: Option<(time::Duration, Timestamp<T>)>,
+    on_expired: Option<ExpiredCallback<T>>,
+    slow_item: Option<(time::Duration, SlowItemCallback)>,
+    stragglers: Option<Arc<Stragglers>>,
+    drop_tracker: Option<Arc<DropTracker>>,
+    // set when the stage is dropped with `DropPolicy::Abort`
+    abandoned: Arc<AtomicBool>,
+    // poll for items before blocking, see `Workload::TinyItems`
+    spin: bool,
+    // the stage of the pipeline the worker is working for, if any
+    stage_context: Option<StageContext>,
+    // name and id of the stage, e.g. `double#3`, for the thread names
+    label: String,
+    on_worker_start: Option<WorkerHook>,
+    on_worker_stop: Option<WorkerHook>,
+    between_items: Option<WorkerHook>,
+    // fraction of time each worker can spend processing, see `cpu_quota`
+    duty_cycle: Option<f64>,
+    #[cfg(feature = "priority")]
+    priority: Option<ThreadPriority>,
+    ingress_profiler: Option<WorkerProfilerFactory>,
+    egress_profiler: Option<WorkerProfilerFactory>,
+}
+
+impl<T, O> Clone for Worker<T, O> {
+    fn clone(&self) -> Self {
+        Self {
+            in_rx: self.in_rx.clone(),
+            out_tx: self.out_tx.clone(),
+            tail_tx: self.tail_tx.clone(),
+            worker_panicked: self.worker_panicked.clone(),
+            worker_panic: self.worker_panic.clone(),
+            progress: self.progress.clone(),
+            item_format: self.item_format.clone(),
+            max_age: self.max_age.clone(),
+            on_expired: self.on_expired.clone(),
+            slow_item: self.slow_item.clone(),
+            stragglers: self.stragglers.clone(),
+            drop_tracker: self.drop_tracker.clone(),
+            abandoned: self.abandoned.clone(),
+            spin: self.spin,
+            stage_context: self.stage_context.clone(),
+            label: self.label.clone(),
+            on_worker_start: self.on_worker_start.clone(),
+            on_worker_stop: self.on_worker_stop.clone(),
+            between_items: self.between_items.clone(),
+            duty_cycle: self.duty_cycle,
+            #[cfg(feature = "priority")]
+            priority: self.priority,
+            ingress_profiler: self.ingress_profiler.clone(),
+            egress_profiler: self.egress_profiler.clone(),
+        }
+    }
+}
+
+impl<T, O> Worker<T, O> {
+    fn run<F>(self, worker_i: usize, f: F)
+    where
+        F: FnMut(usize, T) -> O,
+    {
+        self.run_stateful(worker_i, f, |f, i, item| (f)(i, item), |_f| None)
+    }
+
+    /// Process all the items passing `state` to `f`, then pass it to `finish`
+    ///
+    /// Items returned by `finish` are sent to the tail channel.
+    fn run_stateful<S, F, FIN, R>(self, worker_i: usize, mut state: S, mut f: F, finish: FIN)
+    where
+        F: FnMut(&mut S, usize, T) -> O,
+        FIN: FnOnce(S) -> R,
+        R: IntoIterator<Item = O>,
+    {
+        let drop_indicator = DropIndicator::new(self.worker_panicked.clone());
+        let _stage_context = self.stage_context.clone().map(StageContext::enter);
+
+        #[cfg(feature = "priority")]
+        if let Some(priority) = self.priority {
+            priority.apply();
+        }
+
+        if let Some(hook) = self.on_worker_start.as_ref() {
+            (hook)(worker_i);
+        }
+
+        let mut ingress_profiler = self.ingress_profiler.as_ref().map(|make| (make)(worker_i));
+        let mut egress_profiler = self.egress_profiler.as_ref().map(|make| (make)(worker_i));
+
+        loop {
+            if let Some(profiler) = ingress_profiler.as_mut() {
+                profiler.start();
+            }
+            let received = match self.spin.then(|| workload::spin_recv(&self.in_rx)) {
+                Some(Some(received)) => Ok(received),
+                _ => self.in_rx.recv(),
+            };
+            if let Some(profiler) = ingress_profiler.as_mut() {
+                profiler.end();
+            }
+            let (i, item) = match received {
+                Ok(received) => received,
+                Err(_) => break,
+            };
+            if self.abandoned.load(SeqCst) {
+                if let Some(drop_tracker) = self.drop_tracker.as_ref() {
+                    drop_tracker.inc_skipped();
+                }
+                continue;
+            }
+            let item = match self.check_age(item) {
+                Some(item) => item,
+                None => {
+                    // let the stage know there is no result to wait for
+                    let _ = self.out_tx.send((i, None));
+                    continue;
+                }
+            };
+            if let Some(progress) = self.progress.as_ref() {
+                progress.inc_started();
+            }
+
+            let rendered = self.item_format.as_ref().map(|format| (format)(&item));
+            let start = (self.slow_item.is_some()
+                || self.stragglers.is_some()
+                || self.duty_cycle.is_some())
+            .then(time::Instant::now);
+
+            let item = match panic::catch_unwind(AssertUnwindSafe(|| (f)(&mut state, i, item))) {
+                Ok(item) => item,
+                Err(payload) => {
+                    self.record_panic(i, rendered, payload.as_ref());
+                    panic::resume_unwind(payload);
+                }
+            };
+
+            if let (Some((threshold, callback)), Some(start)) = (self.slow_item.as_ref(), start) {
+                let duration = start.elapsed();
+                if *threshold <= duration {
+                    (callback)(duration, rendered.as_deref().unwrap_or_default());
+                }
+            }
+
+            if let (Some(stragglers), Some(start)) = (self.stragglers.as_ref(), start) {
+                stragglers.record(i, start.elapsed(), || rendered.clone());
+            }
+
+            if let Some(progress) = self.progress.as_ref() {
+                progress.inc_completed();
+            }
+            if let Some(drop_tracker) = self.drop_tracker.as_ref() {
+                drop_tracker.inc_completed();
+            }
+
+            if let Some(profiler) = egress_profiler.as_mut() {
+                profiler.start();
+            }
+            // we ignore send failures, if the receiver is gone
+            // we just throw the work away
+            let _ = self.out_tx.send((i, Some(item)));
+            if let Some(profiler) = egress_profiler.as_mut() {
+                profiler.end();
+            }
+
+            if let (Some(duty_cycle), Some(start)) = (self.duty_cycle, start) {
+                let busy = start.elapsed();
+                thread::sleep(busy.mul_f64((1.0 - duty_cycle) / duty_cycle));
+            }
+
+            if let Some(hook) = self.between_items.as_ref() {
+                (hook)(worker_i);
+            }
+        }
+
+        let tail = (finish)(state);
+        if let Some(tail_tx) = self.tail_tx.as_ref() {
+            for item in tail {
+                if let Some(drop_tracker) = self.drop_tracker.as_ref() {
+                    drop_tracker.inc_completed();
+                }
+                // same as above
+                let _ = tail_tx.send(item);
+            }
+        }
+
+        if let Some(hook) = self.on_worker_stop.as_ref() {
+            (hook)(worker_i);
         }
 
-        ret
+        drop_indicator.cancel();
     }
 
-    pub fn with_scoped<'env, 'scope, F, O>(
-        self,
-        scope: &'scope Scope<'env>,
-        f: F,
-    ) -> ParallelMap<I, O>
-    where
-        I: Iterator,
-        F: 'env + Send + Clone,
-        O: Send + 'env,
-        I::Item: Send + 'env,
-        F: FnMut(I::Item) -> O,
-    {
-        let (ret, in_rx, out_tx) = self.with_common();
-
-        for _ in 0..ret.num_threads {
-            let in_rx = in_rx.clone();
-            let out_tx = out_tx.clone();
-            let mut f = f.clone();
-            let drop_indicator = DropIndicator::new(ret.worker_panicked.clone());
-
-            scope.spawn(move |_scope| {
-                for (i, item) in in_rx.into_iter() {
-                    // we ignore send failures, if the receiver is gone
-                    // we just throw the work away
-                    let _ = out_tx.send((i, (f)(item)));
-                }
-                drop_indicator.cancel();
-            });
+    /// Return `item`, unless it's older than `max_age`
+    fn check_age(&self, item: T) -> Option<T> {
+        let (max_age, timestamp) = match self.max_age.as_ref() {
+            Some(max_age) => max_age,
+            None => return Some(item),
+        };
+        // timestamps in the future count as new
+        let age = (timestamp)(&item).elapsed().unwrap_or_default();
+        if age <= *max_age {
+            return Some(item);
+        }
+        if let Some(callback) = self.on_expired.as_ref() {
+            (callback)(item);
         }
+        None
+    }
 
-        ret
+    /// Name of the thread of the worker `worker_i`, e.g. `double#3/0`
+    fn thread_name(&self, worker_i: usize) -> String {
+        format!("{}/{}", self.label, worker_i)
+    }
+
+    /// Save the details of the panic, unless some other worker panicked already
+    fn record_panic(&self, index: usize, item: Option<String>, payload: &(dyn Any + Send)) {
+        self.worker_panic.record(index, item, payload);
     }
 }
 
@@ -178,18 +1881,88 @@ where
     num_threads: usize,
     // max number of items in flight
     buffer_size: usize,
+    // how many more items can be pulled from `iter`, if limited
+    limit_left: Option<usize>,
+    // bytes held by the items in flight, if measured
+    memory: Option<MemoryAccounting<I::Item>>,
+    // the slowest items, reported when done
+    stragglers: Option<Arc<Stragglers>>,
+    // counts of the items completed and returned, reported when done
+    drop_tracker: Option<Arc<DropTracker>>,
+    // what to do with the work in flight when dropped
+    drop_policy: DropPolicy,
+    // tells the workers to skip the remaining items, see `DropPolicy::Abort`
+    abandoned: Arc<AtomicBool>,
+    // poll for results before blocking, see `Workload::TinyItems`
+    spin: bool,
+    // return the results as they come, see `ParallelMapConfig::unordered`
+    unordered: bool,
+    // can items be skipped, see `max_item_age` and `shed_load`
+    may_skip: bool,
+    // dropping items while the workers are behind, if enabled
+    load_shedding: Option<LoadShedding>,
+    // number of items returned by `finish` of the workers so far
+    tail_returned: usize,
+    // unique id of the stage, see `ParallelMap::id`
+    id: u64,
+    // handles the results not pulled, see `DropPolicy::Finish`
+    on_orphaned: Option<OrphanCallback<O>>,
+    // name of the stage, for diagnostics
+    name: Option<String>,
+    // progress counters watched by the `watchdog` and the `sampler`
+    progress: Option<Arc<Progress>>,
+    // stall detection, if enabled
+    watchdog: Option<Watchdog>,
+    // occupancy sampling, if enabled
+    sampler: Option<Sampler>,
     /// the id of the work we are going to send next
     next_tx_i: usize,
     /// the id of response we are waiting for
     next_rx_i: usize,
     /// did any worker thread failed us
     worker_panicked: Arc<AtomicBool>,
+    /// details of the first worker panic
+    worker_panic: PanicSlot,
+    /// sending work failed; reported after all completed items were returned
+    send_error: Option<PipelineError>,
+    /// how often to check for worker panics while waiting for items
+    panic_poll_interval: time::Duration,
     /// responses we received before we needed them
-    out_of_order: Vec<(usize, O)>,
+    out_of_order: ReorderBuffer<(usize, Option<O>)>,
+    /// the map function, if running on the consuming thread, see `with_enumerate_inline`
+    inline: Option<InlineBox<I::Item, O>>,
     // stuff we created when we started workers
     inner: Option<ParallelMapInner<I::Item, O>>,
 }
 
+impl<I, O> fmt::Debug for ParallelMap<I, O>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelMap")
+            .field("name", &self.name)
+            .field("num_threads", &self.num_threads)
+            .field("buffer_size", &self.buffer_size)
+            .field("in_flight", &(self.next_tx_i - self.next_rx_i))
+            .field("out_of_order", &self.out_of_order.len())
+            .field("sent", &self.next_tx_i)
+            .field("received", &self.next_rx_i)
+            .field("limit_left", &self.limit_left)
+            .field(
+                "shed",
+                &self.load_shedding.as_ref().map_or(0, LoadShedding::shed),
+            )
+            .field(
+                "memory_used",
+                &self.memory.as_ref().map(|memory| memory.meter.current()),
+            )
+            .field("iter_done", &self.iter_done)
+            .field("worker_panicked", &self.worker_panicked.load(SeqCst))
+            .finish_non_exhaustive()
+    }
+}
+
 impl<I, O> ParallelMap<I, O>
 where
     I: Iterator,
@@ -198,59 +1971,247 @@ where
 {
     /// Fill the worker incoming queue with work
     fn pump_tx(&mut self) {
-        if self.iter_done {
+        if self.iter_done || self.send_error.is_some() {
             return;
         }
 
         while self.next_tx_i < self.next_rx_i + self.buffer_size {
-            if let Some(item) = self.iter.next() {
-                self.inner
+            if self.next_tx_i != self.next_rx_i
+                && self.memory.as_ref().is_some_and(MemoryAccounting::is_full)
+            {
+                // wait for some items to be returned first
+                break;
+            }
+            if let Some(item) = self.pull() {
+                if let Some(shedding) = self.load_shedding.as_mut() {
+                    // all the workers are busy, and the queue in front of them is full
+                    let congested = self
+                        .inner
+                        .as_ref()
+                        .and_then(|inner| inner.tx.as_ref())
+                        .is_some_and(|tx| tx.is_full());
+                    if shedding.should_shed(congested) {
+                        self.skip_slot();
+                        continue;
+                    }
+                }
+                if let Some(memory) = self.memory.as_mut() {
+                    memory.push(&item);
+                }
+                let sent = self
+                    .inner
                     .as_ref()
                     .expect("not started")
                     .tx
                     .as_ref()
                     .expect("inner-iterator exhausted")
-                    .send((self.next_tx_i, item))
-                    .expect("send failed");
+                    .send((self.next_tx_i, item));
+                if sent.is_err() {
+                    // no workers are left to receive it
+                    self.send_error = Some(self.worker_error("all worker threads terminated"));
+                    return;
+                }
                 self.next_tx_i += 1;
             } else {
-                self.iter_done = true;
-                self.inner.as_mut().expect("not started").tx = None;
+                self.finish_tx();
                 break;
             }
         }
     }
-}
 
-impl<I, O> Iterator for ParallelMap<I, O>
-where
-    I: Iterator,
-    I::Item: Send,
-    O: Send,
-{
-    type Item = O;
+    /// Use up the id of an item that is not sent to the workers
+    ///
+    /// Keeps the ids in line with the positions in the inner iterator. When ordered,
+    /// leaves an empty slot, like the items skipped by the workers.
+    fn skip_slot(&mut self) {
+        let i = self.next_tx_i;
+        self.next_tx_i += 1;
+        if self.unordered {
+            // the received items are only counted
+            self.next_rx_i += 1;
+        } else {
+            self.out_of_order.insert(i - self.next_rx_i, (i, None));
+            if let Some(memory) = self.memory.as_mut() {
+                memory.push_skipped();
+            }
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.pump_tx();
+    /// Pull the next item from the inner iterator, respecting the limit
+    fn pull(&mut self) -> Option<I::Item> {
+        match self.limit_left.as_mut() {
+            Some(0) => None,
+            Some(left) => {
+                *left -= 1;
+                self.iter.next()
+            }
+            None => self.iter.next(),
+        }
+    }
+
+    /// Mark the inner iterator as exhausted and let the workers know
+    fn finish_tx(&mut self) {
+        self.iter_done = true;
+        self.inner.as_mut().expect("not started").tx = None;
+    }
+
+    /// Skip `n` items of the inner iterator without sending them to workers
+    ///
+    /// Returns the number of items skipped, fewer than `n` if the inner iterator
+    /// ran out, when there are items returned after it (see [`ParallelMap::has_tail`]).
+    /// Can only be called when there's no work in flight.
+    fn skip_tx(&mut self, n: usize) -> usize {
+        if self.iter_done || n == 0 {
+            return 0;
+        }
+
+        debug_assert_eq!(self.next_tx_i, self.next_rx_i);
+
+        if let Some(left) = self.limit_left.as_mut() {
+            if *left < n {
+                // no point pulling any more items
+                let skipped = mem::take(left);
+                self.next_tx_i += skipped;
+                self.next_rx_i += skipped;
+                self.finish_tx();
+                return skipped;
+            }
+            *left -= n;
+        }
+
+        if self.has_tail() {
+            // counted one by one, so the rest can be skipped from the tail
+            let skipped = self.iter.by_ref().take(n).count();
+            self.next_tx_i += skipped;
+            self.next_rx_i += skipped;
+            if skipped < n {
+                self.finish_tx();
+            }
+            return skipped;
+        }
+
+        // `nth(n - 1)` consumes exactly `n` items, unless the iterator runs out
+        if self.iter.nth(n - 1).is_some() {
+            // keep the ids in line with the positions in the inner iterator
+            self.next_tx_i += n;
+            self.next_rx_i += n;
+        } else {
+            // nothing comes after the inputs, so it doesn't matter how many were left
+            self.finish_tx();
+        }
+        n
+    }
+
+    /// Stop accounting for the oldest item in flight, after its result was returned
+    fn release_memory(&mut self) {
+        if let Some(memory) = self.memory.as_mut() {
+            memory.pop();
+        }
+    }
+
+    /// Update the counters observed by the watchdog
+    fn update_progress(&mut self, done: bool) {
+        if let Some(progress) = self.progress.as_ref() {
+            progress.set_sent(self.next_tx_i);
+            progress.set_received(self.next_rx_i);
+        }
+
+        if done {
+            // nothing can stall or move anymore
+            self.watchdog = None;
+            self.sampler = None;
+            if let Some(stragglers) = self.stragglers.take() {
+                stragglers.report();
+            }
+        }
+    }
+
+    /// The error describing the worker panic, with its details if known
+    fn worker_error(&self, reason: &'static str) -> PipelineError {
+        self.worker_panic
+            .error(PipelineError::new("parallel_map", reason).with_stage_id(self.id))
+    }
+
+    /// Receive items the workers sent when they were done, if enabled
+    ///
+    /// Can only be called after all ordered items were received.
+    fn recv_tail(&mut self) -> Result<Option<O>, PipelineError> {
+        let tail_rx = match self.inner.as_ref().expect("not started").tail_rx.as_ref() {
+            Some(tail_rx) => tail_rx,
+            None => return Ok(None),
+        };
+
+        loop {
+            match tail_rx.recv_timeout(self.panic_poll_interval) {
+                Ok(item) => return Ok(Some(item)),
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                    if self.worker_panicked.load(SeqCst) {
+                        return Err(self.worker_error("panic indicator set"));
+                    }
+                }
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                    if self.worker_panicked.load(SeqCst) {
+                        return Err(self.worker_error("panic indicator set"));
+                    }
+                    // all workers are done
+                    self.inner.as_mut().expect("not started").tail_rx = None;
+                    return Ok(None);
+                }
+            }
+        }
+    }
 
+    /// Receive the next item in order, without sending any more work
+    ///
+    /// Or just the next one received, if unordered.
+    fn recv_next(&mut self) -> Result<Option<(usize, O)>, PipelineError> {
         loop {
-            // inner iterator is done, and all work sent was already received back
-            if self.next_rx_i == self.next_tx_i && self.iter_done {
-                return None;
+            // all work sent was already received back
+            if self.next_rx_i == self.next_tx_i {
+                return Ok(None);
             }
 
             // check if we didn't receive this item out of order
-            if let Some(index) = self
-                .out_of_order
-                .iter()
-                .position(|(i, _)| (i == &self.next_rx_i))
-            {
-                let item = self.out_of_order.swap_remove(index).1;
+            if let Some((item_i, item)) = self.out_of_order.pop_next() {
+                self.next_rx_i += 1;
+                self.release_memory();
+                match item {
+                    Some(item) => return Ok(Some((item_i, item))),
+                    // skipped by the worker
+                    None => continue,
+                }
+            }
+
+            let (item_i, item) = self.recv_any()?;
+            if self.unordered || item_i == self.next_rx_i {
+                if !self.unordered {
+                    self.out_of_order.skip_next();
+                }
                 self.next_rx_i += 1;
-                self.pump_tx();
-                return Some(item);
+                self.release_memory();
+                match item {
+                    Some(item) => return Ok(Some((item_i, item))),
+                    None => continue,
+                }
+            } else {
+                assert!(item_i > self.next_rx_i);
+                self.out_of_order
+                    .insert(item_i - self.next_rx_i, (item_i, item));
             }
+        }
+    }
 
+    /// Receive any item from the workers
+    ///
+    /// Can only be called when there's work in flight.
+    fn recv_any(&self) -> Result<(usize, Option<O>), PipelineError> {
+        if self.spin {
+            let rx = &self.inner.as_ref().expect("not started").rx;
+            if let Some(item) = workload::spin_recv(rx) {
+                return Ok(item);
+            }
+        }
+        loop {
             // there are multiple ways to detect worker panics, but here we
             // use a timeout to periodically check atomic bool.
             match self
@@ -258,31 +2219,418 @@ where
                 .as_ref()
                 .expect("not started")
                 .rx
-                .recv_timeout(std::time::Duration::from_micros(100))
+                .recv_timeout(self.panic_poll_interval)
             {
-                Ok((item_i, item)) => {
-                    if item_i == self.next_rx_i {
-                        self.next_rx_i += 1;
-                        self.pump_tx();
-                        return Some(item);
-                    } else {
-                        assert!(item_i > self.next_rx_i);
-                        self.out_of_order.push((item_i, item));
-                    }
-                }
+                Ok(item) => return Ok(item),
                 Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
                     if self.worker_panicked.load(SeqCst) {
-                        panic!("parallel_map worker thread panicked: panic indicator set");
+                        return Err(self.worker_error("panic indicator set"));
                     }
                 }
                 Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
-                    panic!("parallel_map worker thread panicked: channel disconnected");
+                    return Err(self.worker_error("channel disconnected"));
+                }
+            }
+        }
+    }
+
+    /// Wait for all the items sent to the workers so far, without pulling any more
+    ///
+    /// The results are buffered, and returned by the following calls to `next`.
+    /// Returns the number of items pulled from the inner iterator so far, all of
+    /// which are now processed. Useful for checkpointing resumable jobs: once
+    /// the returned number of items is consumed, everything up to that position
+    /// in the input is done.
+    pub fn flush(&mut self) -> usize {
+        if self.inner.is_some() {
+            while self.out_of_order.len() < self.next_tx_i - self.next_rx_i {
+                let (i, item) = self.recv_any().unwrap_or_else(|e| panic!("{}", e));
+                if self.unordered {
+                    self.out_of_order.push_back((i, item));
+                } else {
+                    self.out_of_order.insert(i - self.next_rx_i, (i, item));
+                }
+            }
+        }
+        self.update_progress(false);
+
+        self.next_tx_i
+    }
+
+    /// Max number of items in flight
+    pub(crate) fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Outputs received from the workers, but not returned yet
+    pub(crate) fn buffered(&self) -> impl Iterator<Item = &O> {
+        self.out_of_order
+            .iter()
+            .filter_map(|(_i, item)| item.as_ref())
+    }
+
+    /// Items pulled from the inner iterator, but not returned yet
+    pub(crate) fn in_flight(&self) -> usize {
+        self.next_tx_i - self.next_rx_i
+    }
+
+    /// Can `finish` of stateful workers return items after all the ordered ones
+    fn has_tail(&self) -> bool {
+        self.inner
+            .as_ref()
+            .is_some_and(|inner| inner.tail_rx.is_some())
+    }
+
+    /// Map the next item on the consuming thread, see `with_enumerate_inline`
+    fn next_inline(&mut self) -> Option<(usize, O)> {
+        let item = if self.iter_done { None } else { self.pull() };
+        let item = match item {
+            Some(item) => item,
+            None => {
+                self.iter_done = true;
+                let tail = self.inline.as_mut().expect("inline").next_tail();
+                // numbered after all the inputs
+                let item = tail.map(|item| {
+                    let i = self.next_tx_i + self.tail_returned;
+                    self.tail_returned += 1;
+                    (i, item)
+                });
+                self.update_progress(item.is_none());
+                return item;
+            }
+        };
+        let i = self.next_tx_i;
+        self.next_tx_i += 1;
+        self.next_rx_i += 1;
+        self.update_progress(false);
+
+        Some((i, self.inline.as_mut().expect("inline").map(i, item)))
+    }
+
+    /// Like [`Iterator::next`], but returns worker failures as errors, instead of panicking
+    fn try_next(&mut self) -> Result<Option<O>, PipelineError> {
+        Ok(self.try_next_indexed()?.map(|(_i, item)| item))
+    }
+
+    /// Like [`ParallelMap::try_next`], with the index of the input of the item
+    fn try_next_indexed(&mut self) -> Result<Option<(usize, O)>, PipelineError> {
+        if self.inline.is_some() {
+            return Ok(self.next_inline());
+        }
+
+        self.pump_tx();
+        self.update_progress(false);
+
+        let mut item = self.recv_next()?;
+
+        if item.is_none() {
+            if let Some(e) = self.send_error.take() {
+                return Err(e);
+            }
+            if self.iter_done {
+                // numbered after all the inputs
+                item = self.recv_tail()?.map(|item| {
+                    let i = self.next_tx_i + self.tail_returned;
+                    self.tail_returned += 1;
+                    (i, item)
+                });
+            }
+        }
+        if item.is_some() {
+            self.count_returned();
+        }
+
+        // get the workers busy before returning
+        self.pump_tx();
+        self.update_progress(item.is_none());
+
+        Ok(item)
+    }
+
+    /// Number of items dropped by [`ParallelMapConfig::shed_load`] so far
+    pub fn shed_count(&self) -> usize {
+        self.load_shedding.as_ref().map_or(0, LoadShedding::shed)
+    }
+
+    /// Unique id of the stage
+    ///
+    /// Included in the names of the worker threads (e.g. `double#3/0`, for the first
+    /// worker of the stage named `double`), in the errors, and the stall reports,
+    /// so the messages of concurrent pipelines can be told apart.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Call `callback` with every result completed, but not pulled, when the stage is dropped
+    ///
+    /// Only with [`DropPolicy::Finish`]. Dropping the stage then blocks until all the
+    /// items in flight are processed. The results are passed in no particular order.
+    pub fn on_orphaned<C>(mut self, callback: C) -> Self
+    where
+        C: FnMut(O) + Send + 'static,
+    {
+        self.on_orphaned = Some(Box::new(callback));
+        self
+    }
+
+    /// Return every result with the index of its input, i.e. its position in the inner iterator
+    ///
+    /// Mostly useful with [`ParallelMapConfig::unordered`], to reorder the results later,
+    /// or write them at the right place of a preallocated output. Items returned by
+    /// `finish` of stateful workers are numbered after all the inputs.
+    ///
+    /// ```
+    /// use pariter::IteratorExt as _;
+    ///
+    /// let mut squares = vec![0; 100];
+    /// for (i, square) in (0..100)
+    ///     .parallel_map_custom(|o| o.unordered(), |x| x * x)
+    ///     .with_input_indices()
+    /// {
+    ///     squares[i] = square;
+    /// }
+    /// assert_eq!(squares[9], 81);
+    /// ```
+    pub fn with_input_indices(self) -> WithInputIndices<I, O> {
+        WithInputIndices { iter: self }
+    }
+
+    /// Return worker failures as `Err` items, instead of panicking
+    ///
+    /// Nothing is returned after the first error. Useful where panics are not acceptable,
+    /// e.g. in request handlers or across FFI boundaries.
+    ///
+    /// Note: with scoped threads, the scope still reports the worker panic when it ends.
+    pub fn results(self) -> ParallelMapResults<I, O> {
+        ParallelMapResults {
+            iter: self,
+            failed: false,
+        }
+    }
+}
+
+impl<I, O> ParallelMap<I, O>
+where
+    I: Iterator,
+{
+    /// Pass the results in flight to the `on_orphaned` callback
+    fn deliver_orphaned(&mut self, mut callback: OrphanCallback<O>) {
+        let mut inner = match self.inner.take() {
+            Some(inner) => inner,
+            None => return,
+        };
+        // no more work, so the workers exit after processing the items in flight
+        inner.tx = None;
+
+        for (_i, item) in mem::take(&mut self.out_of_order) {
+            let item = match item {
+                Some(item) => item,
+                None => continue,
+            };
+            (callback)(item);
+            self.count_returned();
+        }
+        for item in inner.rx.iter().filter_map(|(_i, item)| item) {
+            (callback)(item);
+            self.count_returned();
+        }
+        if let Some(tail_rx) = inner.tail_rx.as_ref() {
+            for item in tail_rx.iter() {
+                (callback)(item);
+                self.count_returned();
+            }
+        }
+    }
+
+    /// Count an item as returned, i.e. not discarded, see [`DropStats`]
+    fn count_returned(&self) {
+        if let Some(drop_tracker) = self.drop_tracker.as_ref() {
+            drop_tracker.inc_returned();
+        }
+    }
+}
+
+impl<I, O> Drop for ParallelMap<I, O>
+where
+    I: Iterator,
+{
+    fn drop(&mut self) {
+        match self.drop_policy {
+            DropPolicy::Abort => self.abandoned.store(true, SeqCst),
+            DropPolicy::Finish => {
+                if let Some(callback) = self.on_orphaned.take() {
+                    self.deliver_orphaned(callback);
                 }
             }
         }
     }
+}
+
+impl<I, O> Describe for ParallelMap<I, O>
+where
+    I: Iterator + Describe,
+{
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        self.iter.describe_into(nodes);
+        nodes.push(PipelineNode {
+            name: self.name.clone(),
+            threads: Some(self.num_threads),
+            buffer: Some(self.buffer_size),
+            ..PipelineNode::new("parallel_map")
+        });
+    }
+}
+
+impl<I, O> Iterator for ParallelMap<I, O>
+where
+    I: Iterator,
+    I::Item: Send,
+    O: Send,
+{
+    type Item = O;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Items that were already sent to the workers need to be waited for,
+    /// but the remaining ones are skipped in the inner iterator directly,
+    /// without calling the map function on them.
+    fn nth(&mut self, mut n: usize) -> Option<Self::Item> {
+        while 0 < n && self.next_rx_i < self.next_tx_i {
+            match self.recv_next() {
+                Ok(Some(_)) => self.count_returned(),
+                Ok(None) => {}
+                Err(e) => panic!("{}", e),
+            }
+            n -= 1;
+        }
+
+        n -= self.skip_tx(n);
+        // the rest are returned after all the inputs, see `ParallelMapBuilder::with_state`
+        while 0 < n {
+            self.next()?;
+            n -= 1;
+        }
+
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        let (lower, upper) = match self.limit_left {
+            Some(left) => (
+                cmp::min(lower, left),
+                Some(upper.map_or(left, |upper| cmp::min(upper, left))),
+            ),
+            None => (lower, upper),
+        };
+
+        // items pulled already, being processed or waiting in the reorder buffer
+        let in_flight = self.in_flight();
+        // `finish` of stateful workers can return any number of items
+        let tail = self.has_tail();
+        let lower = if self.may_skip {
+            0
+        } else {
+            lower.saturating_add(in_flight)
+        };
+        (
+            lower,
+            upper
+                .and_then(|upper| upper.checked_add(in_flight))
+                .filter(|_| !tail),
+        )
+    }
+}
+
+/// [`ParallelMap`] returning the results with the indices of their inputs
+///
+/// See [`ParallelMap::with_input_indices`].
+pub struct WithInputIndices<I, O>
+where
+    I: Iterator,
+{
+    iter: ParallelMap<I, O>,
+}
+
+impl<I, O> fmt::Debug for WithInputIndices<I, O>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithInputIndices")
+            .field("iter", &self.iter)
+            .finish()
+    }
+}
+
+impl<I, O> Iterator for WithInputIndices<I, O>
+where
+    I: Iterator,
+    I::Item: Send,
+    O: Send,
+{
+    type Item = (usize, O);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter
+            .try_next_indexed()
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
         self.iter.size_hint()
     }
 }
+
+/// [`ParallelMap`] returning worker failures as errors
+///
+/// See [`ParallelMap::results`].
+pub struct ParallelMapResults<I, O>
+where
+    I: Iterator,
+{
+    iter: ParallelMap<I, O>,
+    // was an error returned already
+    failed: bool,
+}
+
+impl<I, O> fmt::Debug for ParallelMapResults<I, O>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelMapResults")
+            .field("iter", &self.iter)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl<I, O> Iterator for ParallelMapResults<I, O>
+where
+    I: Iterator,
+    I::Item: Send,
+    O: Send,
+{
+    type Item = Result<O, PipelineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let res = self.iter.try_next().transpose();
+        self.failed = matches!(res, Some(Err(_)));
+        res
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            self.iter.size_hint()
+        }
+    }
+}
@@ -0,0 +1,471 @@
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{reorder::ReorderBuffer, PipelineError};
+use std::{
+    any::Any,
+    cmp,
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc, Condvar, Mutex, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread,
+};
+
+/// Default number of executor threads
+///
+/// The futures are expected to spend most of the time waiting for I/O,
+/// so a couple of threads can drive a lot of them.
+const DEFAULT_THREADS: usize = 2;
+
+/// Default max number of futures in flight
+const DEFAULT_CONCURRENCY: usize = 64;
+
+type Response<O> = (usize, Result<O, Box<dyn Any + Send>>);
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A future spawned on the [`Executor`]
+struct Task {
+    // `None` once completed
+    future: Mutex<Option<BoxFuture>>,
+    // the queue to put the task back on, when woken up
+    queue: Weak<RunQueue>,
+    // is the task in the `queue` already
+    scheduled: AtomicBool,
+}
+
+impl Task {
+    /// Poll the future, if not completed already
+    fn run(self: Arc<Self>) {
+        // wake-ups from now on need to poll it again
+        self.scheduled.store(false, SeqCst);
+
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = self.future.lock().expect("lock poisoned");
+        if let Some(fut) = future.as_mut() {
+            if fut.as_mut().poll(&mut cx).is_ready() {
+                *future = None;
+            }
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.scheduled.swap(true, SeqCst) {
+            // if the executor is gone, there's nothing to run the task anyway
+            if let Some(queue) = self.queue.upgrade() {
+                queue.push(self.clone());
+            }
+        }
+    }
+}
+
+/// Tasks ready to be polled
+///
+/// Tasks only keep weak references to it, so pending tasks are freed
+/// as soon as the executor is gone.
+#[derive(Default)]
+struct RunQueue {
+    // ready tasks, and is the executor shutting down
+    state: Mutex<(VecDeque<Arc<Task>>, bool)>,
+    ready: Condvar,
+}
+
+impl RunQueue {
+    fn push(&self, task: Arc<Task>) {
+        self.state.lock().expect("lock poisoned").0.push_back(task);
+        self.ready.notify_one();
+    }
+
+    /// The next task to poll, if there is one ready
+    fn try_pop(&self) -> Option<Arc<Task>> {
+        self.state.lock().expect("lock poisoned").0.pop_front()
+    }
+
+    /// Wait for the next task to poll, or `None` if shutting down
+    fn pop(&self) -> Option<Arc<Task>> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        loop {
+            if state.1 {
+                return None;
+            }
+            if let Some(task) = state.0.pop_front() {
+                return Some(task);
+            }
+            state = self.ready.wait(state).expect("lock poisoned");
+        }
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.1 = true;
+        state.0.clear();
+        self.ready.notify_all();
+    }
+}
+
+/// A small, dedicated thread pool polling futures
+///
+/// The threads exit as soon as it's dropped. On targets without threads, the futures
+/// are polled on the consuming thread instead, see [`Executor::run_next`].
+struct Executor {
+    queue: Arc<RunQueue>,
+    // no threads were spawned
+    inline: bool,
+}
+
+impl Executor {
+    fn new(num_threads: usize) -> Self {
+        let queue = Arc::new(RunQueue::default());
+        let inline = !crate::threads_supported();
+
+        if !inline {
+            for _ in 0..num_threads {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    while let Some(task) = queue.pop() {
+                        task.run();
+                    }
+                });
+            }
+        }
+
+        Self { queue, inline }
+    }
+
+    /// Poll a future that's ready to make progress on the calling thread, if there is one
+    fn run_next(&self) -> bool {
+        match self.queue.try_pop() {
+            Some(task) => {
+                task.run();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn spawn(&self, future: BoxFuture) {
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(future)),
+            queue: Arc::downgrade(&self.queue),
+            scheduled: AtomicBool::new(true),
+        });
+        self.queue.push(task);
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+/// Catches panics of the inner future, so they can be passed to the consumer
+struct CatchUnwind<F>(Pin<Box<F>>);
+
+impl<F> Future for CatchUnwind<F>
+where
+    F: Future,
+{
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.0.as_mut().poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+pub struct ParallelMapAsyncBuilder<I> {
+    // the iterator we wrapped
+    iter: I,
+    // number of executor threads to use
+    num_threads: Option<usize>,
+    // max number of futures in flight
+    concurrency: Option<usize>,
+}
+
+impl<I> fmt::Debug for ParallelMapAsyncBuilder<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelMapAsyncBuilder")
+            .field("num_threads", &self.num_threads)
+            .field("concurrency", &self.concurrency)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> ParallelMapAsyncBuilder<I>
+where
+    I: Iterator,
+{
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter,
+            num_threads: None,
+            concurrency: None,
+        }
+    }
+
+    /// Number of executor threads polling the futures
+    ///
+    /// Two by default. Unlike with [`crate::ParallelMapBuilder::threads`], this does
+    /// not limit the number of items processed at the same time, see
+    /// [`ParallelMapAsyncBuilder::concurrency`].
+    pub fn threads(self, num: usize) -> Self {
+        Self {
+            num_threads: Some(num),
+            ..self
+        }
+    }
+
+    /// Max number of futures in flight, regardless of the number of threads
+    ///
+    /// 64 by default. E.g. 256 concurrent HTTP requests can be driven by just
+    /// a couple of threads.
+    pub fn concurrency(self, num: usize) -> Self {
+        Self {
+            concurrency: Some(num),
+            ..self
+        }
+    }
+
+    pub fn with<F, Fut, O>(self, f: F) -> ParallelMapAsync<I, F, O>
+    where
+        F: FnMut(I::Item) -> Fut,
+        Fut: Future<Output = O> + Send + 'static,
+        O: Send + 'static,
+    {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let concurrency = cmp::max(1, self.concurrency.unwrap_or(DEFAULT_CONCURRENCY));
+        ParallelMapAsync {
+            iter: self.iter,
+            iter_done: false,
+            f,
+            executor: Executor::new(cmp::max(1, self.num_threads.unwrap_or(DEFAULT_THREADS))),
+            concurrency,
+            tx,
+            rx,
+            next_tx_i: 0,
+            next_rx_i: 0,
+            out_of_order: ReorderBuffer::with_capacity(concurrency),
+        }
+    }
+}
+
+/// Like [`crate::ParallelMap`], but the mapping function returns futures, driven by
+/// a small dedicated executor
+///
+/// See [`crate::IteratorExt::parallel_map_async`].
+pub struct ParallelMapAsync<I, F, O> {
+    // the iterator we wrapped
+    iter: I,
+    // is `iter` exhausted
+    iter_done: bool,
+    // creates the futures
+    f: F,
+    // runs the futures
+    executor: Executor,
+    // max number of futures in flight
+    concurrency: usize,
+    tx: Sender<Response<O>>,
+    rx: Receiver<Response<O>>,
+    /// the id of the future we are going to spawn next
+    next_tx_i: usize,
+    /// the id of response we are waiting for
+    next_rx_i: usize,
+    /// responses we received before we needed them
+    out_of_order: ReorderBuffer<O>,
+}
+
+impl<I, F, O> fmt::Debug for ParallelMapAsync<I, F, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelMapAsync")
+            .field("buffer_size", &self.concurrency)
+            .field("in_flight", &(self.next_tx_i - self.next_rx_i))
+            .field("out_of_order", &self.out_of_order.len())
+            .field("iter_done", &self.iter_done)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, F, Fut, O> ParallelMapAsync<I, F, O>
+where
+    I: Iterator,
+    F: FnMut(I::Item) -> Fut,
+    Fut: Future<Output = O> + Send + 'static,
+    O: Send + 'static,
+{
+    /// Spawn futures on the executor, until there's enough of them in flight
+    fn pump_tx(&mut self) {
+        while !self.iter_done && self.next_tx_i < self.next_rx_i + self.concurrency {
+            let item = match self.iter.next() {
+                Some(item) => item,
+                None => {
+                    self.iter_done = true;
+                    break;
+                }
+            };
+
+            let i = self.next_tx_i;
+            let future = CatchUnwind(Box::pin((self.f)(item)));
+            let tx = self.tx.clone();
+            self.executor.spawn(Box::pin(async move {
+                let res = future.await;
+                // we ignore send failures, if the receiver is gone
+                // we just throw the work away
+                let _ = tx.send((i, res));
+            }));
+            self.next_tx_i += 1;
+        }
+    }
+
+    /// Like [`Iterator::next`], but returns panics of the futures as errors, instead of panicking
+    fn try_next(&mut self) -> Result<Option<O>, PipelineError> {
+        self.pump_tx();
+
+        loop {
+            if self.next_rx_i == self.next_tx_i {
+                return Ok(None);
+            }
+
+            if let Some(item) = self.out_of_order.pop_next() {
+                self.next_rx_i += 1;
+                self.pump_tx();
+                return Ok(Some(item));
+            }
+
+            let (i, res) = match self.recv() {
+                Some(response) => response,
+                None => {
+                    return Err(PipelineError::new(
+                        "parallel_map_async",
+                        "no future can make progress",
+                    ))
+                }
+            };
+            match res {
+                Ok(item) => self.out_of_order.insert(i - self.next_rx_i, item),
+                Err(payload) => {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned());
+                    return Err(PipelineError::new("parallel_map_async", "future panicked")
+                        .with_panic(i, None, message));
+                }
+            }
+        }
+    }
+
+    /// Wait for the next future to complete
+    ///
+    /// Without executor threads, polls the futures until one does, or none can
+    /// make progress, as nothing else could wake them up.
+    fn recv(&self) -> Option<Response<O>> {
+        if !self.executor.inline {
+            // we hold a sender ourselves, so this can't disconnect
+            return Some(self.rx.recv().expect("sender alive"));
+        }
+        loop {
+            if let Ok(response) = self.rx.try_recv() {
+                return Some(response);
+            }
+            if !self.executor.run_next() {
+                return None;
+            }
+        }
+    }
+
+    /// Return panics of the futures as `Err` items, instead of panicking
+    ///
+    /// Nothing is returned after the first error. See [`crate::ParallelMap::results`].
+    pub fn results(self) -> ParallelMapAsyncResults<I, F, O> {
+        ParallelMapAsyncResults {
+            iter: self,
+            failed: false,
+        }
+    }
+}
+
+impl<I, F, Fut, O> Iterator for ParallelMapAsync<I, F, O>
+where
+    I: Iterator,
+    F: FnMut(I::Item) -> Fut,
+    Fut: Future<Output = O> + Send + 'static,
+    O: Send + 'static,
+{
+    type Item = O;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let in_flight = self.next_tx_i - self.next_rx_i;
+        let (lower, upper) = self.iter.size_hint();
+        (
+            lower.saturating_add(in_flight),
+            upper.and_then(|upper| upper.checked_add(in_flight)),
+        )
+    }
+}
+
+/// [`ParallelMapAsync`] returning panics of the futures as errors
+///
+/// See [`ParallelMapAsync::results`].
+pub struct ParallelMapAsyncResults<I, F, O> {
+    iter: ParallelMapAsync<I, F, O>,
+    // was an error returned already
+    failed: bool,
+}
+
+impl<I, F, O> fmt::Debug for ParallelMapAsyncResults<I, F, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelMapAsyncResults")
+            .field("iter", &self.iter)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl<I, F, Fut, O> Iterator for ParallelMapAsyncResults<I, F, O>
+where
+    I: Iterator,
+    F: FnMut(I::Item) -> Fut,
+    Fut: Future<Output = O> + Send + 'static,
+    O: Send + 'static,
+{
+    type Item = Result<O, PipelineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let res = self.iter.try_next().transpose();
+        self.failed = matches!(res, Some(Err(_)));
+        res
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            self.iter.size_hint()
+        }
+    }
+}
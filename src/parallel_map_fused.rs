@@ -0,0 +1,102 @@
+use crate::{ParallelMap, ParallelMapBuilder};
+
+use std::fmt;
+
+/// A [`ParallelMap`] stage, that further stages can be fused into
+///
+/// The stage is started when the first item is pulled. Until then,
+/// [`ParallelMapFused::then_parallel_map`] composes more functions, run
+/// by the same worker one after another, so each item crosses just two channels
+/// and one reorder buffer, no matter the number of stages.
+///
+/// See [`crate::IteratorExt::parallel_map_fused`].
+pub struct ParallelMapFused<I, F, O>
+where
+    I: Iterator,
+{
+    // the stage, until started
+    pending: Option<(ParallelMapBuilder<I>, F)>,
+    running: Option<ParallelMap<I, O>>,
+}
+
+impl<I, F, O> fmt::Debug for ParallelMapFused<I, F, O>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelMapFused")
+            .field(
+                "pending",
+                &self.pending.as_ref().map(|(builder, _)| builder),
+            )
+            .field("running", &self.running)
+            .finish()
+    }
+}
+
+impl<I, F, O> ParallelMapFused<I, F, O>
+where
+    I: Iterator,
+    I::Item: Send + 'static,
+    F: FnMut(I::Item) -> O,
+    F: 'static + Send + Clone,
+    O: Send + 'static,
+{
+    pub(crate) fn new(builder: ParallelMapBuilder<I>, f: F) -> Self {
+        Self {
+            pending: Some((builder, f)),
+            running: None,
+        }
+    }
+
+    /// Run `f2` on the outputs of this stage, on the same worker threads
+    ///
+    /// # Panics
+    ///
+    /// Panics if any items were pulled already.
+    pub fn then_parallel_map<F2, O2>(
+        self,
+        mut f2: F2,
+    ) -> ParallelMapFused<I, impl FnMut(I::Item) -> O2 + 'static + Send + Clone, O2>
+    where
+        F2: FnMut(O) -> O2,
+        F2: 'static + Send + Clone,
+        O2: Send + 'static,
+    {
+        let (builder, mut f) = self
+            .pending
+            .expect("stages can only be fused before the first item is pulled");
+        ParallelMapFused::new(builder, move |item| (f2)((f)(item)))
+    }
+
+    fn running(&mut self) -> &mut ParallelMap<I, O> {
+        let pending = &mut self.pending;
+        self.running.get_or_insert_with(|| {
+            let (builder, f) = pending.take().expect("pending");
+            builder.with(f)
+        })
+    }
+}
+
+impl<I, F, O> Iterator for ParallelMapFused<I, F, O>
+where
+    I: Iterator,
+    I::Item: Send + 'static,
+    F: FnMut(I::Item) -> O,
+    F: 'static + Send + Clone,
+    O: Send + 'static,
+{
+    type Item = O;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.running().next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match (self.running.as_ref(), self.pending.as_ref()) {
+            (Some(running), _) => running.size_hint(),
+            (None, Some((builder, _))) => builder.size_hint(),
+            (None, None) => (0, Some(0)),
+        }
+    }
+}
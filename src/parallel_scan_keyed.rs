@@ -0,0 +1,579 @@
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+
+use super::{
+    inline::{InlineMap, NoThreads},
+    parallel_map::{ItemFormat, PanicSlot, DEFAULT_PANIC_POLL_INTERVAL},
+    reorder::ReorderBuffer,
+    DropIndicator, ParallelMapBuilder, PipelineError, Scope,
+};
+
+use std::{
+    cmp,
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
+    time,
+};
+
+pub struct ParallelScanKeyedBuilder<I>
+where
+    I: Iterator,
+{
+    // the iterator we wrapped
+    iter: I,
+    // number of worker threads to use
+    num_threads: Option<usize>,
+    // max number of items in flight
+    buffer_size: Option<usize>,
+    // how often to check for worker panics while waiting for items
+    panic_poll_interval: Option<time::Duration>,
+    // render items for diagnostics
+    item_format: Option<ItemFormat<I::Item>>,
+}
+
+impl<I> fmt::Debug for ParallelScanKeyedBuilder<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelScanKeyedBuilder")
+            .field("num_threads", &self.num_threads)
+            .field("buffer_size", &self.buffer_size)
+            .field("panic_poll_interval", &self.panic_poll_interval)
+            .field("item_format", &self.item_format.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> ParallelScanKeyedBuilder<I>
+where
+    I: Iterator,
+{
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter,
+            num_threads: None,
+            buffer_size: None,
+            panic_poll_interval: None,
+            item_format: None,
+        }
+    }
+
+    pub fn threads(self, num: usize) -> Self {
+        Self {
+            num_threads: Some(num),
+            ..self
+        }
+    }
+
+    pub fn buffer_size(self, num: usize) -> Self {
+        Self {
+            buffer_size: Some(num),
+            ..self
+        }
+    }
+
+    /// Check for worker panics every `interval` while waiting for items
+    ///
+    /// See [`ParallelMapBuilder::panic_poll_interval`]. A zero `interval` is ignored.
+    pub fn panic_poll_interval(self, interval: time::Duration) -> Self {
+        if interval.is_zero() {
+            return self;
+        }
+        Self {
+            panic_poll_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Render the input items with `Debug` for diagnostics
+    ///
+    /// See [`ParallelMapBuilder::debug_items`].
+    pub fn debug_items(self) -> Self
+    where
+        I::Item: fmt::Debug,
+    {
+        self.format_items(|item| format!("{:?}", item))
+    }
+
+    /// Like [`ParallelScanKeyedBuilder::debug_items`], but with a custom `format` function
+    pub fn format_items<D>(self, format: D) -> Self
+    where
+        D: Fn(&I::Item) -> String + Send + Sync + 'static,
+    {
+        Self {
+            item_format: Some(Arc::new(format)),
+            ..self
+        }
+    }
+
+    /// Set up everything but the worker threads
+    #[allow(clippy::type_complexity)]
+    fn with_common<KF, K, O>(
+        self,
+        key: KF,
+    ) -> (
+        ParallelScanKeyed<I, KF, K, O>,
+        Vec<ScanWorker<K, I::Item, O>>,
+    ) {
+        let num_threads = ParallelMapBuilder::<I>::num_threads(self.num_threads);
+        let buffer_size = cmp::max(1, self.buffer_size.unwrap_or(num_threads * 2));
+
+        // Note: even if all the items in flight go to the same worker, it has
+        // enough capacity to hold them, so sending never blocks.
+        let (out_tx, out_rx) = crossbeam_channel::bounded(buffer_size);
+        let worker_panicked = Arc::new(AtomicBool::new(false));
+        let worker_panic = PanicSlot::default();
+
+        let (txs, workers) = (0..num_threads)
+            .map(|_| {
+                let (in_tx, in_rx) = crossbeam_channel::bounded(buffer_size);
+                (
+                    in_tx,
+                    ScanWorker {
+                        in_rx,
+                        out_tx: out_tx.clone(),
+                        worker_panicked: worker_panicked.clone(),
+                        worker_panic: worker_panic.clone(),
+                        item_format: self.item_format.clone(),
+                    },
+                )
+            })
+            .unzip();
+
+        (
+            ParallelScanKeyed {
+                iter: self.iter,
+                iter_done: false,
+                id: crate::next_stage_id(),
+                key,
+                buffer_size,
+                next_tx_i: 0,
+                next_rx_i: 0,
+                worker_panicked,
+                worker_panic,
+                send_error: None,
+                inline: None,
+                panic_poll_interval: self
+                    .panic_poll_interval
+                    .unwrap_or(DEFAULT_PANIC_POLL_INTERVAL),
+                out_of_order: ReorderBuffer::with_capacity(buffer_size),
+                txs,
+                rx: out_rx,
+            },
+            workers,
+        )
+    }
+
+    /// Process items with per-key state, keeping items with the same key in order
+    ///
+    /// `key` is called on the calling thread for every item, and all items
+    /// with the same key are processed sequentially by the same worker thread,
+    /// which keeps the state of each key it has seen, created with `init` the first
+    /// time the key shows up. Items with different keys are processed in parallel.
+    ///
+    /// Results are returned in the order of the inner iterator.
+    pub fn with<KF, K, INIT, S, F, O>(
+        self,
+        key: KF,
+        init: INIT,
+        f: F,
+    ) -> ParallelScanKeyed<I, KF, K, O>
+    where
+        I::Item: Send + 'static,
+        KF: FnMut(&I::Item) -> K + 'static,
+        K: Hash + Eq + Send + 'static,
+        INIT: FnMut(&K) -> S + 'static + Send + Clone,
+        S: 'static,
+        F: FnMut(&mut S, I::Item) -> O + 'static + Send + Clone,
+        O: Send + 'static,
+    {
+        let (mut ret, workers) = self.with_common(key);
+
+        if !crate::threads_supported() {
+            ret.inline = Some(Box::new(NoThreads::new(InlineScan {
+                states: HashMap::new(),
+                init,
+                f,
+            })));
+            return ret;
+        }
+
+        for worker in workers {
+            let init = init.clone();
+            let f = f.clone();
+            std::thread::spawn(move || worker.run(init, f));
+        }
+
+        ret
+    }
+
+    /// See [`ParallelScanKeyedBuilder::with`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads (like `wasm32-unknown-unknown`), like
+    /// [`crate::ParallelMapBuilder::with_scoped`].
+    pub fn with_scoped<'env, 'scope, KF, K, INIT, S, F, O>(
+        self,
+        scope: &'scope Scope<'env>,
+        key: KF,
+        init: INIT,
+        f: F,
+    ) -> ParallelScanKeyed<I, KF, K, O>
+    where
+        I::Item: Send + 'env,
+        KF: FnMut(&I::Item) -> K + 'env,
+        K: Hash + Eq + Send + 'env,
+        INIT: FnMut(&K) -> S + 'env + Send + Clone,
+        F: FnMut(&mut S, I::Item) -> O + 'env + Send + Clone,
+        O: Send + 'env,
+    {
+        crate::assert_threads_supported("scoped parallel_scan_keyed");
+        let (ret, workers) = self.with_common(key);
+
+        for worker in workers {
+            let init = init.clone();
+            let f = f.clone();
+            scope.spawn(move |_scope| worker.run(init, f));
+        }
+
+        ret
+    }
+}
+
+/// Everything a worker thread needs, besides the functions
+struct ScanWorker<K, T, O> {
+    in_rx: Receiver<(usize, K, T)>,
+    out_tx: Sender<(usize, O)>,
+    worker_panicked: Arc<AtomicBool>,
+    worker_panic: PanicSlot,
+    item_format: Option<ItemFormat<T>>,
+}
+
+impl<K, T, O> ScanWorker<K, T, O>
+where
+    K: Hash + Eq,
+{
+    fn run<S, INIT, F>(self, mut init: INIT, mut f: F)
+    where
+        INIT: FnMut(&K) -> S,
+        F: FnMut(&mut S, T) -> O,
+    {
+        let drop_indicator = DropIndicator::new(self.worker_panicked.clone());
+        let mut states = HashMap::new();
+
+        for (i, key, item) in self.in_rx.iter() {
+            let state = match states.get_mut(&key) {
+                Some(state) => state,
+                None => {
+                    let state = (init)(&key);
+                    states.entry(key).or_insert(state)
+                }
+            };
+
+            let rendered = self.item_format.as_ref().map(|format| (format)(&item));
+            let item = match panic::catch_unwind(AssertUnwindSafe(|| (f)(state, item))) {
+                Ok(item) => item,
+                Err(payload) => {
+                    self.worker_panic.record(i, rendered, payload.as_ref());
+                    panic::resume_unwind(payload);
+                }
+            };
+
+            // we ignore send failures, if the receiver is gone
+            // we just throw the work away
+            let _ = self.out_tx.send((i, item));
+        }
+
+        drop_indicator.cancel();
+    }
+}
+
+/// The states of all the keys, on the consuming thread, on targets without threads
+struct InlineScan<K, S, INIT, F> {
+    states: HashMap<K, S>,
+    init: INIT,
+    f: F,
+}
+
+impl<K, T, O, S, INIT, F> InlineMap<(K, T), O> for InlineScan<K, S, INIT, F>
+where
+    K: Hash + Eq,
+    INIT: FnMut(&K) -> S,
+    F: FnMut(&mut S, T) -> O,
+{
+    fn map(&mut self, _i: usize, (key, item): (K, T)) -> O {
+        let init = &mut self.init;
+        let state = self.states.entry(key).or_insert_with_key(|key| (init)(key));
+        (self.f)(state, item)
+    }
+}
+
+/// See [`ParallelScanKeyedBuilder::with`]
+type InlineBox<K, T, O> = Box<dyn InlineMap<(K, T), O> + Send>;
+
+/// Like [`std::iter::Map`] with state kept per key, multi-threaded
+///
+/// See [`crate::IteratorExt::parallel_scan_keyed`].
+pub struct ParallelScanKeyed<I, KF, K, O>
+where
+    I: Iterator,
+{
+    // the iterator we wrapped
+    iter: I,
+    // is `iter` exhausted
+    iter_done: bool,
+    // unique id of the stage
+    id: u64,
+    // computes the key of each item
+    key: KF,
+    // max number of items in flight
+    buffer_size: usize,
+    /// the id of the work we are going to send next
+    next_tx_i: usize,
+    /// the id of response we are waiting for
+    next_rx_i: usize,
+    /// did any worker thread failed us
+    worker_panicked: Arc<AtomicBool>,
+    /// details of the first worker panic
+    worker_panic: PanicSlot,
+    /// sending work failed; reported after all completed items were returned
+    send_error: Option<PipelineError>,
+    /// the states and functions, if running on the consuming thread
+    inline: Option<InlineBox<K, I::Item, O>>,
+    /// how often to check for worker panics while waiting for items
+    panic_poll_interval: time::Duration,
+    /// responses we received before we needed them
+    out_of_order: ReorderBuffer<O>,
+    // incoming queue of each worker, dropped when `iter` is exhausted
+    txs: Vec<Sender<(usize, K, I::Item)>>,
+    rx: Receiver<(usize, O)>,
+}
+
+impl<I, KF, K, O> fmt::Debug for ParallelScanKeyed<I, KF, K, O>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelScanKeyed")
+            .field("id", &self.id)
+            .field("buffer_size", &self.buffer_size)
+            .field("in_flight", &(self.next_tx_i - self.next_rx_i))
+            .field("out_of_order", &self.out_of_order.len())
+            .field("iter_done", &self.iter_done)
+            .field("worker_panicked", &self.worker_panicked.load(SeqCst))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, KF, K, O> ParallelScanKeyed<I, KF, K, O>
+where
+    I: Iterator,
+    KF: FnMut(&I::Item) -> K,
+    K: Hash,
+{
+    /// Fill the worker incoming queues with work
+    fn pump_tx(&mut self) {
+        if self.iter_done || self.send_error.is_some() {
+            return;
+        }
+
+        while self.next_tx_i < self.next_rx_i + self.buffer_size {
+            if let Some(item) = self.iter.next() {
+                let key = (self.key)(&item);
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                let worker_i = (hasher.finish() % self.txs.len() as u64) as usize;
+
+                if self.txs[worker_i]
+                    .send((self.next_tx_i, key, item))
+                    .is_err()
+                {
+                    // the worker is gone, which only happens if it panicked
+                    self.send_error = Some(self.worker_error("channel disconnected"));
+                    return;
+                }
+                self.next_tx_i += 1;
+            } else {
+                self.iter_done = true;
+                self.txs.clear();
+                break;
+            }
+        }
+    }
+
+    /// The error describing the worker panic, with its details if known
+    fn worker_error(&self, reason: &'static str) -> PipelineError {
+        self.worker_panic
+            .error(PipelineError::new("parallel_scan_keyed", reason).with_stage_id(self.id))
+    }
+
+    /// Receive the next item in order, without sending any more work
+    fn recv_next(&mut self) -> Result<Option<O>, PipelineError> {
+        loop {
+            // all work sent was already received back
+            if self.next_rx_i == self.next_tx_i {
+                return Ok(None);
+            }
+
+            // check if we didn't receive this item out of order
+            if let Some(item) = self.out_of_order.pop_next() {
+                self.next_rx_i += 1;
+                return Ok(Some(item));
+            }
+
+            match self.rx.recv_timeout(self.panic_poll_interval) {
+                Ok((item_i, item)) => {
+                    if item_i == self.next_rx_i {
+                        self.out_of_order.skip_next();
+                        self.next_rx_i += 1;
+                        return Ok(Some(item));
+                    } else {
+                        assert!(item_i > self.next_rx_i);
+                        self.out_of_order.insert(item_i - self.next_rx_i, item);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.worker_panicked.load(SeqCst) {
+                        return Err(self.worker_error("panic indicator set"));
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(self.worker_error("channel disconnected"));
+                }
+            }
+        }
+    }
+
+    /// Like [`Iterator::next`], but returns worker failures as errors, instead of panicking
+    fn try_next(&mut self) -> Result<Option<O>, PipelineError> {
+        if let Some(inline) = self.inline.as_mut() {
+            let item = if self.iter_done {
+                None
+            } else {
+                self.iter.next()
+            };
+            let item = match item {
+                Some(item) => item,
+                None => {
+                    self.iter_done = true;
+                    return Ok(None);
+                }
+            };
+            let key = (self.key)(&item);
+            let i = self.next_tx_i;
+            self.next_tx_i += 1;
+            self.next_rx_i += 1;
+            return Ok(Some(inline.map(i, (key, item))));
+        }
+
+        self.pump_tx();
+
+        let item = self.recv_next()?;
+
+        if item.is_none() {
+            if let Some(e) = self.send_error.take() {
+                return Err(e);
+            }
+        }
+
+        // get the workers busy before returning
+        self.pump_tx();
+
+        Ok(item)
+    }
+
+    /// Unique id of the stage, included in the errors
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Return worker failures as `Err` items, instead of panicking
+    ///
+    /// See [`crate::ParallelMap::results`].
+    pub fn results(self) -> ParallelScanKeyedResults<I, KF, K, O> {
+        ParallelScanKeyedResults {
+            iter: self,
+            failed: false,
+        }
+    }
+}
+
+impl<I, KF, K, O> Iterator for ParallelScanKeyed<I, KF, K, O>
+where
+    I: Iterator,
+    KF: FnMut(&I::Item) -> K,
+    K: Hash,
+{
+    type Item = O;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let in_flight = self.next_tx_i - self.next_rx_i;
+        let (lower, upper) = self.iter.size_hint();
+        (
+            lower.saturating_add(in_flight),
+            upper.and_then(|upper| upper.checked_add(in_flight)),
+        )
+    }
+}
+
+/// [`ParallelScanKeyed`] returning worker failures as errors
+///
+/// See [`ParallelScanKeyed::results`].
+pub struct ParallelScanKeyedResults<I, KF, K, O>
+where
+    I: Iterator,
+{
+    iter: ParallelScanKeyed<I, KF, K, O>,
+    // was an error returned already
+    failed: bool,
+}
+
+impl<I, KF, K, O> fmt::Debug for ParallelScanKeyedResults<I, KF, K, O>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelScanKeyedResults")
+            .field("iter", &self.iter)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl<I, KF, K, O> Iterator for ParallelScanKeyedResults<I, KF, K, O>
+where
+    I: Iterator,
+    KF: FnMut(&I::Item) -> K,
+    K: Hash,
+{
+    type Item = Result<O, PipelineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let res = self.iter.try_next().transpose();
+        self.failed = matches!(res, Some(Err(_)));
+        res
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            self.iter.size_hint()
+        }
+    }
+}
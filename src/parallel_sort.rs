@@ -0,0 +1,626 @@
+use crate::{chunks::Chunks, ParallelMap, ParallelMapBuilder, Scope};
+
+use std::{
+    cmp::{self, Reverse},
+    collections::BinaryHeap,
+    fmt, fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    vec,
+};
+
+/// Default number of items in each sorted run
+const DEFAULT_RUN_SIZE: usize = 64 * 1024;
+
+/// Writes one item to a spill file, see [`ParallelSortBuilder::spill`]
+type Encode<T> = Arc<dyn Fn(&T, &mut dyn Write) -> io::Result<()> + Send + Sync>;
+
+/// Reads one item from a spill file, returning `None` at the end of it
+type Decode<T> = Arc<dyn Fn(&mut dyn BufRead) -> io::Result<Option<T>> + Send + Sync>;
+
+/// Runs sorted by the worker threads
+type Runs<I> = ParallelMap<Chunks<I>, io::Result<Run<<I as Iterator>::Item>>>;
+
+/// Counter used to create unique spill file names
+static NEXT_SPILL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Everything needed to spill runs to disk
+struct Spill<T> {
+    // directory to create the files in
+    dir: PathBuf,
+    // max number of items kept in memory
+    memory_budget: usize,
+    // number of items currently kept in memory
+    in_memory: AtomicUsize,
+    encode: Encode<T>,
+    decode: Decode<T>,
+}
+
+impl<T> Spill<T> {
+    /// Keep `run` in memory if it fits in the budget, or write it to a file
+    fn store(&self, run: Vec<T>) -> io::Result<Run<T>> {
+        let len = run.len();
+        // count the run only if it fits, so runs being spilled don't take up the
+        // budget of runs sorted concurrently
+        if self
+            .in_memory
+            .fetch_update(SeqCst, SeqCst, |in_memory| {
+                in_memory
+                    .checked_add(len)
+                    .filter(|in_memory| *in_memory <= self.memory_budget)
+            })
+            .is_ok()
+        {
+            return Ok(Run::Memory(run));
+        }
+
+        let path = self.dir.join(format!(
+            "pariter-sort-{}-{}",
+            std::process::id(),
+            NEXT_SPILL_ID.fetch_add(1, SeqCst)
+        ));
+        // make sure the file is removed, even if writing it fails
+        let (file, handle) = SpillFile::create(path)?;
+        self.write(handle, &run)
+            .map_err(|e| file.error("failed to spill to", e))?;
+        Ok(Run::File { file, len })
+    }
+
+    /// Items of a run kept in memory were returned or dropped
+    fn release(&self, len: usize) {
+        self.in_memory.fetch_sub(len, SeqCst);
+    }
+
+    fn write(&self, file: fs::File, run: &[T]) -> io::Result<()> {
+        let mut writer = BufWriter::new(file);
+        for item in run {
+            (self.encode)(item, &mut writer)?;
+        }
+        writer.flush()
+    }
+}
+
+/// A spill file, removed when dropped
+struct SpillFile(PathBuf);
+
+impl SpillFile {
+    /// Create the file at `path`
+    ///
+    /// Fails if it exists already, instead of truncating (and then removing) a file
+    /// that is not ours, as the names of spill files are predictable.
+    fn create(path: PathBuf) -> io::Result<(Self, fs::File)> {
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => Ok((Self(path), file)),
+            Err(e) => Err(io::Error::new(
+                e.kind(),
+                format!("failed to spill to {}: {}", path.display(), e),
+            )),
+        }
+    }
+
+    /// `e` with the path of the file
+    fn error(&self, what: &str, e: io::Error) -> io::Error {
+        io::Error::new(e.kind(), format!("{} {}: {}", what, self.0.display(), e))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// A sorted run of items
+enum Run<T> {
+    Memory(Vec<T>),
+    File { file: SpillFile, len: usize },
+}
+
+impl<T> Run<T> {
+    /// Number of items in the run
+    fn len(&self) -> usize {
+        match self {
+            Run::Memory(run) => run.len(),
+            Run::File { len, .. } => *len,
+        }
+    }
+
+    fn into_iter(self, spill: Option<&Arc<Spill<T>>>) -> io::Result<RunIter<T>> {
+        Ok(match self {
+            Run::Memory(run) => RunIter::Memory(MemoryRun {
+                items: run.into_iter(),
+                spill: spill.cloned(),
+            }),
+            Run::File { file, .. } => {
+                let reader = fs::File::open(&file.0)
+                    .map_err(|e| file.error("failed to read spilled run from", e))?;
+                RunIter::File {
+                    reader: BufReader::new(reader),
+                    decode: spill.expect("spilled without a decoder").decode.clone(),
+                    file,
+                }
+            }
+        })
+    }
+}
+
+/// Items of a sorted run kept in memory, released from the budget as they are returned
+struct MemoryRun<T> {
+    items: vec::IntoIter<T>,
+    spill: Option<Arc<Spill<T>>>,
+}
+
+impl<T> Iterator for MemoryRun<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.items.next()?;
+        if let Some(spill) = self.spill.as_ref() {
+            spill.release(1);
+        }
+        Some(item)
+    }
+}
+
+impl<T> Drop for MemoryRun<T> {
+    fn drop(&mut self) {
+        if let Some(spill) = self.spill.as_ref() {
+            spill.release(self.items.len());
+        }
+    }
+}
+
+/// Items of a sorted run, in order
+enum RunIter<T> {
+    Memory(MemoryRun<T>),
+    File {
+        reader: BufReader<fs::File>,
+        decode: Decode<T>,
+        // removes the file when we're done
+        file: SpillFile,
+    },
+}
+
+impl<T> Iterator for RunIter<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            RunIter::Memory(iter) => iter.next().map(Ok),
+            RunIter::File {
+                reader,
+                decode,
+                file,
+            } => (decode)(reader)
+                .map_err(|e| file.error("failed to read spilled run from", e))
+                .transpose(),
+        }
+    }
+}
+
+/// The next item of a run, during the merge
+struct Head<K, T> {
+    key: K,
+    // index of the run, to keep the sort stable
+    run: usize,
+    item: T,
+}
+
+impl<K: Ord, T> PartialEq for Head<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl<K: Ord, T> Eq for Head<K, T> {}
+
+impl<K: Ord, T> PartialOrd for Head<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, T> Ord for Head<K, T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (&self.key, self.run).cmp(&(&other.key, other.run))
+    }
+}
+
+pub struct ParallelSortBuilder<I>
+where
+    I: Iterator,
+{
+    // the iterator we wrapped
+    iter: I,
+    // number of worker threads to use
+    num_threads: Option<usize>,
+    // number of items in each sorted run
+    run_size: usize,
+    // spilling to disk, if enabled
+    spill: Option<Spill<I::Item>>,
+}
+
+impl<I> fmt::Debug for ParallelSortBuilder<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelSortBuilder")
+            .field("num_threads", &self.num_threads)
+            .field("run_size", &self.run_size)
+            .field(
+                "spill",
+                &self
+                    .spill
+                    .as_ref()
+                    .map(|spill| (&spill.dir, spill.memory_budget)),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> ParallelSortBuilder<I>
+where
+    I: Iterator,
+{
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter,
+            num_threads: None,
+            run_size: DEFAULT_RUN_SIZE,
+            spill: None,
+        }
+    }
+
+    pub fn threads(self, num: usize) -> Self {
+        Self {
+            num_threads: Some(num),
+            ..self
+        }
+    }
+
+    /// Number of items in each run sorted by a worker thread
+    ///
+    /// Defaults to 64k. Must be positive.
+    pub fn run_size(self, num: usize) -> Self {
+        assert!(0 < num, "run size must be positive");
+        Self {
+            run_size: num,
+            ..self
+        }
+    }
+
+    /// Spill sorted runs to files in `dir` once more than `memory_budget` items are kept in memory
+    ///
+    /// `encode` writes a single item, and `decode` reads it back, returning `None`
+    /// at the end of the file. Files are removed as soon as they are merged, or the
+    /// iterator is dropped. Without spilling, all the items are kept in memory.
+    ///
+    /// Failures to write or read the files make the iterator panic, or are returned
+    /// by [`ParallelSort::results`].
+    pub fn spill<E, D>(
+        self,
+        dir: impl Into<PathBuf>,
+        memory_budget: usize,
+        encode: E,
+        decode: D,
+    ) -> Self
+    where
+        E: Fn(&I::Item, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static,
+        D: Fn(&mut dyn BufRead) -> io::Result<Option<I::Item>> + Send + Sync + 'static,
+    {
+        Self {
+            spill: Some(Spill {
+                dir: dir.into(),
+                memory_budget,
+                in_memory: AtomicUsize::new(0),
+                encode: Arc::new(encode),
+                decode: Arc::new(decode),
+            }),
+            ..self
+        }
+    }
+
+    pub fn with<KF, K>(self, key: KF) -> ParallelSort<I, KF, K>
+    where
+        I::Item: Send + 'static,
+        KF: FnMut(&I::Item) -> K + 'static + Send + Clone,
+        K: Ord,
+    {
+        let spill = self.spill.map(Arc::new);
+        let runs = ParallelMapBuilder::new(Chunks::new(self.iter, self.run_size))
+            .threads(self.num_threads.unwrap_or(0))
+            .with({
+                let mut sorter = Sorter {
+                    key: key.clone(),
+                    spill: spill.clone(),
+                };
+                move |run| sorter.sort(run)
+            });
+
+        ParallelSort::new(runs, key, spill)
+    }
+
+    /// See [`ParallelSortBuilder::with`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    pub fn with_scoped<'env, 'scope, KF, K>(
+        self,
+        scope: &'scope Scope<'env>,
+        key: KF,
+    ) -> ParallelSort<I, KF, K>
+    where
+        I::Item: Send + 'env,
+        KF: FnMut(&I::Item) -> K + 'env + Send + Clone,
+        K: Ord,
+    {
+        let spill = self.spill.map(Arc::new);
+        let runs = ParallelMapBuilder::new(Chunks::new(self.iter, self.run_size))
+            .threads(self.num_threads.unwrap_or(0))
+            .with_scoped(scope, {
+                let mut sorter = Sorter {
+                    key: key.clone(),
+                    spill: spill.clone(),
+                };
+                move |run| sorter.sort(run)
+            });
+
+        ParallelSort::new(runs, key, spill)
+    }
+}
+
+/// Sorts runs on the worker threads, and spills them if needed
+struct Sorter<KF, T> {
+    key: KF,
+    spill: Option<Arc<Spill<T>>>,
+}
+
+impl<KF, T> Clone for Sorter<KF, T>
+where
+    KF: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            spill: self.spill.clone(),
+        }
+    }
+}
+
+impl<KF, T> Sorter<KF, T> {
+    fn sort<K>(&mut self, mut run: Vec<T>) -> io::Result<Run<T>>
+    where
+        KF: FnMut(&T) -> K,
+        K: Ord,
+    {
+        let key = &mut self.key;
+        run.sort_by_key(|item| (key)(item));
+        match self.spill.as_ref() {
+            Some(spill) => spill.store(run),
+            None => Ok(Run::Memory(run)),
+        }
+    }
+}
+
+/// Merging of sorted runs
+struct Merge<K, T> {
+    // the next item of every non-empty run
+    heads: BinaryHeap<Reverse<Head<K, T>>>,
+    runs: Vec<RunIter<T>>,
+    // number of items not returned yet
+    remaining: usize,
+    // reading a run failed; reported after the item already read is returned
+    error: Option<io::Error>,
+}
+
+/// Sorts items by key, using multiple threads
+///
+/// See [`crate::IteratorExt::parallel_sort_by_key`].
+pub struct ParallelSort<I, KF, K>
+where
+    I: Iterator,
+{
+    // runs being sorted, until they are all done
+    runs: Option<Runs<I>>,
+    // merge of all the runs, once they are done
+    merge: Option<Merge<K, I::Item>>,
+    // computes the key of each item
+    key: KF,
+    // spilling of runs, if enabled
+    spill: Option<Arc<Spill<I::Item>>>,
+}
+
+impl<I, KF, K> fmt::Debug for ParallelSort<I, KF, K>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelSort")
+            .field("runs", &self.runs)
+            .field(
+                "merging",
+                &self.merge.as_ref().map(|merge| merge.runs.len()),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, KF, K> ParallelSort<I, KF, K>
+where
+    I: Iterator,
+    I::Item: Send,
+    KF: FnMut(&I::Item) -> K,
+    K: Ord,
+{
+    fn new(runs: Runs<I>, key: KF, spill: Option<Arc<Spill<I::Item>>>) -> Self {
+        Self {
+            runs: Some(runs),
+            merge: None,
+            key,
+            spill,
+        }
+    }
+
+    /// Wait for all the runs to be sorted, and start merging them
+    fn start_merge(&mut self) -> io::Result<()> {
+        if let Some(runs) = self.runs.take() {
+            let runs = runs.collect::<io::Result<Vec<_>>>()?;
+            let remaining = runs.iter().map(Run::len).sum();
+            let mut runs = runs
+                .into_iter()
+                .map(|run| run.into_iter(self.spill.as_ref()))
+                .collect::<io::Result<Vec<_>>>()?;
+
+            let mut heads = BinaryHeap::with_capacity(runs.len());
+            for (i, run) in runs.iter_mut().enumerate() {
+                if let Some(item) = run.next().transpose()? {
+                    heads.push(Reverse(Head {
+                        key: (self.key)(&item),
+                        run: i,
+                        item,
+                    }));
+                }
+            }
+
+            self.merge = Some(Merge {
+                heads,
+                runs,
+                remaining,
+                error: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Like [`Iterator::next`], but returns I/O failures of spilling as errors, instead of panicking
+    fn try_next(&mut self) -> io::Result<Option<I::Item>> {
+        self.start_merge()?;
+        // merging failed to start
+        let merge = match self.merge.as_mut() {
+            Some(merge) => merge,
+            None => return Ok(None),
+        };
+        if let Some(e) = merge.error.take() {
+            return Err(e);
+        }
+
+        let Reverse(head) = match merge.heads.pop() {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        merge.remaining -= 1;
+        match merge.runs[head.run].next().transpose() {
+            Ok(Some(item)) => merge.heads.push(Reverse(Head {
+                key: (self.key)(&item),
+                run: head.run,
+                item,
+            })),
+            Ok(None) => {}
+            Err(e) => merge.error = Some(e),
+        }
+
+        Ok(Some(head.item))
+    }
+
+    /// Return I/O failures of spilling as `Err` items, instead of panicking
+    ///
+    /// Nothing is returned after the first error. Worker panics still panic,
+    /// see [`ParallelMap::results`].
+    pub fn results(self) -> ParallelSortResults<I, KF, K> {
+        ParallelSortResults {
+            iter: self,
+            failed: false,
+        }
+    }
+}
+
+impl<I, KF, K> Iterator for ParallelSort<I, KF, K>
+where
+    I: Iterator,
+    I::Item: Send,
+    KF: FnMut(&I::Item) -> K,
+    K: Ord,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next()
+            .unwrap_or_else(|e| panic!("parallel_sort {}", e))
+    }
+
+    /// Exact once all the input was sorted, i.e. after the first item is returned
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match (self.merge.as_ref(), self.runs.as_ref()) {
+            (Some(merge), _) if merge.error.is_some() => (0, Some(merge.remaining)),
+            (Some(merge), _) => (merge.remaining, Some(merge.remaining)),
+            // not known until all the input is drained
+            (None, Some(_)) => (0, None),
+            // merging failed to start
+            (None, None) => (0, Some(0)),
+        }
+    }
+}
+
+/// [`ParallelSort`] returning I/O failures of spilling as errors
+///
+/// See [`ParallelSort::results`].
+pub struct ParallelSortResults<I, KF, K>
+where
+    I: Iterator,
+{
+    iter: ParallelSort<I, KF, K>,
+    // was an error returned already
+    failed: bool,
+}
+
+impl<I, KF, K> fmt::Debug for ParallelSortResults<I, KF, K>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelSortResults")
+            .field("iter", &self.iter)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl<I, KF, K> Iterator for ParallelSortResults<I, KF, K>
+where
+    I: Iterator,
+    I::Item: Send,
+    KF: FnMut(&I::Item) -> K,
+    K: Ord,
+{
+    type Item = io::Result<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let res = self.iter.try_next().transpose();
+        self.failed = matches!(res, Some(Err(_)));
+        res
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            let (_lower, upper) = self.iter.size_hint();
+            // one more item for the error
+            (0, upper.and_then(|upper| upper.checked_add(1)))
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{ParallelMap, ParallelMapBuilder, Scope};
+
+use std::{
+    cmp,
+    collections::{hash_map::DefaultHasher, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+/// Number of shards of [`ShardedSet`]
+const SHARDS: usize = 16;
+
+/// A set that can be inserted into from multiple threads
+///
+/// Keys are spread over multiple independently locked shards,
+/// to keep the contention low.
+pub(crate) struct ShardedSet<K> {
+    shards: Vec<Mutex<HashSet<K>>>,
+}
+
+impl<K> ShardedSet<K>
+where
+    K: Hash + Eq,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::new(HashSet::new())).collect(),
+        }
+    }
+
+    /// Insert `key`, returning `true` if it was not present yet
+    pub(crate) fn insert(&self, key: K) -> bool {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let shard = &self.shards[(hasher.finish() % SHARDS as u64) as usize];
+
+        shard
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(key)
+    }
+}
+
+/// What a worker decided about an item
+enum Verdict<K, T> {
+    /// Needs to be checked on the calling thread, in order
+    Keyed(K, T),
+    /// First occurrence of its key
+    Kept(T),
+    /// Duplicate
+    Dropped,
+}
+
+/// Wrap `key` into a function deciding about the items on the worker threads
+fn verdict<T, K, F>(
+    relaxed: bool,
+    seen: Arc<ShardedSet<K>>,
+    mut key: F,
+) -> impl FnMut(T) -> Verdict<K, T> + Clone
+where
+    K: Hash + Eq,
+    F: FnMut(&T) -> K + Clone,
+{
+    move |item| {
+        let key = (key)(&item);
+        if !relaxed {
+            Verdict::Keyed(key, item)
+        } else if seen.insert(key) {
+            Verdict::Kept(item)
+        } else {
+            Verdict::Dropped
+        }
+    }
+}
+
+pub struct ParallelUniqueBuilder<I>
+where
+    I: Iterator,
+{
+    // the builder of the underlying `parallel_map`
+    inner: ParallelMapBuilder<I>,
+    // let the workers decide which duplicate to keep
+    relaxed: bool,
+}
+
+impl<I> fmt::Debug for ParallelUniqueBuilder<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelUniqueBuilder")
+            .field("inner", &self.inner)
+            .field("relaxed", &self.relaxed)
+            .finish()
+    }
+}
+
+impl<I> ParallelUniqueBuilder<I>
+where
+    I: Iterator,
+{
+    pub fn new(iter: I) -> Self {
+        Self {
+            inner: ParallelMapBuilder::new(iter),
+            relaxed: false,
+        }
+    }
+
+    pub fn threads(self, num: usize) -> Self {
+        Self {
+            inner: self.inner.threads(num),
+            ..self
+        }
+    }
+
+    pub fn buffer_size(self, num: usize) -> Self {
+        Self {
+            inner: self.inner.buffer_size(num),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapBuilder::limit`]
+    pub fn limit(self, num: usize) -> Self {
+        Self {
+            inner: self.inner.limit(num),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapBuilder::name`]
+    pub fn name(self, name: impl Into<String>) -> Self {
+        Self {
+            inner: self.inner.name(name),
+            ..self
+        }
+    }
+
+    /// Let the worker threads decide which item of the same key is kept
+    ///
+    /// By default, the first occurrence of every key in the inner iterator is kept,
+    /// which requires checking the keys on the calling thread. In relaxed mode
+    /// the workers check the keys themselves, so any of the duplicates can be kept.
+    /// The items that are kept are still returned in order.
+    pub fn relaxed(self) -> Self {
+        Self {
+            relaxed: true,
+            ..self
+        }
+    }
+
+    pub fn with<F, K>(self, key: F) -> ParallelUnique<I, K>
+    where
+        I::Item: Send + 'static,
+        F: FnMut(&I::Item) -> K + 'static + Send + Clone,
+        K: Hash + Eq + Send + 'static,
+    {
+        let seen = Arc::new(ShardedSet::new());
+        ParallelUnique {
+            iter: self.inner.with(verdict(self.relaxed, seen.clone(), key)),
+            seen,
+        }
+    }
+
+    /// See [`ParallelUniqueBuilder::with`]
+    ///
+    /// # Panics
+    ///
+    /// On targets without threads, see [`ParallelMapBuilder::with_scoped`].
+    pub fn with_scoped<'env, 'scope, F, K>(
+        self,
+        scope: &'scope Scope<'env>,
+        key: F,
+    ) -> ParallelUnique<I, K>
+    where
+        I::Item: Send + 'env,
+        F: FnMut(&I::Item) -> K + 'env + Send + Clone,
+        K: Hash + Eq + Send + 'env,
+    {
+        let seen = Arc::new(ShardedSet::new());
+        ParallelUnique {
+            iter: self
+                .inner
+                .with_scoped(scope, verdict(self.relaxed, seen.clone(), key)),
+            seen,
+        }
+    }
+}
+
+/// Returns only the first item of every key, computing the keys
+/// on multiple threads
+///
+/// See [`crate::IteratorExt::parallel_unique`].
+pub struct ParallelUnique<I, K>
+where
+    I: Iterator,
+{
+    // the iterator we wrapped
+    iter: ParallelMap<I, Verdict<K, I::Item>>,
+    // keys seen so far
+    seen: Arc<ShardedSet<K>>,
+}
+
+impl<I, K> fmt::Debug for ParallelUnique<I, K>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelUnique")
+            .field("iter", &self.iter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, K> Iterator for ParallelUnique<I, K>
+where
+    I: Iterator,
+    I::Item: Send,
+    K: Hash + Eq + Send,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next()? {
+                Verdict::Keyed(key, item) => {
+                    if self.seen.insert(key) {
+                        return Some(item);
+                    }
+                }
+                Verdict::Kept(item) => return Some(item),
+                Verdict::Dropped => {}
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // all the items could be duplicates of the first one
+        let (lower, upper) = self.iter.size_hint();
+        (cmp::min(lower, 1), upper)
+    }
+}
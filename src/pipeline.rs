@@ -0,0 +1,750 @@
+//! Declaring pipelines as named stages
+//!
+//! Instead of chaining adapters by hand, declare the stages with names and options,
+//! and let the [`Pipeline`] wire them consistently: every parallel stage is named
+//! after its stage (see [`crate::Describe`]), and profiled if
+//! [`Pipeline::profile_stages`] was used.
+//!
+//! ```
+//! use pariter::pipeline::{par_filter, par_map, Pipeline};
+//!
+//! let mut sum = 0;
+//! Pipeline::from(0..100)
+//!     .stage("square", par_map(|x: u64| x * x).threads(4))
+//!     .stage("even", par_filter(|x: &u64| x % 2 == 0))
+//!     .stage("offset", |iter: pariter::pipeline::StageIter<_>| iter.map(|x| x + 1))
+//!     .sink(|x| {
+//!         sum += x;
+//!         Ok::<_, std::convert::Infallible>(())
+//!     })
+//!     .run()
+//!     .unwrap();
+//! ```
+//!
+//! Pipelines built inside the stages of other pipelines, e.g. in the closures of
+//! [`par_map`], are nested under them: their stages are named after the path of
+//! the enclosing stages (like `outer/inner`, see [`current_stage_path`]), and
+//! profiled with the profilers of the outermost pipeline, unless they set their own.
+use crate::{
+    profile::Profiler, Describe, ParallelFilter, ParallelFilterBuilder, ParallelMap,
+    ParallelMapBuilder, PipelineNode, ProfileIngress, Readahead, ReadaheadBuilder, Scope,
+};
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Creates the profiler of a stage, given its name
+type StageProfilerFactory = Arc<dyn Fn(&str) -> Box<dyn Profiler + Send> + Send + Sync>;
+
+thread_local! {
+    /// The stage the current thread is working for, if any
+    static CURRENT_STAGE: RefCell<Option<StageContext>> = const { RefCell::new(None) };
+}
+
+/// The stage a thread is working for, inherited by the pipelines built on it
+#[derive(Clone)]
+pub(crate) struct StageContext {
+    // names of all the enclosing stages, joined with `/`
+    path: String,
+    profiler: Option<StageProfilerFactory>,
+}
+
+impl StageContext {
+    /// The context of the thread, if any
+    pub(crate) fn current() -> Option<Self> {
+        CURRENT_STAGE.with(|current| current.borrow().clone())
+    }
+
+    /// Make `self` the context of the thread, until the returned guard is dropped
+    pub(crate) fn enter(self) -> StageContextGuard {
+        let previous = CURRENT_STAGE.with(|current| current.replace(Some(self)));
+        StageContextGuard { previous }
+    }
+}
+
+/// Restores the previous [`StageContext`] of the thread
+pub(crate) struct StageContextGuard {
+    previous: Option<StageContext>,
+}
+
+impl Drop for StageContextGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT_STAGE.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Path of the stage `name`, nested under the current stage, if any
+fn qualified_name(name: &str) -> String {
+    match current_stage_path() {
+        Some(path) => format!("{}/{}", path, name),
+        None => name.to_owned(),
+    }
+}
+
+/// Names of all the stages of the [`Pipeline`]s the current thread is working for,
+/// joined with `/`, e.g. `outer/inner`
+///
+/// `None` outside of the worker threads of pipeline stages.
+pub fn current_stage_path() -> Option<String> {
+    CURRENT_STAGE.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(|context| context.path.clone())
+    })
+}
+
+/// The iterator returned by every stage of a [`Pipeline`]
+///
+/// Measures the time spent waiting for the stage to produce items,
+/// if [`Pipeline::profile_stages`] was used.
+pub type StageIter<I> = ProfileIngress<I, Box<dyn Profiler + Send>>;
+
+/// A boxed iterator, to assemble pipelines at runtime
+///
+/// See [`Pipeline::boxed`] and [`Pipeline::boxed_stage`].
+pub type BoxedIter<T> = Box<dyn Iterator<Item = T> + Send>;
+
+/// A [`ParallelMap`] of the same type regardless of the stages before it
+pub type BoxedParallelMap<T, O> = ParallelMap<BoxedIter<T>, O>;
+
+/// A [`DynStage`] boxed, for stages picked at runtime
+pub type BoxedStage<T, O> = Box<dyn DynStage<T, O>>;
+
+/// Box `iter`, hiding its type
+pub fn boxed<I>(iter: I) -> BoxedIter<I::Item>
+where
+    I: Iterator + Send + 'static,
+{
+    Box::new(iter)
+}
+
+/// An object-safe version of [`Stage`], for stages picked at runtime
+///
+/// Implemented for all the [`Stage`]s of boxed iterators.
+pub trait DynStage<T, O>: Send {
+    /// Wrap `iter` in the stage named `name`
+    fn build_boxed(self: Box<Self>, name: &str, iter: BoxedIter<T>) -> BoxedIter<O>;
+}
+
+impl<S, T, O> DynStage<T, O> for S
+where
+    S: Stage<BoxedIter<T>> + Send,
+    S::Output: Iterator<Item = O> + Send + 'static,
+{
+    fn build_boxed(self: Box<Self>, name: &str, iter: BoxedIter<T>) -> BoxedIter<O> {
+        Box::new((*self).build(name, iter))
+    }
+}
+
+/// A stage of a [`Pipeline`]
+///
+/// Implemented by [`par_map`], [`par_filter`], [`readahead`], and any `FnOnce`
+/// transforming the iterator (for sequential stages).
+pub trait Stage<I> {
+    type Output: Iterator;
+
+    /// Wrap `iter` in the stage named `name`
+    fn build(self, name: &str, iter: I) -> Self::Output;
+}
+
+impl<I, F, O> Stage<I> for F
+where
+    F: FnOnce(I) -> O,
+    O: Iterator,
+{
+    type Output = O;
+
+    fn build(self, _name: &str, iter: I) -> Self::Output {
+        (self)(iter)
+    }
+}
+
+/// A [`crate::IteratorExt::parallel_map`] stage
+#[derive(Debug, Clone)]
+pub struct ParMap<F> {
+    f: F,
+    threads: Option<usize>,
+    buffer_size: Option<usize>,
+}
+
+/// Declare a [`crate::IteratorExt::parallel_map`] stage
+pub fn par_map<F>(f: F) -> ParMap<F> {
+    ParMap {
+        f,
+        threads: None,
+        buffer_size: None,
+    }
+}
+
+impl<F> ParMap<F> {
+    /// See [`ParallelMapBuilder::threads`]
+    pub fn threads(self, num: usize) -> Self {
+        Self {
+            threads: Some(num),
+            ..self
+        }
+    }
+
+    /// See [`ParallelMapBuilder::buffer_size`]
+    pub fn buffer_size(self, num: usize) -> Self {
+        Self {
+            buffer_size: Some(num),
+            ..self
+        }
+    }
+}
+
+impl<I, F, O> Stage<I> for ParMap<F>
+where
+    I: Iterator,
+    I::Item: Send + 'static,
+    F: FnMut(I::Item) -> O + Send + Clone + 'static,
+    O: Send + 'static,
+{
+    type Output = ParallelMap<I, O>;
+
+    fn build(self, name: &str, iter: I) -> Self::Output {
+        let mut builder = ParallelMapBuilder::new(iter).name(name);
+        if let Some(threads) = self.threads {
+            builder = builder.threads(threads);
+        }
+        if let Some(buffer_size) = self.buffer_size {
+            builder = builder.buffer_size(buffer_size);
+        }
+        builder.with(self.f)
+    }
+}
+
+/// A [`crate::IteratorExt::parallel_filter`] stage
+#[derive(Debug, Clone)]
+pub struct ParFilter<F> {
+    f: F,
+    threads: Option<usize>,
+    buffer_size: Option<usize>,
+}
+
+/// Declare a [`crate::IteratorExt::parallel_filter`] stage
+pub fn par_filter<F>(f: F) -> ParFilter<F> {
+    ParFilter {
+        f,
+        threads: None,
+        buffer_size: None,
+    }
+}
+
+impl<F> ParFilter<F> {
+    /// See [`ParallelFilterBuilder::threads`]
+    pub fn threads(self, num: usize) -> Self {
+        Self {
+            threads: Some(num),
+            ..self
+        }
+    }
+
+    /// See [`ParallelFilterBuilder::buffer_size`]
+    pub fn buffer_size(self, num: usize) -> Self {
+        Self {
+            buffer_size: Some(num),
+            ..self
+        }
+    }
+}
+
+impl<I, F> Stage<I> for ParFilter<F>
+where
+    I: Iterator,
+    I::Item: Send + 'static,
+    F: FnMut(&I::Item) -> bool + Send + Clone + 'static,
+{
+    type Output = ParallelFilter<I>;
+
+    fn build(self, name: &str, iter: I) -> Self::Output {
+        let mut builder = ParallelFilterBuilder::new(iter).name(name);
+        if let Some(threads) = self.threads {
+            builder = builder.threads(threads);
+        }
+        if let Some(buffer_size) = self.buffer_size {
+            builder = builder.buffer_size(buffer_size);
+        }
+        builder.with(self.f)
+    }
+}
+
+/// A [`crate::IteratorExt::readahead`] stage
+#[derive(Debug, Clone, Default)]
+pub struct ReadaheadStage {
+    buffer_size: Option<usize>,
+}
+
+/// Declare a [`crate::IteratorExt::readahead`] stage
+pub fn readahead() -> ReadaheadStage {
+    ReadaheadStage::default()
+}
+
+impl ReadaheadStage {
+    /// See [`ReadaheadBuilder::buffer_size`]
+    pub fn buffer_size(self, num: usize) -> Self {
+        Self {
+            buffer_size: Some(num),
+        }
+    }
+}
+
+impl<I> Stage<I> for ReadaheadStage
+where
+    I: Iterator + Send + 'static,
+    I::Item: Send + 'static,
+{
+    type Output = Readahead<I>;
+
+    fn build(self, _name: &str, iter: I) -> Self::Output {
+        let mut builder = ReadaheadBuilder::new(iter);
+        if let Some(buffer_size) = self.buffer_size {
+            builder = builder.buffer_size(buffer_size);
+        }
+        builder.with()
+    }
+}
+
+/// A pipeline of named stages
+///
+/// See the [module documentation](self).
+pub struct Pipeline<I> {
+    iter: I,
+    profiler: Option<StageProfilerFactory>,
+}
+
+impl<I> From<I> for Pipeline<I>
+where
+    I: Iterator,
+{
+    /// Nested pipelines inherit the profilers of the enclosing one
+    fn from(iter: I) -> Self {
+        Self {
+            iter,
+            profiler: StageContext::current().and_then(|context| context.profiler),
+        }
+    }
+}
+
+impl<I> fmt::Debug for Pipeline<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("profiler", &self.profiler.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> Pipeline<I>
+where
+    I: Iterator,
+{
+    /// Profile all the following stages, with profilers created by `make` from the stage names
+    ///
+    /// The profilers measure the time spent waiting for each stage to produce items,
+    /// like [`crate::IteratorExt::profile_ingress`].
+    pub fn profile_stages<M, P>(self, make: M) -> Self
+    where
+        M: Fn(&str) -> P + Send + Sync + 'static,
+        P: Profiler + Send + 'static,
+    {
+        Self {
+            profiler: Some(Arc::new(move |name| {
+                Box::new(make(name)) as Box<dyn Profiler + Send>
+            })),
+            ..self
+        }
+    }
+
+    /// Add a stage named `name`
+    pub fn stage<S>(self, name: &str, stage: S) -> Pipeline<StageIter<S::Output>>
+    where
+        S: Stage<I>,
+    {
+        let path = qualified_name(name);
+        let profiler = match self.profiler.as_ref() {
+            Some(make) => (make)(&path),
+            None => Box::new(()),
+        };
+
+        // the worker threads of the stage inherit the context
+        let context = StageContext {
+            path,
+            profiler: self.profiler.clone(),
+        }
+        .enter();
+        let iter = stage.build(name, self.iter);
+        drop(context);
+
+        Pipeline {
+            iter: ProfileIngress::new(iter, profiler),
+            profiler: self.profiler,
+        }
+    }
+
+    /// Box the iterator, so stages can be added conditionally, at runtime
+    ///
+    /// ```
+    /// use pariter::pipeline::{par_filter, par_map, BoxedStage, Pipeline};
+    ///
+    /// let skip_odd = true;
+    /// let mut pipeline = Pipeline::from(0..10u32)
+    ///     .stage("double", par_map(|x: u32| x * 3))
+    ///     .boxed();
+    /// if skip_odd {
+    ///     let stage: BoxedStage<u32, u32> = Box::new(par_filter(|x: &u32| x % 2 == 0));
+    ///     pipeline = pipeline.boxed_stage("even", stage);
+    /// }
+    ///
+    /// assert_eq!(pipeline.count(), 5);
+    /// ```
+    pub fn boxed(self) -> Pipeline<BoxedIter<I::Item>>
+    where
+        I: Send + 'static,
+    {
+        Pipeline {
+            iter: boxed(self.iter),
+            profiler: self.profiler,
+        }
+    }
+
+    /// Like [`Pipeline::stage`], but with a boxed stage, keeping the type of the pipeline
+    pub fn boxed_stage<O>(self, name: &str, stage: BoxedStage<I::Item, O>) -> Pipeline<BoxedIter<O>>
+    where
+        I: Send + 'static,
+        O: Send + 'static,
+    {
+        let path = qualified_name(name);
+        let profiler = match self.profiler.as_ref() {
+            Some(make) => (make)(&path),
+            None => Box::new(()),
+        };
+
+        let context = StageContext {
+            path,
+            profiler: self.profiler.clone(),
+        }
+        .enter();
+        let iter = stage.build_boxed(name, boxed(self.iter));
+        drop(context);
+
+        Pipeline {
+            iter: boxed(ProfileIngress::new(iter, profiler)),
+            profiler: self.profiler,
+        }
+    }
+
+    /// Consume the items with `sink`
+    ///
+    /// Nothing happens until [`PipelineSink::run`] is called.
+    pub fn sink<F, E>(self, sink: F) -> PipelineSink<I, F>
+    where
+        F: FnMut(I::Item) -> Result<(), E>,
+    {
+        PipelineSink {
+            iter: self.iter,
+            sink,
+        }
+    }
+}
+
+impl<I> Iterator for Pipeline<I>
+where
+    I: Iterator,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<I> Describe for Pipeline<I>
+where
+    I: Describe,
+{
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        self.iter.describe_into(nodes);
+    }
+}
+
+/// Runs pipelines to completion, owning the threads of all their stages
+///
+/// The stages are built inside a [`crate::scope`], so they can borrow from the
+/// environment, and all their threads are joined before [`PipelineRunner::run`] returns.
+/// Instead of panicking, failures are recorded in the returned [`PipelineReport`].
+///
+/// ```
+/// use pariter::{pipeline::PipelineRunner, IteratorExt};
+///
+/// let offset = 1;
+/// let report = PipelineRunner::new(0..100u64).run(
+///     |scope, pipeline| {
+///         pipeline.stage("offset", |iter: std::ops::Range<u64>| {
+///             iter.parallel_map_scoped(scope, |x| x + offset)
+///         })
+///     },
+///     |_| Ok::<_, std::convert::Infallible>(()),
+/// );
+///
+/// assert!(report.is_success());
+/// assert_eq!(report.items(), 100);
+/// ```
+pub struct PipelineRunner<I> {
+    source: I,
+}
+
+impl<I> fmt::Debug for PipelineRunner<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipelineRunner").finish_non_exhaustive()
+    }
+}
+
+impl<I> PipelineRunner<I>
+where
+    I: Iterator,
+{
+    /// Create a runner of pipelines pulling the items from `source`
+    pub fn new(source: I) -> Self {
+        Self { source }
+    }
+
+    /// Build the pipeline with `build`, and consume its items with `sink`
+    ///
+    /// Stops at the first error returned by the sink, or the first panic,
+    /// of either the sink, or any of the stage threads.
+    ///
+    /// The stages are profiled (see [`Pipeline::profile_stages`]), to report
+    /// the time spent waiting for each of them, so `build` should not replace the profilers.
+    pub fn run<'env, B, O, S, E>(self, build: B, mut sink: S) -> PipelineReport<E>
+    where
+        B: FnOnce(&Scope<'env>, Pipeline<I>) -> Pipeline<O>,
+        O: Iterator,
+        S: FnMut(O::Item) -> Result<(), E>,
+    {
+        let stages = Arc::new(Mutex::new(vec![]));
+        let pipeline = Pipeline::from(self.source).profile_stages({
+            let stages = stages.clone();
+            move |name| StageProfiler::new(name, &stages)
+        });
+
+        let start = Instant::now();
+        let mut items = 0;
+        let mut error = None;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::scope(|scope| {
+                for item in build(scope, pipeline) {
+                    if let Err(e) = (sink)(item) {
+                        error = Some(e);
+                        break;
+                    }
+                    items += 1;
+                }
+            })
+        }));
+
+        let panic_message = match result {
+            Ok(Ok(())) => None,
+            Ok(Err(panic)) => Some(panic_message(panic)),
+            Err(panic) => Some(panic_message(panic)),
+        };
+
+        let stages = stages
+            .lock()
+            .expect("lock")
+            .iter()
+            .map(|stage: &Arc<Mutex<StageReport>>| stage.lock().expect("lock").clone())
+            .collect();
+
+        PipelineReport {
+            items,
+            elapsed: start.elapsed(),
+            stages,
+            error,
+            panic_message,
+        }
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    // panics of stage threads, collected by the scope
+    let panic = match panic.downcast::<Vec<Box<dyn Any + Send>>>() {
+        Ok(mut panics) if !panics.is_empty() => panics.swap_remove(0),
+        Ok(_) => return "unknown panic".into(),
+        Err(panic) => panic,
+    };
+
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".into()
+    }
+}
+
+/// Records the time spent waiting for a stage of a [`PipelineRunner`]
+struct StageProfiler {
+    report: Arc<Mutex<StageReport>>,
+    start: Instant,
+}
+
+impl StageProfiler {
+    /// Stages with the same name, e.g. nested in every item of a stage, share the report
+    fn new(name: &str, stages: &Mutex<Vec<Arc<Mutex<StageReport>>>>) -> Self {
+        let mut stages = stages.lock().expect("lock");
+        let existing = stages
+            .iter()
+            .find(|stage| stage.lock().expect("lock").name == name)
+            .cloned();
+        let report = existing.unwrap_or_else(|| {
+            let report = Arc::new(Mutex::new(StageReport {
+                name: name.to_owned(),
+                waited: Duration::default(),
+            }));
+            stages.push(report.clone());
+            report
+        });
+        Self {
+            report,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Profiler for StageProfiler {
+    fn start(&mut self) {
+        self.start = Instant::now();
+    }
+
+    fn end(&mut self) {
+        let elapsed = self.start.elapsed();
+        self.report.lock().expect("lock").waited += elapsed;
+    }
+}
+
+/// Stats of a single stage, in a [`PipelineReport`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageReport {
+    name: String,
+    waited: Duration,
+}
+
+impl StageReport {
+    /// Name of the stage, prefixed with the names of the enclosing stages, if nested
+    ///
+    /// See [`current_stage_path`].
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of stages this one is nested in
+    pub fn depth(&self) -> usize {
+        self.name.matches('/').count()
+    }
+
+    /// Total time spent waiting for the stage to produce items
+    ///
+    /// Includes the time spent waiting for the stages before it.
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+}
+
+/// The outcome of a [`PipelineRunner::run`]
+#[derive(Debug)]
+pub struct PipelineReport<E> {
+    items: usize,
+    elapsed: Duration,
+    stages: Vec<StageReport>,
+    error: Option<E>,
+    panic_message: Option<String>,
+}
+
+impl<E> PipelineReport<E> {
+    /// Number of items consumed successfully by the sink
+    pub fn items(&self) -> usize {
+        self.items
+    }
+
+    /// Total duration of the run
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Stats of every stage, in the order they were added
+    pub fn stages(&self) -> &[StageReport] {
+        &self.stages
+    }
+
+    /// The error returned by the sink, if any
+    pub fn error(&self) -> Option<&E> {
+        self.error.as_ref()
+    }
+
+    /// Message of the first panic, of the sink or any of the stage threads, if any
+    pub fn panic_message(&self) -> Option<&str> {
+        self.panic_message.as_deref()
+    }
+
+    /// Did the pipeline run to completion, with no errors and no panics
+    pub fn is_success(&self) -> bool {
+        self.error.is_none() && self.panic_message.is_none()
+    }
+
+    /// The error returned by the sink, if any
+    ///
+    /// # Panics
+    ///
+    /// Resumes the first panic, if the pipeline panicked.
+    pub fn into_result(self) -> Result<(), E> {
+        if let Some(message) = self.panic_message {
+            panic!("{}", message);
+        }
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A [`Pipeline`] with the consumer of its items
+///
+/// See [`Pipeline::sink`].
+pub struct PipelineSink<I, F> {
+    iter: I,
+    sink: F,
+}
+
+impl<I, F> fmt::Debug for PipelineSink<I, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipelineSink").finish_non_exhaustive()
+    }
+}
+
+impl<I, F, E> PipelineSink<I, F>
+where
+    I: Iterator,
+    F: FnMut(I::Item) -> Result<(), E>,
+{
+    /// Run the pipeline to completion, stopping at the first error returned by the sink
+    pub fn run(self) -> Result<(), E> {
+        let mut sink = self.sink;
+        for item in self.iter {
+            (sink)(item)?;
+        }
+        Ok(())
+    }
+}
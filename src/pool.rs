@@ -0,0 +1,394 @@
+use crate::{Scope, Spawner};
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Virtual time a job of weight 1 takes, in the weighted dispatch of queued jobs
+const STRIDE: u64 = 1 << 20;
+
+/// Keeps the worker threads of stages alive between runs, to reuse them
+///
+/// A [`Spawner`]: pass clones to [`crate::ParallelMapBuilder::spawner`] of stages
+/// run repeatedly, e.g. once per input file, so their workers start on already
+/// spawned threads. Threads are only spawned when none are idle, as every worker
+/// occupies a thread until its stage is done.
+///
+/// The threads exit once all the clones of the pool are dropped, and the jobs they
+/// run are done.
+///
+/// ```
+/// use pariter::{IteratorExt as _, Pool};
+///
+/// let pool = Pool::new();
+/// for file in 0..3 {
+///     let sum: u64 = (0..100u64)
+///         .parallel_map_custom(|o| o.threads(4).spawner(pool.clone()), move |x| x * file)
+///         .sum();
+///     assert_eq!(sum, 4950 * file);
+/// }
+/// assert!(pool.threads() <= 4);
+/// ```
+///
+/// With [`Pool::max_threads`] set, the jobs waiting for a thread are dispatched
+/// between the pipelines in proportion to the weights given with [`Pool::weighted`].
+/// A job is a whole worker, running until its stage is done, so this only decides
+/// which pipelines get the threads freed up first. It's not fair scheduling: the
+/// items of pipelines already running aren't interleaved with the waiting ones.
+#[derive(Clone)]
+pub struct Pool {
+    handle: Arc<PoolHandle>,
+    lane: usize,
+    weight: u32,
+}
+
+/// Shuts the threads down when the last clone of the [`Pool`] is dropped
+struct PoolHandle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    job_ready: Condvar,
+}
+
+/// Health of a [`Pool`], as returned by [`Pool::stats`]
+///
+/// The jobs are the workers of the stages spawning with the pool, each processing
+/// any number of items, so the counts of jobs say nothing about the throughput of
+/// the pipelines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PoolStats {
+    /// Threads alive
+    pub threads: usize,
+    /// Threads waiting for jobs
+    pub idle: usize,
+    /// Jobs waiting for a thread, per [`Pool::pipeline`] with any
+    pub queued: Vec<(usize, usize)>,
+    /// Jobs done, i.e. workers that finished
+    pub jobs_completed: u64,
+    /// Time since the pool was created
+    pub uptime: Duration,
+}
+
+impl PoolStats {
+    /// Jobs waiting for a thread, of all the pipelines
+    pub fn queued_total(&self) -> usize {
+        self.queued.iter().map(|(_, queued)| queued).sum()
+    }
+
+    /// Jobs done per second, since the pool was created
+    ///
+    /// The rate at which stage workers finish, not the rate of processed items.
+    pub fn jobs_completed_per_sec(&self) -> f64 {
+        let secs = self.uptime.as_secs_f64();
+        if secs == 0.0 {
+            0.0
+        } else {
+            self.jobs_completed as f64 / secs
+        }
+    }
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} threads ({} idle), {} jobs queued, {} jobs completed ({:.2} jobs/s)",
+            self.threads,
+            self.idle,
+            self.queued_total(),
+            self.jobs_completed,
+            self.jobs_completed_per_sec()
+        )
+    }
+}
+
+/// Jobs of the pipelines spawning with the same [`Pool`] clone
+struct Lane {
+    jobs: VecDeque<Job>,
+    weight: u32,
+    // virtual time of the next job dispatched
+    pass: u64,
+}
+
+struct State {
+    // only lanes with jobs waiting
+    lanes: BTreeMap<usize, Lane>,
+    queued: usize,
+    // virtual time of the last job dispatched
+    time: u64,
+    next_lane: usize,
+    max_threads: Option<usize>,
+    completed: u64,
+    created: Instant,
+    // threads alive, and waiting for jobs
+    threads: usize,
+    idle: usize,
+    idle_timeout: Option<Duration>,
+    shutdown: bool,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Push `job` to the queue of `lane`
+    fn push(state: &mut State, lane: usize, weight: u32, job: Job) {
+        let time = state.time;
+        state
+            .lanes
+            .entry(lane)
+            .or_insert_with(|| Lane {
+                jobs: VecDeque::new(),
+                weight,
+                pass: time,
+            })
+            .jobs
+            .push_back(job);
+        state.queued += 1;
+    }
+
+    /// Pop a job of the lane behind the most in virtual time
+    fn pop(state: &mut State) -> Option<Job> {
+        let (&id, _) = state.lanes.iter().min_by_key(|(_, lane)| lane.pass)?;
+        let lane = state.lanes.get_mut(&id).expect("lane exists");
+        let job = lane.jobs.pop_front().expect("lanes are not empty");
+        let pass = lane.pass;
+        lane.pass += STRIDE / u64::from(lane.weight);
+        if lane.jobs.is_empty() {
+            state.lanes.remove(&id);
+        }
+        state.time = pass;
+        state.queued -= 1;
+        Some(job)
+    }
+
+    /// Run `job`, if any, and then the queued jobs, until shut down
+    fn run(&self, mut job: Option<Job>) {
+        loop {
+            let ran = job.take().is_some_and(|job| {
+                // the stages report the panics of their workers themselves
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                true
+            });
+
+            let mut state = self.lock();
+            if ran {
+                state.completed += 1;
+            }
+            loop {
+                if let Some(next) = Self::pop(&mut state) {
+                    job = Some(next);
+                    break;
+                }
+                if state.shutdown {
+                    state.threads -= 1;
+                    return;
+                }
+                state.idle += 1;
+                state = match state.idle_timeout {
+                    Some(timeout) => {
+                        let (state, result) = self
+                            .job_ready
+                            .wait_timeout(state, timeout)
+                            .unwrap_or_else(|poisoned| poisoned.into_inner());
+                        let mut state = state;
+                        if result.timed_out() && state.queued == 0 {
+                            state.idle -= 1;
+                            state.threads -= 1;
+                            return;
+                        }
+                        state
+                    }
+                    None => self
+                        .job_ready
+                        .wait(state)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner()),
+                };
+                state.idle -= 1;
+            }
+        }
+    }
+}
+
+impl Drop for PoolHandle {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.job_ready.notify_all();
+    }
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pool {
+    /// A pool spawning threads as needed
+    pub fn new() -> Self {
+        Self {
+            handle: Arc::new(PoolHandle {
+                shared: Arc::new(Shared {
+                    state: Mutex::new(State {
+                        lanes: BTreeMap::new(),
+                        queued: 0,
+                        time: 0,
+                        next_lane: 1,
+                        max_threads: None,
+                        completed: 0,
+                        created: Instant::now(),
+                        threads: 0,
+                        idle: 0,
+                        idle_timeout: None,
+                        shutdown: false,
+                    }),
+                    job_ready: Condvar::new(),
+                }),
+            }),
+            lane: 0,
+            weight: 1,
+        }
+    }
+
+    /// A pool with `threads` threads spawned in `scope` up front
+    ///
+    /// For pipelines run repeatedly in the same scope. The threads are joined when the scope
+    /// ends, so the pool must be dropped before that. When more workers are needed at once,
+    /// the extra threads are spawned as with [`Pool::new`].
+    ///
+    /// On targets without threads, no threads are spawned, as the stages run on the
+    /// consuming thread without spawning their workers.
+    pub fn scoped<'env>(scope: &Scope<'env>, threads: usize) -> Self {
+        let pool = Self::new();
+        if !crate::threads_supported() {
+            return pool;
+        }
+        pool.shared().lock().threads += threads;
+        for _ in 0..threads {
+            let shared = pool.shared().clone();
+            scope
+                .builder()
+                .name("pariter-pool".into())
+                .spawn(move |_scope| shared.run(None))
+                .expect("failed to spawn pool thread");
+        }
+        pool
+    }
+
+    /// Let threads idle for longer than `timeout` exit
+    ///
+    /// For long-lived services running pipelines only now and then. The threads are
+    /// spawned again when needed.
+    pub fn idle_timeout(self, timeout: Duration) -> Self {
+        self.shared().lock().idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit the number of threads to `max`
+    ///
+    /// Workers spawned when all the threads are busy wait for one to finish, so
+    /// the pipelines sharing the pool must not depend on each other, and `max` must
+    /// be at least the number of workers of every single pipeline.
+    pub fn max_threads(self, max: usize) -> Self {
+        assert!(max > 0, "max_threads must be at least 1");
+        self.shared().lock().max_threads = Some(max);
+        self
+    }
+
+    /// A clone of the pool for a pipeline with `weight`
+    ///
+    /// When jobs wait for a thread, those of the pipelines spawning with it are
+    /// dispatched `weight` times as often as those with weight 1, the weight of the
+    /// pool itself. Every call makes a separate pipeline, so the workers of a big batch
+    /// queued first don't all start before those of the pipelines queued after it.
+    ///
+    /// Only the order in which queued workers start is weighted: once started, a worker
+    /// keeps its thread until its stage is done, however many items it processes.
+    pub fn weighted(&self, weight: u32) -> Self {
+        assert!(weight > 0, "weight must be at least 1");
+        let mut state = self.shared().lock();
+        let lane = state.next_lane;
+        state.next_lane += 1;
+        Self {
+            handle: self.handle.clone(),
+            lane,
+            weight,
+        }
+    }
+
+    fn shared(&self) -> &Arc<Shared> {
+        &self.handle.shared
+    }
+
+    /// Number of threads alive
+    pub fn threads(&self) -> usize {
+        self.shared().lock().threads
+    }
+
+    /// Id of the pipeline the jobs spawned with this clone belong to, in [`PoolStats::queued`]
+    ///
+    /// `0` for the pool itself, and for its plain clones.
+    pub fn pipeline(&self) -> usize {
+        self.lane
+    }
+
+    /// Threads, queued and completed jobs, for exporting the health of the pool
+    pub fn stats(&self) -> PoolStats {
+        let state = self.shared().lock();
+        PoolStats {
+            threads: state.threads,
+            idle: state.idle,
+            queued: state
+                .lanes
+                .iter()
+                .map(|(&lane, jobs)| (lane, jobs.jobs.len()))
+                .collect(),
+            jobs_completed: state.completed,
+            uptime: state.created.elapsed(),
+        }
+    }
+}
+
+impl Spawner for Pool {
+    fn spawn(&self, job: Job) {
+        let mut state = self.shared().lock();
+        if state.queued < state.idle || state.max_threads.is_some_and(|max| max <= state.threads) {
+            Shared::push(&mut state, self.lane, self.weight, job);
+            drop(state);
+            self.shared().job_ready.notify_one();
+            return;
+        }
+
+        state.threads += 1;
+        drop(state);
+        let shared = self.shared().clone();
+        thread::Builder::new()
+            .name("pariter-pool".into())
+            .spawn(move || shared.run(Some(job)))
+            .expect("failed to spawn pool thread");
+    }
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.shared().lock();
+        f.debug_struct("Pool")
+            .field("threads", &state.threads)
+            .field("idle", &state.idle)
+            .field("queued", &state.queued)
+            .finish()
+    }
+}
@@ -0,0 +1,42 @@
+/// Scheduling priority of worker threads
+///
+/// See [`crate::ParallelMapBuilder::priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadPriority {
+    /// Leave the priority unchanged
+    Normal,
+    /// The lowest priority available
+    Lowest,
+    /// Increase the nice value of the thread by the given amount
+    ///
+    /// Note: on Linux the nice value is per-thread, while on other unix systems
+    /// it might affect the whole process.
+    #[cfg(unix)]
+    Nice(i32),
+}
+
+impl ThreadPriority {
+    /// Apply to the current thread
+    ///
+    /// Best effort: failures are ignored, the thread just keeps running
+    /// at its current priority.
+    pub(crate) fn apply(self) {
+        match self {
+            ThreadPriority::Normal => {}
+            #[cfg(unix)]
+            ThreadPriority::Lowest => Self::nice(19),
+            #[cfg(not(unix))]
+            ThreadPriority::Lowest => {}
+            #[cfg(unix)]
+            ThreadPriority::Nice(inc) => Self::nice(inc),
+        }
+    }
+
+    #[cfg(unix)]
+    fn nice(inc: i32) {
+        // Safety: `nice` has no memory safety requirements
+        unsafe {
+            libc::nice(inc);
+        }
+    }
+}
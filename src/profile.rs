@@ -1,5 +1,20 @@
+use crate::{Describe, PipelineNode};
+
+mod builder;
+mod deadline;
+mod latency;
+mod memory;
+mod occupancy;
+mod sampled;
 mod simple;
 
+pub use builder::{ProfilerBuilder, ReportingProfiler};
+pub use deadline::{DeadlineMonitor, DeadlineProfiler, DeadlineStats};
+pub use latency::{LatencyRecorder, LatencyStats, RecordLatency};
+pub use memory::MemoryMeter;
+pub(crate) use occupancy::Sampler;
+pub use occupancy::{OccupancyRecorder, OccupancySample};
+pub use sampled::{RateLimited, Sampled};
 pub use simple::{TotalTimeProfiler, TotalTimeStats};
 
 /// An interface to profile iterator consumption/prodution performance
@@ -23,6 +38,24 @@ pub trait Profiler {
     fn end(&mut self);
 }
 
+/// A profiler that does nothing
+impl Profiler for () {
+    fn start(&mut self) {}
+    fn end(&mut self) {}
+}
+
+impl<P> Profiler for Box<P>
+where
+    P: Profiler + ?Sized,
+{
+    fn start(&mut self) {
+        (**self).start()
+    }
+    fn end(&mut self) {
+        (**self).end()
+    }
+}
+
 /// Profiles the time spent waiting for the downstream
 /// iterator step to consume the previous returned item
 /// and ask for the next one (or in other words, the time
@@ -57,6 +90,24 @@ impl<I, P> ProfileIngress<I, P> {
     }
 }
 
+impl<I, P> Describe for ProfileEgress<I, P>
+where
+    I: Describe,
+{
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        self.inner.describe_into(nodes);
+    }
+}
+
+impl<I, P> Describe for ProfileIngress<I, P>
+where
+    I: Describe,
+{
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        self.inner.describe_into(nodes);
+    }
+}
+
 impl<I, P> Iterator for ProfileEgress<I, P>
 where
     I: Iterator,
@@ -76,7 +127,7 @@ where
 
         self.profiler.start();
 
-        return item;
+        item
     }
 }
 
@@ -93,6 +144,6 @@ where
         let item = self.inner.next();
 
         self.profiler.end();
-        return item;
+        item
     }
 }
@@ -0,0 +1,104 @@
+use super::{
+    simple::{TotalTimeStats, WallClockReporter},
+    TotalTimeProfiler,
+};
+
+use std::{fmt, sync::Arc, time};
+
+/// Default period of [`ProfilerBuilder`] reports
+const DEFAULT_PERIOD: time::Duration = time::Duration::from_secs(10);
+
+/// Where [`ProfilerBuilder`] profilers report to
+type Target = Arc<dyn Fn(&str, &TotalTimeStats) + Send + Sync>;
+
+/// A [`TotalTimeProfiler`] built by [`ProfilerBuilder`]
+pub type ReportingProfiler =
+    TotalTimeProfiler<WallClockReporter<Box<dyn Fn(&TotalTimeStats) + Send>>>;
+
+/// Builds [`TotalTimeProfiler`]s reporting periodically, with a stage name
+///
+/// By default, every 10 seconds, to stderr, like:
+/// `parse#3: blocked 870ms of 1s (87%)`, where `3` is a unique id of the profiler,
+/// telling apart the stages of concurrent pipelines with the same names.
+///
+/// ```
+/// use pariter::{profile::ProfilerBuilder, IteratorExt as _};
+///
+/// (0..10)
+///     .profile_egress(ProfilerBuilder::new("parse").period_millis(1000).build())
+///     .for_each(drop);
+/// ```
+#[derive(Clone)]
+pub struct ProfilerBuilder {
+    name: String,
+    period: time::Duration,
+    target: Option<Target>,
+}
+
+impl fmt::Debug for ProfilerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProfilerBuilder")
+            .field("name", &self.name)
+            .field("period", &self.period)
+            .field("target", &self.target.is_some())
+            .finish()
+    }
+}
+
+impl ProfilerBuilder {
+    /// Profile the stage named `name`
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            period: DEFAULT_PERIOD,
+            target: None,
+        }
+    }
+
+    /// Report every `period` of wall time
+    pub fn period(self, period: time::Duration) -> Self {
+        Self { period, ..self }
+    }
+
+    pub fn period_millis(self, millis: u64) -> Self {
+        self.period(time::Duration::from_millis(millis))
+    }
+
+    /// Report to stderr (the default)
+    pub fn to_stderr(self) -> Self {
+        Self {
+            target: None,
+            ..self
+        }
+    }
+
+    /// Report with `target`, e.g. to a logger or metrics, called with the name and the stats
+    ///
+    /// The name is suffixed with a unique id of the profiler, like `parse#3`.
+    pub fn to<T>(self, target: T) -> Self
+    where
+        T: Fn(&str, &TotalTimeStats) + Send + Sync + 'static,
+    {
+        Self {
+            target: Some(Arc::new(target)),
+            ..self
+        }
+    }
+
+    pub fn build(&self) -> ReportingProfiler {
+        let name = format!("{}#{}", self.name, crate::next_stage_id());
+        let report: Box<dyn Fn(&TotalTimeStats) + Send> = match self.target.clone() {
+            Some(target) => Box::new(move |stats| (target)(&name, stats)),
+            None => Box::new(move |stats| {
+                eprintln!(
+                    "{}: blocked {:?} of {:?} ({:.0}%)",
+                    name,
+                    stats.total(),
+                    stats.wall_time(),
+                    stats.utilization() * 100.0
+                )
+            }),
+        };
+        TotalTimeProfiler::every(self.period, report)
+    }
+}
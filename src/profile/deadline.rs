@@ -0,0 +1,175 @@
+use super::Profiler;
+
+use std::{
+    convert::TryFrom,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+    time,
+};
+
+/// Counts the items of each named stage taking longer than a deadline
+///
+/// Create a [`DeadlineProfiler`] for every stage with [`DeadlineMonitor::profiler`],
+/// e.g. in [`crate::pipeline::Pipeline::profile_stages`], and read the counts per
+/// stage with [`DeadlineMonitor::stats`]. Every `start`/`end` pair is an item, so,
+/// unlike the [`super::TotalTimeProfiler`] summing up the time, it tells how many
+/// items missed the deadline, which is what per-stage SLAs are about.
+///
+/// Cloning returns a handle to the same monitor.
+///
+/// ```
+/// use pariter::{pipeline::{par_map, Pipeline}, profile::DeadlineMonitor};
+/// use std::time::Duration;
+///
+/// let monitor = DeadlineMonitor::new(Duration::from_millis(10));
+/// let count = Pipeline::from(0..10)
+///     .profile_stages({
+///         let monitor = monitor.clone();
+///         move |name| monitor.profiler(name)
+///     })
+///     .stage("double", par_map(|x: u32| x * 2))
+///     .count();
+///
+/// for stats in monitor.stats() {
+///     println!("{}", stats);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DeadlineMonitor {
+    deadline: time::Duration,
+    stages: Arc<Mutex<Vec<Stage>>>,
+}
+
+/// Name of a stage, and its counts
+type Stage = (String, Arc<DeadlineCounters>);
+
+#[derive(Debug, Default)]
+struct DeadlineCounters {
+    items: AtomicUsize,
+    exceeded: AtomicUsize,
+    // in nanoseconds
+    max: AtomicU64,
+}
+
+impl DeadlineMonitor {
+    pub fn new(deadline: time::Duration) -> Self {
+        Self {
+            deadline,
+            stages: Arc::default(),
+        }
+    }
+
+    /// A profiler counting the items of the stage named `name`
+    ///
+    /// Profilers of stages with the same name share the counts.
+    pub fn profiler(&self, name: &str) -> DeadlineProfiler {
+        let mut stages = self.stages.lock().expect("lock");
+        let counters = match stages.iter().find(|(stage, _)| stage == name) {
+            Some((_, counters)) => counters.clone(),
+            None => {
+                let counters = Arc::new(DeadlineCounters::default());
+                stages.push((name.to_owned(), counters.clone()));
+                counters
+            }
+        };
+
+        DeadlineProfiler {
+            deadline: self.deadline,
+            counters,
+            start: time::Instant::now(),
+        }
+    }
+
+    /// Counts of every stage, in the order they were created
+    pub fn stats(&self) -> Vec<DeadlineStats> {
+        self.stages
+            .lock()
+            .expect("lock")
+            .iter()
+            .map(|(name, counters)| DeadlineStats {
+                name: name.clone(),
+                deadline: self.deadline,
+                items: counters.items.load(SeqCst),
+                exceeded: counters.exceeded.load(SeqCst),
+                max: time::Duration::from_nanos(counters.max.load(SeqCst)),
+            })
+            .collect()
+    }
+}
+
+/// A [`Profiler`] counting the items taking longer than a deadline
+///
+/// See [`DeadlineMonitor::profiler`].
+#[derive(Debug)]
+pub struct DeadlineProfiler {
+    deadline: time::Duration,
+    counters: Arc<DeadlineCounters>,
+    start: time::Instant,
+}
+
+impl Profiler for DeadlineProfiler {
+    fn start(&mut self) {
+        self.start = time::Instant::now();
+    }
+
+    fn end(&mut self) {
+        let elapsed = self.start.elapsed();
+        self.counters.items.fetch_add(1, SeqCst);
+        if self.deadline < elapsed {
+            self.counters.exceeded.fetch_add(1, SeqCst);
+        }
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.counters.max.fetch_max(nanos, SeqCst);
+    }
+}
+
+/// Items of a stage over the deadline
+///
+/// See [`DeadlineMonitor::stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadlineStats {
+    name: String,
+    deadline: time::Duration,
+    items: usize,
+    exceeded: usize,
+    max: time::Duration,
+}
+
+impl DeadlineStats {
+    /// Name of the stage
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn deadline(&self) -> time::Duration {
+        self.deadline
+    }
+
+    /// Number of items measured
+    pub fn items(&self) -> usize {
+        self.items
+    }
+
+    /// Number of items taking longer than the deadline
+    pub fn exceeded(&self) -> usize {
+        self.exceeded
+    }
+
+    /// The longest time taken by any item
+    pub fn max(&self) -> time::Duration {
+        self.max
+    }
+}
+
+impl fmt::Display for DeadlineStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}/{} items over the {:?} deadline, max: {:?}",
+            self.name, self.exceeded, self.items, self.deadline, self.max
+        )
+    }
+}
@@ -0,0 +1,148 @@
+use crate::Traced;
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time,
+};
+
+/// Distribution of the end-to-end latencies of the items
+///
+/// See [`LatencyRecorder::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    count: usize,
+    min: time::Duration,
+    p50: time::Duration,
+    p90: time::Duration,
+    p99: time::Duration,
+    max: time::Duration,
+    mean: time::Duration,
+}
+
+impl LatencyStats {
+    /// Number of items recorded
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn min(&self) -> time::Duration {
+        self.min
+    }
+
+    /// The median latency
+    pub fn p50(&self) -> time::Duration {
+        self.p50
+    }
+
+    pub fn p90(&self) -> time::Duration {
+        self.p90
+    }
+
+    pub fn p99(&self) -> time::Duration {
+        self.p99
+    }
+
+    pub fn max(&self) -> time::Duration {
+        self.max
+    }
+
+    pub fn mean(&self) -> time::Duration {
+        self.mean
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} items, min: {:?}, p50: {:?}, p90: {:?}, p99: {:?}, max: {:?}",
+            self.count, self.min, self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+/// Records the time from [`Traced`] items entering the pipeline to being returned at its end
+///
+/// Pass a clone to [`crate::IteratorExt::record_latency`] at the end of the pipeline,
+/// and read the distribution with [`LatencyRecorder::stats`]. Unlike the stage
+/// profilers, which measure the time stages are blocked, this is the latency
+/// of each item, which is what SLOs are usually about.
+///
+/// Note: latencies are kept in memory until the recorder is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyRecorder {
+    latencies: Arc<Mutex<Vec<time::Duration>>>,
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Distribution of the latencies recorded so far, if any
+    pub fn stats(&self) -> Option<LatencyStats> {
+        let mut latencies = self.latencies.lock().expect("lock poisoned").clone();
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable();
+
+        let count = latencies.len();
+        let percentile = |p: usize| latencies[(count - 1) * p / 100];
+        let total: time::Duration = latencies.iter().sum();
+        Some(LatencyStats {
+            count,
+            min: latencies[0],
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: latencies[count - 1],
+            mean: total / count as u32,
+        })
+    }
+
+    pub(crate) fn record(&self, latency: time::Duration) {
+        self.latencies.lock().expect("lock poisoned").push(latency);
+    }
+}
+
+/// Records the latencies of [`Traced`] items
+///
+/// See [`crate::IteratorExt::record_latency`].
+pub struct RecordLatency<I> {
+    // the iterator we wrapped
+    iter: I,
+    recorder: LatencyRecorder,
+}
+
+impl<I> RecordLatency<I> {
+    pub(crate) fn new(iter: I, recorder: LatencyRecorder) -> Self {
+        Self { iter, recorder }
+    }
+}
+
+impl<I> fmt::Debug for RecordLatency<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordLatency")
+            .field("recorder", &self.recorder)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, T> Iterator for RecordLatency<I>
+where
+    I: Iterator<Item = Traced<T>>,
+{
+    type Item = Traced<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next()?;
+        self.recorder.record(item.elapsed());
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
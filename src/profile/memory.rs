@@ -0,0 +1,45 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering::SeqCst},
+    Arc,
+};
+
+/// Bytes held by a stage, as measured by the user-provided `size_of`
+///
+/// Pass a clone to [`crate::ParallelMapBuilder::memory_meter`], and read the usage
+/// with [`MemoryMeter::current`] and [`MemoryMeter::peak`], while the stage is
+/// running or after it's done.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryMeter {
+    inner: Arc<MemoryMeterInner>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryMeterInner {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl MemoryMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes held by the stage right now
+    pub fn current(&self) -> usize {
+        self.inner.current.load(SeqCst)
+    }
+
+    /// The most bytes held by the stage at any point
+    pub fn peak(&self) -> usize {
+        self.inner.peak.load(SeqCst)
+    }
+
+    pub(crate) fn add(&self, bytes: usize) {
+        let current = self.inner.current.fetch_add(bytes, SeqCst) + bytes;
+        self.inner.peak.fetch_max(current, SeqCst);
+    }
+
+    pub(crate) fn sub(&self, bytes: usize) {
+        self.inner.current.fetch_sub(bytes, SeqCst);
+    }
+}
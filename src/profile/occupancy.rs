@@ -0,0 +1,98 @@
+use crossbeam_channel::{RecvTimeoutError, Sender};
+
+use crate::watchdog::Progress;
+
+use std::{
+    sync::{Arc, Mutex},
+    thread, time,
+};
+
+/// Occupancy of the channels of a stage at a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OccupancySample {
+    elapsed: time::Duration,
+    input: usize,
+    output: usize,
+}
+
+impl OccupancySample {
+    /// Time since the stage was started
+    pub fn elapsed(&self) -> time::Duration {
+        self.elapsed
+    }
+
+    /// Number of items waiting for a worker thread to pick them up
+    pub fn input(&self) -> usize {
+        self.input
+    }
+
+    /// Number of items processed by the worker threads, but not yet returned by the stage
+    pub fn output(&self) -> usize {
+        self.output
+    }
+}
+
+/// A time series of [`OccupancySample`]s
+///
+/// Pass a clone to [`crate::ParallelMapBuilder::sample_occupancy`], and read the samples
+/// with [`OccupancyRecorder::samples`], e.g. for plotting the utilization of the stage.
+/// Constantly full input means the workers can't keep up, constantly full output
+/// means the consumer can't keep up.
+///
+/// Note: samples are kept in memory until the recorder is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct OccupancyRecorder {
+    samples: Arc<Mutex<Vec<OccupancySample>>>,
+}
+
+impl OccupancyRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Samples recorded so far
+    pub fn samples(&self) -> Vec<OccupancySample> {
+        self.samples.lock().expect("lock poisoned").clone()
+    }
+
+    fn record(&self, sample: OccupancySample) {
+        self.samples.lock().expect("lock poisoned").push(sample);
+    }
+}
+
+/// A handle to a sampler thread
+///
+/// The thread exits as soon as the handle is dropped.
+pub(crate) struct Sampler {
+    _stop_tx: Sender<()>,
+}
+
+impl Sampler {
+    pub(crate) fn spawn(
+        progress: Arc<Progress>,
+        interval: time::Duration,
+        recorder: OccupancyRecorder,
+    ) -> Self {
+        let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+
+        thread::spawn(move || {
+            let start = time::Instant::now();
+
+            loop {
+                match stop_rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                }
+
+                let (input, output) = progress.occupancy();
+                recorder.record(OccupancySample {
+                    elapsed: start.elapsed(),
+                    input,
+                    output,
+                });
+            }
+        });
+
+        Self { _stop_tx: stop_tx }
+    }
+}
@@ -0,0 +1,108 @@
+use super::Profiler;
+
+use std::time;
+
+/// Runs the inner profiler on one of every `n` items only
+///
+/// For expensive profilers, like histograms or tracing, in hot pipelines.
+/// The items skipped cost a counter increment.
+#[derive(Debug)]
+pub struct Sampled<P> {
+    inner: P,
+    n: usize,
+    // items since the last one profiled
+    count: usize,
+    // is the current item being profiled
+    active: bool,
+}
+
+impl<P> Sampled<P> {
+    /// Profile one of every `n` items with `inner`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn new(inner: P, n: usize) -> Self {
+        assert!(0 < n, "sampling rate must be positive");
+        Self {
+            inner,
+            n,
+            count: 0,
+            active: false,
+        }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+impl<P> Profiler for Sampled<P>
+where
+    P: Profiler,
+{
+    fn start(&mut self) {
+        self.active = self.count == 0;
+        self.count = (self.count + 1) % self.n;
+        if self.active {
+            self.inner.start();
+        }
+    }
+
+    fn end(&mut self) {
+        if self.active {
+            self.inner.end();
+        }
+    }
+}
+
+/// Runs the inner profiler on at most one item per `period`
+///
+/// Like [`Sampled`], but the overhead is bounded regardless of the rate of items.
+#[derive(Debug)]
+pub struct RateLimited<P> {
+    inner: P,
+    period: time::Duration,
+    // when the last item profiled started
+    last: Option<time::Instant>,
+    // is the current item being profiled
+    active: bool,
+}
+
+impl<P> RateLimited<P> {
+    /// Profile at most one item per `period` with `inner`
+    pub fn new(inner: P, period: time::Duration) -> Self {
+        Self {
+            inner,
+            period,
+            last: None,
+            active: false,
+        }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+impl<P> Profiler for RateLimited<P>
+where
+    P: Profiler,
+{
+    fn start(&mut self) {
+        let now = time::Instant::now();
+        self.active = self
+            .last
+            .is_none_or(|last| self.period <= now.duration_since(last));
+        if self.active {
+            self.last = Some(now);
+            self.inner.start();
+        }
+    }
+
+    fn end(&mut self) {
+        if self.active {
+            self.inner.end();
+        }
+    }
+}
@@ -5,6 +5,11 @@ use std::time;
 pub struct TotalTimeStats {
     current: time::Duration,
     total: time::Duration,
+    // blocked time, unlike `total` never adjusted by reporters
+    blocked: time::Duration,
+    // when the profiler was created, and the wall time since, as of the last `end`
+    created: time::Instant,
+    wall_time: time::Duration,
 }
 
 /// Something that can react to [`TotalTimeProfilerStats`] tracked by [`TotalTimeProfiler`].
@@ -65,10 +70,7 @@ where
     /// ```
     pub fn new(f: F) -> Self {
         Self {
-            stats: TotalTimeStats {
-                current: time::Duration::default(),
-                total: time::Duration::default(),
-            },
+            stats: TotalTimeStats::new(),
 
             start: time::Instant::now(),
             reporter: f,
@@ -86,10 +88,7 @@ where
 
     pub fn periodically(period: time::Duration, f: F) -> Self {
         Self {
-            stats: TotalTimeStats {
-                current: time::Duration::default(),
-                total: time::Duration::default(),
-            },
+            stats: TotalTimeStats::new(),
 
             start: time::Instant::now(),
             reporter: PeriodicReporter::new_millis(period, f),
@@ -97,6 +96,55 @@ where
     }
 }
 
+impl<F> TotalTimeProfiler<WallClockReporter<F>>
+where
+    F: Fn(&TotalTimeStats),
+{
+    pub fn every_millis(millis: u64, f: F) -> Self {
+        Self::every(time::Duration::from_millis(millis), f)
+    }
+
+    /// Call `f` with the stats every `period` of wall time
+    ///
+    /// Unlike [`TotalTimeProfiler::periodically`], reports even if the stage is never
+    /// blocked, so dashboards get regular samples. The time is checked whenever an item
+    /// is measured, so nothing is reported while the stage is stuck.
+    pub fn every(period: time::Duration, f: F) -> Self {
+        Self {
+            stats: TotalTimeStats::new(),
+            start: time::Instant::now(),
+            reporter: WallClockReporter {
+                period,
+                last: time::Instant::now(),
+                f,
+            },
+        }
+    }
+}
+
+/// Reporter calling a function every period of wall time
+///
+/// Use [`TotalTimeProfiler::every_millis`] instead
+pub struct WallClockReporter<F> {
+    period: time::Duration,
+    // when last reported
+    last: time::Instant,
+    f: F,
+}
+
+impl<F> Reporter for WallClockReporter<F>
+where
+    F: Fn(&TotalTimeStats),
+{
+    fn handle_stats(&mut self, stats: &mut TotalTimeStats) {
+        let now = time::Instant::now();
+        if self.period <= now.duration_since(self.last) {
+            self.last = now;
+            (self.f)(stats);
+        }
+    }
+}
+
 /// Reporter calling a function every time the total accumulated time
 /// being tracked crosses certain threshold
 ///
@@ -125,6 +173,16 @@ where
 }
 
 impl TotalTimeStats {
+    fn new() -> Self {
+        Self {
+            current: time::Duration::default(),
+            total: time::Duration::default(),
+            blocked: time::Duration::default(),
+            created: time::Instant::now(),
+            wall_time: time::Duration::default(),
+        }
+    }
+
     fn periodically(&mut self, period: time::Duration, f: impl FnOnce()) {
         if self.total >= period {
             self.total -= period;
@@ -137,6 +195,22 @@ impl TotalTimeStats {
         self.total
     }
 
+    /// Wall time since the profiler was created, as of the last measurement
+    pub fn wall_time(&self) -> time::Duration {
+        self.wall_time
+    }
+
+    /// Fraction of the [`TotalTimeStats::wall_time`] spent blocked, from 0.0 to 1.0
+    ///
+    /// E.g. `0.87` for a stage idle 87% of the time. Not affected by adjusting
+    /// the total with [`TotalTimeStats::total_mut`].
+    pub fn utilization(&self) -> f64 {
+        if self.wall_time.is_zero() {
+            return 0.0;
+        }
+        (self.blocked.as_secs_f64() / self.wall_time.as_secs_f64()).min(1.0)
+    }
+
     /// Get mutable reference to total accumulated time
     ///
     /// Your free to adjust it.
@@ -162,6 +236,8 @@ where
             .saturating_sub(time::Duration::from_micros(1));
 
         self.stats.total = self.stats.total.saturating_add(self.stats.current);
+        self.stats.blocked = self.stats.blocked.saturating_add(self.stats.current);
+        self.stats.wall_time = self.stats.created.elapsed();
 
         let Self {
             ref mut reporter,
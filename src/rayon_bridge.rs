@@ -0,0 +1,146 @@
+use crossbeam_channel::{Receiver, Sender};
+
+use std::{
+    any::Any,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+type Response<O> = (usize, Result<O, Box<dyn Any + Send>>);
+
+/// Results of a function applied to items on the rayon thread pool, in the original order
+///
+/// See [`crate::IteratorExt::into_rayon_ordered`].
+pub struct RayonOrdered<I, O>
+where
+    I: Iterator,
+{
+    // the iterator we wrapped
+    iter: I,
+    // is `iter` exhausted
+    iter_done: bool,
+    // function applied to the items
+    f: Arc<dyn Fn(I::Item) -> O + Send + Sync>,
+    // max number of items in flight
+    buffer_size: usize,
+    tx: Sender<Response<O>>,
+    rx: Receiver<Response<O>>,
+    /// the id of the work we are going to spawn next
+    next_tx_i: usize,
+    /// the id of response we are waiting for
+    next_rx_i: usize,
+    /// responses we received before we needed them
+    out_of_order: Vec<(usize, O)>,
+}
+
+impl<I, O> RayonOrdered<I, O>
+where
+    I: Iterator,
+    I::Item: Send + 'static,
+    O: Send + 'static,
+{
+    pub(crate) fn new<F>(iter: I, f: F) -> Self
+    where
+        F: Fn(I::Item) -> O + Send + Sync + 'static,
+    {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        Self {
+            iter,
+            iter_done: false,
+            f: Arc::new(f),
+            buffer_size: rayon::current_num_threads() * 2,
+            tx,
+            rx,
+            next_tx_i: 0,
+            next_rx_i: 0,
+            out_of_order: vec![],
+        }
+    }
+
+    /// Spawn jobs on the rayon thread pool, until there's enough of them in flight
+    fn pump_tx(&mut self) {
+        while !self.iter_done && self.next_tx_i < self.next_rx_i + self.buffer_size {
+            let item = match self.iter.next() {
+                Some(item) => item,
+                None => {
+                    self.iter_done = true;
+                    break;
+                }
+            };
+
+            let i = self.next_tx_i;
+            let f = self.f.clone();
+            let tx = self.tx.clone();
+            rayon::spawn(move || {
+                // rayon aborts on panics in spawned jobs, so pass them
+                // to the consumer instead
+                let res = panic::catch_unwind(AssertUnwindSafe(|| (f)(item)));
+                // we ignore send failures, if the receiver is gone
+                // we just throw the work away
+                let _ = tx.send((i, res));
+            });
+            self.next_tx_i += 1;
+        }
+    }
+}
+
+impl<I, O> fmt::Debug for RayonOrdered<I, O>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RayonOrdered")
+            .field("buffer_size", &self.buffer_size)
+            .field("in_flight", &(self.next_tx_i - self.next_rx_i))
+            .field("out_of_order", &self.out_of_order.len())
+            .field("iter_done", &self.iter_done)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, O> Iterator for RayonOrdered<I, O>
+where
+    I: Iterator,
+    I::Item: Send + 'static,
+    O: Send + 'static,
+{
+    type Item = O;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pump_tx();
+
+        loop {
+            if self.next_rx_i == self.next_tx_i {
+                return None;
+            }
+
+            if let Some(index) = self
+                .out_of_order
+                .iter()
+                .position(|(i, _)| *i == self.next_rx_i)
+            {
+                let item = self.out_of_order.swap_remove(index).1;
+                self.next_rx_i += 1;
+                self.pump_tx();
+                return Some(item);
+            }
+
+            // we hold a sender ourselves, so this can't disconnect
+            let (i, res) = self.rx.recv().expect("sender alive");
+            match res {
+                Ok(item) => self.out_of_order.push((i, item)),
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let in_flight = self.next_tx_i - self.next_rx_i;
+        let (lower, upper) = self.iter.size_hint();
+        (
+            lower.saturating_add(in_flight),
+            upper.and_then(|upper| upper.checked_add(in_flight)),
+        )
+    }
+}
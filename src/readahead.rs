@@ -1,13 +1,18 @@
 use crossbeam_channel::Sender;
 
-use crate::Scope;
+use crate::{BuildError, Describe, PipelineNode, Scope};
 use std::{
+    cmp,
+    collections::VecDeque,
+    fmt,
     marker::PhantomData,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{atomic::AtomicBool, Arc, Condvar, Mutex},
     thread,
 };
 
 use crate::DropIndicator;
+#[cfg(feature = "priority")]
+use crate::ThreadPriority;
 
 pub struct ReadaheadBuilder<I>
 where
@@ -15,8 +20,123 @@ where
 {
     // the iterator we wrapped
     iter: I,
+    // options not depending on the type of the items
+    config: ReadaheadConfig,
+    // number of returned items to retain, and how to copy them
+    lookbehind: Option<(usize, CloneItem<I::Item>)>,
+    // max total size of the buffered items, and the size of an item
+    buffer_bytes: Option<(usize, ItemSize<I::Item>)>,
+}
+
+/// Options of [`ReadaheadBuilder`] not depending on the iterator
+///
+/// Can be defined once, and used for many pipelines, with
+/// [`crate::IteratorExt::readahead_cfg`] or [`ReadaheadBuilder::config`].
+#[derive(Debug, Clone, Default)]
+pub struct ReadaheadConfig {
     // max number of items in flight
     buffer_size: Option<usize>,
+    // scheduling priority of the thread
+    #[cfg(feature = "priority")]
+    priority: Option<ThreadPriority>,
+}
+
+impl ReadaheadConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buffer_size(mut self, num: usize) -> Self {
+        // Note: not `..self`, as it's the only field without the `priority` feature
+        self.buffer_size = Some(num);
+        self
+    }
+
+    /// See [`crate::ParallelMapConfig::priority`]
+    #[cfg(feature = "priority")]
+    pub fn priority(self, priority: ThreadPriority) -> Self {
+        Self {
+            priority: Some(priority),
+            ..self
+        }
+    }
+}
+
+/// Size of an item, see [`ReadaheadBuilder::buffer_bytes`]
+type ItemSize<T> = Arc<dyn Fn(&T) -> usize + Send + Sync>;
+
+/// Limits the total size of the items buffered in the channel
+struct ByteBudget<T> {
+    limit: usize,
+    size: ItemSize<T>,
+    // total size of the buffered items, and is the receiver gone
+    state: Mutex<(usize, bool)>,
+    changed: Condvar,
+}
+
+impl<T> ByteBudget<T> {
+    /// Wait until there is room for `item`, and take it
+    ///
+    /// A single item is always let through, even if larger than the limit.
+    /// Returns `false` if the receiver is gone.
+    fn acquire(&self, item: &T) -> bool {
+        let size = (self.size)(item);
+        let mut state = self.state.lock().expect("lock poisoned");
+        while !state.1 && state.0 != 0 && self.limit < state.0 + size {
+            state = self.changed.wait(state).expect("lock poisoned");
+        }
+        state.0 += size;
+        !state.1
+    }
+
+    /// Return the room taken by `item`
+    fn release(&self, item: &T) {
+        let size = (self.size)(item);
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.0 = state.0.saturating_sub(size);
+        self.changed.notify_all();
+    }
+
+    /// Let the producer know the receiver is gone
+    fn close(&self) {
+        self.state.lock().expect("lock poisoned").1 = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Send all the items of `iter` to `tx`, respecting the `budget`
+fn produce<I>(iter: I, tx: &Sender<I::Item>, budget: Option<&ByteBudget<I::Item>>)
+where
+    I: Iterator,
+{
+    for i in iter {
+        if let Some(budget) = budget {
+            if !budget.acquire(&i) {
+                return;
+            }
+        }
+        // don't panic if the receiver disconnects
+        let _ = tx.send(i);
+    }
+}
+
+/// Copies items retained for [`Readahead::recent`]
+type CloneItem<T> = fn(&T) -> T;
+
+impl<I> fmt::Debug for ReadaheadBuilder<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadaheadBuilder")
+            .field("config", &self.config)
+            .field("lookbehind", &self.lookbehind.as_ref().map(|(n, _)| n))
+            .field(
+                "buffer_bytes",
+                &self.buffer_bytes.as_ref().map(|(limit, _)| limit),
+            )
+            .finish_non_exhaustive()
+    }
 }
 
 impl<I> ReadaheadBuilder<I>
@@ -26,52 +146,201 @@ where
     pub fn new(iter: I) -> Self {
         Self {
             iter,
-            buffer_size: None,
+            config: ReadaheadConfig::default(),
+            lookbehind: None,
+            buffer_bytes: None,
         }
     }
 
+    /// See [`ReadaheadConfig::buffer_size`]
     pub fn buffer_size(self, num: usize) -> Self {
         Self {
-            buffer_size: Some(num),
+            config: self.config.buffer_size(num),
+            ..self
+        }
+    }
+
+    /// See [`ReadaheadConfig::priority`]
+    #[cfg(feature = "priority")]
+    pub fn priority(self, priority: ThreadPriority) -> Self {
+        Self {
+            config: self.config.priority(priority),
+            ..self
+        }
+    }
+
+    /// Use all the options of `config`, replacing the ones set so far
+    pub fn config(self, config: &ReadaheadConfig) -> Self {
+        Self {
+            config: config.clone(),
             ..self
         }
     }
 
-    fn with_common(self) -> (Readahead<I>, Sender<I::Item>, I)
+    /// Retain the last `n` returned items, available with [`Readahead::recent`]
+    ///
+    /// Useful for processing depending on the previous items, e.g. diffing
+    /// the current record against the previous one.
+    pub fn lookbehind(self, n: usize) -> Self
+    where
+        I::Item: Clone,
+    {
+        Self {
+            lookbehind: Some((n, I::Item::clone)),
+            ..self
+        }
+    }
+
+    /// Stop reading ahead when the buffered items take more than `limit` bytes
+    ///
+    /// The size of the items is measured with `size`, which should be cheap, as
+    /// it's called twice for every item. With items of wildly varying sizes, limiting
+    /// the number of buffered items doesn't control the memory usage. Unless
+    /// [`ReadaheadBuilder::buffer_size`] is used too, the number of buffered items is
+    /// not limited.
+    ///
+    /// A single item larger than `limit` is still buffered, when nothing else is.
+    pub fn buffer_bytes<S>(self, limit: usize, size: S) -> Self
+    where
+        S: Fn(&I::Item) -> usize + Send + Sync + 'static,
+    {
+        Self {
+            buffer_bytes: Some((limit, Arc::new(size))),
+            ..self
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn with_common(
+        self,
+    ) -> (
+        Readahead<I>,
+        Sender<I::Item>,
+        I,
+        Option<Arc<ByteBudget<I::Item>>>,
+    )
     where
         I: Iterator,
     {
-        let buffer_size = self.buffer_size.unwrap_or(0);
+        let buffer_size = self.config.buffer_size.unwrap_or(0);
 
-        let (tx, rx) = crossbeam_channel::bounded(buffer_size);
+        let (tx, rx) = match (self.config.buffer_size, self.buffer_bytes.as_ref()) {
+            // only the size of the items is limited
+            (None, Some(_)) => crossbeam_channel::unbounded(),
+            _ => crossbeam_channel::bounded(buffer_size),
+        };
+        let budget = self.buffer_bytes.map(|(limit, size)| {
+            Arc::new(ByteBudget {
+                limit,
+                size,
+                state: Mutex::new((0, false)),
+                changed: Condvar::new(),
+            })
+        });
         (
             Readahead {
                 _iter_marker: PhantomData,
+                inline: None,
+                buffer_size,
                 iter_size_hint: self.iter.size_hint(),
+                yielded: 0,
                 inner: Some(ReadaheadInner { rx }),
                 worker_panicked: Arc::new(AtomicBool::new(false)),
+                peeked: VecDeque::new(),
+                lookbehind: self.lookbehind,
+                recent: VecDeque::new(),
+                budget: budget.clone(),
             },
             tx,
             self.iter,
+            budget,
         )
     }
 
-    pub fn with(self) -> Readahead<I>
+    /// Don't spawn a thread, and pull the inner iterator when items are needed
+    ///
+    /// The fallback for targets without threads.
+    pub(crate) fn with_inline(self) -> Readahead<I> {
+        let (mut ret, _tx, iter, _budget) = self.with_common();
+        ret.inline = Some(iter);
+        ret
+    }
+
+    /// The iterator, and the job of the thread feeding it
+    ///
+    /// Not boxed: both constructors spawn the job directly.
+    fn with_worker(self) -> (Readahead<I>, impl FnOnce() + Send)
     where
-        I: Iterator + 'static + Send,
-        I::Item: Send + 'static,
+        I: Send,
+        I::Item: Send,
     {
-        let (ret, tx, mut iter) = self.with_common();
+        #[cfg(feature = "priority")]
+        let priority = self.config.priority;
+        let (ret, tx, iter, budget) = self.with_common();
 
         let drop_indicator = DropIndicator::new(ret.worker_panicked.clone());
-        thread::spawn(move || {
-            while let Some(i) = iter.next() {
-                // don't panic if the receiver disconnects
-                let _ = tx.send(i);
+        let worker = move || {
+            #[cfg(feature = "priority")]
+            if let Some(priority) = priority {
+                priority.apply();
             }
+
+            produce(iter, &tx, budget.as_deref());
             drop_indicator.cancel();
-        });
+        };
+
+        (ret, worker)
+    }
+
+    /// Check the configuration for values that make no sense
+    fn validate(&self) -> Result<(), BuildError> {
+        if self
+            .buffer_bytes
+            .as_ref()
+            .is_some_and(|(limit, _)| *limit == 0)
+        {
+            return Err(BuildError::ZeroBufferBytes);
+        }
+        Ok(())
+    }
+
+    /// Like [`ReadaheadBuilder::with`], but returns an error on invalid configuration
+    ///
+    /// [`ReadaheadBuilder::with`] buffers a single item at a time with
+    /// `buffer_bytes(0, ..)`, which is reported by this method instead.
+    pub fn try_with(self) -> Result<Readahead<I>, BuildError>
+    where
+        I: Iterator + 'static + Send,
+        I::Item: Send + 'static,
+    {
+        self.validate()?;
+        Ok(self.with())
+    }
+
+    /// Like [`ReadaheadBuilder::with_scoped`], but returns an error on invalid configuration
+    pub fn try_with_scoped<'env, 'scope>(
+        self,
+        scope: &'scope Scope<'env>,
+    ) -> Result<Readahead<I>, BuildError>
+    where
+        I: Iterator + 'env + Send,
+        I::Item: Send + 'env,
+    {
+        self.validate()?;
+        Ok(self.with_scoped(scope))
+    }
+
+    pub fn with(self) -> Readahead<I>
+    where
+        I: Iterator + 'static + Send,
+        I::Item: Send + 'static,
+    {
+        if !crate::threads_supported() {
+            return self.with_inline();
+        }
 
+        let (ret, worker) = self.with_worker();
+        thread::spawn(worker);
         ret
     }
 
@@ -80,17 +349,12 @@ where
         I: Iterator + 'env + Send,
         I::Item: Send + 'env,
     {
-        let (ret, tx, mut iter) = self.with_common();
-
-        let drop_indicator = DropIndicator::new(ret.worker_panicked.clone());
-        scope.spawn(move |_scope| {
-            while let Some(i) = iter.next() {
-                // don't panic if the receiver disconnects
-                let _ = tx.send(i);
-            }
-            drop_indicator.cancel();
-        });
+        if !crate::threads_supported() {
+            return self.with_inline();
+        }
 
+        let (ret, worker) = self.with_worker();
+        scope.spawn(move |_scope| worker());
         ret
     }
 }
@@ -101,9 +365,34 @@ where
     I: Iterator,
 {
     _iter_marker: PhantomData<I>,
+    // the inner iterator, if running on the consuming thread, see `with_inline`
+    inline: Option<I>,
+    buffer_size: usize,
     iter_size_hint: (usize, Option<usize>),
+    // number of items returned so far, see `size_hint`
+    yielded: usize,
     inner: Option<ReadaheadInner<I>>,
     worker_panicked: Arc<AtomicBool>,
+    // items received, but not returned yet, see `peek`
+    peeked: VecDeque<I::Item>,
+    // number of returned items to retain, and how to copy them
+    lookbehind: Option<(usize, CloneItem<I::Item>)>,
+    // copies of the last returned items, see `recent`
+    recent: VecDeque<I::Item>,
+    // limit of the size of the buffered items, if any
+    budget: Option<Arc<ByteBudget<I::Item>>>,
+}
+
+impl<I> Drop for Readahead<I>
+where
+    I: Iterator,
+{
+    fn drop(&mut self) {
+        // the producer might be waiting for room in the buffer
+        if let Some(budget) = self.budget.as_ref() {
+            budget.close();
+        }
+    }
 }
 
 struct ReadaheadInner<I>
@@ -113,17 +402,87 @@ where
     rx: crossbeam_channel::Receiver<I::Item>,
 }
 
-impl<I> Iterator for Readahead<I>
+impl<I> fmt::Debug for Readahead<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Readahead");
+        d.field("buffer_size", &self.buffer_size);
+        if let Some(inner) = self.inner.as_ref() {
+            d.field("buffered", &inner.rx.len());
+        }
+        d.field("peeked", &self.peeked.len());
+        d.field(
+            "worker_panicked",
+            &self
+                .worker_panicked
+                .load(std::sync::atomic::Ordering::SeqCst),
+        )
+        .finish_non_exhaustive()
+    }
+}
+
+impl<I> Describe for Readahead<I>
+where
+    I: Iterator,
+{
+    fn describe_into(&self, nodes: &mut Vec<PipelineNode>) {
+        // the inner iterator was moved to the readahead thread
+        nodes.push(PipelineNode {
+            threads: Some(1),
+            buffer: Some(self.buffer_size),
+            ..PipelineNode::new("readahead")
+        });
+    }
+}
+
+impl<I> Readahead<I>
 where
     I: Iterator,
     I: Send,
     I::Item: Send,
 {
-    type Item = I::Item;
+    /// Return a reference to the next item, without advancing the iterator
+    ///
+    /// Like [`std::iter::Peekable::peek`], but without giving up the readahead.
+    pub fn peek(&mut self) -> Option<&I::Item> {
+        self.peek_n(1).first()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Return references to the next (up to) `n` items, without advancing the iterator
+    ///
+    /// Fewer than `n` items are returned only at the end of the iterator.
+    /// Useful for parsers needing a lookahead of multiple tokens.
+    pub fn peek_n(&mut self, n: usize) -> &[I::Item] {
+        while self.peeked.len() < n {
+            match self.recv() {
+                Some(item) => self.peeked.push_back(item),
+                None => break,
+            }
+        }
+        let peeked = self.peeked.make_contiguous();
+        &peeked[..cmp::min(n, peeked.len())]
+    }
+
+    /// The last returned items, oldest first
+    ///
+    /// Empty unless [`ReadaheadBuilder::lookbehind`] was used.
+    pub fn recent(&self) -> impl DoubleEndedIterator<Item = &I::Item> + ExactSizeIterator {
+        self.recent.iter()
+    }
+
+    fn recv(&mut self) -> Option<I::Item> {
+        if let Some(iter) = self.inline.as_mut() {
+            return iter.next();
+        }
         match self.inner.as_ref().expect("thread started").rx.recv() {
-            Ok(i) => Some(i),
+            Ok(i) => {
+                if let Some(budget) = self.budget.as_ref() {
+                    budget.release(&i);
+                }
+                Some(i)
+            }
             Err(crossbeam_channel::RecvError) => {
                 if self
                     .worker_panicked
@@ -136,8 +495,124 @@ where
             }
         }
     }
+}
+
+impl<I> Iterator for Readahead<I>
+where
+    I: Iterator,
+    I: Send,
+    I::Item: Send,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.peeked.pop_front().or_else(|| self.recv())?;
+        self.yielded += 1;
+
+        if let Some((n, clone_item)) = self.lookbehind {
+            if 0 < n {
+                if self.recent.len() == n {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back((clone_item)(&item));
+            }
+        }
+
+        Some(item)
+    }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.iter_size_hint
+        let peeked = self.peeked.len();
+        if let Some(iter) = self.inline.as_ref() {
+            let (lower, upper) = iter.size_hint();
+            return (
+                lower.saturating_add(peeked),
+                upper.and_then(|upper| upper.checked_add(peeked)),
+            );
+        }
+
+        // the items received and not returned yet are certainly there
+        let buffered = peeked + self.inner.as_ref().map_or(0, |inner| inner.rx.len());
+        let (lower, upper) = self.iter_size_hint;
+        (
+            cmp::max(lower.saturating_sub(self.yielded), buffered),
+            upper.map(|upper| cmp::max(upper.saturating_sub(self.yielded), buffered)),
+        )
     }
 }
+
+/// Returns the items of the inner iterator up to, and including the first `Err`
+///
+/// See [`crate::IteratorExt::readahead_results`].
+#[derive(Debug)]
+pub struct StopOnError<I> {
+    // the iterator we wrapped
+    iter: I,
+    // was an error returned already
+    failed: bool,
+}
+
+impl<I> StopOnError<I> {
+    pub(crate) fn new(iter: I) -> Self {
+        Self {
+            iter,
+            failed: false,
+        }
+    }
+}
+
+impl<I, T, E> Iterator for StopOnError<I>
+where
+    I: Iterator<Item = Result<T, E>>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let item = self.iter.next()?;
+        self.failed = item.is_err();
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            (0, self.iter.size_hint().1)
+        }
+    }
+}
+
+/// Run the `producer` of items on a separate thread, like [`crate::IteratorExt::readahead`]
+///
+/// Producing items often involves blocking I/O. With it, there's no need to implement
+/// an iterator just to get readahead. The `producer` is called until it returns `None`.
+///
+/// ```
+/// let mut lines = vec!["a", "b", "c"].into_iter();
+/// let items: Vec<_> = pariter::from_fn_readahead(move || lines.next()).collect();
+///
+/// assert_eq!(items, vec!["a", "b", "c"]);
+/// ```
+pub fn from_fn_readahead<T, F>(producer: F) -> Readahead<std::iter::FromFn<F>>
+where
+    F: FnMut() -> Option<T> + Send + 'static,
+    T: Send + 'static,
+{
+    ReadaheadBuilder::new(std::iter::from_fn(producer)).with()
+}
+
+/// Scoped version of [`from_fn_readahead`]
+pub fn from_fn_readahead_scoped<'env, 'scope, T, F>(
+    scope: &'scope Scope<'env>,
+    producer: F,
+) -> Readahead<std::iter::FromFn<F>>
+where
+    F: FnMut() -> Option<T> + Send + 'env,
+    T: Send + 'env,
+{
+    ReadaheadBuilder::new(std::iter::from_fn(producer)).with_scoped(scope)
+}
@@ -0,0 +1,198 @@
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{DropIndicator, Scope};
+
+use std::{
+    fmt,
+    iter::{Skip, StepBy},
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
+    thread,
+};
+
+/// The partitions of the source run by each producer thread
+type Partition<I> = StepBy<Skip<I>>;
+
+/// A source of items split between multiple producer threads
+///
+/// See [`crate::IteratorExt::readahead_split`].
+pub struct ReadaheadSplit<I>
+where
+    I: Iterator,
+{
+    // one channel per partition if ordered, or a single shared one
+    rxs: Vec<Receiver<I::Item>>,
+    // the channel to receive the next item from
+    next_rx: usize,
+    // is any partition exhausted, if ordered, or all of them, if not
+    done: bool,
+    worker_panicked: Arc<AtomicBool>,
+    // the source, if pulled on the consuming thread, see `new_inline`
+    inline: Option<I>,
+}
+
+impl<I> ReadaheadSplit<I>
+where
+    I: Iterator + Clone,
+{
+    /// Split `iter` into `n` interleaved partitions
+    ///
+    /// Returns the adapter, and the partitions with the channels they should be sent to.
+    #[allow(clippy::type_complexity)]
+    fn new_common(
+        iter: I,
+        n: usize,
+        ordered: bool,
+    ) -> (Self, Vec<(Partition<I>, Sender<I::Item>, DropIndicator)>) {
+        assert!(0 < n, "number of producer threads must be positive");
+
+        let worker_panicked = Arc::new(AtomicBool::new(false));
+        let mut rxs = vec![];
+        let mut partitions = vec![];
+        let mut shared_tx = None;
+        for i in 0..n {
+            let tx = if ordered {
+                let (tx, rx) = crossbeam_channel::bounded(0);
+                rxs.push(rx);
+                tx
+            } else {
+                shared_tx
+                    .get_or_insert_with(|| {
+                        let (tx, rx) = crossbeam_channel::bounded(0);
+                        rxs.push(rx);
+                        tx
+                    })
+                    .clone()
+            };
+            partitions.push((
+                iter.clone().skip(i).step_by(n),
+                tx,
+                DropIndicator::new(worker_panicked.clone()),
+            ));
+        }
+
+        (
+            Self {
+                rxs,
+                next_rx: 0,
+                done: false,
+                worker_panicked,
+                inline: None,
+            },
+            partitions,
+        )
+    }
+
+    /// Don't spawn any producers, and pull `iter` on the consuming thread when items are needed
+    ///
+    /// The fallback for targets without threads.
+    fn new_inline(iter: I, n: usize) -> Self {
+        assert!(0 < n, "number of producer threads must be positive");
+
+        Self {
+            rxs: vec![],
+            next_rx: 0,
+            done: false,
+            worker_panicked: Arc::new(AtomicBool::new(false)),
+            inline: Some(iter),
+        }
+    }
+
+    pub(crate) fn new(iter: I, n: usize, ordered: bool) -> Self
+    where
+        I: Send + 'static,
+        I::Item: Send + 'static,
+    {
+        if !crate::threads_supported() {
+            return Self::new_inline(iter, n);
+        }
+
+        let (ret, partitions) = Self::new_common(iter, n, ordered);
+        for (partition, tx, drop_indicator) in partitions {
+            thread::spawn(move || {
+                for item in partition {
+                    // don't panic if the receiver disconnects
+                    let _ = tx.send(item);
+                }
+                drop_indicator.cancel();
+            });
+        }
+        ret
+    }
+
+    pub(crate) fn new_scoped<'env, 'scope>(
+        scope: &'scope Scope<'env>,
+        iter: I,
+        n: usize,
+        ordered: bool,
+    ) -> Self
+    where
+        I: Send + 'env,
+        I::Item: Send + 'env,
+    {
+        if !crate::threads_supported() {
+            return Self::new_inline(iter, n);
+        }
+
+        let (ret, partitions) = Self::new_common(iter, n, ordered);
+        for (partition, tx, drop_indicator) in partitions {
+            scope.spawn(move |_scope| {
+                for item in partition {
+                    // don't panic if the receiver disconnects
+                    let _ = tx.send(item);
+                }
+                drop_indicator.cancel();
+            });
+        }
+        ret
+    }
+}
+
+impl<I> fmt::Debug for ReadaheadSplit<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadaheadSplit")
+            .field("channels", &self.rxs.len())
+            .field("done", &self.done)
+            .field("worker_panicked", &self.worker_panicked.load(SeqCst))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> Iterator for ReadaheadSplit<I>
+where
+    I: Iterator,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(iter) = self.inline.as_mut() {
+            return iter.next();
+        }
+
+        match self.rxs[self.next_rx].recv() {
+            Ok(item) => {
+                // partitions are interleaved, so the order is restored
+                // by receiving from them in turns
+                self.next_rx = (self.next_rx + 1) % self.rxs.len();
+                Some(item)
+            }
+            Err(crossbeam_channel::RecvError) => {
+                if self.worker_panicked.load(SeqCst) {
+                    panic!("readahead worker thread panicked: panic indicator set");
+                }
+                // with interleaved partitions, the first one exhausted
+                // means there are no more items in any of them
+                self.done = true;
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// A pool of reusable objects, e.g. big buffers, shared by the consumer and the workers
+///
+/// The consumer returns the objects it's done with with [`Recycler::recycle`], and
+/// the workers of [`crate::ParallelMapBuilder::with_recycler`] get them back instead
+/// of allocating new ones, cutting the allocator pressure of pipelines streaming
+/// large buffers.
+///
+/// Objects are handed out as they were recycled, so `f` should clear them if needed.
+pub struct Recycler<T> {
+    inner: Arc<RecyclerInner<T>>,
+}
+
+struct RecyclerInner<T> {
+    // objects waiting to be reused
+    pool: Mutex<Vec<T>>,
+    // max number of objects kept in `pool`
+    max_pooled: Option<usize>,
+    // creates new objects when the pool is empty
+    make: Box<dyn Fn() -> T + Send + Sync>,
+}
+
+impl<T> Clone for Recycler<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Recycler<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recycler")
+            .field("pooled", &self.pooled())
+            .field("max_pooled", &self.inner.max_pooled)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Recycler<T> {
+    /// Create a pool, using `make` to create new objects when it's empty
+    pub fn new<M>(make: M) -> Self
+    where
+        M: Fn() -> T + Send + Sync + 'static,
+    {
+        Self::with_max_pooled(None, make)
+    }
+
+    /// Like [`Recycler::new`], but keeps at most `max` objects, dropping
+    /// the ones recycled over it
+    pub fn bounded<M>(max: usize, make: M) -> Self
+    where
+        M: Fn() -> T + Send + Sync + 'static,
+    {
+        Self::with_max_pooled(Some(max), make)
+    }
+
+    fn with_max_pooled<M>(max_pooled: Option<usize>, make: M) -> Self
+    where
+        M: Fn() -> T + Send + Sync + 'static,
+    {
+        Self {
+            inner: Arc::new(RecyclerInner {
+                pool: Mutex::new(Vec::new()),
+                max_pooled,
+                make: Box::new(make),
+            }),
+        }
+    }
+
+    /// Take an object from the pool, or create a new one if it's empty
+    pub fn take(&self) -> T {
+        let recycled = self.inner.pool.lock().expect("lock poisoned").pop();
+        recycled.unwrap_or_else(|| (self.inner.make)())
+    }
+
+    /// Return an object to the pool, for reuse
+    pub fn recycle(&self, obj: T) {
+        let mut pool = self.inner.pool.lock().expect("lock poisoned");
+        if self.inner.max_pooled.is_none_or(|max| pool.len() < max) {
+            pool.push(obj);
+        }
+    }
+
+    /// Number of objects waiting in the pool
+    pub fn pooled(&self) -> usize {
+        self.inner.pool.lock().expect("lock poisoned").len()
+    }
+}
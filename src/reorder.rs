@@ -0,0 +1,165 @@
+use std::{array, cmp, collections::VecDeque, mem};
+
+/// Number of slots stored inline by default, enough for the default buffer of up to 4 threads
+pub(crate) const INLINE_SLOTS: usize = 8;
+
+/// Results received out of order, in slots indexed by their distance from the next one
+///
+/// Replaces searching a list of `(index, item)` pairs on every `next()`: the next
+/// result is always in the first slot, if it arrived already. Allocated once, with
+/// room for all the items in flight. Up to `N` slots are stored inline, in a ring,
+/// so stages with small buffers don't chase a pointer to the heap on every `next()`.
+pub(crate) struct ReorderBuffer<O, const N: usize = INLINE_SLOTS> {
+    slots: Slots<O, N>,
+    // number of filled slots
+    len: usize,
+}
+
+enum Slots<O, const N: usize> {
+    Inline {
+        ring: [Option<O>; N],
+        // position of the slot of the next item in `ring`
+        head: usize,
+        // number of slots in use, i.e. the offset of the last one plus one
+        extent: usize,
+    },
+    Heap(VecDeque<Option<O>>),
+}
+
+impl<O, const N: usize> Default for ReorderBuffer<O, N> {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<O, const N: usize> ReorderBuffer<O, N> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let slots = if 0 < N && capacity <= N {
+            Slots::Inline {
+                ring: array::from_fn(|_| None),
+                head: 0,
+                extent: 0,
+            }
+        } else {
+            Slots::Heap(VecDeque::with_capacity(capacity))
+        };
+        Self { slots, len: 0 }
+    }
+
+    /// Move the slots to the heap, to make room for `offset`
+    fn spill(&mut self, offset: usize) {
+        if let Slots::Inline { ring, head, extent } = &mut self.slots {
+            let mut heap = VecDeque::with_capacity(cmp::max(offset + 1, 2 * N));
+            heap.extend((0..*extent).map(|i| ring[(*head + i) % N].take()));
+            self.slots = Slots::Heap(heap);
+        }
+    }
+
+    /// Store `item`, `offset` items after the next one
+    pub(crate) fn insert(&mut self, offset: usize, item: O) {
+        if N <= offset {
+            self.spill(offset);
+        }
+        let slot = match &mut self.slots {
+            Slots::Inline { ring, head, extent } => {
+                *extent = cmp::max(*extent, offset + 1);
+                &mut ring[(*head + offset) % N]
+            }
+            Slots::Heap(slots) => {
+                if slots.len() <= offset {
+                    slots.resize_with(offset + 1, || None);
+                }
+                &mut slots[offset]
+            }
+        };
+        debug_assert!(slot.is_none(), "item received twice");
+        *slot = Some(item);
+        self.len += 1;
+    }
+
+    /// Store `item` after all the others, when the order doesn't matter
+    pub(crate) fn push_back(&mut self, item: O) {
+        match &mut self.slots {
+            Slots::Inline { extent, .. } => {
+                let offset = *extent;
+                self.insert(offset, item);
+            }
+            Slots::Heap(slots) => {
+                slots.push_back(Some(item));
+                self.len += 1;
+            }
+        }
+    }
+
+    /// Take the next item, if it was received already
+    pub(crate) fn pop_next(&mut self) -> Option<O> {
+        let item = match &mut self.slots {
+            Slots::Inline { ring, head, extent } => {
+                let item = ring[*head].take()?;
+                *head = (*head + 1) % N;
+                *extent -= 1;
+                item
+            }
+            Slots::Heap(slots) => {
+                let item = slots.front_mut()?.take()?;
+                slots.pop_front();
+                item
+            }
+        };
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// Move on to the item after the next one, which was returned without being stored
+    pub(crate) fn skip_next(&mut self) {
+        let slot = match &mut self.slots {
+            Slots::Inline { ring, head, extent } => {
+                let slot = ring[*head].take();
+                *head = (*head + 1) % N;
+                *extent = extent.saturating_sub(1);
+                slot
+            }
+            Slots::Heap(slots) => slots.pop_front().flatten(),
+        };
+        debug_assert!(slot.is_none(), "skipped a stored item");
+    }
+
+    /// Number of items stored
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Are the slots stored inline
+    #[cfg(test)]
+    pub(crate) fn is_inline(&self) -> bool {
+        matches!(self.slots, Slots::Inline { .. })
+    }
+
+    /// The items stored, in order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &O> {
+        let (inline, heap) = match &self.slots {
+            Slots::Inline { ring, head, extent } => (
+                Some((0..*extent).filter_map(move |i| ring[(*head + i) % N].as_ref())),
+                None,
+            ),
+            Slots::Heap(slots) => (None, Some(slots.iter().flatten())),
+        };
+        inline
+            .into_iter()
+            .flatten()
+            .chain(heap.into_iter().flatten())
+    }
+}
+
+impl<O, const N: usize> IntoIterator for ReorderBuffer<O, N> {
+    type Item = O;
+    type IntoIter = std::iter::Flatten<std::collections::vec_deque::IntoIter<Option<O>>>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.spill(0);
+        match mem::replace(&mut self.slots, Slots::Heap(VecDeque::new())) {
+            Slots::Heap(slots) => slots.into_iter().flatten(),
+            Slots::Inline { .. } => unreachable!("spilled"),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{DropIndicator, ParallelMap, Scope};
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
+    thread,
+};
+
+/// The items delivered to one of the routes of [`crate::IteratorExt::parallel_route`]
+pub struct Route<T> {
+    rx: Receiver<T>,
+    // index of the route
+    index: usize,
+    // did the router thread fail us
+    router_panicked: Arc<AtomicBool>,
+}
+
+impl<T> fmt::Debug for Route<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("index", &self.index)
+            .field("buffered", &self.rx.len())
+            .field("router_panicked", &self.router_panicked.load(SeqCst))
+            .finish()
+    }
+}
+
+impl<T> Route<T> {
+    /// Index of the route, as returned by the classifier
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<T> Iterator for Route<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rx.recv() {
+            Ok(item) => Some(item),
+            Err(crossbeam_channel::RecvError) => {
+                if self.router_panicked.load(SeqCst) {
+                    panic!("route thread panicked: panic indicator set");
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Channels to `num` routes, each holding up to `buffer_size` items
+pub(crate) fn channels<T>(
+    num: usize,
+    buffer_size: usize,
+) -> (Vec<Sender<T>>, Vec<Route<T>>, Arc<AtomicBool>) {
+    assert!(0 < num, "number of routes must be positive");
+    let router_panicked = Arc::new(AtomicBool::new(false));
+    let (txs, routes) = (0..num)
+        .map(|index| {
+            let (tx, rx) = crossbeam_channel::bounded(buffer_size);
+            (
+                tx,
+                Route {
+                    rx,
+                    index,
+                    router_panicked: router_panicked.clone(),
+                },
+            )
+        })
+        .unzip();
+    (txs, routes, router_panicked)
+}
+
+/// Deliver the classified items of `pipeline` to their routes, in order
+///
+/// Stops early once all the routes were dropped.
+fn dispatch<I, T>(pipeline: ParallelMap<I, (usize, T)>, txs: Vec<Sender<T>>)
+where
+    I: Iterator,
+    I::Item: Send,
+    T: Send,
+{
+    let mut txs: Vec<_> = txs.into_iter().map(Some).collect();
+    let mut live = txs.len();
+
+    for (route, item) in pipeline {
+        if let Some(tx) = txs[route].as_ref() {
+            if tx.send(item).is_err() {
+                // nobody is interested in this route anymore
+                txs[route] = None;
+                live -= 1;
+                if live == 0 {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Run [`dispatch`] in a thread of its own
+pub(crate) fn spawn<I, T>(
+    pipeline: ParallelMap<I, (usize, T)>,
+    txs: Vec<Sender<T>>,
+    router_panicked: Arc<AtomicBool>,
+) where
+    I: Iterator + Send + 'static,
+    I::Item: Send + 'static,
+    T: Send + 'static,
+{
+    let drop_indicator = DropIndicator::new(router_panicked);
+    thread::spawn(move || {
+        dispatch(pipeline, txs);
+        drop_indicator.cancel();
+    });
+}
+
+/// Like [`spawn`], but in a thread of `scope`
+pub(crate) fn spawn_scoped<'env, 'scope, I, T>(
+    scope: &'scope Scope<'env>,
+    pipeline: ParallelMap<I, (usize, T)>,
+    txs: Vec<Sender<T>>,
+    router_panicked: Arc<AtomicBool>,
+) where
+    I: Iterator + Send + 'env,
+    I::Item: Send + 'env,
+    T: Send + 'env,
+{
+    let drop_indicator = DropIndicator::new(router_panicked);
+    scope.spawn(move |_scope| {
+        dispatch(pipeline, txs);
+        drop_indicator.cancel();
+    });
+}
@@ -0,0 +1,27 @@
+use crate::TopK;
+
+/// Select `n` items with weighted reservoir sampling (A-Res)
+///
+/// `iter` returns items with their weights, `rng` returns numbers in `[0, 1)`.
+/// The selected items are returned in order.
+pub(crate) fn reservoir<I, T, R>(n: usize, iter: I, mut rng: R) -> Vec<T>
+where
+    I: Iterator<Item = (f64, T)>,
+    R: FnMut() -> f64,
+{
+    let mut reservoir = TopK::new(n);
+
+    for (i, (weight, item)) in iter.enumerate() {
+        if weight.is_nan() || weight <= 0.0 {
+            continue;
+        }
+        let key = (rng)().powf(1.0 / weight);
+        // keys are in `[0, 1]`, and the bit patterns of non-negative
+        // floats are ordered the same way as their values
+        reservoir.push(key.to_bits(), (i, item));
+    }
+
+    let mut selected = reservoir.into_sorted_items();
+    selected.sort_unstable_by_key(|(i, _)| *i);
+    selected.into_iter().map(|(_i, item)| item).collect()
+}
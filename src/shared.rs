@@ -0,0 +1,59 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A cloneable handle to an iterator, pulled by multiple threads
+///
+/// Every item goes to exactly one of the clones, whichever pulls it first,
+/// so the order is lost across them. See [`crate::IteratorExt::into_shared`].
+pub struct SharedIter<I> {
+    inner: Arc<Mutex<I>>,
+}
+
+impl<I> SharedIter<I> {
+    pub(crate) fn new(iter: I) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(iter)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, I> {
+        // a panic in `next` doesn't stop the other clones
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<I> Clone for SharedIter<I> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<I> fmt::Debug for SharedIter<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedIter")
+            .field("handles", &Arc::strong_count(&self.inner))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> Iterator for SharedIter<I>
+where
+    I: Iterator,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lock().next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // other clones can take any of the remaining items
+        (0, self.lock().size_hint().1)
+    }
+}
@@ -0,0 +1,44 @@
+use std::thread;
+
+/// Starts the worker threads of a stage
+///
+/// By default workers are started with [`std::thread::spawn`]. Implement it to use a
+/// thread pool (like [`crate::Pool`]), or to start the threads some other way, and
+/// pass it to [`crate::ParallelMapConfig::spawner`].
+///
+/// The rest of the stage still needs `std`: the workers get their items over channels,
+/// and their panics are caught to be passed to the consumer.
+///
+/// Closures taking the job are spawners too.
+pub trait Spawner: Send + Sync {
+    /// Run `job` on a thread of its own
+    ///
+    /// The `job` only returns when the stage is done, so it must not be run on
+    /// the calling thread, nor wait for other jobs of the same stage to finish.
+    fn spawn(&self, job: Box<dyn FnOnce() + Send>);
+}
+
+impl<F> Spawner for F
+where
+    F: Fn(Box<dyn FnOnce() + Send>) + Send + Sync,
+{
+    fn spawn(&self, job: Box<dyn FnOnce() + Send>) {
+        (self)(job)
+    }
+}
+
+/// Run `job` with `spawner`, or on a new thread named `name`, if not set
+pub(crate) fn spawn<J>(spawner: Option<&dyn Spawner>, name: String, job: J)
+where
+    J: FnOnce() + Send + 'static,
+{
+    match spawner {
+        Some(spawner) => spawner.spawn(Box::new(job)),
+        None => {
+            thread::Builder::new()
+                .name(name)
+                .spawn(job)
+                .expect("failed to spawn worker thread");
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    convert::TryFrom,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time,
+};
+
+/// Callback of [`crate::ParallelMapConfig::report_stragglers`]
+pub(crate) type StragglerCallback = Arc<dyn Fn(&[Straggler]) + Send + Sync>;
+
+/// One of the slowest items of a stage
+///
+/// See [`crate::ParallelMapConfig::report_stragglers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Straggler {
+    index: usize,
+    duration: time::Duration,
+    item: Option<String>,
+}
+
+impl Straggler {
+    /// Position of the item in the inner iterator
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Time it took to process the item
+    pub fn duration(&self) -> time::Duration {
+        self.duration
+    }
+
+    /// Rendering of the item, if enabled with [`crate::ParallelMapBuilder::format_items`]
+    pub fn item(&self) -> Option<&str> {
+        self.item.as_deref()
+    }
+}
+
+impl fmt::Display for Straggler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "item #{} took {:?}", self.index, self.duration)?;
+        if let Some(item) = self.item.as_ref() {
+            write!(f, " ({})", item)?;
+        }
+        Ok(())
+    }
+}
+
+/// Duration, index and rendering of an item
+type Entry = (time::Duration, usize, Option<String>);
+
+/// The slowest items seen by the workers so far
+pub(crate) struct Stragglers {
+    // number of items to keep
+    n: usize,
+    // duration of the fastest item kept, in nanoseconds, once `n` are kept
+    //
+    // Lets the workers skip the lock for all the other items.
+    threshold: AtomicU64,
+    // fastest first
+    top: Mutex<BinaryHeap<Reverse<Entry>>>,
+    callback: StragglerCallback,
+}
+
+impl Stragglers {
+    pub(crate) fn new(n: usize, callback: StragglerCallback) -> Self {
+        Self {
+            n,
+            threshold: AtomicU64::new(0),
+            top: Mutex::new(BinaryHeap::with_capacity(n + 1)),
+            callback,
+        }
+    }
+
+    /// Record the `duration` of processing item `index`, rendered with `render` if needed
+    pub(crate) fn record(
+        &self,
+        index: usize,
+        duration: time::Duration,
+        render: impl FnOnce() -> Option<String>,
+    ) {
+        if self.n == 0 || nanos(duration) <= self.threshold.load(Relaxed) {
+            return;
+        }
+
+        let mut top = self.top.lock().expect("lock poisoned");
+        top.push(Reverse((duration, index, render())));
+        if self.n < top.len() {
+            top.pop();
+        }
+        if top.len() == self.n {
+            if let Some(Reverse((fastest, _, _))) = top.peek() {
+                self.threshold.store(nanos(*fastest), Relaxed);
+            }
+        }
+    }
+
+    /// Call the callback with the slowest items, slowest first
+    pub(crate) fn report(&self) {
+        let top = std::mem::take(&mut *self.top.lock().expect("lock poisoned"));
+        let stragglers: Vec<_> = top
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((duration, index, item))| Straggler {
+                index,
+                duration,
+                item,
+            })
+            .collect();
+        (self.callback)(&stragglers);
+    }
+}
+
+fn nanos(duration: time::Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
@@ -1,5 +1,10 @@
 use crate::TotalTimeProfiler;
 
+use std::sync::{
+    atomic::{AtomicUsize, Ordering::SeqCst},
+    Arc,
+};
+
 use super::IteratorExt;
 use quickcheck_macros::quickcheck;
 
@@ -18,6 +23,343 @@ fn map_vs_map_parallel(v: Vec<usize>, threads: usize, max_in_flight: usize) -> b
     m == mp
 }
 
+#[quickcheck]
+fn map_vs_batch_map_parallel(v: Vec<usize>, threads: usize, batch_size: usize) -> bool {
+    let batch_size = batch_size % 16 + 1;
+    let m: Vec<_> = v.iter().map(|x| x / 2).collect();
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_batch_map_custom(
+            |o| o.threads(threads % 8),
+            batch_size,
+            move |batch| {
+                assert!(batch.len() <= batch_size);
+                batch.into_iter().map(|x| x / 2).collect()
+            },
+        )
+        .collect();
+
+    m == mp
+}
+
+#[test]
+#[should_panic(expected = "different number of outputs")]
+fn batch_map_wrong_output_len() {
+    (0..10)
+        .parallel_batch_map(4, |mut batch: Vec<i32>| {
+            batch.pop();
+            batch
+        })
+        .for_each(drop);
+}
+
+#[quickcheck]
+fn map_vs_map_inline(v: Vec<usize>, limit: usize, skip: usize) -> bool {
+    use crate::{ParallelMapBuilder, ReadaheadBuilder};
+
+    let (limit, skip) = (limit % 64, skip % 8);
+    let m: Vec<_> = v.iter().take(limit).map(|x| x / 2).skip(skip).collect();
+    let mi: Vec<_> = ParallelMapBuilder::new(v.iter().copied())
+        .limit(limit)
+        .with_enumerate_inline(|_i, x| x / 2)
+        .skip(skip)
+        .collect();
+    let ri: Vec<_> = ReadaheadBuilder::new(v.iter().copied())
+        .with_inline()
+        .collect();
+
+    m == mi && v == ri
+}
+
+#[quickcheck]
+fn state_vs_state_inline(v: Vec<u32>) -> bool {
+    use crate::{inline::InlineState, ParallelMapBuilder};
+
+    let mi: Vec<_> = ParallelMapBuilder::new(v.iter().copied())
+        .with_inline(
+            InlineState::new(
+                |_worker_i| 0,
+                |sum: &mut u64, x| {
+                    *sum += u64::from(x);
+                    u64::from(x / 2)
+                },
+                |sum| vec![sum],
+            ),
+            true,
+        )
+        .collect();
+
+    // the sum returned by `finish` comes last
+    let (mapped, tail) = mi.split_at(v.len());
+    mapped
+        .iter()
+        .copied()
+        .eq(v.iter().map(|x| u64::from(x / 2)))
+        && tail == [v.iter().copied().map(u64::from).sum::<u64>()]
+}
+
+#[test]
+fn state_nth_past_the_input() {
+    use crate::{inline::InlineState, ParallelMapBuilder};
+
+    // yield `[0, 1, 2, 100, 200]`
+    let threaded = || {
+        (0..3).parallel_map_with_state_custom(
+            |o| o.threads(1),
+            |_| (),
+            |_, x| x,
+            |_| vec![100, 200],
+        )
+    };
+    let inline = || {
+        ParallelMapBuilder::new(0..3).with_inline(
+            InlineState::new(|_| (), |_: &mut (), x| x, |_| vec![100, 200]),
+            true,
+        )
+    };
+
+    for n in 0..6 {
+        let expected = [0, 1, 2, 100, 200].get(n).copied();
+        assert_eq!(threaded().nth(n), expected);
+        assert_eq!(inline().nth(n), expected);
+    }
+
+    let mut it = threaded();
+    assert_eq!(it.nth(1), Some(1));
+    assert_eq!(it.nth(2), Some(200));
+    assert_eq!(it.next(), None);
+}
+
+#[test]
+fn inline_map_starts_no_helper_threads() {
+    use crate::ParallelMapBuilder;
+
+    let reports = Arc::new(AtomicUsize::new(0));
+    let v: Vec<_> = ParallelMapBuilder::new(0..2)
+        .stall_warning(std::time::Duration::from_millis(1), {
+            let reports = reports.clone();
+            move |_report| {
+                reports.fetch_add(1, SeqCst);
+            }
+        })
+        .with_enumerate_inline(|_i, x| {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            x
+        })
+        .collect();
+
+    assert_eq!(v, vec![0, 1]);
+    assert_eq!(reports.load(SeqCst), 0);
+}
+
+#[quickcheck]
+fn map_ok_vs_map_ok_parallel(v: Vec<Result<u32, i8>>, threads: usize) -> bool {
+    let m: Vec<_> = v.iter().map(|r| r.map(|x| x / 2)).collect();
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_map_ok_custom(|o| o.threads(threads % 8), |x| x / 2)
+        .collect();
+
+    m == mp
+}
+
+#[quickcheck]
+fn filter_ok_vs_filter_ok_parallel(v: Vec<Result<u32, i8>>, threads: usize) -> bool {
+    let m: Vec<_> = v
+        .iter()
+        .filter(|r| r.as_ref().map_or(true, |x| x % 3 == 0))
+        .cloned()
+        .collect();
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_filter_ok_custom(|o| o.threads(threads % 8), |x| x % 3 == 0)
+        .collect();
+
+    m == mp
+}
+
+#[quickcheck]
+fn partition_result_vs_partition_result_parallel(v: Vec<i64>, threads: usize) -> bool {
+    let f = |x: i64| if x % 3 == 0 { Err(x) } else { Ok(x / 2) };
+    let oks: Vec<_> = v.iter().filter_map(|x| f(*x).ok()).collect();
+    let errs: Vec<_> = v
+        .iter()
+        .enumerate()
+        .filter_map(|(i, x)| f(*x).err().map(|e| (i, e)))
+        .collect();
+
+    v.into_iter()
+        .partition_result_parallel_custom(|o| o.threads(threads % 8), f)
+        == (oks, errs)
+}
+
+#[quickcheck]
+fn map_keep_input_vs_map(v: Vec<String>, threads: usize) -> bool {
+    let m: Vec<_> = v.iter().map(|s| (s.clone(), s.len())).collect();
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_map_keep_input_custom(|o| o.threads(threads % 8), |s| s.len())
+        .collect();
+
+    m == mp
+}
+
+#[quickcheck]
+fn map_fused_vs_map(v: Vec<u32>, threads: usize) -> bool {
+    let m: Vec<_> = v
+        .iter()
+        .map(|x| x / 2)
+        .map(|x| x + 1)
+        .map(u64::from)
+        .collect();
+    let fused = v
+        .into_iter()
+        .parallel_map_fused_custom(|o| o.threads(threads % 8), |x| x / 2)
+        .then_parallel_map(|x| x + 1)
+        .then_parallel_map(u64::from);
+    let size_hint = fused.size_hint();
+    let mp: Vec<_> = fused.collect();
+
+    size_hint == (m.len(), Some(m.len())) && m == mp
+}
+
+#[test]
+#[should_panic(expected = "before the first item is pulled")]
+fn map_fused_after_start() {
+    let mut fused = (0..10).parallel_map_fused(|x| x + 1);
+    fused.next();
+    fused.then_parallel_map(|x| x * 2).for_each(drop);
+}
+
+#[quickcheck]
+fn checksum_vs_sequential(v: Vec<Vec<u8>>, threads: usize) -> bool {
+    use crate::checksum::{crc32, Checksummed};
+
+    let mut checksums = v
+        .clone()
+        .into_iter()
+        .parallel_checksum_custom(|o| o.threads(threads % 8));
+    let returned: Vec<Checksummed<Vec<u8>>> = checksums.by_ref().collect();
+    let sequential: Vec<u8> = v
+        .iter()
+        .flat_map(|item| crc32(item).to_le_bytes())
+        .collect();
+
+    returned.iter().all(Checksummed::verify)
+        && returned
+            .into_iter()
+            .map(Checksummed::into_inner)
+            .collect::<Vec<_>>()
+            == v
+        && checksums.digest() == crc32(&sequential)
+}
+
+#[test]
+fn checksum_scoped_verify() {
+    use crate::checksum::{crc32, Checksummed};
+
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    let items: Vec<Checksummed<Vec<u8>>> = crate::scope(|scope| {
+        vec![vec![1u8, 2], vec![3]]
+            .into_iter()
+            .parallel_checksum_scoped(scope)
+            .collect()
+    })
+    .expect("scope");
+    assert!(items.iter().all(Checksummed::verify));
+    assert_eq!(items[1].checksum(), crc32(&[3]));
+}
+
+#[quickcheck]
+fn skip_vs_skip_parallel(v: Vec<usize>, threads: usize, skip: usize, step: usize) -> bool {
+    let skip = skip % 64;
+    let step = step % 8;
+    let m: Vec<_> = v
+        .clone()
+        .into_iter()
+        .map(|x| x / 2)
+        .skip(skip)
+        .step_by(step + 1)
+        .collect();
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_map_custom(|o| o.threads(threads % 32), |x| x / 2)
+        .skip(skip)
+        .step_by(step + 1)
+        .collect();
+
+    m == mp
+}
+
+#[test]
+fn nth_does_not_map_skipped_items() {
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let mut iter = (0..100).parallel_map_custom(|o| o.threads(2).buffer_size(4), {
+        let calls = calls.clone();
+        move |x| {
+            calls.fetch_add(1, SeqCst);
+            x
+        }
+    });
+
+    assert_eq!(iter.nth(50), Some(50));
+    assert_eq!(iter.nth(10), Some(61));
+    drop(iter);
+
+    // only items sent to the workers before the skips got mapped
+    assert!(calls.load(SeqCst) <= 3 * 4);
+}
+
+#[quickcheck]
+fn take_vs_limit_parallel(v: Vec<usize>, threads: usize, limit: usize) -> bool {
+    let limit = limit % 64;
+    let m: Vec<_> = v.clone().into_iter().take(limit).map(|x| x / 2).collect();
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_map_custom(|o| o.threads(threads % 32).limit(limit), |x| x / 2)
+        .collect();
+
+    m == mp
+}
+
+#[test]
+fn limit_does_not_map_items_past_it() {
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let v: Vec<_> = (0..100)
+        .parallel_map_custom(|o| o.threads(2).buffer_size(16).limit(5), {
+            let calls = calls.clone();
+            move |x| {
+                calls.fetch_add(1, SeqCst);
+                x
+            }
+        })
+        .collect();
+
+    assert_eq!(v, vec![0, 1, 2, 3, 4]);
+    assert_eq!(calls.load(SeqCst), 5);
+}
+
+#[quickcheck]
+fn enumerate_vs_map_parallel_enumerate(v: Vec<usize>, threads: usize, skip: usize) -> bool {
+    let skip = skip % 16;
+    let m: Vec<_> = v
+        .clone()
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| i ^ x)
+        .skip(skip)
+        .collect();
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_map_enumerate_custom(|o| o.threads(threads % 32), |i, x| i ^ x)
+        .skip(skip)
+        .collect();
+
+    m == mp
+}
+
 #[quickcheck]
 fn map_vs_map_parallel_double(v: Vec<usize>, threads: usize, max_in_flight: usize) -> bool {
     let m: Vec<_> = v.clone().into_iter().map(|x| x / 2).collect();
@@ -51,6 +393,21 @@ fn map_vs_map_parallel_scoped_double(v: Vec<usize>, threads: usize, max_in_fligh
     m == mp
 }
 
+#[quickcheck]
+fn map_vs_map_parallel_scoped_cloned_and_copied(v: Vec<String>, threads: usize) -> bool {
+    let m: Vec<usize> = v.iter().map(|x| x.len()).collect();
+    let mp: Vec<usize> = super::scope(|s| {
+        v.iter()
+            .parallel_map_scoped_cloned_custom(s, |o| o.threads(threads % 32), |x| x.len())
+            .collect()
+    })
+    .expect("failed");
+    let mp2: Vec<usize> =
+        super::scope(|s| m.iter().parallel_map_scoped_copied(s, |x| x).collect()).expect("failed");
+
+    m == mp && m == mp2
+}
+
 #[quickcheck]
 fn map_vs_map_parallel_with_readahead(v: Vec<usize>, threads: usize) -> bool {
     let m: Vec<_> = v.clone().into_iter().map(|x| x / 2).collect();
@@ -149,72 +506,589 @@ fn iter_vs_readhead_scoped(v: Vec<usize>, out: usize) -> bool {
 }
 
 #[quickcheck]
-fn filter_vs_parallel_filter(v: Vec<usize>) -> bool {
-    let m: Vec<_> = v.clone().into_iter().filter(|x| x % 2 == 0).collect();
+fn parallel_map_with_state_emits_tail(v: Vec<u32>, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
     let mp: Vec<_> = v
         .clone()
         .into_iter()
-        .parallel_filter(|x| x % 2 == 0)
+        .parallel_map_with_state_custom(
+            |o| o.threads(threads),
+            |_worker_i| 0u64,
+            |sum, x| {
+                *sum += u64::from(x);
+                u64::from(x)
+            },
+            Some,
+        )
         .collect();
 
-    m == mp
+    let (ordered, tail) = mp.split_at(v.len());
+    ordered
+        == v.iter()
+            .map(|x| u64::from(*x))
+            .collect::<Vec<_>>()
+            .as_slice()
+        && tail.len() == threads
+        && tail.iter().sum::<u64>() == v.iter().map(|x| u64::from(*x)).sum::<u64>()
 }
 
 #[quickcheck]
-fn filter_vs_parallel_filter_scoped(v: Vec<usize>) -> bool {
-    let m: Vec<_> = v.iter().filter(|x| *x % 2 == 0).collect();
-    let mp: Vec<_> = super::scope(|s| {
-        v.iter()
-            .parallel_filter_scoped(s, |x| *x % 2 == 0)
-            .collect()
-    })
-    .expect("failed");
+fn group_by_vs_sequential(v: Vec<u8>, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
 
-    m == mp
+    let mut expected: Vec<(u8, usize)> = vec![];
+    for x in &v {
+        match expected.last_mut() {
+            Some((k, count)) if *k == x % 3 => *count += 1,
+            _ => expected.push((x % 3, 1)),
+        }
+    }
+
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_group_by_custom(
+            |o| o.threads(threads),
+            |x| x % 3,
+            |k, group| {
+                assert!(group.iter().all(|x| x % 3 == *k));
+                group.len()
+            },
+        )
+        .collect();
+
+    expected == mp
 }
 
-#[test]
-#[should_panic]
-fn panic_always_1() {
-    (0..10)
-        .parallel_map_custom(|o| o.threads(1), |_| panic!("foo"))
-        .count();
+#[quickcheck]
+fn dedup_by_vs_sequential(v: Vec<u8>, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
+
+    let mut expected = v.clone();
+    expected.dedup_by_key(|x| *x % 4);
+
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_dedup_by_custom(|o| o.threads(threads), |x| x % 4)
+        .collect();
+
+    expected == mp
+}
+
+#[quickcheck]
+fn unique_vs_sequential(v: Vec<(u8, u8)>, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
+
+    let mut seen = std::collections::HashSet::new();
+    let expected: Vec<_> = v.iter().filter(|(k, _)| seen.insert(*k)).cloned().collect();
+
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_unique_custom(|o| o.threads(threads), |(k, _)| *k)
+        .collect();
+
+    expected == mp
+}
+
+#[quickcheck]
+fn unique_relaxed_keeps_one_per_key(v: Vec<(u8, u8)>, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
+
+    let expected: std::collections::HashSet<_> = v.iter().map(|(k, _)| *k).collect();
+
+    let mp: Vec<_> = v
+        .clone()
+        .into_iter()
+        .parallel_unique_custom(|o| o.threads(threads).relaxed(), |(k, _)| *k)
+        .collect();
+
+    let keys: std::collections::HashSet<_> = mp.iter().map(|(k, _)| *k).collect();
+    // kept items are a subsequence of the input
+    let mut input = v.iter();
+    keys == expected && keys.len() == mp.len() && mp.iter().all(|item| input.any(|i| i == item))
+}
+
+#[quickcheck]
+fn top_k_vs_sort(v: Vec<u32>, k: usize, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
+    let k = k % 10;
+
+    let mut expected = v.clone();
+    expected.sort_by(|a, b| b.cmp(a));
+    expected.truncate(k);
+
+    let top = v
+        .into_iter()
+        .parallel_top_k_custom(|o| o.threads(threads), k, |x| *x);
+
+    expected == top
 }
 
 #[test]
-#[should_panic]
-fn panic_always_8() {
-    (0..10)
-        .parallel_map_custom(|o| o.threads(8), |_| panic!("foo"))
-        .count();
+fn top_k_larger_than_input() {
+    for k in [4, 1 << 20, usize::MAX] {
+        let top = (0..3u32).parallel_top_k_custom(|o| o.threads(2), k, |x| *x);
+        assert_eq!(top, [2, 1, 0]);
+    }
+}
+
+#[quickcheck]
+fn sample_is_ordered_subset(v: Vec<u8>, n: usize, seed: u64, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
+    let n = n % 10;
+
+    // xorshift, good enough for a test
+    let mut state = seed | 1;
+    let rng = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    };
+
+    let sample = v.clone().into_iter().parallel_sample_custom(
+        |o| o.threads(threads),
+        n,
+        |x| f64::from(*x % 4),
+        rng,
+    );
+
+    let positive = v.iter().filter(|x| *x % 4 != 0).count();
+    let mut input = v.iter();
+    sample.len() == std::cmp::min(n, positive)
+        && sample.iter().all(|x| x % 4 != 0)
+        && sample.iter().all(|item| input.any(|i| i == item))
+}
+
+#[quickcheck]
+fn sort_by_key_vs_sort(v: Vec<(u8, u16)>, run_size: usize, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
+    let run_size = run_size % 10 + 1;
+
+    let mut expected = v.clone();
+    expected.sort_by_key(|(k, _)| *k);
+
+    let sorted: Vec<_> = v
+        .into_iter()
+        .parallel_sort_by_key_custom(|o| o.threads(threads).run_size(run_size), |(k, _)| *k)
+        .collect();
+
+    expected == sorted
 }
 
 #[test]
-#[should_panic]
-fn panic_once_1() {
-    (0..10)
-        .parallel_map_custom(
-            |o| o.threads(1),
-            |i| {
-                if i == 5 {
-                    panic!("foo");
-                } else {
-                    i
-                }
+fn sort_by_key_spills() {
+    let dir = std::env::temp_dir().join(format!("pariter-sort-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let v: Vec<u32> = (0..1000).map(|i| (i * 7919) % 1000).collect();
+    let sorted: Vec<_> = v
+        .into_iter()
+        .parallel_sort_by_key_custom(
+            |o| {
+                o.run_size(100).spill(
+                    &dir,
+                    200,
+                    |x, w| w.write_all(&x.to_le_bytes()),
+                    |r| {
+                        let mut buf = [0; 4];
+                        if r.fill_buf()?.is_empty() {
+                            return Ok(None);
+                        }
+                        r.read_exact(&mut buf)?;
+                        Ok(Some(u32::from_le_bytes(buf)))
+                    },
+                )
             },
+            |x| *x,
         )
-        .count();
+        .collect();
+
+    assert_eq!(sorted, (0..1000).collect::<Vec<_>>());
+    // spill files are removed once merged
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir(&dir).unwrap();
 }
 
 #[test]
-#[should_panic]
-fn panic_once_8() {
-    (0..10)
-        .parallel_map_custom(
-            |o| o.threads(8),
-            |i| {
-                if i == 5 {
-                    panic!("foo");
+fn sort_by_key_size_hint() {
+    let mut sorted = (0..1000u32)
+        .rev()
+        .parallel_sort_by_key_custom(|o| o.run_size(100), |x| *x);
+
+    assert_eq!(sorted.size_hint(), (0, None));
+    assert_eq!(sorted.next(), Some(0));
+    assert_eq!(sorted.size_hint(), (999, Some(999)));
+    assert_eq!(sorted.count(), 999);
+}
+
+#[test]
+fn sort_by_key_results_returns_spill_errors() {
+    let dir = std::env::temp_dir().join(format!(
+        "pariter-sort-missing-{}/not-created",
+        std::process::id()
+    ));
+
+    let res: Vec<_> = (0..1000u32)
+        .rev()
+        .parallel_sort_by_key_custom(
+            |o| {
+                o.run_size(100).spill(
+                    &dir,
+                    200,
+                    |x, w| w.write_all(&x.to_le_bytes()),
+                    |_r| Ok(None),
+                )
+            },
+            |x| *x,
+        )
+        .results()
+        .collect();
+
+    assert_eq!(res.len(), 1);
+    let e = res[0].as_ref().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    assert!(e.to_string().starts_with("failed to spill to"));
+}
+
+#[quickcheck]
+fn window_map_vs_sequential(v: Vec<u8>, size: usize, step: usize, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
+    let size = size % 5 + 1;
+    let step = step % 7 + 1;
+
+    let expected: Vec<Vec<u8>> = v.windows(size).step_by(step).map(|w| w.to_vec()).collect();
+
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_window_map_custom(|o| o.threads(threads), size, step, |w| w.to_vec())
+        .collect();
+
+    expected == mp
+}
+
+#[test]
+fn windows_next_after_none() {
+    let mut windows = crate::Windows::new(0..5, 3, 2);
+    assert_eq!(windows.next(), Some(vec![0, 1, 2]));
+    assert_eq!(windows.next(), Some(vec![2, 3, 4]));
+    assert_eq!(windows.next(), None);
+    assert_eq!(windows.next(), None);
+    assert_eq!(windows.next(), None);
+}
+
+#[quickcheck]
+fn merge_join_vs_nested_loops(left: Vec<u8>, right: Vec<(u8, u8)>, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
+    let mut left = left;
+    left.sort_unstable();
+    let mut right = right;
+    right.sort_by_key(|(k, _)| *k);
+
+    let mut expected = vec![];
+    for l in &left {
+        for (k, r) in &right {
+            if l == k {
+                expected.push((*l, *r));
+            }
+        }
+    }
+
+    let mut mp = vec![];
+    for pairs in left.into_iter().merge_join_custom(
+        |o| o.threads(threads),
+        right,
+        |l| *l,
+        |(k, _)| *k,
+        |k, left, right| {
+            assert!(left.iter().all(|l| l == k));
+            let mut pairs = vec![];
+            for l in left {
+                pairs.extend(right.iter().map(|(_, r)| (l, *r)));
+            }
+            pairs
+        },
+    ) {
+        mp.extend(pairs);
+    }
+
+    expected == mp
+}
+
+#[quickcheck]
+fn fan_in_restores_order(v: Vec<u16>, shards: usize) -> bool {
+    let shards = shards % 5 + 1;
+
+    let mut output = vec![];
+    super::scope(|s| {
+        super::fan_in_scoped(
+            s,
+            (0..shards).map(|shard| {
+                v.iter()
+                    .enumerate()
+                    .skip(shard)
+                    .step_by(shards)
+                    .parallel_map_scoped(s, |(i, x)| (i, *x))
+            }),
+        )
+        .for_each(|x| output.push(x));
+    })
+    .expect("failed");
+
+    output == v
+}
+
+#[quickcheck]
+fn readahead_split_vs_sequential(v: Vec<u32>, n: u8) -> bool {
+    let n = usize::from(n % 8) + 1;
+    let ordered =
+        v.iter()
+            .map(|x| x / 2)
+            .eq(v.clone().into_iter().map(|x| x / 2).readahead_split(n));
+
+    let mut unordered: Vec<_> = v.clone().into_iter().readahead_split_unordered(n).collect();
+    unordered.sort_unstable();
+    let mut expected = v;
+    expected.sort_unstable();
+
+    ordered && unordered == expected
+}
+
+#[test]
+fn readahead_split_scoped() {
+    let v: Vec<_> = (0..100).collect();
+    super::scope(|scope| {
+        assert!(v.iter().eq(v.iter().readahead_split_scoped(scope, 3)));
+        assert_eq!(
+            v.iter()
+                .readahead_split_unordered_scoped(scope, 3)
+                .sum::<i32>(),
+            v.iter().sum()
+        );
+    })
+    .unwrap();
+}
+
+#[quickcheck]
+fn scan_keyed_vs_sequential(v: Vec<(u8, u32)>, threads: usize) -> bool {
+    let threads = threads % 8 + 1;
+
+    let mut sums = std::collections::HashMap::new();
+    let expected: Vec<_> = v
+        .iter()
+        .map(|&(k, x)| {
+            let sum = sums.entry(k % 4).or_insert(0u64);
+            *sum += u64::from(x);
+            *sum
+        })
+        .collect();
+
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_scan_keyed_custom(
+            |o| o.threads(threads),
+            |&(k, _)| k % 4,
+            |_k| 0u64,
+            |sum, (_k, x)| {
+                *sum += u64::from(x);
+                *sum
+            },
+        )
+        .collect();
+
+    expected == mp
+}
+
+#[test]
+fn scan_keyed_scoped() {
+    let v: Vec<_> = (0..100).collect();
+
+    let res: Vec<_> = super::scope(|s| {
+        v.iter()
+            .parallel_scan_keyed_scoped(
+                s,
+                |x| *x % 3,
+                |_k| 0,
+                |count, x| {
+                    *count += 1;
+                    (*x, *count)
+                },
+            )
+            .collect()
+    })
+    .expect("failed");
+
+    assert_eq!(res.len(), 100);
+    assert!(res.iter().all(|(x, count)| *count == x / 3 + 1));
+}
+
+#[test]
+#[should_panic]
+fn scan_keyed_panic() {
+    (0..100)
+        .parallel_scan_keyed(
+            |x| x % 2,
+            |_k| (),
+            |_, x| {
+                if x == 51 {
+                    panic!("foo");
+                }
+                x
+            },
+        )
+        .count();
+}
+
+#[test]
+fn scan_keyed_out_of_order() {
+    // with one key per worker, later items of the fast key arrive first
+    let res: Vec<_> = (0..100)
+        .parallel_scan_keyed_custom(
+            |o| {
+                o.threads(2)
+                    .buffer_size(16)
+                    .panic_poll_interval(std::time::Duration::from_millis(1))
+            },
+            |x| x % 2,
+            |_k| 0,
+            |count, x| {
+                if x % 2 == 0 {
+                    std::thread::sleep(std::time::Duration::from_micros(200));
+                }
+                *count += 1;
+                (x, *count)
+            },
+        )
+        .collect();
+
+    assert_eq!(res.len(), 100);
+    assert!(res.iter().enumerate().all(|(i, (x, _))| i == *x));
+    assert!(res.iter().all(|(x, count)| *count == x / 2 + 1));
+}
+
+#[test]
+fn scan_keyed_results_returns_worker_panics() {
+    let res: Vec<_> = (0..10)
+        .parallel_scan_keyed_custom(
+            |o| o.threads(1).debug_items(),
+            |x| x % 2,
+            |_k| (),
+            |_, x| {
+                if x == 3 {
+                    panic!("foo");
+                }
+                x
+            },
+        )
+        .results()
+        .collect();
+
+    assert_eq!(res.len(), 4);
+    assert!(res[..3].iter().all(|r| r.is_ok()));
+    let e = res[3].as_ref().unwrap_err();
+    assert_eq!(e.item_index(), Some(3));
+    assert_eq!(e.item(), Some("3"));
+    assert_eq!(e.panic_message(), Some("foo"));
+    assert!(e.to_string().starts_with("parallel_scan_keyed#"));
+}
+
+#[quickcheck]
+fn update_vs_parallel_update(v: Vec<usize>, threads: usize) -> bool {
+    let m: Vec<_> = v.clone().into_iter().map(|x| x / 2).collect();
+    let mp: Vec<_> = v
+        .into_iter()
+        .parallel_update_custom(|o| o.threads(threads % 32), |x| *x /= 2)
+        .collect();
+
+    m == mp
+}
+
+#[quickcheck]
+fn inspect_vs_parallel_inspect_scoped(v: Vec<usize>, threads: usize) -> bool {
+    let sum = AtomicUsize::new(0);
+    let mp: Vec<_> = super::scope(|s| {
+        v.iter()
+            .parallel_inspect_scoped_custom(
+                s,
+                |o| o.threads(threads % 32),
+                |x| {
+                    sum.fetch_add(**x % 1000, SeqCst);
+                },
+            )
+            .collect()
+    })
+    .expect("failed");
+
+    v.iter().collect::<Vec<_>>() == mp
+        && v.iter().map(|x| x % 1000).sum::<usize>() == sum.load(SeqCst)
+}
+
+#[quickcheck]
+fn filter_vs_parallel_filter(v: Vec<usize>) -> bool {
+    let m: Vec<_> = v.clone().into_iter().filter(|x| x % 2 == 0).collect();
+    let mp: Vec<_> = v
+        .clone()
+        .into_iter()
+        .parallel_filter(|x| x % 2 == 0)
+        .collect();
+
+    m == mp
+}
+
+#[quickcheck]
+fn filter_vs_parallel_filter_scoped(v: Vec<usize>) -> bool {
+    let m: Vec<_> = v.iter().filter(|x| *x % 2 == 0).collect();
+    let mp: Vec<_> = super::scope(|s| {
+        v.iter()
+            .parallel_filter_scoped(s, |x| *x % 2 == 0)
+            .collect()
+    })
+    .expect("failed");
+
+    m == mp
+}
+
+#[test]
+#[should_panic]
+fn panic_always_1() {
+    (0..10)
+        .parallel_map_custom(|o| o.threads(1), |_| panic!("foo"))
+        .count();
+}
+
+#[test]
+#[should_panic]
+fn panic_always_8() {
+    (0..10)
+        .parallel_map_custom(|o| o.threads(8), |_| panic!("foo"))
+        .count();
+}
+
+#[test]
+#[should_panic]
+fn panic_once_1() {
+    (0..10)
+        .parallel_map_custom(
+            |o| o.threads(1),
+            |i| {
+                if i == 5 {
+                    panic!("foo");
+                } else {
+                    i
+                }
+            },
+        )
+        .count();
+}
+
+#[test]
+#[should_panic]
+fn panic_once_8() {
+    (0..10)
+        .parallel_map_custom(
+            |o| o.threads(8),
+            |i| {
+                if i == 5 {
+                    panic!("foo");
                 } else {
                     i
                 }
@@ -290,3 +1164,2275 @@ fn panic_before_a_point_8() {
         )
         .count();
 }
+
+#[test]
+fn debug_shows_pipeline_state() {
+    let mut iter = (0..10).parallel_map_custom(|o| o.threads(2).buffer_size(3), |x| x);
+    assert_eq!(iter.next(), Some(0));
+
+    let debug = format!("{:?}", iter);
+    assert!(debug.contains("num_threads: 2"), "{}", debug);
+    assert!(debug.contains("buffer_size: 3"), "{}", debug);
+    assert!(debug.contains("in_flight: 3"), "{}", debug);
+    assert!(debug.contains("iter_done: false"), "{}", debug);
+
+    let debug = format!("{:?}", (0..10).readahead_custom(|o| o.buffer_size(4)));
+    assert!(debug.contains("buffer_size: 4"), "{}", debug);
+}
+
+#[test]
+fn stall_warning_reports_stalled_stage() {
+    let reports = Arc::new(AtomicUsize::new(0));
+
+    let v: Vec<_> = (0..2)
+        .parallel_map_custom(
+            |o| {
+                o.threads(1)
+                    .name("sleepy")
+                    .stall_warning(std::time::Duration::from_millis(10), {
+                        let reports = reports.clone();
+                        move |report| {
+                            assert_eq!(report.name(), Some("sleepy"));
+                            assert!(0 < report.in_flight());
+                            reports.fetch_add(1, SeqCst);
+                        }
+                    })
+            },
+            |x| {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                x
+            },
+        )
+        .collect();
+
+    assert_eq!(v, vec![0, 1]);
+    assert!(0 < reports.load(SeqCst));
+}
+
+#[test]
+fn on_slow_item_reports_slow_items() {
+    let slow = Arc::new(std::sync::Mutex::new(vec![]));
+
+    let v: Vec<_> = (0..6)
+        .parallel_map_custom(
+            |o| {
+                o.threads(2)
+                    .on_slow_item(std::time::Duration::from_millis(50), {
+                        let slow = slow.clone();
+                        move |_duration, item| slow.lock().unwrap().push(item.to_owned())
+                    })
+            },
+            |x| {
+                if x == 3 {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+                x
+            },
+        )
+        .collect();
+
+    assert_eq!(v, (0..6).collect::<Vec<_>>());
+    assert_eq!(*slow.lock().unwrap(), vec!["3".to_owned()]);
+}
+
+#[test]
+#[should_panic(expected = "while processing item #5 (5): foo")]
+fn panic_message_includes_item() {
+    (0..10)
+        .parallel_map_custom(
+            |o| o.threads(2).debug_items(),
+            |i| {
+                if i == 5 {
+                    panic!("foo");
+                } else {
+                    i
+                }
+            },
+        )
+        .count();
+}
+
+#[test]
+fn worker_hooks_run_in_each_worker() {
+    thread_local! {
+        static WORKER: std::cell::Cell<Option<usize>> = const { std::cell::Cell::new(None) };
+    }
+    let started = Arc::new(AtomicUsize::new(0));
+    let stopped = Arc::new(AtomicUsize::new(0));
+
+    let v: Vec<_> = (0..100)
+        .parallel_map_custom(
+            |o| {
+                o.threads(4)
+                    .on_worker_start({
+                        let started = started.clone();
+                        move |i| {
+                            WORKER.with(|w| w.set(Some(i)));
+                            started.fetch_add(1, SeqCst);
+                        }
+                    })
+                    .on_worker_stop({
+                        let stopped = stopped.clone();
+                        move |i| {
+                            assert_eq!(WORKER.with(|w| w.get()), Some(i));
+                            stopped.fetch_add(1, SeqCst);
+                        }
+                    })
+            },
+            |x| {
+                assert!(WORKER.with(|w| w.get()).is_some());
+                x
+            },
+        )
+        .collect();
+
+    assert_eq!(v, (0..100).collect::<Vec<_>>());
+    assert_eq!(started.load(SeqCst), 4);
+    // workers stop shortly after the inner iterator is exhausted
+    while stopped.load(SeqCst) != 4 {
+        std::thread::yield_now();
+    }
+}
+
+#[test]
+#[should_panic]
+fn panic_in_finish() {
+    (0..10)
+        .parallel_map_with_state(|_| (), |_, i| i, |_| -> Option<i32> { panic!("foo") })
+        .count();
+}
+
+#[test]
+fn throttle_limits_rate() {
+    let blocked = Arc::new(AtomicUsize::new(0));
+
+    let start = std::time::Instant::now();
+    let v: Vec<_> = (0..11)
+        .throttle_profiled(
+            100.0,
+            TotalTimeProfiler::new({
+                let blocked = blocked.clone();
+                move |stats: &mut crate::TotalTimeStats| {
+                    blocked.store(stats.total().as_millis() as usize, SeqCst);
+                }
+            }),
+        )
+        .parallel_map(|i| i)
+        .collect();
+
+    assert_eq!(v, (0..11).collect::<Vec<_>>());
+    // the first item is free
+    assert!(std::time::Duration::from_millis(100) <= start.elapsed());
+    // and most of the time is spent waiting on the rate limit
+    assert!(50 <= blocked.load(SeqCst));
+}
+
+#[test]
+fn cpu_quota_sleeps_between_items() {
+    let start = std::time::Instant::now();
+    let v: Vec<_> = (0..8)
+        .parallel_map_custom(
+            |o| o.threads(2).cpu_quota(1.0),
+            |i| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                i
+            },
+        )
+        .collect();
+
+    assert_eq!(v, (0..8).collect::<Vec<_>>());
+    // each of the 2 workers is busy half of the time
+    assert!(std::time::Duration::from_millis(70) <= start.elapsed());
+}
+
+#[cfg(all(feature = "priority", target_os = "linux"))]
+#[test]
+fn priority_lowest() {
+    let all_lowest = (0..10)
+        .parallel_map_custom(
+            |o| o.threads(2).priority(crate::ThreadPriority::Lowest),
+            // on Linux, the nice value is per-thread
+            |_| unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) },
+        )
+        .all(|nice| nice == 19);
+
+    assert!(all_lowest);
+}
+
+#[test]
+fn circuit_breaker_opens_and_recovers() {
+    use crate::{BreakerError, CircuitBreaker};
+
+    let breaker = CircuitBreaker::new(3, std::time::Duration::from_millis(50));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let res: Vec<_> = (0..10)
+        .parallel_map_breaker_custom(|o| o.threads(1), breaker.clone(), {
+            let calls = calls.clone();
+            move |i| {
+                calls.fetch_add(1, SeqCst);
+                Err::<(), _>(i)
+            }
+        })
+        .collect();
+
+    // the first 3 failures open the breaker
+    assert_eq!(calls.load(SeqCst), 3);
+    assert_eq!(res[2], Err(BreakerError::Failed(2)));
+    assert_eq!(res[3], Err(BreakerError::Open(3)));
+    assert!(breaker.is_open());
+
+    std::thread::sleep(std::time::Duration::from_millis(60));
+
+    // a successful probe closes it
+    let res: Vec<_> = (0..3)
+        .parallel_map_breaker(breaker.clone(), Ok::<_, ()>)
+        .collect();
+    assert_eq!(res, vec![Ok(0), Ok(1), Ok(2)]);
+    assert!(!breaker.is_open());
+}
+
+#[test]
+fn circuit_breaker_waits() {
+    use crate::CircuitBreaker;
+
+    let breaker = CircuitBreaker::new(1, std::time::Duration::from_millis(20)).wait_when_open();
+
+    let res: Vec<_> = (0..4)
+        .parallel_map_breaker_custom(
+            |o| o.threads(1),
+            breaker,
+            |i| {
+                if i == 0 {
+                    Err(())
+                } else {
+                    Ok(i)
+                }
+            },
+        )
+        .collect();
+
+    assert!(res[1..].iter().all(|r| r.is_ok()));
+}
+
+#[test]
+fn barrier_finishes_upstream_first() {
+    let written = Arc::new(AtomicUsize::new(0));
+
+    let verified: Vec<_> = (0..20)
+        .parallel_map({
+            let written = written.clone();
+            move |i| {
+                written.fetch_add(1, SeqCst);
+                i
+            }
+        })
+        .barrier()
+        .parallel_map({
+            let written = written.clone();
+            move |i| (i, written.load(SeqCst))
+        })
+        .collect();
+
+    assert_eq!(verified.len(), 20);
+    assert!(verified
+        .iter()
+        .enumerate()
+        .all(|(i, (x, written))| i == *x && *written == 20));
+}
+
+#[test]
+fn flush_processes_in_flight_items() {
+    let processed = Arc::new(AtomicUsize::new(0));
+
+    let mut iter = (0..100).parallel_map_custom(|o| o.threads(4).buffer_size(8), {
+        let processed = processed.clone();
+        move |i| {
+            processed.fetch_add(1, SeqCst);
+            i
+        }
+    });
+
+    assert_eq!(iter.next(), Some(0));
+    let done = iter.flush();
+    assert_eq!(processed.load(SeqCst), done);
+    // `next` keeps the workers busy
+    assert!(1 < done);
+
+    assert_eq!(iter.collect::<Vec<_>>(), (1..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn checkpoint_resume() {
+    use crate::checkpoint::Checkpoint;
+
+    let persisted = Arc::new(AtomicUsize::new(0));
+    let persist = {
+        let persisted = persisted.clone();
+        move |completed| persisted.store(completed, SeqCst)
+    };
+
+    // interrupted after 25 items
+    let mut output: Vec<_> = (0..100)
+        .resume_from(0)
+        .parallel_map(|(i, x)| (i, x * 2))
+        .checkpoint(Checkpoint::new(0).persist_every(10, persist.clone()))
+        .take(26)
+        .map(|(_i, x)| x)
+        .collect();
+    assert_eq!(persisted.load(SeqCst), 20);
+    output.truncate(20);
+
+    let resume_from = persisted.load(SeqCst);
+    output.extend(
+        (0..100)
+            .resume_from(resume_from)
+            .parallel_map(|(i, x)| (i, x * 2))
+            .checkpoint(Checkpoint::new(resume_from).persist_every(10, persist))
+            .map(|(_i, x)| x),
+    );
+
+    assert_eq!(persisted.load(SeqCst), 100);
+    assert_eq!(output, (0..100).map(|x| x * 2).collect::<Vec<_>>());
+}
+
+#[test]
+fn checkpoint_out_of_order() {
+    let checkpoint = crate::checkpoint::Checkpoint::new(0);
+    for i in [2, 0, 3, 5, 1] {
+        checkpoint.complete(i);
+    }
+    assert_eq!(checkpoint.completed(), 4);
+}
+
+#[test]
+fn acknowledged_redelivers_unacked() {
+    use crate::checkpoint::Checkpoint;
+
+    // item #3 fails the first time
+    let checkpoint = Checkpoint::new(0);
+    let mut done = vec![];
+    for item in (0..10).resume_from(0).acknowledged(checkpoint.clone()) {
+        if item.index() != 3 {
+            done.push(item.ack());
+        }
+    }
+    assert_eq!(done.len(), 9);
+    assert_eq!(checkpoint.completed(), 3);
+
+    let resume_from = checkpoint.completed();
+    let mut items = (0..10)
+        .resume_from(resume_from)
+        .acknowledged(Checkpoint::new(resume_from));
+    let item = items.next_ack().unwrap();
+    assert_eq!((item.index(), *item), (3, 3));
+}
+
+#[test]
+fn try_with_validates() {
+    use crate::{BuildError, ParallelMapBuilder};
+
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .buffer_size(0)
+            .try_with(|x| x)
+            .err(),
+        Some(BuildError::ZeroBufferSize)
+    );
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .threads(4)
+            .buffer_size(2)
+            .try_with(|x| x)
+            .err(),
+        Some(BuildError::BufferSmallerThanThreads {
+            buffer_size: 2,
+            threads: 4
+        })
+    );
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .memory_cap(100)
+            .try_with(|x| x)
+            .err(),
+        Some(BuildError::MemoryCapWithoutSizeOf)
+    );
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .cpu_quota(f64::NAN)
+            .try_with(|x| x)
+            .err(),
+        Some(BuildError::NonPositiveCpuQuota)
+    );
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .panic_poll_interval(std::time::Duration::ZERO)
+            .try_with(|x| x)
+            .err(),
+        Some(BuildError::ZeroPanicPollInterval)
+    );
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .sample_occupancy(
+                std::time::Duration::ZERO,
+                crate::profile::OccupancyRecorder::new()
+            )
+            .try_with(|x| x)
+            .err(),
+        Some(BuildError::ZeroSamplingInterval)
+    );
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .shed_load(std::time::Duration::ZERO, 1.5)
+            .try_with(|x| x)
+            .err(),
+        Some(BuildError::InvalidShedProbability)
+    );
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .stall_warning(std::time::Duration::ZERO, |_| {})
+            .try_with(|x| x)
+            .err(),
+        Some(BuildError::ZeroStallPeriod)
+    );
+    struct NoopArena;
+    impl crate::Arena for NoopArena {
+        fn reset(&mut self) {}
+    }
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .try_with_arena(0, |_| NoopArena, |_arena, x| x)
+            .err(),
+        Some(BuildError::ZeroEpoch)
+    );
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .threads(2)
+            .try_with(|x| x)
+            .expect("valid")
+            .collect::<Vec<_>>(),
+        (0..10).collect::<Vec<_>>()
+    );
+    // the number of threads is picked automatically
+    assert_eq!(
+        ParallelMapBuilder::new(0..10)
+            .threads(0)
+            .try_with(|x| x)
+            .expect("valid")
+            .count(),
+        10
+    );
+}
+
+#[test]
+fn invalid_values_are_adjusted_by_with() {
+    use crate::ParallelMapBuilder;
+
+    let res: Vec<_> = ParallelMapBuilder::new(0..10)
+        .stall_warning(std::time::Duration::ZERO, |_| {})
+        .cpu_quota(0.0)
+        .panic_poll_interval(std::time::Duration::ZERO)
+        .shed_load(std::time::Duration::ZERO, -1.0)
+        .with(|x| x)
+        .collect();
+    assert_eq!(res, (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn filter_and_readahead_try_with_validate() {
+    use crate::{BuildError, ParallelFilterBuilder, ReadaheadBuilder};
+
+    assert_eq!(
+        ParallelFilterBuilder::new(0..10)
+            .buffer_size(0)
+            .try_with(|_| true)
+            .err(),
+        Some(BuildError::ZeroBufferSize)
+    );
+    assert_eq!(
+        ParallelFilterBuilder::new(0..10)
+            .try_with(|x| x % 2 == 0)
+            .expect("valid")
+            .count(),
+        5
+    );
+    assert_eq!(
+        ReadaheadBuilder::new(0..10)
+            .buffer_bytes(0, |_| 1)
+            .try_with()
+            .err(),
+        Some(BuildError::ZeroBufferBytes)
+    );
+    assert_eq!(
+        ReadaheadBuilder::new(0..10)
+            .buffer_bytes(4, |_| 1)
+            .try_with()
+            .expect("valid")
+            .count(),
+        10
+    );
+}
+
+#[test]
+fn all_workers_dead_before_sending() {
+    let res: Vec<_> = (0..10)
+        .parallel_map_custom(
+            |o| o.threads(2).on_worker_start(|_| panic!("no luck")),
+            |x| x,
+        )
+        .results()
+        .collect();
+
+    // whether the workers are found dead when sending or receiving, no item gets through
+    assert_eq!(res.len(), 1);
+    let err = res[0].as_ref().unwrap_err();
+    assert!(err.to_string().contains("worker thread panicked"));
+}
+
+#[test]
+fn results_returns_worker_panics() {
+    let res: Vec<_> = (0..10)
+        .parallel_map_custom(
+            |o| o.threads(1).debug_items(),
+            |x| {
+                if x == 3 {
+                    panic!("foo");
+                }
+                x
+            },
+        )
+        .results()
+        .collect();
+
+    assert_eq!(res.len(), 4);
+    assert!(res[..3].iter().all(|r| r.is_ok()));
+    let e = res[3].as_ref().unwrap_err();
+    assert_eq!(e.item_index(), Some(3));
+    assert_eq!(e.item(), Some("3"));
+    assert_eq!(e.panic_message(), Some("foo"));
+}
+
+#[test]
+#[should_panic(expected = "foo")]
+fn panic_poll_interval_detects_panics() {
+    (0..10)
+        .parallel_map_custom(
+            |o| o.panic_poll_interval(std::time::Duration::from_millis(5)),
+            |x| {
+                if x == 5 {
+                    panic!("foo");
+                }
+                x
+            },
+        )
+        .for_each(drop);
+}
+
+#[test]
+fn worker_profilers() {
+    struct Counting(Arc<AtomicUsize>);
+
+    impl crate::Profiler for Counting {
+        fn start(&mut self) {
+            self.0.fetch_add(1, SeqCst);
+        }
+        fn end(&mut self) {}
+    }
+
+    let ingress = Arc::new(AtomicUsize::new(0));
+    let egress = Arc::new(AtomicUsize::new(0));
+
+    let res: Vec<_> = (0..10)
+        .parallel_map_custom(
+            |o| {
+                let ingress = ingress.clone();
+                let egress = egress.clone();
+                o.threads(2)
+                    .profile_worker_ingress(move |_| Counting(ingress.clone()))
+                    .profile_worker_egress(move |_| Counting(egress.clone()))
+            },
+            |x| x,
+        )
+        .collect();
+
+    assert_eq!(res, (0..10).collect::<Vec<_>>());
+    // one more receive per worker, finding the channel closed
+    assert_eq!(ingress.load(SeqCst), 12);
+    assert_eq!(egress.load(SeqCst), 10);
+}
+
+#[test]
+fn sample_occupancy() {
+    let recorder = crate::profile::OccupancyRecorder::new();
+
+    let res: Vec<_> = (0..20)
+        .parallel_map_custom(
+            |o| {
+                o.threads(2)
+                    .buffer_size(4)
+                    .sample_occupancy(std::time::Duration::from_millis(1), recorder.clone())
+            },
+            |x| {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+                x
+            },
+        )
+        .collect();
+
+    assert_eq!(res, (0..20).collect::<Vec<_>>());
+    let samples = recorder.samples();
+    assert!(!samples.is_empty());
+    assert!(samples.windows(2).all(|w| w[0].elapsed() <= w[1].elapsed()));
+}
+
+#[test]
+fn memory_cap() {
+    let meter = crate::profile::MemoryMeter::new();
+
+    let res: Vec<_> = (0..20)
+        .map(|i| vec![i; 10])
+        .parallel_map_custom(
+            |o| {
+                o.threads(4)
+                    .buffer_size(16)
+                    .size_of(|v: &Vec<u8>| v.len())
+                    .memory_cap(30)
+                    .memory_meter(meter.clone())
+            },
+            |v| {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                v[0]
+            },
+        )
+        .collect();
+
+    assert_eq!(res, (0..20).collect::<Vec<_>>());
+    assert_eq!(meter.current(), 0);
+    assert_eq!(meter.peak(), 30);
+
+    // a single item over the cap still goes through
+    let res: Vec<_> = (0..3)
+        .map(|i| vec![i; 100])
+        .parallel_map_custom(
+            |o| o.size_of(|v: &Vec<u8>| v.len()).memory_cap(30),
+            |v| v[0],
+        )
+        .collect();
+    assert_eq!(res, vec![0, 1, 2]);
+}
+
+#[test]
+fn with_recycler() {
+    use crate::{ParallelMapBuilder, Recycler};
+
+    let recycler = Recycler::bounded(4, Vec::<u64>::new);
+
+    let sums: Vec<u64> = ParallelMapBuilder::new(0..100u64)
+        .threads(2)
+        .with_recycler(recycler.clone(), |i, mut buf: Vec<u64>| {
+            buf.clear();
+            buf.extend(0..i);
+            buf
+        })
+        .map(|buf| {
+            let sum = buf.iter().sum();
+            recycler.recycle(buf);
+            sum
+        })
+        .collect();
+
+    assert_eq!(
+        sums,
+        (0..100)
+            .map(|i: u64| i * i.saturating_sub(1) / 2)
+            .collect::<Vec<_>>()
+    );
+    assert!(0 < recycler.pooled() && recycler.pooled() <= 4);
+}
+
+#[test]
+fn with_arena() {
+    use crate::{Arena, ParallelMapBuilder};
+
+    struct Counting {
+        used: usize,
+        resets: Arc<AtomicUsize>,
+    }
+
+    impl Arena for Counting {
+        fn reset(&mut self) {
+            self.used = 0;
+            self.resets.fetch_add(1, SeqCst);
+        }
+    }
+
+    let made = Arc::new(AtomicUsize::new(0));
+    let resets = Arc::new(AtomicUsize::new(0));
+
+    let res: Vec<usize> = ParallelMapBuilder::new(0..100usize)
+        .threads(2)
+        .with_arena(
+            5,
+            {
+                let made = made.clone();
+                let resets = resets.clone();
+                move |_worker_i| {
+                    made.fetch_add(1, SeqCst);
+                    Counting {
+                        used: 0,
+                        resets: resets.clone(),
+                    }
+                }
+            },
+            |arena, i| {
+                arena.used += 1;
+                assert!(arena.used <= 5);
+                i
+            },
+        )
+        .map(|item| *item)
+        .collect();
+
+    assert_eq!(res, (0..100).collect::<Vec<_>>());
+    // every worker went through at least 10 epochs
+    assert!(made.load(SeqCst) < 20);
+    assert!(0 < resets.load(SeqCst));
+}
+
+#[test]
+fn custom_spawner() {
+    let spawned = Arc::new(AtomicUsize::new(0));
+
+    let res: Vec<_> = (0..20)
+        .parallel_map_custom(
+            |o| {
+                let spawned = spawned.clone();
+                o.threads(3).spawner(move |job: Box<dyn FnOnce() + Send>| {
+                    spawned.fetch_add(1, SeqCst);
+                    std::thread::spawn(job);
+                })
+            },
+            |x| x * 2,
+        )
+        .collect();
+
+    assert_eq!(res, (0..20).map(|x| x * 2).collect::<Vec<_>>());
+    assert_eq!(spawned.load(SeqCst), 3);
+}
+
+#[test]
+fn traced() {
+    let res: Vec<_> = (10..20)
+        .traced()
+        .stamp("source")
+        .parallel_map(|t| t.map(|x| x * 2))
+        .stamp("doubled")
+        .collect();
+
+    for (i, t) in res.iter().enumerate() {
+        assert_eq!(t.index(), i);
+        assert_eq!(**t, (10 + i) * 2);
+        let names: Vec<_> = t.stamps().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["source", "doubled"]);
+        assert!(t.entered() <= t.stamps()[0].1);
+        assert!(t.stamps()[0].1 <= t.stamps()[1].1);
+    }
+}
+
+#[test]
+fn record_latency() {
+    let recorder = crate::profile::LatencyRecorder::new();
+    assert_eq!(recorder.stats(), None);
+
+    let res: Vec<_> = (0..50)
+        .traced()
+        .parallel_map(|t| {
+            t.map(|x| {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                x
+            })
+        })
+        .record_latency(recorder.clone())
+        .map(|t| t.into_inner())
+        .collect();
+
+    assert_eq!(res, (0..50).collect::<Vec<_>>());
+    let stats = recorder.stats().expect("recorded");
+    assert_eq!(stats.count(), 50);
+    assert!(std::time::Duration::from_millis(1) <= stats.min());
+    assert!(stats.min() <= stats.p50() && stats.p50() <= stats.p90());
+    assert!(stats.p90() <= stats.p99() && stats.p99() <= stats.max());
+}
+
+#[test]
+fn report_stragglers() {
+    let reported = Arc::new(std::sync::Mutex::new(None));
+
+    let res: Vec<_> = (0..30)
+        .parallel_map_custom(
+            |o| {
+                let reported = reported.clone();
+                o.threads(4)
+                    .debug_items()
+                    .report_stragglers(3, move |stragglers| {
+                        *reported.lock().unwrap() = Some(stragglers.to_vec());
+                    })
+            },
+            |x| {
+                if x % 10 == 7 {
+                    std::thread::sleep(std::time::Duration::from_millis(20 + x as u64));
+                }
+                x
+            },
+        )
+        .collect();
+
+    assert_eq!(res, (0..30).collect::<Vec<_>>());
+    let stragglers = reported.lock().unwrap().take().expect("reported");
+    let indices: Vec<_> = stragglers.iter().map(|s| s.index()).collect();
+    assert_eq!(indices, [27, 17, 7]);
+    assert_eq!(stragglers[0].item(), Some("27"));
+    assert!(std::time::Duration::from_millis(47) <= stragglers[0].duration());
+}
+
+#[test]
+fn parallel_route() {
+    let routes = (0..100).parallel_route(3, |x| x % 3);
+    assert_eq!(routes.len(), 3);
+
+    let collected: Vec<Vec<_>> = super::scope(|scope| {
+        let handles: Vec<_> = routes
+            .into_iter()
+            .map(|route| scope.spawn(move |_| route.collect::<Vec<_>>()))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    })
+    .unwrap();
+
+    for (i, items) in collected.iter().enumerate() {
+        assert_eq!(items, &(0..100).filter(|x| x % 3 == i).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn parallel_route_dropped_route() {
+    let mut routes = (0..100).parallel_route(2, |x| x % 2);
+    drop(routes.pop());
+    let evens: Vec<_> = routes.pop().unwrap().collect();
+    assert_eq!(evens, (0..100).step_by(2).collect::<Vec<_>>());
+}
+
+#[test]
+#[should_panic(expected = "route thread panicked")]
+fn parallel_route_out_of_range() {
+    let mut routes = (0..10).parallel_route(2, |_| 2);
+    routes[0].next();
+}
+
+#[test]
+fn drop_stats_discarded() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx = std::sync::Mutex::new(tx);
+    let mut map = (0..100).parallel_map_custom(
+        |o| {
+            o.threads(2)
+                .buffer_size(10)
+                .report_drop_stats(move |stats| tx.lock().unwrap().send(stats.clone()).unwrap())
+        },
+        |x| x,
+    );
+    assert_eq!(map.next(), Some(0));
+    assert_eq!(map.nth(1), Some(2));
+    drop(map);
+    let stats = rx.recv().unwrap();
+    assert!(0 < stats.discarded + stats.skipped && stats.discarded + stats.skipped <= 10);
+    assert!(rx.try_recv().is_err());
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx = std::sync::Mutex::new(tx);
+    let sum: i32 = (0..100)
+        .parallel_map_custom(
+            |o| o.report_drop_stats(move |stats| tx.lock().unwrap().send(stats.clone()).unwrap()),
+            |x| x,
+        )
+        .sum();
+    assert_eq!(sum, 4950);
+    assert_eq!(rx.recv().unwrap().discarded, 0);
+}
+
+#[test]
+fn drop_policy_finish_delivers_orphans() {
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    let processed = Arc::new(AtomicUsize::new(0));
+    let orphans = Arc::new(std::sync::Mutex::new(vec![]));
+    let mut map = (0..100)
+        .parallel_map_custom(
+            |o| {
+                o.threads(2)
+                    .buffer_size(10)
+                    .drop_policy(crate::DropPolicy::Finish)
+            },
+            {
+                let processed = processed.clone();
+                move |x| {
+                    processed.fetch_add(1, SeqCst);
+                    x
+                }
+            },
+        )
+        .on_orphaned({
+            let orphans = orphans.clone();
+            move |x| orphans.lock().unwrap().push(x)
+        });
+    let pulled: Vec<_> = map.by_ref().take(5).collect();
+    drop(map);
+
+    let mut orphans = orphans.lock().unwrap().clone();
+    orphans.sort_unstable();
+    assert_eq!(pulled, vec![0, 1, 2, 3, 4]);
+    assert!(!orphans.is_empty());
+    assert_eq!(orphans, (5..5 + orphans.len() as i32).collect::<Vec<_>>());
+    assert_eq!(processed.load(SeqCst), 5 + orphans.len());
+}
+
+#[test]
+fn drop_policy_abort_skips_queued() {
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    let processed = Arc::new(AtomicUsize::new(0));
+    let mut map = (0..100).parallel_map_custom(|o| o.threads(1).buffer_size(10), {
+        let processed = processed.clone();
+        move |x| {
+            processed.fetch_add(1, SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            x
+        }
+    });
+    assert_eq!(map.next(), Some(0));
+    drop(map);
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert!(processed.load(SeqCst) < 5);
+}
+
+#[test]
+fn deadline_monitor_counts_per_stage() {
+    use crate::{profile::DeadlineMonitor, Profiler};
+    use std::time::Duration;
+
+    let monitor = DeadlineMonitor::new(Duration::from_millis(5));
+    let mut fast = monitor.profiler("fast");
+    let mut slow = monitor.profiler("slow");
+    for i in 0..4 {
+        fast.start();
+        fast.end();
+        slow.start();
+        if i % 2 == 0 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        slow.end();
+    }
+    // shares the counts of the stage with the same name
+    let mut fast = monitor.profiler("fast");
+    fast.start();
+    fast.end();
+
+    let stats = monitor.stats();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(
+        (stats[0].name(), stats[0].items(), stats[0].exceeded()),
+        ("fast", 5, 0)
+    );
+    assert_eq!(
+        (stats[1].name(), stats[1].items(), stats[1].exceeded()),
+        ("slow", 4, 2)
+    );
+    assert!(Duration::from_millis(10) <= stats[1].max());
+}
+
+#[test]
+fn profiler_sampled_and_rate_limited() {
+    use crate::{
+        profile::{DeadlineMonitor, RateLimited, Sampled},
+        Profiler,
+    };
+    use std::time::Duration;
+
+    let monitor = DeadlineMonitor::new(Duration::from_secs(1));
+    let mut sampled = Sampled::new(monitor.profiler("sampled"), 3);
+    let mut rate_limited =
+        RateLimited::new(monitor.profiler("rate_limited"), Duration::from_secs(60));
+    for _ in 0..10 {
+        sampled.start();
+        sampled.end();
+        rate_limited.start();
+        rate_limited.end();
+    }
+
+    let items: Vec<_> = monitor.stats().iter().map(|stats| stats.items()).collect();
+    assert_eq!(items, vec![4, 1]);
+}
+
+#[test]
+fn total_time_utilization() {
+    use crate::{Profiler, TotalTimeProfiler};
+    use std::time::Duration;
+
+    let utilization = Arc::new(std::sync::Mutex::new(0.0));
+    let mut profiler = TotalTimeProfiler::new({
+        let utilization = utilization.clone();
+        move |stats: &mut crate::TotalTimeStats| {
+            *utilization.lock().unwrap() = stats.utilization();
+            // adjusting the total doesn't affect the utilization
+            *stats.total_mut() = Duration::ZERO;
+        }
+    });
+    std::thread::sleep(Duration::from_millis(20));
+    profiler.start();
+    std::thread::sleep(Duration::from_millis(20));
+    profiler.end();
+    let first = *utilization.lock().unwrap();
+    assert!(0.2 < first && first < 0.8, "{}", first);
+
+    profiler.start();
+    std::thread::sleep(Duration::from_millis(40));
+    profiler.end();
+    assert!(first < *utilization.lock().unwrap());
+}
+
+#[test]
+fn total_time_every_reports_unblocked_stages() {
+    use std::sync::atomic::AtomicUsize;
+
+    let reports = Arc::new(AtomicUsize::new(0));
+    let profiler = crate::TotalTimeProfiler::every_millis(5, {
+        let reports = reports.clone();
+        move |_stats| {
+            reports.fetch_add(1, SeqCst);
+        }
+    });
+    // the consumer is slow, so the stage is never blocked
+    (0..20)
+        .profile_ingress(profiler)
+        .for_each(|_| std::thread::sleep(std::time::Duration::from_millis(1)));
+    assert!(1 <= reports.load(SeqCst));
+}
+
+#[test]
+fn profiler_builder_reports_to_target() {
+    let reports = Arc::new(std::sync::Mutex::new(vec![]));
+    let profiler = crate::profile::ProfilerBuilder::new("slow source")
+        .period_millis(1)
+        .to({
+            let reports = reports.clone();
+            move |name, stats| {
+                reports
+                    .lock()
+                    .unwrap()
+                    .push((name.to_owned(), stats.total()))
+            }
+        })
+        .build();
+    (0..5)
+        .inspect(|_| std::thread::sleep(std::time::Duration::from_millis(2)))
+        .profile_ingress(profiler)
+        .for_each(drop);
+
+    let reports = reports.lock().unwrap();
+    assert!(!reports.is_empty());
+    assert!(reports
+        .iter()
+        .all(|(name, _)| name.starts_with("slow source#")));
+    assert!(std::time::Duration::from_millis(2) <= reports[0].1);
+}
+
+#[test]
+fn readahead_profiled_custom_sets_options() {
+    let v: Vec<_> = (0..100)
+        .readahead_profiled_custom((), (), |o| o.buffer_size(1))
+        .collect();
+    assert_eq!(v, (0..100).collect::<Vec<_>>());
+
+    let v: Vec<_> = crate::scope(|scope| {
+        (0..100)
+            .readahead_scoped_profiled_custom(scope, (), (), |o| o.buffer_size(3))
+            .collect()
+    })
+    .unwrap();
+    assert_eq!(v, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn parallel_map_profiled_variants() {
+    use crate::profile::DeadlineMonitor;
+
+    let monitor = DeadlineMonitor::new(std::time::Duration::from_secs(1));
+    let v: Vec<_> = (0..10)
+        .parallel_map_profiled(monitor.profiler("tx"), monitor.profiler("rx"), |x| x * 2)
+        .collect();
+    assert_eq!(v, (0..10).map(|x| x * 2).collect::<Vec<_>>());
+    let items: Vec<_> = monitor.stats().iter().map(|stats| stats.items()).collect();
+    // the final `None` is measured by the ingress profiler
+    assert_eq!(items, vec![10, 11]);
+
+    let v: Vec<_> = (0..10)
+        .parallel_map_profiled_custom((), (), |o| o.threads(2), |x| x + 1)
+        .collect();
+    assert_eq!(v, (1..11).collect::<Vec<_>>());
+
+    let offset = 3;
+    let v: Vec<_> = crate::scope(|scope| {
+        let a: Vec<_> = (0..10)
+            .parallel_map_scoped_profiled(scope, (), (), |x| x + offset)
+            .collect();
+        let b: Vec<_> = (0..10)
+            .parallel_map_scoped_profiled_custom(scope, (), (), |o| o.threads(1), |x| x + offset)
+            .collect();
+        assert_eq!(a, b);
+        a
+    })
+    .unwrap();
+    assert_eq!(v, (3..13).collect::<Vec<_>>());
+}
+
+#[test]
+fn stage_ids_in_thread_names_and_errors() {
+    let other = (0..4).parallel_map_custom(|o| o.name("double"), |x| x * 2);
+    let mut map = (0..4).parallel_map_custom(
+        |o| o.name("double").threads(1),
+        |_| std::thread::current().name().map(str::to_owned),
+    );
+    assert_ne!(map.id(), other.id());
+
+    let expected = format!("double#{}/0", map.id());
+    assert!(map.all(|name| name.as_deref() == Some(&*expected)));
+
+    let mut results = (0..4)
+        .parallel_map(|x| {
+            if x == 2 {
+                panic!("foo");
+            }
+            x
+        })
+        .results();
+    let error = results.find_map(Result::err).unwrap();
+    assert!(error.stage_id().is_some());
+    assert!(error
+        .to_string()
+        .starts_with(&format!("parallel_map#{}", error.stage_id().unwrap())));
+}
+
+#[test]
+fn describe_pipeline() {
+    use crate::Describe;
+
+    let pipeline = (0..10)
+        .map(|x| x + 1)
+        .describe_as("numbers")
+        .parallel_map_custom(|o| o.threads(2).buffer_size(4).name("double"), |x| x * 2)
+        .parallel_filter_custom(|o| o.threads(3), |x| x % 3 == 0);
+
+    assert_eq!(
+        pipeline.describe().to_string(),
+        "source `numbers` -> parallel_map `double` (threads: 2, buffer: 4) \
+         -> parallel_filter (threads: 3, buffer: 6)"
+    );
+    assert_eq!(pipeline.describe().nodes().len(), 3);
+    assert_eq!(pipeline.count(), 3);
+}
+
+#[test]
+fn pipeline_stages() {
+    use crate::{
+        pipeline::{par_filter, par_map, readahead, Pipeline, StageIter},
+        Describe,
+    };
+
+    let profiled = Arc::new(std::sync::Mutex::new(vec![]));
+
+    let pipeline = Pipeline::from((0..100).describe_as("numbers"))
+        .profile_stages({
+            let profiled = profiled.clone();
+            move |name| {
+                profiled.lock().unwrap().push(name.to_string());
+            }
+        })
+        .stage("square", par_map(|x: i64| x * x).threads(2))
+        .stage("even", par_filter(|x: &i64| x % 2 == 0).threads(3))
+        .stage("buffer", readahead().buffer_size(4));
+
+    assert_eq!(
+        *profiled.lock().unwrap(),
+        vec!["square".to_string(), "even".into(), "buffer".into()]
+    );
+    assert_eq!(
+        pipeline.describe().to_string(),
+        "readahead (threads: 1, buffer: 4)"
+    );
+
+    let pipeline = Pipeline::from((0..100).describe_as("numbers"))
+        .stage("square", par_map(|x: i64| x * x).threads(2))
+        .stage("even", par_filter(|x: &i64| x % 2 == 0).threads(3));
+    assert_eq!(
+        pipeline.describe().to_string(),
+        "source `numbers` -> parallel_map `square` (threads: 2, buffer: 4) \
+         -> parallel_filter `even` (threads: 3, buffer: 6)"
+    );
+
+    let mut res = vec![];
+    let err = pipeline
+        .stage("offset", |iter: StageIter<_>| iter.map(|x| x + 1))
+        .sink(|x| {
+            if 1000 < x {
+                return Err(x);
+            }
+            res.push(x);
+            Ok(())
+        })
+        .run();
+
+    assert_eq!(err, Err(1025));
+    assert_eq!(
+        res,
+        (0..32i64).step_by(2).map(|x| x * x + 1).collect::<Vec<_>>()
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[quickcheck]
+fn into_rayon_ordered_vs_map(v: Vec<u32>) -> bool {
+    v.iter()
+        .map(|x| x / 2)
+        .eq(v.clone().into_iter().into_rayon_ordered(|x| x / 2))
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+#[should_panic(expected = "foo")]
+fn into_rayon_ordered_panic() {
+    (0..10)
+        .into_rayon_ordered(|x| {
+            if x == 5 {
+                panic!("foo");
+            }
+            x
+        })
+        .for_each(drop);
+}
+
+/// A future completing after `duration`, woken up by a helper thread
+#[cfg(feature = "async")]
+struct Sleep {
+    duration: std::time::Duration,
+    started: bool,
+    done: Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(feature = "async")]
+impl Sleep {
+    fn new(duration: std::time::Duration) -> Self {
+        Self {
+            duration,
+            started: false,
+            done: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl std::future::Future for Sleep {
+    type Output = ();
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<()> {
+        if self.done.load(SeqCst) {
+            return std::task::Poll::Ready(());
+        }
+        if !self.started {
+            self.started = true;
+            let (duration, done, waker) = (self.duration, self.done.clone(), cx.waker().clone());
+            std::thread::spawn(move || {
+                std::thread::sleep(duration);
+                done.store(true, SeqCst);
+                waker.wake();
+            });
+        }
+        std::task::Poll::Pending
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn parallel_map_async() {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+    let res: Vec<_> = (0..100)
+        .parallel_map_async_custom(
+            |o| o.threads(1),
+            |x| {
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                async move {
+                    let now = in_flight.fetch_add(1, SeqCst) + 1;
+                    max_in_flight.fetch_max(now, SeqCst);
+                    Sleep::new(std::time::Duration::from_millis((100 - x) % 7)).await;
+                    in_flight.fetch_sub(1, SeqCst);
+                    x * 2
+                }
+            },
+        )
+        .collect();
+
+    assert_eq!(res, (0..100).map(|x| x * 2).collect::<Vec<_>>());
+    // many futures were in flight on a single thread
+    assert!(1 < max_in_flight.load(SeqCst));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn parallel_map_async_concurrency() {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+    let res: Vec<_> = (0..50)
+        .parallel_map_async_custom(
+            |o| o.threads(4).concurrency(3),
+            |x| {
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                async move {
+                    let now = in_flight.fetch_add(1, SeqCst) + 1;
+                    max_in_flight.fetch_max(now, SeqCst);
+                    Sleep::new(std::time::Duration::from_millis(1)).await;
+                    in_flight.fetch_sub(1, SeqCst);
+                    x
+                }
+            },
+        )
+        .collect();
+
+    assert_eq!(res, (0..50).collect::<Vec<_>>());
+    assert!(max_in_flight.load(SeqCst) <= 3);
+}
+
+#[cfg(feature = "async")]
+#[test]
+#[should_panic(expected = "foo")]
+fn parallel_map_async_panic() {
+    (0..10)
+        .parallel_map_async(|x| async move {
+            if x == 5 {
+                panic!("foo");
+            }
+            x
+        })
+        .for_each(drop);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn parallel_map_async_results() {
+    let res: Vec<_> = (0..10)
+        .parallel_map_async(|x| async move {
+            if x == 5 {
+                panic!("foo");
+            }
+            x
+        })
+        .results()
+        .collect();
+
+    assert_eq!(res.len(), 6);
+    assert!(res[..5].iter().all(Result::is_ok));
+    let err = res[5].as_ref().unwrap_err();
+    assert_eq!(err.item_index(), Some(5));
+    assert_eq!(err.panic_message(), Some("foo"));
+    assert!(err.to_string().starts_with("parallel_map_async"), "{}", err);
+}
+
+#[test]
+fn from_fn_readahead() {
+    let mut n = 0;
+    let res: Vec<_> = crate::from_fn_readahead(move || {
+        n += 1;
+        (n <= 5).then_some(n)
+    })
+    .collect();
+    assert_eq!(res, vec![1, 2, 3, 4, 5]);
+
+    let mut input = vec![1, 2, 3];
+    super::scope(|scope| {
+        let res: Vec<_> = crate::from_fn_readahead_scoped(scope, || input.pop()).collect();
+        assert_eq!(res, vec![3, 2, 1]);
+    })
+    .unwrap();
+}
+
+#[test]
+fn readahead_peek() {
+    let mut iter = (0..5).readahead();
+
+    assert_eq!(iter.peek(), Some(&0));
+    assert_eq!(iter.peek_n(3), &[0, 1, 2]);
+    assert_eq!(iter.next(), Some(0));
+    assert_eq!(iter.peek(), Some(&1));
+    assert_eq!(iter.peek_n(10), &[1, 2, 3, 4]);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn readahead_lookbehind() {
+    let mut iter = (0..5).readahead_custom(|o| o.lookbehind(2));
+
+    assert_eq!(iter.recent().count(), 0);
+    assert_eq!(iter.next(), Some(0));
+    assert!(iter.recent().eq(&[0]));
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(iter.next(), Some(2));
+    assert!(iter.recent().eq(&[1, 2]));
+    assert_eq!(iter.peek(), Some(&3));
+    assert!(iter.recent().eq(&[1, 2]));
+    assert_eq!(iter.by_ref().count(), 2);
+    assert!(iter.recent().eq(&[3, 4]));
+}
+
+#[test]
+fn readahead_buffer_bytes() {
+    let pulled = Arc::new(AtomicUsize::new(0));
+
+    let mut iter = (0..10)
+        .map({
+            let pulled = pulled.clone();
+            move |i| {
+                pulled.fetch_add(1, SeqCst);
+                vec![0u8; 10 * i]
+            }
+        })
+        .readahead_custom(|o| o.buffer_bytes(30, |v| v.len()));
+
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    // items of 0, 10 and 20 bytes fit in the budget, the producer
+    // is blocked holding the next one
+    assert_eq!(pulled.load(SeqCst), 4);
+
+    assert_eq!(iter.next().map(|v| v.len()), Some(0));
+    assert_eq!(iter.next().map(|v| v.len()), Some(10));
+    assert_eq!(iter.map(|v| v.len()).sum::<usize>(), 440);
+
+    // a blocked producer doesn't prevent the scope from ending
+    let pulled = AtomicUsize::new(0);
+    super::scope(|scope| {
+        let mut iter = (0..10)
+            .map(|i| {
+                pulled.fetch_add(1, SeqCst);
+                vec![0u8; 10 * i]
+            })
+            .readahead_scoped_custom(scope, |o| o.buffer_bytes(1, |v| v.len()));
+        assert_eq!(iter.next().map(|v| v.len()), Some(0));
+    })
+    .unwrap();
+    assert!(pulled.load(SeqCst) < 10);
+}
+
+#[test]
+fn readahead_map_runs_in_reader_thread() {
+    let consumer = std::thread::current().id();
+    let res: Vec<_> = (0..5)
+        .readahead_map(move |x| (x * 2, std::thread::current().id() != consumer))
+        .collect();
+    assert_eq!(res, (0..5).map(|x| (x * 2, true)).collect::<Vec<_>>());
+
+    let offset = 1;
+    super::scope(|scope| {
+        let res: Vec<_> = (0..5).readahead_map_scoped(scope, |x| x + offset).collect();
+        assert_eq!(res, vec![1, 2, 3, 4, 5]);
+    })
+    .unwrap();
+}
+
+#[test]
+fn readahead_results_stops_on_error() {
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let res: Vec<Result<i32, i32>> = (0..100)
+        .map({
+            let pulled = pulled.clone();
+            move |x| {
+                pulled.fetch_add(1, SeqCst);
+                if x == 3 {
+                    Err(x)
+                } else {
+                    Ok(x)
+                }
+            }
+        })
+        .readahead_results_custom(|o| o.buffer_size(10))
+        .collect();
+
+    assert_eq!(res, vec![Ok(0), Ok(1), Ok(2), Err(3)]);
+    assert_eq!(pulled.load(SeqCst), 4);
+
+    super::scope(|scope| {
+        let res: Vec<Result<i32, ()>> = (0..3).map(Ok).readahead_results_scoped(scope).collect();
+        assert_eq!(res, vec![Ok(0), Ok(1), Ok(2)]);
+    })
+    .unwrap();
+}
+
+#[test]
+fn parallel_filter_size_hint_counts_in_flight() {
+    // the buffer is big enough to pull all the items upfront
+    let mut iter = (0..10).parallel_filter_custom(|o| o.threads(2).buffer_size(16), |_| true);
+
+    assert_eq!(iter.next(), Some(0));
+    let (lower, upper) = iter.size_hint();
+    assert!(lower <= 9);
+    assert!(upper.is_none_or(|upper| 9 <= upper));
+    assert_eq!(iter.count(), 9);
+}
+
+#[test]
+fn parallel_filter_size_hint() {
+    let mut iter = (0..10).parallel_filter(|x| x % 2 == 0);
+
+    let (lower, upper) = iter.size_hint();
+    assert_eq!(lower, 0);
+    assert!(upper.is_none_or(|upper| 5 <= upper));
+    assert!(!iter.is_size_hint_exact());
+
+    assert_eq!(iter.by_ref().count(), 5);
+    assert_eq!(iter.size_hint(), (0, Some(0)));
+    assert!(iter.is_size_hint_exact());
+}
+
+fn assert_send<T: Send>(_: &T) {}
+
+#[test]
+fn pipelines_are_send() {
+    let map = (0..10).parallel_map(|x| x * 2);
+    let filter = (0..10).parallel_filter(|x| x % 2 == 0);
+    let readahead = (0..10).readahead_custom(|o| o.lookbehind(1).buffer_bytes(100, |_| 1));
+    assert_send(&map);
+    assert_send(&filter);
+    assert_send(&readahead);
+
+    // a constructed pipeline can be driven by another thread
+    let pipeline = readahead
+        .parallel_map(|x| x + 1)
+        .parallel_filter(|x| x % 2 == 0);
+    let sum = std::thread::spawn(move || pipeline.sum::<i32>())
+        .join()
+        .unwrap();
+    assert_eq!(sum, 30);
+    assert_eq!(map.count() + filter.count(), 15);
+}
+
+#[test]
+fn reusable_configs() {
+    use crate::{ParallelMapConfig, ReadaheadConfig};
+
+    let started = Arc::new(AtomicUsize::new(0));
+    let config = ParallelMapConfig::new()
+        .threads(2)
+        .buffer_size(4)
+        .on_worker_start({
+            let started = started.clone();
+            move |_| {
+                started.fetch_add(1, SeqCst);
+            }
+        });
+    let readahead_config = ReadaheadConfig::new().buffer_size(2);
+
+    for n in 0..3 {
+        let res: Vec<_> = (0..n * 10)
+            .readahead_cfg(&readahead_config)
+            .parallel_map_cfg(&config, |x| x * 2)
+            .collect();
+        assert_eq!(res, (0..n * 10).map(|x| x * 2).collect::<Vec<_>>());
+    }
+    assert_eq!(started.load(SeqCst), 6);
+
+    let v: Vec<_> = (0..10).collect();
+    super::scope(|scope| {
+        let res: Vec<_> = v
+            .iter()
+            .readahead_scoped_cfg(scope, &readahead_config)
+            .parallel_map_scoped_cfg(scope, &config, |x| x * 2)
+            .collect();
+        assert_eq!(res, v.iter().map(|x| x * 2).collect::<Vec<_>>());
+    })
+    .unwrap();
+
+    // options set later on the builder override the config
+    let res: Vec<_> = (0..10)
+        .parallel_filter_custom(|o| o.config(&config).threads(1), |x| x % 2 == 0)
+        .collect();
+    assert_eq!(res, vec![0, 2, 4, 6, 8]);
+}
+
+#[test]
+fn pipeline_boxed_stages() {
+    use crate::pipeline::{boxed, par_filter, par_map, BoxedParallelMap, BoxedStage, Pipeline};
+
+    for (filter, expected) in [(false, 45), (true, 20)] {
+        let mut stages: Vec<(&str, BoxedStage<u64, u64>)> = vec![];
+        if filter {
+            stages.push(("even", Box::new(par_filter(|x: &u64| *x & 1 == 0))));
+        }
+        stages.push(("id", Box::new(par_map(|x: u64| x))));
+
+        let mut pipeline = Pipeline::from(0..10u64).boxed();
+        for (name, stage) in stages {
+            pipeline = pipeline.boxed_stage(name, stage);
+        }
+        assert_eq!(pipeline.sum::<u64>(), expected);
+    }
+
+    let map: BoxedParallelMap<u64, u64> = boxed((0..10).map(|x| x + 1)).parallel_map(|x| x * 2);
+    assert_eq!(map.sum::<u64>(), 110);
+}
+
+#[test]
+fn pipeline_runner_report() {
+    use crate::pipeline::{par_map, PipelineRunner, StageIter};
+
+    let report = PipelineRunner::new(0..100u64).run(
+        |_scope, pipeline| {
+            pipeline
+                .stage("double", par_map(|x: u64| x * 2))
+                .stage("id", |iter: StageIter<_>| iter)
+        },
+        |x| if x < 100 { Ok(()) } else { Err(x) },
+    );
+    assert_eq!(report.items(), 50);
+    assert_eq!(report.error(), Some(&100));
+    assert!(report.panic_message().is_none());
+    let names: Vec<_> = report.stages().iter().map(|s| s.name()).collect();
+    assert_eq!(names, vec!["double", "id"]);
+
+    let report = PipelineRunner::new(0..100u64).run(
+        |scope, pipeline| {
+            pipeline.stage("panic", |iter: std::ops::Range<u64>| {
+                iter.parallel_map_scoped(scope, |x| {
+                    if x == 10 {
+                        panic!("stage failed");
+                    }
+                    x
+                })
+            })
+        },
+        |_| Ok::<_, ()>(()),
+    );
+    assert!(!report.is_success());
+    assert!(report.items() <= 10);
+    assert!(report.panic_message().is_some());
+}
+
+#[test]
+fn tune_estimate() {
+    let estimate = crate::tune::estimate(|x: u64| x + 1, 0..1000);
+    assert!(1 < estimate.chunk_size());
+    assert_eq!(estimate.buffer_size(), estimate.threads() * 2);
+
+    let estimate = crate::tune::estimate(
+        |_| std::thread::sleep(std::time::Duration::from_millis(1)),
+        0..5,
+    );
+    assert_eq!(estimate.chunk_size(), 1);
+    assert!(estimate.per_item() >= std::time::Duration::from_millis(1));
+    assert_eq!(estimate.threads() > 1, num_cpus::get_physical() > 1);
+}
+
+#[test]
+fn write_rotating() {
+    let dir = std::env::temp_dir().join(format!("pariter-rotating-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pattern = dir.join("out-{}.txt").to_str().unwrap().to_owned();
+
+    let paths = (0..100)
+        .parallel_map(|i| format!("{:03}\n", i))
+        .write_rotating(pattern.clone(), 40)
+        .unwrap();
+    // 10 items of 4 bytes per file
+    assert_eq!(paths.len(), 10);
+    let contents: String = paths
+        .iter()
+        .map(|path| std::fs::read_to_string(path).unwrap())
+        .collect();
+    let expected: String = (0..100).map(|i| format!("{:03}\n", i)).collect();
+    assert_eq!(contents, expected);
+
+    let paths = (0..10)
+        .map(|i| vec![i as u8])
+        .write_rotating_custom(pattern, |o| o.max_items(3))
+        .unwrap();
+    assert_eq!(paths.len(), 4);
+    assert_eq!(std::fs::read(&paths[3]).unwrap(), vec![9]);
+
+    assert!((0..1)
+        .map(|_| "x")
+        .write_rotating(dir.join("out").to_str().unwrap(), 1)
+        .is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compress_round_trip() {
+    use crate::compress::{read_compressed_chunks, write_compressed_ordered};
+
+    // run-length encoding as a codec
+    let compress = |chunk: &[u8]| {
+        let mut out = vec![];
+        for &b in chunk {
+            match out.len() {
+                n if 2 <= n && out[n - 1] == b && out[n - 2] < 255 => out[n - 2] += 1,
+                _ => out.extend([1, b]),
+            }
+        }
+        Ok(out)
+    };
+    let decompress = |frame: Vec<u8>| {
+        Ok(frame
+            .chunks(2)
+            .flat_map(|run| std::iter::repeat_n(run[1], usize::from(run[0])))
+            .collect())
+    };
+
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i / 7) as u8).collect();
+    let mut compressed = vec![];
+    let read = write_compressed_ordered(&data[..], &mut compressed, 333, compress).unwrap();
+    assert_eq!(read, data.len() as u64);
+    assert!(compressed.len() < data.len());
+
+    let chunks: std::io::Result<Vec<Vec<u8>>> =
+        read_compressed_chunks(&compressed[..], decompress).collect();
+    assert_eq!(chunks.unwrap().concat(), data);
+
+    // truncated input
+    let chunks: Vec<_> =
+        read_compressed_chunks(&compressed[..compressed.len() - 1], decompress).collect();
+    assert!(chunks.last().unwrap().is_err());
+}
+
+#[test]
+fn pipeline_nested_stages() {
+    use crate::pipeline::{current_stage_path, par_map, Pipeline, PipelineRunner};
+
+    assert_eq!(current_stage_path(), None);
+    let report = PipelineRunner::new(0..10u64).run(
+        |_scope, pipeline| {
+            pipeline.stage(
+                "outer",
+                par_map(|x: u64| {
+                    assert_eq!(current_stage_path().as_deref(), Some("outer"));
+                    Pipeline::from(0..x)
+                        .stage("inner", par_map(|y: u64| y * 2))
+                        .sum::<u64>()
+                })
+                .threads(2),
+            )
+        },
+        |_| Ok::<_, ()>(()),
+    );
+
+    assert!(report.is_success());
+    let stages: Vec<_> = report
+        .stages()
+        .iter()
+        .map(|stage| (stage.name(), stage.depth()))
+        .collect();
+    assert_eq!(stages, vec![("outer", 0), ("outer/inner", 1)]);
+}
+
+#[test]
+fn pool_reuses_threads() {
+    use crate::Pool;
+
+    let thread_ids = || {
+        let pool = Pool::new();
+        let mut ids = std::collections::HashSet::new();
+        for run in 0..5u64 {
+            ids.extend(
+                (0..50u64)
+                    .parallel_map_custom(
+                        |o| o.threads(2).spawner(pool.clone()),
+                        move |_| (std::thread::current().id(), run),
+                    )
+                    .map(|(id, _run)| id),
+            );
+        }
+        (ids.len(), pool.threads())
+    };
+    let (ids, threads) = thread_ids();
+    assert!(ids <= threads && threads <= 4, "{} {}", ids, threads);
+
+    crate::scope(|scope| {
+        let pool = Pool::scoped(scope, 2);
+        for _ in 0..3 {
+            let sum: u64 = (0..10u64)
+                .parallel_map_custom(|o| o.threads(2).spawner(pool.clone()), |x| x)
+                .sum();
+            assert_eq!(sum, 45);
+        }
+        assert!(pool.threads() <= 4);
+    })
+    .unwrap();
+}
+
+#[test]
+fn pool_idle_timeout() {
+    use crate::Pool;
+
+    let pool = Pool::new().idle_timeout(std::time::Duration::from_millis(10));
+    let run = || {
+        (0..20u64)
+            .parallel_map_custom(|o| o.threads(2).spawner(pool.clone()), |x| x)
+            .sum::<u64>()
+    };
+    assert_eq!(run(), 190);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while pool.threads() != 0 {
+        assert!(std::time::Instant::now() < deadline);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    assert_eq!(run(), 190);
+}
+
+#[test]
+fn pool_weighted_dispatch() {
+    use crate::{Pool, Spawner};
+    use std::sync::{mpsc, Arc, Mutex};
+
+    let pool = Pool::new().max_threads(1);
+    let batch = pool.weighted(1);
+    let urgent = pool.weighted(3);
+
+    // keep the only thread busy, until all the jobs are queued
+    let (release, released) = mpsc::channel::<()>();
+    pool.spawn(Box::new(move || {
+        let _ = released.recv();
+    }));
+
+    let order = Arc::new(Mutex::new(vec![]));
+    let (done_tx, done_rx) = mpsc::channel();
+    for (name, spawner) in [("batch", &batch), ("urgent", &urgent)] {
+        for _ in 0..8 {
+            let order = order.clone();
+            let done_tx = done_tx.clone();
+            spawner.spawn(Box::new(move || {
+                order.lock().unwrap().push(name);
+                done_tx.send(()).unwrap();
+            }));
+        }
+    }
+    release.send(()).unwrap();
+    for _ in 0..16 {
+        done_rx.recv().unwrap();
+    }
+
+    let order = order.lock().unwrap();
+    let urgent_first = order[..8].iter().filter(|name| **name == "urgent").count();
+    assert_eq!(urgent_first, 6, "{:?}", order);
+    assert_eq!(pool.threads(), 1);
+}
+
+#[test]
+fn pool_stats() {
+    use crate::{Pool, Spawner};
+    use std::sync::mpsc;
+
+    let pool = Pool::new().max_threads(1);
+    let pipeline = pool.weighted(2);
+    let (release, released) = mpsc::channel::<()>();
+    pool.spawn(Box::new(move || {
+        let _ = released.recv();
+    }));
+    let (done_tx, done_rx) = mpsc::channel();
+    for _ in 0..3 {
+        let done_tx = done_tx.clone();
+        pipeline.spawn(Box::new(move || done_tx.send(()).unwrap()));
+    }
+
+    let stats = pool.stats();
+    assert_eq!(stats.threads, 1);
+    assert_eq!(stats.queued, vec![(pipeline.pipeline(), 3)]);
+    assert_eq!(stats.queued_total(), 3);
+
+    release.send(()).unwrap();
+    for _ in 0..3 {
+        done_rx.recv().unwrap();
+    }
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while pool.stats().jobs_completed != 4 {
+        assert!(std::time::Instant::now() < deadline);
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    let stats = pool.stats();
+    assert!(stats.queued.is_empty());
+    assert!(stats.jobs_completed_per_sec() > 0.0);
+    assert!(stats.to_string().starts_with("1 threads"), "{}", stats);
+}
+
+#[test]
+fn readahead_size_hint_shrinks() {
+    let mut iter = (0..10).readahead_custom(|o| o.buffer_size(4));
+    assert_eq!(iter.size_hint(), (10, Some(10)));
+    iter.by_ref().take(3).for_each(drop);
+    assert_eq!(iter.size_hint(), (7, Some(7)));
+    assert_eq!(iter.peek_n(2), &[3, 4]);
+    assert_eq!(iter.size_hint(), (7, Some(7)));
+    assert_eq!(iter.by_ref().count(), 7);
+    assert_eq!(iter.size_hint(), (0, Some(0)));
+
+    let mut iter = (0..10).filter(|x| x % 2 == 0).readahead();
+    assert_eq!(iter.size_hint(), (0, Some(10)));
+    assert_eq!(iter.peek_n(3).len(), 3);
+    assert_eq!(iter.size_hint(), (3, Some(10)));
+    iter.next();
+    assert_eq!(iter.size_hint().1, Some(9));
+}
+
+#[test]
+fn parallel_map_size_hint_counts_in_flight() {
+    let mut iter = (0..100).parallel_map_custom(|o| o.threads(2).buffer_size(8), |x| x);
+    assert_eq!(iter.size_hint(), (100, Some(100)));
+    assert_eq!(iter.next(), Some(0));
+    assert_eq!(iter.size_hint(), (99, Some(99)));
+    iter.by_ref().take(50).for_each(drop);
+    assert_eq!(iter.size_hint(), (49, Some(49)));
+    assert_eq!(iter.collect::<Vec<_>>().len(), 49);
+
+    let mut iter = (0..10).parallel_map_custom(|o| o.limit(4), |x| x);
+    iter.next();
+    assert_eq!(iter.size_hint(), (3, Some(3)));
+}
+
+#[quickcheck]
+fn optimize_for_tiny_items(v: Vec<u32>, threads: u8) -> bool {
+    let threads = usize::from(threads % 8) + 1;
+    let mapped: Vec<_> = v
+        .clone()
+        .into_iter()
+        .parallel_map_custom(
+            |o| o.threads(threads).optimize_for(crate::Workload::TinyItems),
+            |x| u64::from(x) + 1,
+        )
+        .collect();
+    mapped == v.into_iter().map(|x| u64::from(x) + 1).collect::<Vec<_>>()
+}
+
+#[test]
+fn diagnose_compares_runs() {
+    let diagnosis = crate::diagnose(0..20u64, |x| {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        x
+    });
+    assert_eq!(diagnosis.items(), 20);
+    assert!(diagnosis.sequential() >= std::time::Duration::from_millis(20));
+    assert!(diagnosis.to_string().starts_with("20 items: sequential"));
+    if 2 < diagnosis.threads() {
+        assert!(diagnosis.is_worth_parallelizing(), "{}", diagnosis);
+    }
+
+    let diagnosis = crate::diagnose(Vec::<u8>::new(), |x| x);
+    assert_eq!(diagnosis.overhead_per_item(), std::time::Duration::ZERO);
+}
+
+#[quickcheck]
+fn reorder_buffer_returns_in_order(offsets: Vec<u8>) -> bool {
+    use crate::reorder::ReorderBuffer;
+
+    // a permutation of `0..n`, by sorting the indices by the random keys
+    let mut order: Vec<usize> = (0..offsets.len()).collect();
+    order.sort_by_key(|i| offsets[*i]);
+
+    let mut buffer = ReorderBuffer::<usize>::with_capacity(order.len());
+    let mut next = 0;
+    let mut returned = vec![];
+    for i in order {
+        buffer.insert(i - next, i);
+        while let Some(item) = buffer.pop_next() {
+            returned.push(item);
+            next += 1;
+        }
+    }
+    buffer.len() == 0 && returned == (0..offsets.len()).collect::<Vec<_>>()
+}
+
+#[quickcheck]
+fn reorder_buffer_inline_vs_heap(offsets: Vec<u8>, capacity: u8) -> bool {
+    use crate::reorder::ReorderBuffer;
+
+    let mut order: Vec<usize> = (0..offsets.len()).collect();
+    order.sort_by_key(|i| offsets[*i]);
+
+    // might not fit in the 4 slots stored inline, and spill to the heap
+    let mut inline = ReorderBuffer::<usize, 4>::with_capacity(usize::from(capacity % 8));
+    let mut heap = ReorderBuffer::<usize, 0>::with_capacity(order.len());
+    let (mut next, mut returned_inline, mut returned_heap) = (0, vec![], vec![]);
+    for i in order {
+        inline.insert(i - next, i);
+        heap.insert(i - next, i);
+        if inline.iter().ne(heap.iter()) {
+            return false;
+        }
+        while let Some(item) = inline.pop_next() {
+            returned_inline.push(item);
+            returned_heap.extend(heap.pop_next());
+            next += 1;
+        }
+    }
+    returned_inline == returned_heap && heap.len() == 0
+}
+
+#[test]
+fn reorder_buffer_stores_small_buffers_inline() {
+    use crate::reorder::{ReorderBuffer, INLINE_SLOTS};
+
+    let mut buffer = ReorderBuffer::<char>::with_capacity(INLINE_SLOTS);
+    assert!(buffer.is_inline());
+    buffer.insert(2, 'c');
+    buffer.skip_next();
+    buffer.push_back('d');
+    buffer.insert(0, 'b');
+    assert!(buffer.is_inline());
+    assert_eq!(buffer.iter().copied().collect::<String>(), "bcd");
+    assert_eq!(buffer.pop_next(), Some('b'));
+
+    // an item too far ahead moves the slots to the heap
+    buffer.insert(INLINE_SLOTS, 'z');
+    assert!(!buffer.is_inline());
+    assert_eq!(buffer.into_iter().collect::<String>(), "cdz");
+
+    assert!(!ReorderBuffer::<u8>::with_capacity(INLINE_SLOTS + 1).is_inline());
+}
+
+#[quickcheck]
+fn into_shared_returns_every_item_once(v: Vec<u32>, consumers: u8) -> bool {
+    let consumers = usize::from(consumers % 4) + 1;
+    let shared = v.clone().into_iter().parallel_map(|x| x).into_shared();
+    let mut pulled: Vec<u32> = crate::scope(|scope| {
+        let handles: Vec<_> = (0..consumers)
+            .map(|_| {
+                let shared = shared.clone();
+                scope.spawn(move |_| shared.collect::<Vec<_>>())
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+    .unwrap();
+
+    let mut expected = v;
+    expected.sort_unstable();
+    pulled.sort_unstable();
+    pulled == expected
+}
+
+#[quickcheck]
+fn unordered_with_input_indices(v: Vec<u32>, threads: u8) -> bool {
+    let threads = usize::from(threads % 8) + 1;
+    let mut indexed: Vec<_> = v
+        .clone()
+        .into_iter()
+        .parallel_map_custom(|o| o.threads(threads).unordered(), |x| u64::from(x) * 2)
+        .with_input_indices()
+        .collect();
+    indexed.sort_unstable();
+    indexed
+        == v.into_iter()
+            .map(|x| u64::from(x) * 2)
+            .enumerate()
+            .collect::<Vec<_>>()
+}
+
+#[test]
+fn unordered_does_not_wait_for_slow_items() {
+    let first: Vec<_> = (0..8)
+        .parallel_map_custom(
+            |o| o.threads(4).unordered(),
+            |x| {
+                if x == 0 {
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                }
+                x
+            },
+        )
+        .with_input_indices()
+        .take(3)
+        .collect();
+    assert!(first.iter().all(|(i, x)| *i != 0 && i == x), "{:?}", first);
+
+    // flushed results are returned too
+    let mut iter = (0..20).parallel_map_custom(|o| o.threads(2).unordered(), |x| x);
+    iter.next();
+    iter.flush();
+    let mut rest: Vec<_> = iter.collect();
+    rest.sort_unstable();
+    assert_eq!(rest.len(), 19);
+}
+
+#[quickcheck]
+fn write_at_offsets_layout(v: Vec<u16>) -> bool {
+    let mut out = std::io::Cursor::new(vec![]);
+    let written = v
+        .clone()
+        .into_iter()
+        .parallel_map_custom(|o| o.threads(4).unordered(), |x| x.to_le_bytes())
+        .with_input_indices()
+        .write_at_offsets(&mut out, |i| i as u64 * 2)
+        .unwrap();
+
+    let expected: Vec<u8> = v.iter().flat_map(|x| x.to_le_bytes()).collect();
+    written == expected.len() as u64 && out.into_inner() == expected
+}
+
+#[quickcheck]
+fn parallel_terminals(v: Vec<u32>) -> bool {
+    let sum: u64 = v.clone().into_iter().parallel_sum_by(u64::from);
+    let evens = v.clone().into_iter().parallel_count(|x| x % 2 == 0);
+    let max =
+        v.clone()
+            .into_iter()
+            .parallel_fold(|| None, |max, x| max.max(Some(x)), |a, b| a.max(b));
+
+    sum == v.iter().copied().map(u64::from).sum::<u64>()
+        && evens == v.iter().filter(|x| *x % 2 == 0).count()
+        && max == v.iter().copied().max()
+}
+
+#[test]
+#[should_panic(expected = "odd item")]
+fn parallel_terminals_propagate_panics() {
+    (0..100).parallel_count(|x| {
+        assert!(x != 51, "odd item");
+        true
+    });
+}
+
+#[test]
+fn between_items_hook() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let sum: u32 = (0..50u32)
+        .parallel_map_custom(
+            |o| {
+                o.threads(3).between_items({
+                    let calls = calls.clone();
+                    move |worker_i| {
+                        assert!(worker_i < 3);
+                        calls.fetch_add(1, SeqCst);
+                    }
+                })
+            },
+            |x| x,
+        )
+        .sum();
+    assert_eq!(sum, 1225);
+    assert_eq!(calls.load(SeqCst), 50);
+}
+
+#[test]
+fn max_item_age_skips_stale_items() {
+    use std::time::{Duration, SystemTime};
+
+    let now = SystemTime::now();
+    let stale = now - Duration::from_secs(60);
+    // every third item was enqueued long ago
+    let items: Vec<_> = (0..30u32)
+        .map(|i| (i, if i % 3 == 0 { stale } else { now }))
+        .collect();
+
+    let (dead_tx, dead_rx) = crossbeam_channel::unbounded();
+    let iter = items.into_iter().parallel_map_custom(
+        |o| {
+            o.threads(3)
+                .max_item_age(Duration::from_secs(10), |(_, enqueued)| *enqueued)
+                .on_expired(move |(i, _)| dead_tx.send(i).unwrap())
+        },
+        |(i, _)| i,
+    );
+    assert_eq!(iter.size_hint(), (0, Some(30)));
+    let processed: Vec<_> = iter.collect();
+
+    assert_eq!(
+        processed,
+        (0..30).filter(|i| i % 3 != 0).collect::<Vec<_>>()
+    );
+    let mut dead: Vec<_> = dead_rx.iter().collect();
+    dead.sort_unstable();
+    assert_eq!(dead, (0..30).step_by(3).collect::<Vec<_>>());
+}
+
+#[test]
+fn shed_load_drops_items_while_behind() {
+    let mut iter = (0..200u32).parallel_map_custom(
+        |o| {
+            o.threads(1)
+                .buffer_size(4)
+                .shed_load(std::time::Duration::ZERO, 0.5)
+        },
+        |x| {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            x
+        },
+    );
+    assert_eq!(iter.size_hint().0, 0);
+    let processed: Vec<_> = iter.by_ref().collect();
+
+    let shed = iter.shed_count();
+    assert!(0 < shed, "nothing shed");
+    assert_eq!(processed.len() + shed, 200);
+    // still in order, just with gaps
+    assert!(processed.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn shed_load_keeps_input_indices() {
+    let slow = |x| {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        x
+    };
+
+    let mut ordered = crate::ParallelMapBuilder::new(0..200usize)
+        .threads(1)
+        .buffer_size(4)
+        .shed_load(std::time::Duration::ZERO, 0.5)
+        .with_enumerate(move |i, x| (i, slow(x)));
+    let processed: Vec<_> = ordered.by_ref().collect();
+    assert!(0 < ordered.shed_count(), "nothing shed");
+    assert!(processed.iter().all(|(i, x)| i == x));
+
+    let processed: Vec<_> = (0..200usize)
+        .parallel_map_custom(
+            |o| {
+                o.threads(2)
+                    .buffer_size(4)
+                    .unordered()
+                    .shed_load(std::time::Duration::ZERO, 0.5)
+            },
+            slow,
+        )
+        .with_input_indices()
+        .collect();
+    assert!(processed.iter().all(|(i, x)| i == x));
+}
+
+#[test]
+fn shed_load_keeps_items_while_keeping_up() {
+    // the workers are faster than the consumer, so their queue never stays full
+    let mut iter = (0..1000u32).parallel_map_custom(
+        |o| {
+            o.threads(2)
+                .shed_load(std::time::Duration::from_millis(50), 1.0)
+        },
+        |x| x,
+    );
+    assert_eq!(iter.by_ref().count(), 1000);
+    assert_eq!(iter.shed_count(), 0);
+}
@@ -0,0 +1,89 @@
+use crate::profile::Profiler;
+
+use std::{fmt, thread, time};
+
+/// Limits the rate at which items are pulled from the inner iterator
+///
+/// See [`crate::IteratorExt::throttle`].
+pub struct Throttle<I, P> {
+    // the iterator we wrapped
+    iter: I,
+    // measures the time spent blocked on the rate limit
+    profiler: P,
+    // tokens added per second
+    rate: f64,
+    // currently available tokens, at most 1
+    tokens: f64,
+    // when were the tokens last refilled
+    last_refill: time::Instant,
+    // is `iter` exhausted
+    iter_done: bool,
+}
+
+impl<I, P> Throttle<I, P> {
+    pub(crate) fn new(iter: I, items_per_sec: f64, profiler: P) -> Self {
+        assert!(
+            0.0 < items_per_sec && items_per_sec.is_finite(),
+            "rate must be positive and finite"
+        );
+        Self {
+            iter,
+            profiler,
+            rate: items_per_sec,
+            tokens: 1.0,
+            last_refill: time::Instant::now(),
+            iter_done: false,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = time::Instant::now();
+        self.tokens =
+            (self.tokens + now.duration_since(self.last_refill).as_secs_f64() * self.rate).min(1.0);
+        self.last_refill = now;
+    }
+}
+
+impl<I, P> fmt::Debug for Throttle<I, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Throttle")
+            .field("rate", &self.rate)
+            .field("tokens", &self.tokens)
+            .field("iter_done", &self.iter_done)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, P> Iterator for Throttle<I, P>
+where
+    I: Iterator,
+    P: Profiler,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.iter_done {
+            return None;
+        }
+
+        self.refill();
+        if self.tokens < 1.0 {
+            self.profiler.start();
+            thread::sleep(time::Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.rate,
+            ));
+            self.profiler.end();
+            self.refill();
+        }
+        // sleeping can be a bit short, but we don't want to wait again
+        self.tokens = (self.tokens - 1.0).max(0.0);
+
+        let item = self.iter.next();
+        self.iter_done = item.is_none();
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    cmp::{self, Reverse},
+    collections::BinaryHeap,
+};
+
+/// Most items preallocated for, so a huge `k` doesn't allocate for items that never come
+const MAX_PREALLOCATED: usize = 1024;
+
+/// An item ordered by its key only
+struct ByKey<K, T>(K, T);
+
+impl<K: Ord, T> PartialEq for ByKey<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<K: Ord, T> Eq for ByKey<K, T> {}
+
+impl<K: Ord, T> PartialOrd for ByKey<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, T> Ord for ByKey<K, T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+/// Keeps `k` items with the largest keys seen so far
+///
+/// Used as the per-worker state of [`crate::IteratorExt::parallel_top_k`].
+pub(crate) struct TopK<K, T> {
+    k: usize,
+    // min-heap, so the worst item kept is on top
+    heap: BinaryHeap<Reverse<ByKey<K, T>>>,
+}
+
+impl<K: Ord, T> TopK<K, T> {
+    pub(crate) fn new(k: usize) -> Self {
+        Self {
+            k,
+            heap: BinaryHeap::with_capacity(cmp::min(k, MAX_PREALLOCATED)),
+        }
+    }
+
+    pub(crate) fn push(&mut self, key: K, item: T) {
+        if self.heap.len() < self.k {
+            self.heap.push(Reverse(ByKey(key, item)));
+        } else if let Some(mut worst) = self.heap.peek_mut() {
+            if worst.0 .0 < key {
+                *worst = Reverse(ByKey(key, item));
+            }
+        }
+    }
+
+    /// All the items kept, in no particular order
+    pub(crate) fn into_entries(self) -> impl Iterator<Item = (K, T)> {
+        self.heap
+            .into_iter()
+            .map(|Reverse(ByKey(key, item))| (key, item))
+    }
+
+    /// All the items kept, best first
+    pub(crate) fn into_sorted_items(self) -> Vec<T> {
+        // `Reverse` makes the ascending order the best-first one
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(ByKey(_key, item))| item)
+            .collect()
+    }
+}
@@ -0,0 +1,165 @@
+use std::{fmt, ops, time};
+
+/// An item carrying its provenance through the pipeline
+///
+/// Created by [`crate::IteratorExt::traced`], with the position of the item in the
+/// source iterator and the time it entered the pipeline. Stages map the item with
+/// [`Traced::map`], keeping the provenance, and [`crate::IteratorExt::stamp`] records
+/// the time it passed a given point of the pipeline.
+#[derive(Debug, Clone)]
+pub struct Traced<T> {
+    item: T,
+    // position in the source iterator
+    index: usize,
+    // when it was pulled from the source iterator
+    entered: time::Instant,
+    // names of the points passed, and when
+    stamps: Vec<(&'static str, time::Instant)>,
+}
+
+impl<T> Traced<T> {
+    /// Position of the item in the source iterator
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// When the item was pulled from the source iterator
+    pub fn entered(&self) -> time::Instant {
+        self.entered
+    }
+
+    /// Time since the item was pulled from the source iterator
+    pub fn elapsed(&self) -> time::Duration {
+        self.entered.elapsed()
+    }
+
+    /// Points of the pipeline the item passed, and when, in order
+    pub fn stamps(&self) -> &[(&'static str, time::Instant)] {
+        &self.stamps
+    }
+
+    /// Record the item passed the point called `name` now
+    pub fn stamp(&mut self, name: &'static str) {
+        self.stamps.push((name, time::Instant::now()));
+    }
+
+    /// Map the item, keeping its provenance
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Traced<U> {
+        Traced {
+            item: f(self.item),
+            index: self.index,
+            entered: self.entered,
+            stamps: self.stamps,
+        }
+    }
+
+    /// Drop the provenance
+    pub fn into_inner(self) -> T {
+        self.item
+    }
+}
+
+impl<T> ops::Deref for Traced<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.item
+    }
+}
+
+impl<T> ops::DerefMut for Traced<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.item
+    }
+}
+
+/// Wraps the items in [`Traced`]
+///
+/// See [`crate::IteratorExt::traced`].
+pub struct Tracing<I> {
+    // the iterator we wrapped
+    iter: I,
+    // position of the next item
+    next_index: usize,
+}
+
+impl<I> Tracing<I> {
+    pub(crate) fn new(iter: I) -> Self {
+        Self {
+            iter,
+            next_index: 0,
+        }
+    }
+}
+
+impl<I> fmt::Debug for Tracing<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracing")
+            .field("next_index", &self.next_index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> Iterator for Tracing<I>
+where
+    I: Iterator,
+{
+    type Item = Traced<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next()?;
+        let index = self.next_index;
+        self.next_index += 1;
+        Some(Traced {
+            item,
+            index,
+            entered: time::Instant::now(),
+            stamps: Vec::new(),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// Records the time [`Traced`] items pass a point of the pipeline
+///
+/// See [`crate::IteratorExt::stamp`].
+pub struct Stamp<I> {
+    // the iterator we wrapped
+    iter: I,
+    // name of the point
+    name: &'static str,
+}
+
+impl<I> Stamp<I> {
+    pub(crate) fn new(iter: I, name: &'static str) -> Self {
+        Self { iter, name }
+    }
+}
+
+impl<I> fmt::Debug for Stamp<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stamp")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, T> Iterator for Stamp<I>
+where
+    I: Iterator<Item = Traced<T>>,
+{
+    type Item = Traced<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut item = self.iter.next()?;
+        item.stamp(self.name);
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
@@ -0,0 +1,249 @@
+//! Recommending the options of parallel stages
+//!
+//! Parallelizing cheap functions doesn't pay off: every item has to cross the channels
+//! to and from the worker threads. [`estimate`] times a function on a sample of items,
+//! and recommends the number of threads, buffer size, and chunk size, given the
+//! measured cost per item and the (roughly constant) overhead of sending items around.
+//!
+//! ```
+//! use pariter::{tune, IteratorExt as _};
+//!
+//! let estimate = tune::estimate(|x: u64| (0..x).sum::<u64>(), 0..1000);
+//! println!("{}", estimate);
+//!
+//! let sum: u64 = (0..1000u64)
+//!     .parallel_map_custom(|o| o.config(&estimate.config()), |x| (0..x).sum::<u64>())
+//!     .sum();
+//! ```
+//!
+//! [`diagnose`] instead runs `f` both sequentially and in parallel, and compares the two.
+use crate::{IteratorExt as _, ParallelMapBuilder, ParallelMapConfig};
+
+use std::{cmp, convert::TryFrom, fmt, time::Duration, time::Instant};
+
+/// Approximate overhead of passing a single item through a parallel stage
+pub const ITEM_OVERHEAD: Duration = Duration::from_micros(4);
+
+/// Work per item (or chunk of items) worth paying the overhead for
+const MIN_WORK_PER_OVERHEAD: u32 = 10;
+
+/// Upper bound of the recommended chunk size
+const MAX_CHUNK_SIZE: usize = 4096;
+
+/// Options recommended by [`estimate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    per_item: Duration,
+    threads: usize,
+    buffer_size: usize,
+    chunk_size: usize,
+}
+
+impl Estimate {
+    fn from_per_item(per_item: Duration, cores: usize) -> Self {
+        let min_work = ITEM_OVERHEAD * MIN_WORK_PER_OVERHEAD;
+        let chunk_size = if per_item >= min_work {
+            1
+        } else {
+            let per_item = cmp::max(per_item.as_nanos(), 1);
+            let chunk_size = min_work.as_nanos().div_ceil(per_item);
+            cmp::min(chunk_size, MAX_CHUNK_SIZE as u128) as usize
+        };
+
+        // the thread pulling the results handles one chunk per overhead,
+        // so it can't keep more workers than that busy
+        let per_chunk = per_item.as_nanos() * chunk_size as u128;
+        let threads = per_chunk / ITEM_OVERHEAD.as_nanos();
+        let threads = cmp::max(1, cmp::min(threads, cores as u128) as usize);
+
+        Self {
+            per_item,
+            threads,
+            buffer_size: threads * 2,
+            chunk_size,
+        }
+    }
+
+    /// Measured cost of processing a single item
+    pub fn per_item(&self) -> Duration {
+        self.per_item
+    }
+
+    /// Recommended number of threads
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Recommended buffer size
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Recommended number of items processed together
+    ///
+    /// See [`crate::IteratorExt::parallel_batch_map`].
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Is parallelizing worth it at all
+    pub fn is_worth_parallelizing(&self) -> bool {
+        1 < self.threads
+    }
+
+    /// A config with the recommended threads and buffer size
+    pub fn config(&self) -> ParallelMapConfig {
+        ParallelMapConfig::new()
+            .threads(self.threads)
+            .buffer_size(self.buffer_size)
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} per item: threads {}, buffer size {}, chunk size {}",
+            self.per_item, self.threads, self.buffer_size, self.chunk_size
+        )?;
+        if !self.is_worth_parallelizing() {
+            write!(f, " (not worth parallelizing)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Time `f` on the `sample` items, and recommend the options of a stage running it
+///
+/// The sample should be representative, and big enough for the timing to be meaningful.
+pub fn estimate<T, O, F>(mut f: F, sample: impl IntoIterator<Item = T>) -> Estimate
+where
+    F: FnMut(T) -> O,
+{
+    let mut count = 0u32;
+    let start = Instant::now();
+    for item in sample {
+        drop((f)(item));
+        count += 1;
+    }
+    let elapsed = start.elapsed();
+
+    let per_item = elapsed.checked_div(count).unwrap_or_default();
+    Estimate::from_per_item(per_item, num_cpus::get_physical())
+}
+
+/// Sequential and parallel runs of the same function, compared by [`diagnose`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnosis {
+    items: usize,
+    threads: usize,
+    sequential: Duration,
+    parallel: Duration,
+}
+
+impl Diagnosis {
+    /// Number of items in the sample
+    pub fn items(&self) -> usize {
+        self.items
+    }
+
+    /// Number of threads of the parallel run
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Time of the sequential run
+    pub fn sequential(&self) -> Duration {
+        self.sequential
+    }
+
+    /// Time of the parallel run
+    pub fn parallel(&self) -> Duration {
+        self.parallel
+    }
+
+    /// How many times faster the parallel run was, below 1 if slower
+    pub fn speedup(&self) -> f64 {
+        let parallel = self.parallel.as_secs_f64();
+        if parallel == 0.0 {
+            1.0
+        } else {
+            self.sequential.as_secs_f64() / parallel
+        }
+    }
+
+    /// Time the parallel run took per item, on top of the sequential time split between the threads
+    pub fn overhead_per_item(&self) -> Duration {
+        let ideal = self.sequential / self.threads as u32;
+        let items = u32::try_from(self.items).unwrap_or(u32::MAX);
+        self.parallel
+            .saturating_sub(ideal)
+            .checked_div(items)
+            .unwrap_or_default()
+    }
+
+    /// Was the parallel run faster at all
+    pub fn is_worth_parallelizing(&self) -> bool {
+        1.0 < self.speedup()
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} items: sequential {:?}, parallel {:?} on {} threads, speedup {:.2}x, overhead {:?} per item",
+            self.items,
+            self.sequential,
+            self.parallel,
+            self.threads,
+            self.speedup(),
+            self.overhead_per_item()
+        )?;
+        if !self.is_worth_parallelizing() {
+            write!(f, " (not worth parallelizing)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `f` on the `sample` items sequentially, and in a parallel stage, and compare the times
+///
+/// Tells whether parallelizing `f` is worth it at all. The sample should be representative,
+/// and big enough for the timing to be meaningful.
+///
+/// ```
+/// let diagnosis = pariter::diagnose(0..1000u64, |x| (0..x).sum::<u64>());
+/// println!("{}", diagnosis);
+/// ```
+pub fn diagnose<T, O, F>(sample: impl IntoIterator<Item = T>, f: F) -> Diagnosis
+where
+    T: Clone + Send,
+    O: Send,
+    F: FnMut(T) -> O + Clone + Send,
+{
+    let sample: Vec<T> = sample.into_iter().collect();
+    let threads = ParallelMapBuilder::<std::vec::IntoIter<T>>::num_threads(None);
+
+    let start = Instant::now();
+    sample.iter().cloned().map(f.clone()).for_each(drop);
+    let sequential = start.elapsed();
+
+    let items = sample.len();
+    let start = Instant::now();
+    crate::scope(|scope| {
+        sample
+            .into_iter()
+            .parallel_map_scoped_custom(scope, |o| o.threads(threads), f)
+            .for_each(drop)
+    })
+    .expect("diagnosed function panicked");
+    let parallel = start.elapsed();
+
+    Diagnosis {
+        items,
+        threads,
+        sequential,
+        parallel,
+    }
+}
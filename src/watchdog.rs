@@ -0,0 +1,182 @@
+use crossbeam_channel::{RecvTimeoutError, Sender};
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Arc,
+    },
+    thread, time,
+};
+
+/// Callback called by the watchdog when a stage is stalled
+pub(crate) type StallCallback = Arc<dyn Fn(&StallReport<'_>) + Send + Sync>;
+
+/// Counters of items moving through a stage
+///
+/// Updated by the stage and the worker threads, read by the watchdog.
+#[derive(Default, Debug)]
+pub(crate) struct Progress {
+    sent: AtomicUsize,
+    started: AtomicUsize,
+    completed: AtomicUsize,
+    received: AtomicUsize,
+}
+
+impl Progress {
+    pub(crate) fn set_sent(&self, n: usize) {
+        self.sent.store(n, Relaxed);
+    }
+
+    pub(crate) fn set_received(&self, n: usize) {
+        self.received.store(n, Relaxed);
+    }
+
+    pub(crate) fn inc_started(&self) {
+        self.started.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn inc_completed(&self) {
+        self.completed.fetch_add(1, Relaxed);
+    }
+
+    /// Number of items waiting for the workers, and for the consumer
+    pub(crate) fn occupancy(&self) -> (usize, usize) {
+        let (sent, completed, received) = self.snapshot();
+        let started = self.started.load(Relaxed);
+        (
+            sent.saturating_sub(started),
+            completed.saturating_sub(received),
+        )
+    }
+
+    fn snapshot(&self) -> (usize, usize, usize) {
+        (
+            self.sent.load(Relaxed),
+            self.completed.load(Relaxed),
+            self.received.load(Relaxed),
+        )
+    }
+}
+
+/// State of a stalled stage, passed to the callback set with
+/// [`crate::ParallelMapBuilder::stall_warning`]
+#[derive(Debug)]
+pub struct StallReport<'a> {
+    name: Option<&'a str>,
+    id: u64,
+    stalled_for: time::Duration,
+    sent: usize,
+    completed: usize,
+    received: usize,
+}
+
+impl<'a> StallReport<'a> {
+    /// Name of the stage, if it was given one
+    pub fn name(&self) -> Option<&'a str> {
+        self.name
+    }
+
+    /// Unique id of the stage, see [`crate::ParallelMap::id`]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Time since any item last moved through the stage
+    pub fn stalled_for(&self) -> time::Duration {
+        self.stalled_for
+    }
+
+    /// Number of items sent to the worker threads so far
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+
+    /// Number of items the worker threads finished processing so far
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+
+    /// Number of items returned by the stage so far
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Number of items sent to the workers but not yet returned
+    pub fn in_flight(&self) -> usize {
+        self.sent.saturating_sub(self.received)
+    }
+}
+
+impl<'a> fmt::Display for StallReport<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "stage `{}#{}` stalled for {}ms: {} in flight, {} sent, {} completed, {} received",
+            self.name.unwrap_or("<unnamed>"),
+            self.id,
+            self.stalled_for.as_millis(),
+            self.in_flight(),
+            self.sent,
+            self.completed,
+            self.received
+        )
+    }
+}
+
+/// A handle to a watchdog thread
+///
+/// The thread exits as soon as the handle is dropped.
+pub(crate) struct Watchdog {
+    _stop_tx: Sender<()>,
+}
+
+impl Watchdog {
+    pub(crate) fn spawn(
+        name: Option<String>,
+        id: u64,
+        progress: Arc<Progress>,
+        period: time::Duration,
+        callback: StallCallback,
+    ) -> Self {
+        let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+
+        thread::spawn(move || {
+            let mut last = progress.snapshot();
+            let mut last_change = time::Instant::now();
+            let mut last_report = last_change;
+
+            loop {
+                // check a couple of times per period, to report promptly
+                match stop_rx.recv_timeout(period / 4) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                }
+
+                let current = progress.snapshot();
+                let now = time::Instant::now();
+                if current != last {
+                    last = current;
+                    last_change = now;
+                    last_report = now;
+                    continue;
+                }
+
+                if period <= now.duration_since(last_report) {
+                    last_report = now;
+                    let (sent, completed, received) = current;
+                    (callback)(&StallReport {
+                        name: name.as_deref(),
+                        id,
+                        stalled_for: now.duration_since(last_change),
+                        sent,
+                        completed,
+                        received,
+                    });
+                }
+            }
+        });
+
+        Self { _stop_tx: stop_tx }
+    }
+}
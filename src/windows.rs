@@ -0,0 +1,78 @@
+use std::{cmp, collections::VecDeque, fmt};
+
+/// Iterator over overlapping windows of consecutive items
+///
+/// See [`crate::IteratorExt::parallel_window_map`].
+pub struct Windows<I>
+where
+    I: Iterator,
+{
+    // the iterator we wrapped
+    iter: I,
+    // number of items in each window
+    size: usize,
+    // distance between the starts of consecutive windows
+    step: usize,
+    // items of the current window
+    window: VecDeque<I::Item>,
+    // was the first window returned already
+    started: bool,
+}
+
+impl<I> Windows<I>
+where
+    I: Iterator,
+{
+    pub(crate) fn new(iter: I, size: usize, step: usize) -> Self {
+        assert!(0 < size, "window size must be positive");
+        assert!(0 < step, "window step must be positive");
+        Self {
+            iter,
+            size,
+            step,
+            window: VecDeque::with_capacity(size),
+            started: false,
+        }
+    }
+}
+
+impl<I> fmt::Debug for Windows<I>
+where
+    I: Iterator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Windows")
+            .field("size", &self.size)
+            .field("step", &self.step)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> Iterator for Windows<I>
+where
+    I: Iterator,
+    I::Item: Clone,
+{
+    type Item = Vec<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.started {
+            if self.step <= self.size {
+                // the window is only partially filled once the inner iterator ran out
+                let step = cmp::min(self.step, self.window.len());
+                self.window.drain(..step);
+            } else {
+                self.window.clear();
+                // skip the items between the windows
+                self.iter.nth(self.step - self.size - 1)?;
+            }
+        }
+        self.started = true;
+
+        while self.window.len() < self.size {
+            self.window.push_back(self.iter.next()?);
+        }
+
+        Some(self.window.iter().cloned().collect())
+    }
+}
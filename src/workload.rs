@@ -0,0 +1,55 @@
+use crossbeam_channel::{Receiver, TryRecvError};
+
+use std::hint;
+
+/// Times to poll a channel before blocking on it, with [`Workload::TinyItems`]
+const SPIN_LIMIT: usize = 1000;
+
+/// What a stage spends its time on, to tune it for
+///
+/// See [`crate::ParallelMapBuilder::optimize_for`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Workload {
+    /// Items taking long enough to make handing them over between threads cheap
+    #[default]
+    Balanced,
+    /// Items mapped in about a microsecond, or less
+    ///
+    /// Buffers more items, and has the workers and the consumer spin for a while,
+    /// instead of blocking, while waiting for items. This burns some CPU time, but
+    /// avoids putting threads to sleep and waking them up for every item.
+    ///
+    /// Items are still handed over one by one, and returned in order. When even that
+    /// is too slow, map them in batches with [`crate::IteratorExt::parallel_batch_map`].
+    TinyItems,
+}
+
+impl Workload {
+    /// Number of items in flight per worker thread, unless set explicitly
+    pub(crate) fn buffer_per_thread(self) -> usize {
+        match self {
+            Workload::Balanced => 2,
+            Workload::TinyItems => 16,
+        }
+    }
+
+    /// Should threads waiting for items poll for a while before blocking
+    pub(crate) fn spin(self) -> bool {
+        self == Workload::TinyItems
+    }
+}
+
+/// Poll `rx` for a while, returning `None` if there is still nothing to receive
+///
+/// A disconnected channel is left to the blocking receive to report.
+pub(crate) fn spin_recv<T>(rx: &Receiver<T>) -> Option<T> {
+    for _ in 0..SPIN_LIMIT {
+        match rx.try_recv() {
+            Ok(item) => return Some(item),
+            Err(TryRecvError::Empty) => hint::spin_loop(),
+            Err(TryRecvError::Disconnected) => return None,
+        }
+    }
+    None
+}
@@ -0,0 +1,32 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Write every item at the offset `offset_fn` computes from its index
+///
+/// See [`crate::IteratorExt::write_at_offsets`].
+pub(crate) fn write_at_offsets<I, T, W, F>(
+    iter: I,
+    mut writer: W,
+    mut offset_fn: F,
+) -> io::Result<u64>
+where
+    I: Iterator<Item = (usize, T)>,
+    T: AsRef<[u8]>,
+    W: Write + Seek,
+    F: FnMut(usize) -> u64,
+{
+    let mut written = 0;
+    // the position after the last write, to skip seeking to where we are already
+    let mut position = None;
+    for (i, item) in iter {
+        let offset = (offset_fn)(i);
+        if position != Some(offset) {
+            writer.seek(SeekFrom::Start(offset))?;
+        }
+        let bytes = item.as_ref();
+        writer.write_all(bytes)?;
+        position = Some(offset + bytes.len() as u64);
+        written += bytes.len() as u64;
+    }
+    writer.flush()?;
+    Ok(written)
+}
@@ -0,0 +1,175 @@
+use std::{
+    fmt, fs,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    thread,
+};
+
+/// Writes items, in order, to files rotated by size or count
+///
+/// See [`crate::IteratorExt::write_rotating`].
+pub struct WriteRotatingBuilder<I> {
+    iter: I,
+    // path of the files, with `{}` replaced by the index of the file
+    path_pattern: String,
+    max_bytes: Option<u64>,
+    max_items: Option<usize>,
+    buffer_size: Option<usize>,
+}
+
+impl<I> fmt::Debug for WriteRotatingBuilder<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteRotatingBuilder")
+            .field("path_pattern", &self.path_pattern)
+            .field("max_bytes", &self.max_bytes)
+            .field("max_items", &self.max_items)
+            .field("buffer_size", &self.buffer_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I> WriteRotatingBuilder<I>
+where
+    I: Iterator,
+    I::Item: AsRef<[u8]> + Send + 'static,
+{
+    /// Write the items of `iter` to files at `path_pattern`
+    ///
+    /// `{}` in `path_pattern` is replaced with the index of each file, starting at 0.
+    pub fn new(iter: I, path_pattern: impl Into<String>) -> Self {
+        Self {
+            iter,
+            path_pattern: path_pattern.into(),
+            max_bytes: None,
+            max_items: None,
+            buffer_size: None,
+        }
+    }
+
+    /// Start a new file before exceeding `max` bytes
+    ///
+    /// Items are never split between files, so a single item bigger than `max`
+    /// still gets written, to a file of its own.
+    pub fn max_bytes(self, max: u64) -> Self {
+        Self {
+            max_bytes: Some(max),
+            ..self
+        }
+    }
+
+    /// Start a new file after `max` items
+    pub fn max_items(self, max: usize) -> Self {
+        Self {
+            max_items: Some(max),
+            ..self
+        }
+    }
+
+    /// Max number of items waiting for the writer thread
+    pub fn buffer_size(self, num: usize) -> Self {
+        Self {
+            buffer_size: Some(num),
+            ..self
+        }
+    }
+
+    /// Write all the items, returning the paths of the files written
+    ///
+    /// Writing and rotating the files happens on a dedicated thread, while
+    /// the calling thread keeps pulling the items.
+    pub fn with(self) -> io::Result<Vec<PathBuf>> {
+        if !self.path_pattern.contains("{}") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path pattern must contain `{}`",
+            ));
+        }
+
+        let (tx, rx) = crossbeam_channel::bounded::<I::Item>(self.buffer_size.unwrap_or(16));
+        let mut writer = RotatingWriter {
+            path_pattern: self.path_pattern,
+            max_bytes: self.max_bytes,
+            max_items: self.max_items,
+            current: None,
+            paths: vec![],
+        };
+        let thread = thread::spawn(move || {
+            for item in rx {
+                writer.write(item.as_ref())?;
+            }
+            writer.finish()
+        });
+
+        for item in self.iter {
+            if tx.send(item).is_err() {
+                // writer failed, the error will be returned from the thread
+                break;
+            }
+        }
+        drop(tx);
+
+        thread.join().expect("writer thread panicked")
+    }
+}
+
+struct CurrentFile {
+    writer: BufWriter<fs::File>,
+    bytes: u64,
+    items: usize,
+}
+
+struct RotatingWriter {
+    path_pattern: String,
+    max_bytes: Option<u64>,
+    max_items: Option<usize>,
+    current: Option<CurrentFile>,
+    paths: Vec<PathBuf>,
+}
+
+impl RotatingWriter {
+    fn is_full(&self, current: &CurrentFile, len: u64) -> bool {
+        current.items != 0
+            && (self.max_items.is_some_and(|max| max <= current.items)
+                || self.max_bytes.is_some_and(|max| max < current.bytes + len))
+    }
+
+    fn write(&mut self, item: &[u8]) -> io::Result<()> {
+        let len = item.len() as u64;
+        if let Some(current) = self.current.as_ref() {
+            if self.is_full(current, len) {
+                let mut full = self.current.take().expect("current file");
+                full.writer.flush()?;
+            }
+        }
+
+        let current = match self.current.as_mut() {
+            Some(current) => current,
+            None => {
+                let path = PathBuf::from(self.path_pattern.replacen(
+                    "{}",
+                    &self.paths.len().to_string(),
+                    1,
+                ));
+                let file = fs::File::create(&path)?;
+                self.paths.push(path);
+                self.current.insert(CurrentFile {
+                    writer: BufWriter::new(file),
+                    bytes: 0,
+                    items: 0,
+                })
+            }
+        };
+
+        current.writer.write_all(item)?;
+        current.bytes += len;
+        current.items += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<Vec<PathBuf>> {
+        if let Some(mut current) = self.current.take() {
+            current.writer.flush()?;
+        }
+        Ok(self.paths)
+    }
+}